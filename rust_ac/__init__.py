@@ -1,4 +1,5 @@
-from .rust_ac import PServer
+from .rust_ac import PServer, sc2_version, sc2_paths, find_map
+from . import errors
 from .server import Server
 from .supervisor import Supervisor
 from .game_config import GameConfig
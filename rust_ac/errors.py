@@ -0,0 +1,2 @@
+"""Exceptions raised by the arenaclient binding, all derived from ArenaClientError"""
+from .rust_ac import ArenaClientError, AddressInUse, Sc2NotFound, MapNotFound, InvalidConfig, MatchAborted
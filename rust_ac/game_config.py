@@ -1,5 +1,7 @@
 from json import dumps
 
+PROTOCOL_VERSION = 2
+
 
 class GameConfig:
     def __init__(self,
@@ -13,8 +15,17 @@ class GameConfig:
                  max_game_time=60486,
                  light_mode=False,
                  validate_race=False,
+                 strict_race=False,
                  player1_race: str = None,
                  player2_race: str = None,
+                 replay_dir: str = None,
+                 log_dir: str = None,
+                 temp_dir: str = None,
+                 progress_interval: int = 0,
+                 live_tags: bool = False,
+                 player3=None,
+                 player4=None,
+                 teams=None,
                  ):
         self.map_name = map_name
         self.player1 = player1
@@ -27,13 +38,22 @@ class GameConfig:
         self.player1_race = player1_race
         self.player2_race = player2_race
         self.archon = archon
+        self.replay_dir = replay_dir
+        self.log_dir = log_dir
+        self.temp_dir = temp_dir
+        self.progress_interval = progress_interval
+        self.live_tags = live_tags
+        self.player3 = player3
+        self.player4 = player4
+        self.teams = teams
         if validate_race and not player1_race and not player2_race:
             self.validate_race = False
         else:
             self.validate_race = validate_race
+        self.strict_race = strict_race
 
     def to_json(self):
-        return dumps({
+        config = {
             "Map": self.map_name,
             "MaxGameTime": self.max_game_time,
             "Player1": self.player1,
@@ -46,5 +66,30 @@ class GameConfig:
             "ValidateRace": self.validate_race,
             "Player1Race": self.player1_race,
             "Player2Race": self.player2_race,
-            "Archon": self.archon
-        })
+            "Archon": self.archon,
+            "ProtocolVersion": PROTOCOL_VERSION
+        }
+        if self.replay_dir:
+            # Replays get unique generated names in this directory
+            config["ReplayDir"] = self.replay_dir
+            config["ReplayPath"] = ""
+        if self.log_dir:
+            config["LogDir"] = self.log_dir
+        if self.temp_dir:
+            config["TempDir"] = self.temp_dir
+        if self.progress_interval:
+            # The proxy reports the game every progress_interval game loops
+            config["ProgressInterval"] = self.progress_interval
+        if self.player3 and self.player4:
+            # 2v2, Player1 and Player3 against Player2 and Player4 unless teams says otherwise
+            config["Player3"] = self.player3
+            config["Player4"] = self.player4
+            if self.teams:
+                config["Teams"] = list(self.teams)
+        if self.strict_race:
+            # Bots joining as another race than configured lose instead of being corrected
+            config["StrictRace"] = True
+        if self.live_tags:
+            # The proxy sends the tags of the bots as they arrive
+            config["LiveTags"] = True
+        return dumps(config)
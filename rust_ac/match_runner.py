@@ -31,6 +31,7 @@ class MatchRunner:
         for bot in bots:
             self._add_to_cleanup(bot)
             bot.start("123", port=port)
+            await sup.send_bot_pid(bot.name, bot.process.pid)
             if await sup.wait_for_bot(timeout=400):
                 continue
             else:
@@ -84,11 +84,16 @@ class Result:
                 self.result = "Error"
                 return
 
-            elif temp_results[self.bot1] == "Crash":
+            # A bot that could not join voids the match, whatever the other result
+            elif 'InitializationError' in (temp_results[self.bot1], temp_results[self.bot2]):
+                self.result = "InitializationError"
+
+            # Exceeding the memory limit counts as a crash of the bot
+            elif temp_results[self.bot1] in ("Crash", "ResourceLimit"):
                 self.result = "Player1Crash"
                 self.winner = self.bot2
 
-            elif temp_results[self.bot2] == "Crash":
+            elif temp_results[self.bot2] in ("Crash", "ResourceLimit"):
                 self.result = "Player2Crash"
                 self.winner = self.bot1
 
@@ -100,6 +105,15 @@ class Result:
                 self.result = "Player2TimeOut"
                 self.winner = self.bot1
 
+            # A bot that forfeited for a rule violation loses like a defeat
+            elif temp_results[self.bot1] == "Forfeit":
+                self.result = "Player2Win"
+                self.winner = self.bot2
+
+            elif temp_results[self.bot2] == "Forfeit":
+                self.result = "Player1Win"
+                self.winner = self.bot1
+
             elif temp_results[self.bot1] == "Victory":
                 self.result = "Player1Win"
                 self.winner = self.bot1
@@ -116,11 +130,6 @@ class Result:
                 self.result = "Tie"
                 self.winner = "Tie"
 
-            elif temp_results[self.bot1] == 'InitializationError':
-                self.result = "InitializationError"
-
-            elif temp_results[self.bot2] == 'InitializationError':
-                self.result = "InitializationError"
 
         if result.get("GameTime", None):
             self.game_time = result["GameTime"]
@@ -137,6 +146,9 @@ class Result:
             self.bot1_tags = result['Tags'].get(self.bot1, [])
             self.bot2_tags = result['Tags'].get(self.bot2, [])
 
+        if result.get("ReplayPath", None):
+            self.replay_path = result["ReplayPath"]
+
         if self.replay_path is None:
             self.replay_path = os.path.join(
                 self._config.REPLAYS_DIRECTORY, f'{self.match_id}_{self.bot1}_vs_{self.bot2}.SC2Replay')
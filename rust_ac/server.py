@@ -1,5 +1,5 @@
 from . import PServer
-from multiprocessing import Process
+from multiprocessing import Process, Queue
 from .supervisor import Supervisor
 
 
@@ -11,12 +11,19 @@ class Server:
         self.ip_address: str = ip_addr
         self._server = PServer(ip_addr)
         self.process: Process = ...
+        self._ready: Queue = Queue()
 
     def run(self):
-        self.process = Process(target=self._server.run)
+        self.process = Process(target=self._server.run, args=(self._ready.put,))
         self.process.daemon = True
         self.process.start()
 
+    def wait_ready(self, timeout: float = None) -> str:
+        """
+        Waits until the proxy listens, returning its address with the actual port
+        """
+        return self._ready.get(timeout=timeout)
+
     def kill(self):
         self.process.kill()
 
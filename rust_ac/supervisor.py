@@ -1,6 +1,7 @@
 import asyncio
+import json
 from .game_config import GameConfig
-from typing import Optional
+from typing import Dict, List, Optional
 
 from aiohttp import ClientSession, WSMsgType, ClientConnectorError
 from .result import Result
@@ -23,6 +24,18 @@ def valid_msg(msg):
         return False
 
 
+def reassemble(chunks, chunk):
+    """
+    Collects a chunk of an oversized message, returning the full message once all chunks arrived.
+    """
+    parts = chunks.setdefault(chunk["Id"], {})
+    parts[chunk["Index"]] = chunk["Data"]
+    if len(parts) < chunk["Total"]:
+        return None
+    del chunks[chunk["Id"]]
+    return json.loads("".join(parts[i] for i in range(chunk["Total"])))
+
+
 def complete(msg):
     """
     Checks if msg status is complete.
@@ -31,10 +44,17 @@ def complete(msg):
 
 
 class Supervisor:
-    def __init__(self, ip_addr: str, config: Optional[GameConfig] = None):
+    def __init__(self, ip_addr: str, config: Optional[GameConfig] = None, secret: Optional[str] = None):
         self.ip_address: str = ip_addr
+        self._secret: Optional[str] = secret
         self._websocket = None
         self._session = None
+        # Token bots send to join the session of this supervisor, None for the default session
+        self.match_token: Optional[str] = None
+        # Last Progress message of the running game, with ProgressInterval in the config
+        self.progress: Optional[dict] = None
+        # Tags of each bot received during the game, with LiveTags in the config
+        self.tags: Dict[str, List[str]] = {}
         if not config:
             self._config: GameConfig = GameConfig()
         else:
@@ -44,7 +64,7 @@ class Supervisor:
         """
         Connects to address with headers
         """
-        headers = {"shutdown": "true"}
+        headers = self._auth_headers({"shutdown": "true"})
         addr = self._parse_url()
 
         session = ClientSession()
@@ -56,7 +76,7 @@ class Supervisor:
         Connects to address with headers
         """
         ws, session = None, None
-        headers = {"supervisor": "true"}
+        headers = self._auth_headers({"supervisor": "true"})
         addr = self._parse_url()
         for i in range(60):
             await asyncio.sleep(1)
@@ -72,6 +92,11 @@ class Supervisor:
                 if i > 15:
                     return None, None
 
+    def _auth_headers(self, headers: dict) -> dict:
+        if self._secret:
+            headers["supervisor-secret"] = self._secret
+        return headers
+
     def _parse_url(self) -> str:
         addr = self.ip_address.replace("/sc2api", "")
         return "ws://" + addr + '/sc2api'
@@ -82,6 +107,18 @@ class Supervisor:
     async def _send_config(self):
         await self._websocket.send_str(self._config.to_json())
 
+    async def send_bot_pid(self, name: str, pid: int):
+        """
+        Registers the process of a bot, so the proxy can sample its memory
+        """
+        await self._websocket.send_str(f"BotPid {name} {pid}")
+
+    async def register_tokens(self, *tokens: str):
+        """
+        Lets bots connect with these tokens, when the proxy has a supervisor secret
+        """
+        await self._websocket.send_str("RegisterTokens " + " ".join(tokens))
+
     async def wait_for_bot(self, timeout: int = 40) -> bool:
         try:
             msg = await self._websocket.receive(timeout)
@@ -104,17 +141,22 @@ class Supervisor:
             raise ConnectionError("Server sent a CLOSED message")
         if msg.json().get("Status") == "Connected":
             print("Connected to proxy.")
+            self.match_token = msg.json().get("Session")
         await self._send_config()
 
         msg = await self._websocket.receive()
 
         if msg.type == WSMsgType.CLOSED:
             raise ConnectionError("Server sent a CLOSED message")
-        if msg.json().get("Config") == "Received":
+        if msg.json().get("Config") == "Accepted":
             print("Config successfully sent. Bots can be started")
+        elif msg.json().get("Config") == "Rejected":
+            reasons = msg.json().get("Reasons", [])
+            raise ValueError("Config rejected: " + "; ".join(e["Message"] for e in reasons))
 
     async def _wait_for_result(self) -> Result:
         result = Result(self._config)
+        chunks = {}
         async for msg in self._websocket:
             if msg.type == WSMsgType.CLOSED:
                 if not result.has_result():
@@ -122,6 +164,20 @@ class Supervisor:
                     return result
             msg = msg.json()
 
+            if 'Chunk' in msg:
+                msg = reassemble(chunks, msg['Chunk'])
+                if msg is None:
+                    continue
+
+            if 'Progress' in msg:
+                self.progress = msg['Progress']
+                continue
+
+            if 'NewTags' in msg:
+                new_tags = msg['NewTags']
+                self.tags.setdefault(new_tags['Player'], []).extend(new_tags['Tags'])
+                continue
+
             if valid_msg(msg):
                 result.parse_result(msg)
 
@@ -0,0 +1,164 @@
+//! Authentication of connections to the plain listener
+//!
+//! Without a supervisor secret every connection is accepted. With one, supervisors (and
+//! shutdown requests) must send it in the `supervisor-secret` header, and bots must present
+//! a token the supervisor registered with `RegisterTokens <token> [<token>...]`, in the
+//! `bot-token` header or as `?token=` in the URL. Each token admits a single connection,
+//! registering again replaces the tokens of the previous match. Tokens are kept per session,
+//! a bot is only admitted with a token of the session it joins.
+
+use chacha20poly1305::aead::{KeyInit, OsRng};
+use chacha20poly1305::ChaCha20Poly1305;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+/// Header carrying the shared secret of the supervisor
+pub const SUPERVISOR_SECRET_HEADER: &str = "supervisor-secret";
+/// Header carrying the token of a bot
+pub const BOT_TOKEN_HEADER: &str = "bot-token";
+
+/// Secret and tokens checked during the handshake
+#[derive(Debug, Default)]
+pub struct Auth {
+    supervisor_secret: Option<String>,
+    /// Tokens by session, the default session is ""
+    bot_tokens: Mutex<HashMap<String, HashSet<String>>>,
+}
+
+/// Auth shared by the listener and the controller
+pub type SharedAuth = Arc<Auth>;
+
+impl Auth {
+    /// Connections are only checked with a non-empty `supervisor_secret`
+    pub fn new(supervisor_secret: Option<String>) -> Self {
+        Self {
+            supervisor_secret: supervisor_secret.filter(|secret| !secret.is_empty()),
+            bot_tokens: Mutex::default(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.supervisor_secret.is_some()
+    }
+
+    /// Whether a supervisor sending `secret` is accepted
+    pub fn check_supervisor(&self, secret: Option<&str>) -> bool {
+        match (&self.supervisor_secret, secret) {
+            (None, _) => true,
+            (Some(expected), Some(secret)) => {
+                constant_time_eq(expected.as_bytes(), secret.as_bytes())
+            }
+            (Some(_), None) => false,
+        }
+    }
+
+    /// Replace the tokens bots of `session` can connect with
+    pub fn register_tokens(&self, session: &str, tokens: Vec<String>) {
+        self.bot_tokens
+            .lock()
+            .unwrap()
+            .insert(session.to_string(), tokens.into_iter().collect());
+    }
+
+    /// Drop the tokens of a session that ended
+    pub fn forget_session(&self, session: &str) {
+        self.bot_tokens.lock().unwrap().remove(session);
+    }
+
+    /// Whether a bot joining `session` with `token` is accepted, using up the token
+    pub fn admit_bot(&self, session: &str, token: Option<&str>) -> bool {
+        if !self.is_enabled() {
+            return true;
+        }
+        match (self.bot_tokens.lock().unwrap().get_mut(session), token) {
+            (Some(tokens), Some(token)) => tokens.remove(token),
+            _ => false,
+        }
+    }
+}
+
+/// Compare without returning early, so the time taken does not leak the secret
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Random token, e.g. for the smoke test bot
+pub fn random_token() -> String {
+    ChaCha20Poly1305::generate_key(&mut OsRng)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Value of `token` in the query of a connection URL
+pub fn query_token(query: Option<&str>) -> Option<&str> {
+    query_value(query, "token")
+}
+
+/// Non-empty value of `key` in the query of a connection URL
+pub fn query_value<'a>(query: Option<&'a str>, key: &str) -> Option<&'a str> {
+    query?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix(key)?.strip_prefix('='))
+        .filter(|value| !value.is_empty())
+}
+
+/// Tokens of a `RegisterTokens <token> [<token>...]` command, None if `data` is another message
+pub fn parse_register_tokens(data: &str) -> Option<Vec<String>> {
+    let tokens: Vec<String> = data
+        .strip_prefix("RegisterTokens ")?
+        .split_whitespace()
+        .map(String::from)
+        .collect();
+    if tokens.is_empty() {
+        None
+    } else {
+        Some(tokens)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auth() {
+        let open = Auth::new(Some(String::new()));
+        assert!(!open.is_enabled());
+        assert!(open.check_supervisor(None));
+        assert!(open.admit_bot("", None));
+
+        let auth = Auth::new(Some("secret".to_string()));
+        assert!(auth.check_supervisor(Some("secret")));
+        assert!(!auth.check_supervisor(Some("secreT")));
+        assert!(!auth.check_supervisor(None));
+        auth.register_tokens("", vec!["a".to_string(), "b".to_string()]);
+        assert!(auth.admit_bot("", Some("a")));
+        assert!(!auth.admit_bot("", Some("a")));
+        assert!(!auth.admit_bot("", None));
+        auth.register_tokens("", vec!["c".to_string()]);
+        assert!(!auth.admit_bot("", Some("b")));
+        assert!(auth.admit_bot("", Some("c")));
+        auth.register_tokens("s1", vec!["d".to_string(), "e".to_string()]);
+        assert!(!auth.admit_bot("", Some("d")));
+        assert!(auth.admit_bot("s1", Some("d")));
+        auth.forget_session("s1");
+        assert!(!auth.admit_bot("s1", Some("e")));
+        assert_eq!(random_token().len(), 64);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse_register_tokens("RegisterTokens a1 b2"),
+            Some(vec!["a1".to_string(), "b2".to_string()])
+        );
+        assert_eq!(parse_register_tokens("RegisterTokens "), None);
+        assert_eq!(parse_register_tokens("Status"), None);
+        assert_eq!(query_token(Some("x=1&token=abc")), Some("abc"));
+        assert_eq!(query_token(Some("token=")), None);
+        assert_eq!(query_token(None), None);
+        assert_eq!(query_value(Some("tokens=x&match=s1"), "token"), None);
+        assert_eq!(query_value(Some("token=abc&match=s1"), "match"), Some("s1"));
+    }
+}
@@ -21,12 +21,16 @@ impl BuildInfo {
         }
     }
     pub fn get_build_info_from_file() -> BuildInfo {
+        Self::from_file().expect("Could not find .build-info file")
+    }
+    /// Read the build info of the installed SC2 client
+    /// Returns an error if the .build.info file can not be read
+    pub fn from_file() -> Result<BuildInfo, csv::Error> {
         let dir = base_dir();
         let build_info_path = dir.join(".build.info");
         let mut rdr = ReaderBuilder::new()
             .delimiter(b'|')
-            .from_path(build_info_path)
-            .expect("Could not find .build-info file");
+            .from_path(build_info_path)?;
 
         let mut build_info;
         if let Some(result) = rdr.deserialize::<BuildInfo>().flatten().next() {
@@ -35,12 +39,11 @@ impl BuildInfo {
                 .version
                 .split('.')
                 .last()
-                .unwrap()
-                .parse::<u32>()
-                .unwrap();
+                .and_then(|build| build.parse::<u32>().ok())
+                .unwrap_or_default();
             build_info.data_build = build_info.base_build;
-            return build_info;
+            return Ok(build_info);
         }
-        BuildInfo::new()
+        Ok(BuildInfo::new())
     }
 }
@@ -0,0 +1,141 @@
+//! Per-match artifact bundles, one `.tar.zst` file to archive or attach to a dispute
+
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Artifacts of a finished match
+#[derive(Debug, Default)]
+pub struct Bundle {
+    pub match_id: i64,
+    /// Result JSON as sent to the supervisor
+    pub result_json: String,
+    /// Files copied into the bundle as they are, e.g. the replay
+    pub files: Vec<PathBuf>,
+    /// Generated entries, e.g. SC2 stderr snippets and the proxy log
+    pub texts: Vec<(String, String)>,
+    /// Recordings of the match kept outside the bundle, e.g. traces, listed in `index.json`
+    pub recordings: Vec<PathBuf>,
+}
+
+/// Path of the bundle of a match in `dir`
+pub fn bundle_path(dir: &Path, match_id: i64) -> PathBuf {
+    dir.join(format!("match_{}.tar.zst", match_id))
+}
+
+impl Bundle {
+    /// Paths and sizes of the recordings, the size is null for recordings that were not written
+    pub fn recording_index(&self) -> String {
+        let entries: Vec<serde_json::Value> = self
+            .recordings
+            .iter()
+            .map(|path| {
+                serde_json::json!({
+                    "Path": path.to_string_lossy(),
+                    "Bytes": std::fs::metadata(path).ok().map(|m| m.len()),
+                })
+            })
+            .collect();
+        serde_json::to_string_pretty(&entries).unwrap_or_default()
+    }
+
+    /// Write the bundle to `dir`, skipping files that do not exist
+    #[cfg(feature = "bundle")]
+    pub fn write(&self, dir: &Path) -> io::Result<PathBuf> {
+        std::fs::create_dir_all(dir)?;
+        let path = bundle_path(dir, self.match_id);
+        let encoder = zstd::Encoder::new(File::create(&path)?, 0)?;
+        let mut archive = tar::Builder::new(encoder);
+        append_bytes(&mut archive, "result.json", self.result_json.as_bytes())?;
+        for file in self.files.iter().filter(|f| f.is_file()) {
+            let name = file.file_name().unwrap_or_default();
+            archive.append_path_with_name(file, name)?;
+        }
+        for (name, text) in &self.texts {
+            append_bytes(&mut archive, name, text.as_bytes())?;
+        }
+        append_bytes(
+            &mut archive,
+            "index.json",
+            self.recording_index().as_bytes(),
+        )?;
+        archive.into_inner()?.finish()?;
+        Ok(path)
+    }
+
+    #[cfg(not(feature = "bundle"))]
+    pub fn write(&self, _dir: &Path) -> io::Result<PathBuf> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "built without the bundle feature",
+        ))
+    }
+}
+
+#[cfg(feature = "bundle")]
+fn append_bytes<W: io::Write>(
+    archive: &mut tar::Builder<W>,
+    name: &str,
+    data: &[u8],
+) -> io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+    );
+    header.set_cksum();
+    archive.append_data(&mut header, name, data)
+}
+
+#[cfg(all(test, feature = "bundle"))]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_write_bundle() {
+        let dir = tempfile::tempdir().unwrap();
+        let replay = dir.path().join("match.SC2Replay");
+        std::fs::write(&replay, b"replay data").unwrap();
+        let bundle = Bundle {
+            match_id: 7,
+            result_json: "{\"MatchID\": 7}".to_string(),
+            files: vec![replay, dir.path().join("missing.SC2Replay")],
+            texts: vec![("sc2_stderr_Bot1.txt".to_string(), "error".to_string())],
+            recordings: vec![dir.path().join("7_player1.pbtrace")],
+        };
+        let index = bundle.recording_index();
+        assert!(index.contains("7_player1.pbtrace"));
+        assert!(index.contains("\"Bytes\": null"));
+        let path = bundle.write(dir.path()).unwrap();
+        assert_eq!(path, dir.path().join("match_7.tar.zst"));
+
+        let decoder = zstd::Decoder::new(File::open(path).unwrap()).unwrap();
+        let mut archive = tar::Archive::new(decoder);
+        let mut entries: Vec<(String, String)> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let name = entry.path().unwrap().to_string_lossy().into_owned();
+                let mut content = String::new();
+                entry.read_to_string(&mut content).unwrap();
+                (name, content)
+            })
+            .collect();
+        entries.sort();
+        assert_eq!(
+            entries,
+            vec![
+                ("index.json".to_string(), index),
+                ("match.SC2Replay".to_string(), "replay data".to_string()),
+                ("result.json".to_string(), "{\"MatchID\": 7}".to_string()),
+                ("sc2_stderr_Bot1.txt".to_string(), "error".to_string()),
+            ]
+        );
+    }
+}
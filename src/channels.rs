@@ -0,0 +1,191 @@
+//! Capacities and overflow policies of the channels between proxy, controller and games
+//!
+//! Control channels (connections, supervisor actions, HTTP commands, game control)
+//! apply backpressure: a full channel makes the sender wait for the controller.
+//! Telemetry channels drop the oldest message instead, so a slow reader never stalls a game.
+
+use crossbeam::channel::{self, Receiver, SendError, Sender, TrySendError};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Accepted connections waiting for the controller
+pub const CONNECTION_CAPACITY: usize = 32;
+/// Actions from the supervisor listener
+pub const SUPERVISOR_CAPACITY: usize = 64;
+/// Joins and drops reported by the playlist tasks
+pub const PLAYLIST_CAPACITY: usize = 16;
+/// Requests of the HTTP API
+pub const HTTP_CAPACITY: usize = 16;
+/// Control messages between a game and its players or the controller
+pub const GAME_CAPACITY: usize = 16;
+/// Telemetry from a game to the controller
+pub const TELEMETRY_CAPACITY: usize = 256;
+
+/// How often an async sender retries a full channel
+const BACKPRESSURE_POLL: Duration = Duration::from_millis(10);
+
+/// Send from async code, waiting without blocking the runtime while the channel is full
+pub async fn send_with_backpressure<T>(tx: &Sender<T>, mut msg: T) -> Result<(), SendError<T>> {
+    let mut warned = false;
+    loop {
+        match tx.try_send(msg) {
+            Ok(()) => return Ok(()),
+            Err(TrySendError::Full(m)) => {
+                if !warned {
+                    warn!(
+                        "Channel full ({} messages), waiting for the receiver",
+                        tx.len()
+                    );
+                    warned = true;
+                }
+                msg = m;
+                tokio::time::sleep(BACKPRESSURE_POLL).await;
+            }
+            Err(TrySendError::Disconnected(m)) => return Err(SendError(m)),
+        }
+    }
+}
+
+/// Sending half of a telemetry channel, dropping the oldest message when full
+pub struct TelemetrySender<T> {
+    tx: Sender<T>,
+    /// Used to make room, never to consume messages otherwise
+    rx: Receiver<T>,
+    dropped: Arc<AtomicU64>,
+}
+impl<T> TelemetrySender<T> {
+    pub fn send(&self, mut msg: T) {
+        loop {
+            match self.tx.try_send(msg) {
+                Ok(()) | Err(TrySendError::Disconnected(_)) => return,
+                Err(TrySendError::Full(m)) => {
+                    if self.rx.try_recv().is_ok() {
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                    msg = m;
+                }
+            }
+        }
+    }
+}
+
+impl<T> Clone for TelemetrySender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            rx: self.rx.clone(),
+            dropped: self.dropped.clone(),
+        }
+    }
+}
+
+/// Receiving half of a telemetry channel
+pub struct TelemetryReceiver<T> {
+    rx: Receiver<T>,
+    dropped: Arc<AtomicU64>,
+}
+impl<T> TelemetryReceiver<T> {
+    pub fn try_recv(&self) -> Option<T> {
+        self.rx.try_recv().ok()
+    }
+    /// Messages dropped because the channel was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+    pub fn depth(&self) -> ChannelDepth {
+        ChannelDepth {
+            depth: self.rx.len(),
+            capacity: self.rx.capacity().unwrap_or_default(),
+            dropped: self.dropped(),
+        }
+    }
+}
+
+/// Create a drop-oldest telemetry channel
+pub fn telemetry<T>(capacity: usize) -> (TelemetrySender<T>, TelemetryReceiver<T>) {
+    let (tx, rx) = channel::bounded(capacity);
+    let dropped = Arc::new(AtomicU64::new(0));
+    (
+        TelemetrySender {
+            tx,
+            rx: rx.clone(),
+            dropped: dropped.clone(),
+        },
+        TelemetryReceiver { rx, dropped },
+    )
+}
+
+/// Fill level of a channel, reported in the proxy status
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct ChannelDepth {
+    #[serde(rename = "Depth")]
+    pub depth: usize,
+    #[serde(rename = "Capacity")]
+    pub capacity: usize,
+    /// Messages dropped on overflow, only telemetry channels drop
+    #[serde(rename = "Dropped")]
+    pub dropped: u64,
+}
+
+/// Reads the depth of a channel without consuming from it
+pub struct ChannelGauge {
+    name: &'static str,
+    capacity: usize,
+    len: Box<dyn Fn() -> usize + Send + Sync>,
+}
+impl ChannelGauge {
+    pub fn new<T: Send + 'static>(name: &'static str, rx: &Receiver<T>) -> Self {
+        let rx = rx.clone();
+        Self {
+            name,
+            capacity: rx.capacity().unwrap_or_default(),
+            len: Box::new(move || rx.len()),
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+    pub fn depth(&self) -> ChannelDepth {
+        ChannelDepth {
+            depth: (self.len)(),
+            capacity: self.capacity,
+            dropped: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_telemetry_drops_oldest() {
+        let (tx, rx) = telemetry(2);
+        for i in 0..5 {
+            tx.send(i);
+        }
+        assert_eq!(rx.dropped(), 3);
+        assert_eq!(rx.try_recv(), Some(3));
+        assert_eq!(rx.try_recv(), Some(4));
+        assert_eq!(rx.try_recv(), None);
+    }
+
+    #[test]
+    fn test_gauge() {
+        let (tx, rx) = channel::bounded(4);
+        let gauge = ChannelGauge::new("Test", &rx);
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        assert_eq!(
+            gauge.depth(),
+            ChannelDepth {
+                depth: 2,
+                capacity: 4,
+                dropped: 0
+            }
+        );
+    }
+}
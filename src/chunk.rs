@@ -0,0 +1,166 @@
+//! Splitting of oversized supervisor messages
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Messages larger than this are split into chunks, unless configured otherwise
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 1 << 20; // 1MiB
+
+/// Part of a message that was too large to send in a single frame
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    /// Identifier shared by all chunks of one message
+    #[serde(rename = "Id")]
+    pub id: u64,
+    /// Position of this chunk in the message
+    #[serde(rename = "Index")]
+    pub index: usize,
+    /// Number of chunks the message was split into
+    #[serde(rename = "Total")]
+    pub total: usize,
+    /// Location of the complete message on disk, if it was spilled
+    #[serde(default, rename = "SpillPath")]
+    pub spill_path: Option<String>,
+    #[serde(rename = "Data")]
+    pub data: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ChunkMessage {
+    #[serde(rename = "Chunk")]
+    chunk: Chunk,
+}
+
+/// Split a message into serialized chunk messages of at most `max_size` bytes each
+/// The envelope and the JSON escaping of the data count towards the size, only a `max_size`
+/// too small for the envelope and a single character is exceeded
+pub fn split_message(
+    message: &str,
+    max_size: usize,
+    id: u64,
+    spill_path: Option<&Path>,
+) -> Vec<String> {
+    let spill_path = spill_path.map(|p| p.to_string_lossy().into_owned());
+    // There are never more chunks than bytes, so this envelope is at least as long as any other
+    let envelope = serialize_chunk(Chunk {
+        id,
+        index: message.len(),
+        total: message.len(),
+        spill_path: spill_path.clone(),
+        data: String::new(),
+    })
+    .len();
+    let budget = max_size.saturating_sub(envelope).max(MAX_ESCAPED_CHAR_LEN);
+    let mut parts = Vec::new();
+    let mut rest = message;
+    while !rest.is_empty() {
+        let mut escaped = 0;
+        let end = rest
+            .char_indices()
+            .find_map(|(i, c)| {
+                escaped += escaped_len(c);
+                if escaped > budget {
+                    Some(i)
+                } else {
+                    None
+                }
+            })
+            .unwrap_or(rest.len());
+        let (part, tail) = rest.split_at(end);
+        parts.push(part);
+        rest = tail;
+    }
+    let total = parts.len();
+    parts
+        .into_iter()
+        .enumerate()
+        .map(|(index, data)| {
+            serialize_chunk(Chunk {
+                id,
+                index,
+                total,
+                spill_path: spill_path.clone(),
+                data: data.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Longest a single character gets in a JSON string, `\u001f` or 4 bytes of UTF-8
+const MAX_ESCAPED_CHAR_LEN: usize = 6;
+
+/// Bytes of `c` in a JSON string as serde_json writes it
+fn escaped_len(c: char) -> usize {
+    match c {
+        '"' | '\\' | '\n' | '\r' | '\t' | '\u{8}' | '\u{c}' => 2,
+        c if (c as u32) < 0x20 => MAX_ESCAPED_CHAR_LEN,
+        c => c.len_utf8(),
+    }
+}
+
+fn serialize_chunk(chunk: Chunk) -> String {
+    serde_json::to_string(&ChunkMessage { chunk }).expect("Could not serialize Chunk")
+}
+
+/// Reassemble a message from its chunks, returns None if any chunk is missing
+pub fn join_chunks(mut chunks: Vec<Chunk>) -> Option<String> {
+    chunks.sort_by_key(|c| c.index);
+    let total = chunks.first()?.total;
+    if chunks.len() != total || chunks.iter().enumerate().any(|(i, c)| c.index != i) {
+        return None;
+    }
+    Some(chunks.into_iter().map(|c| c.data).collect())
+}
+
+/// Write the complete message to disk so it survives even if a chunk gets lost
+pub fn spill_to_disk(message: &str, dir: &Path, name: &str) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(name);
+    let mut file = File::create(&path)?;
+    file.write_all(message.as_bytes())?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(chunks: &[String]) -> Vec<Chunk> {
+        chunks
+            .iter()
+            .map(|c| serde_json::from_str::<ChunkMessage>(c).unwrap().chunk)
+            .collect()
+    }
+
+    #[test]
+    fn test_split_and_join() {
+        let message = "{\"Result\": \"ÄÖÜ some longer payload\"}";
+        let chunks = split_message(message, 5, 1, None);
+        assert!(chunks.len() > 1);
+        let mut parsed = parse(&chunks);
+        parsed.reverse();
+        assert_eq!(join_chunks(parsed), Some(message.to_string()));
+    }
+
+    #[test]
+    fn test_chunk_size() {
+        let message = "{\"Log\": \"\\\"quoted\\\" \\\\path\\\\ \u{1} ÄÖÜ\"}".repeat(40);
+        let spill = Path::new("/tmp/spill.json");
+        let chunks = split_message(&message, 200, 7, Some(spill));
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            assert!(chunk.len() <= 200, "{} bytes", chunk.len());
+        }
+        assert_eq!(join_chunks(parse(&chunks)), Some(message));
+    }
+
+    #[test]
+    fn test_join_missing_chunk() {
+        let chunks = split_message("0123456789", 4, 1, None);
+        let mut parsed = parse(&chunks);
+        parsed.remove(1);
+        assert_eq!(join_chunks(parsed), None);
+    }
+}
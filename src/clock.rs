@@ -0,0 +1,127 @@
+//! Time source for budgets and reports
+//!
+//! Budgets and durations only use the monotonic clock, so NTP adjustments
+//! cannot stretch or shrink them. The wall clock is used for timestamps only.
+
+use chrono::{DateTime, Duration as WallDuration, Local};
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Source of monotonic and wall-clock time
+pub trait Clock: Send + Sync + Debug {
+    /// Monotonic time, for budgets and durations
+    fn now(&self) -> Instant;
+    /// Wall-clock time, for timestamps in reports
+    fn wall(&self) -> DateTime<Local>;
+}
+
+/// Clock shared between the controller and the games
+pub type SharedClock = Arc<dyn Clock>;
+
+/// Clock of the operating system
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+    fn wall(&self) -> DateTime<Local> {
+        Local::now()
+    }
+}
+
+/// The operating system clock as a `SharedClock`
+pub fn system() -> SharedClock {
+    Arc::new(SystemClock)
+}
+
+/// Clock that only moves when advanced, for tests
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    base: Instant,
+    wall_base: DateTime<Local>,
+    offset: Arc<Mutex<Duration>>,
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self {
+            base: Instant::now(),
+            wall_base: Local::now(),
+            offset: Arc::new(Mutex::new(Duration::default())),
+        }
+    }
+}
+
+impl MockClock {
+    pub fn advance(&self, duration: Duration) {
+        *self.offset.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.base + *self.offset.lock().unwrap()
+    }
+    fn wall(&self) -> DateTime<Local> {
+        let offset = *self.offset.lock().unwrap();
+        self.wall_base + WallDuration::from_std(offset).unwrap_or_else(|_| WallDuration::zero())
+    }
+}
+
+/// Start of an interval on both clocks
+#[derive(Debug, Clone)]
+pub struct Stopwatch {
+    clock: SharedClock,
+    started: Instant,
+    started_at: DateTime<Local>,
+}
+
+impl Stopwatch {
+    pub fn start(clock: SharedClock) -> Self {
+        Self {
+            started: clock.now(),
+            started_at: clock.wall(),
+            clock,
+        }
+    }
+    /// Monotonic time since the start
+    pub fn elapsed(&self) -> Duration {
+        self.clock.now().saturating_duration_since(self.started)
+    }
+    /// Wall-clock start, RFC 3339
+    pub fn started_at(&self) -> String {
+        self.started_at.to_rfc3339()
+    }
+    /// Current wall-clock time, RFC 3339
+    pub fn now_wall(&self) -> String {
+        self.clock.wall().to_rfc3339()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_clock() {
+        let clock = MockClock::default();
+        let start = clock.now();
+        let wall_start = clock.wall();
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(clock.now() - start, Duration::from_secs(5));
+        assert_eq!((clock.wall() - wall_start).num_seconds(), 5);
+    }
+
+    #[test]
+    fn test_stopwatch() {
+        let clock = MockClock::default();
+        let stopwatch = Stopwatch::start(Arc::new(clock.clone()));
+        assert_eq!(stopwatch.elapsed(), Duration::default());
+        clock.advance(Duration::from_millis(1500));
+        assert_eq!(stopwatch.elapsed(), Duration::from_millis(1500));
+        assert_ne!(stopwatch.started_at(), stopwatch.now_wall());
+    }
+}
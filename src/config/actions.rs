@@ -0,0 +1,22 @@
+/// What happens to a request carrying more than `MaxActionsPerFrame` actions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActionLimitMode {
+    /// Forward only the first actions, up to the limit
+    Truncate,
+    /// Answer with an error instead of forwarding the request
+    Reject,
+}
+impl Default for ActionLimitMode {
+    fn default() -> Self {
+        ActionLimitMode::Truncate
+    }
+}
+impl ActionLimitMode {
+    pub fn parse(mode: &str) -> Option<Self> {
+        match &mode.to_lowercase()[..] {
+            "truncate" => Some(Self::Truncate),
+            "reject" => Some(Self::Reject),
+            _ => None,
+        }
+    }
+}
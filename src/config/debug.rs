@@ -0,0 +1,25 @@
+/// Answer to debug requests blocked by `DisableDebug`, see `DebugRequestPolicy`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugRequestPolicy {
+    /// Answered with an empty response, as if SC2 ran them
+    Silent,
+    /// Answered with an error response
+    Deny,
+    /// Answered with an error response, the bot forfeits after `MaxDebugViolations` of them
+    Kick,
+}
+impl Default for DebugRequestPolicy {
+    fn default() -> Self {
+        DebugRequestPolicy::Silent
+    }
+}
+impl DebugRequestPolicy {
+    pub fn parse(mode: &str) -> Option<Self> {
+        match &mode.to_lowercase()[..] {
+            "silent" => Some(Self::Silent),
+            "deny" => Some(Self::Deny),
+            "kick" => Some(Self::Kick),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,71 @@
+//! Per-match directories for replays, logs and temporary files, so hosts can
+//! route the artifacts of each competition separately
+
+use crate::config::{Config, ConfigError};
+use std::fs;
+use std::path::PathBuf;
+
+impl Config {
+    /// Directory for the SC2 logs of the match, None to keep no logs
+    pub fn log_dir(&self) -> Option<PathBuf> {
+        self.log_dir.as_ref().map(PathBuf::from)
+    }
+
+    /// Directory the SC2 processes of the match keep their temporary files in,
+    /// None to use the system temp dir and warm processes from the pool
+    pub fn temp_dir(&self) -> Option<PathBuf> {
+        self.temp_dir.as_ref().map(PathBuf::from)
+    }
+
+    /// Directory for the protocol traces of the players, None to trace nothing, see `trace`
+    pub fn protocol_trace(&self) -> Option<PathBuf> {
+        self.protocol_trace.as_ref().map(PathBuf::from)
+    }
+
+    /// Create the configured directories, replacing them by their absolute paths
+    /// so the result reports exactly where the artifacts went
+    pub fn prepare_dirs(&mut self) -> Result<(), ConfigError> {
+        for (field, dir) in [
+            ("ReplayDir", &mut self.replay_dir),
+            ("LogDir", &mut self.log_dir),
+            ("TempDir", &mut self.temp_dir),
+            ("ProtocolTrace", &mut self.protocol_trace),
+        ] {
+            if let Some(path) = dir {
+                let absolute = fs::create_dir_all(&path)
+                    .and_then(|_| fs::canonicalize(&path))
+                    .map_err(|e| {
+                        ConfigError::new(field, format!("Could not create {:?}: {}", path, e))
+                    })?;
+                *path = absolute.to_string_lossy().into_owned();
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prepare_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config {
+            log_dir: Some(dir.path().join("logs/7").to_string_lossy().into_owned()),
+            temp_dir: Some(dir.path().join("tmp").to_string_lossy().into_owned()),
+            ..Default::default()
+        };
+        config.prepare_dirs().unwrap();
+        let log_dir = config.log_dir().unwrap();
+        assert!(log_dir.is_absolute() && log_dir.is_dir());
+        assert!(config.temp_dir().unwrap().is_dir());
+        assert_eq!(config.replay_dir(), None);
+
+        let file = dir.path().join("file");
+        fs::write(&file, b"").unwrap();
+        config.replay_dir = Some(file.join("replays").to_string_lossy().into_owned());
+        let error = config.prepare_dirs().unwrap_err();
+        assert_eq!(error.field.as_deref(), Some("ReplayDir"));
+    }
+}
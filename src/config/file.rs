@@ -0,0 +1,91 @@
+//! Config files for local use, in JSON, TOML or YAML
+
+use crate::config::{Config, ConfigError};
+use std::path::Path;
+
+/// Format of a config file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+impl ConfigFormat {
+    /// Format from the file extension, None if it is unknown
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "json" => Some(Self::Json),
+            "toml" => Some(Self::Toml),
+            "yaml" | "yml" => Some(Self::Yaml),
+            _ => None,
+        }
+    }
+    pub fn parse(self, data: &str) -> Result<Config, String> {
+        match self {
+            Self::Json => serde_json::from_str(data).map_err(|e| e.to_string()),
+            Self::Toml => toml::from_str(data).map_err(|e| e.to_string()),
+            Self::Yaml => serde_yaml::from_str(data).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+impl Config {
+    /// Load a config file, the format is taken from the extension
+    /// or detected from the contents if the extension is unknown
+    pub fn load_from_path(path: &Path) -> Result<Self, ConfigError> {
+        let data = std::fs::read_to_string(path)
+            .map_err(|e| ConfigError::general(format!("Could not read {:?}: {}", path, e)))?;
+        match ConfigFormat::from_path(path) {
+            Some(format) => format.parse(&data),
+            // YAML accepts most JSON, so it is tried last
+            None => ConfigFormat::Json
+                .parse(&data)
+                .or_else(|_| ConfigFormat::Toml.parse(&data))
+                .or_else(|_| ConfigFormat::Yaml.parse(&data))
+                .map_err(|_| "Not valid JSON, TOML or YAML".to_string()),
+        }
+        .map_err(|e| ConfigError::general(format!("Invalid config {:?}: {}", path, e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json_config() -> Config {
+        ConfigFormat::Json
+            .parse("{\"Map\": \"AutomatonLE\", \"Player1\": \"Bot1\", \"Player2\": \"Bot2\", \"MaxGameTime\": 60486, \"RealTime\": true}")
+            .unwrap()
+    }
+
+    #[test]
+    fn test_toml() {
+        let config = ConfigFormat::Toml
+            .parse("Map = \"AutomatonLE\"\nPlayer1 = \"Bot1\"\nPlayer2 = \"Bot2\"\nMaxGameTime = 60486\nRealTime = true\n")
+            .unwrap();
+        assert_eq!(config, json_config());
+    }
+
+    #[test]
+    fn test_yaml() {
+        let config = ConfigFormat::Yaml
+            .parse("map: AutomatonLE\nplayer1: Bot1\nplayer2: Bot2\nmax_game_time: 60486\nreal_time: true\n")
+            .unwrap();
+        assert_eq!(config, json_config());
+    }
+
+    #[test]
+    fn test_load_from_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("match.conf");
+        std::fs::write(&path, "Map = \"AutomatonLE\"\nPlayer1 = \"Bot1\"\n").unwrap();
+        let config = Config::load_from_path(&path).unwrap();
+        assert_eq!(config.map(), "AutomatonLE");
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("match.YML")),
+            Some(ConfigFormat::Yaml)
+        );
+        assert!(Config::load_from_path(&dir.path().join("missing.toml")).is_err());
+    }
+}
@@ -1,59 +1,262 @@
 #![allow(missing_docs)]
+mod actions;
+mod debug;
+mod dirs;
+mod file;
+mod ordering;
 mod race;
+mod replay;
+mod speed;
+mod validate;
+mod violations;
+use crate::chunk::DEFAULT_MAX_MESSAGE_SIZE;
+pub use crate::config::actions::ActionLimitMode;
+pub use crate::config::debug::DebugRequestPolicy;
+pub use crate::config::file::ConfigFormat;
+pub use crate::config::ordering::RequestOrdering;
 use crate::config::race::BotRace;
-use crate::sc2::Race;
+pub use crate::config::race::RaceMasking;
+pub use crate::config::speed::GameSpeed;
+pub use crate::config::validate::{rejected_message, ConfigError};
+pub use crate::config::violations::Violation;
+use crate::crypto::ArtifactKey;
+use crate::limits::ResourceLimits;
+use crate::maps::{MapChoice, MapPool};
+use crate::proxy::Heartbeat;
+use crate::sc2::{BuiltinAI, Difficulty, Race};
+use crate::sc2process::{DisplayMode, LaunchOptions, Window};
+#[cfg(feature = "supervisor")]
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "supervisor", derive(JsonSchema))]
+// Fields are read with their PascalCase alias, which is what supervisors send
+#[cfg_attr(feature = "supervisor", schemars(rename_all = "PascalCase"))]
 pub struct Config {
     #[serde(default)]
+    #[cfg_attr(feature = "supervisor", schemars(skip))]
     pub pids: Vec<u32>,
     #[serde(default)]
+    #[cfg_attr(feature = "supervisor", schemars(skip))]
     pub average_frame_time: Vec<HashMap<String, f32>>,
     #[serde(default, alias = "Map")]
-    pub map: String,
+    pub map: MapChoice,
+    #[serde(default, alias = "MapPool")]
+    pub map_pool: Vec<String>,
+    #[serde(default, alias = "MapSeed")]
+    pub map_seed: Option<u64>,
+    #[serde(default, alias = "RandomSeed")]
+    pub random_seed: Option<u32>,
+    #[serde(default, alias = "DisableFog")]
+    pub disable_fog: bool,
+    #[serde(default, alias = "BattlenetMapName")]
+    pub battlenet_map_name: Option<String>,
     #[serde(default, alias = "MaxGameTime")]
     pub max_game_time: u32,
+    #[serde(default, alias = "MaxRealTimeSeconds")]
+    pub max_real_time_seconds: u64,
+    #[serde(default, alias = "LogLevel")]
+    pub log_level: Option<String>,
+    #[serde(default, alias = "Pacing")]
+    pub pacing: bool,
+    #[serde(default, alias = "ProgressInterval")]
+    pub progress_interval: u32,
     #[serde(default, alias = "MaxFrameTime")]
     pub max_frame_time: i32,
     #[serde(default, alias = "Strikes")]
     pub strikes: i32,
+    #[serde(default, alias = "MaxActionsPerFrame")]
+    pub max_actions_per_frame: u32,
+    #[serde(default, alias = "ActionLimitMode")]
+    pub action_limit_mode: Option<String>,
+    #[serde(default, alias = "MaxRequestSize")]
+    pub max_request_size: usize,
+    #[serde(default, alias = "MaxStepSize")]
+    pub max_step_size: u32,
+    #[serde(default, alias = "ForfeitViolations")]
+    pub forfeit_violations: Vec<String>,
+    #[serde(default, alias = "RaceMasking")]
+    pub race_masking: Option<String>,
+    #[serde(default, alias = "ObserverRaceMasking")]
+    pub observer_race_masking: Option<String>,
+    #[serde(default, alias = "RequestOrdering")]
+    pub request_ordering: Option<String>,
+    #[serde(default, alias = "MaxTags")]
+    pub max_tags: u32,
+    #[serde(default, alias = "MaxTagLength")]
+    pub max_tag_length: u32,
+    #[serde(default, alias = "LiveTags")]
+    pub live_tags: bool,
     #[serde(default)]
+    #[cfg_attr(feature = "supervisor", schemars(skip))]
     pub result: Vec<HashMap<String, String>>,
     #[serde(default, alias = "Player1")]
     pub player1: String,
     #[serde(default, alias = "Player2")]
     pub player2: String,
+    #[serde(default, alias = "Player3")]
+    pub player3: Option<String>,
+    #[serde(default, alias = "Player4")]
+    pub player4: Option<String>,
+    #[serde(default, alias = "Teams")]
+    pub teams: Vec<u32>,
     #[serde(default, alias = "ReplayPath")]
     pub replay_path: String,
+    #[serde(default, alias = "ReplayDir")]
+    pub replay_dir: Option<String>,
+    #[serde(default, alias = "FrameTrace")]
+    pub frame_trace: Option<String>,
+    #[serde(default, alias = "FrameTraceMaxBytes")]
+    pub frame_trace_max_bytes: u64,
+    #[serde(default, alias = "ProtocolTrace")]
+    pub protocol_trace: Option<String>,
+    #[serde(default, alias = "ProtocolTraceCompact")]
+    pub protocol_trace_compact: bool,
+    #[serde(default, alias = "LogDir")]
+    pub log_dir: Option<String>,
+    #[serde(default, alias = "TempDir")]
+    pub temp_dir: Option<String>,
     #[serde(default, alias = "MatchID")]
+    #[cfg_attr(feature = "supervisor", schemars(rename = "MatchID"))]
     pub match_id: i64,
     #[serde(default, alias = "ReplayName")]
     pub replay_name: String,
     #[serde(default)]
+    #[cfg_attr(feature = "supervisor", schemars(skip))]
     pub game_time: f32,
     #[serde(default)]
+    #[cfg_attr(feature = "supervisor", schemars(skip))]
     pub game_time_seconds: f32,
     #[serde(default)]
+    #[cfg_attr(feature = "supervisor", schemars(skip))]
     pub game_time_formatted: String,
     #[serde(default, alias = "DisableDebug")]
     pub disable_debug: bool,
+    #[serde(default, alias = "DebugRequestPolicy")]
+    pub debug_request_policy: Option<String>,
+    #[serde(default, alias = "MaxDebugViolations")]
+    pub max_debug_violations: u32,
     #[serde(default, alias = "RealTime")]
     pub real_time: bool,
+    #[serde(default, alias = "RealtimeMaxLag")]
+    pub realtime_max_lag: u32,
+    #[serde(default, alias = "RealtimeLagStrikes")]
+    pub realtime_lag_strikes: bool,
     #[serde(default, alias = "Visualize")]
     pub visualize: bool,
+    #[serde(default, alias = "VisualizePlayer")]
+    pub visualize_player: u32,
+    #[serde(default, alias = "VisualizeWidth")]
+    pub visualize_width: u32,
+    #[serde(default, alias = "VisualizeHeight")]
+    pub visualize_height: u32,
     #[serde(default, alias = "LightMode")]
     pub light_mode: bool,
+    #[serde(default, alias = "ForbidRender")]
+    pub forbid_render: bool,
+    #[serde(default, alias = "MaxSpatialResolution")]
+    pub max_spatial_resolution: u32,
     #[serde(default, alias = "ValidateRace")]
     pub validate_race: bool,
+    #[serde(default, alias = "StrictRace")]
+    pub strict_race: bool,
     #[serde(default, alias = "Player1Race")]
     pub player1_race: Option<String>,
     #[serde(default, alias = "Player2Race")]
     pub player2_race: Option<String>,
+    #[serde(default, alias = "Player3Race")]
+    pub player3_race: Option<String>,
+    #[serde(default, alias = "Player4Race")]
+    pub player4_race: Option<String>,
+    #[serde(default, alias = "Player1Limits")]
+    pub player1_limits: Option<ResourceLimits>,
+    #[serde(default, alias = "Player2Limits")]
+    pub player2_limits: Option<ResourceLimits>,
+    #[serde(default, alias = "Player3Limits")]
+    pub player3_limits: Option<ResourceLimits>,
+    #[serde(default, alias = "Player4Limits")]
+    pub player4_limits: Option<ResourceLimits>,
     #[serde(default, alias = "Archon")]
     pub archon: bool,
+    #[serde(default, alias = "RequireProbe")]
+    pub require_probe: bool,
+    #[serde(default, alias = "RequireCapabilities")]
+    pub require_capabilities: bool,
+    #[serde(default, alias = "ProbeTimeout")]
+    pub probe_timeout: u64,
+    #[serde(default, alias = "HeartbeatSeconds")]
+    pub heartbeat_seconds: u64,
+    #[serde(default, alias = "MissedPongs")]
+    pub missed_pongs: u32,
+    #[serde(default, alias = "MaxMessageSize")]
+    pub max_message_size: usize,
+    #[serde(default, alias = "SpillDir")]
+    pub spill_dir: Option<String>,
+    #[serde(default, alias = "ProtocolVersion")]
+    pub protocol_version: Option<u32>,
+    #[serde(default, alias = "Transparent")]
+    pub transparent: bool,
+    #[serde(default, alias = "StripUnknownFields")]
+    pub strip_unknown_fields: bool,
+    #[serde(default, alias = "WarmupLoops")]
+    pub warmup_loops: u32,
+    #[serde(default, alias = "WarmupSeconds")]
+    pub warmup_seconds: u64,
+    #[serde(default, alias = "WarmupFrameTime")]
+    pub warmup_frame_time: u64,
+    #[serde(default, alias = "ResultPolicy")]
+    pub result_policy: Option<String>,
+    #[serde(default, alias = "ComputerDifficulty")]
+    pub computer_difficulty: Option<String>,
+    #[serde(default, alias = "ArtifactKey")]
+    pub artifact_key: Option<String>,
+    #[serde(default, alias = "ArtifactKeyId")]
+    pub artifact_key_id: Option<String>,
+    #[serde(default, alias = "BundleDir")]
+    pub bundle_dir: Option<String>,
+    #[serde(default, alias = "LobbyMaxAgeSecs")]
+    pub lobby_max_age_secs: u64,
+    #[serde(default, alias = "GameSpeed")]
+    pub game_speed: Option<String>,
+    #[serde(default, alias = "LoopsPerSecond")]
+    pub loops_per_second: f64,
+    #[serde(default, alias = "Sc2Args")]
+    pub sc2_args: Vec<String>,
+    #[serde(default, alias = "Sc2Env")]
+    pub sc2_env: BTreeMap<String, String>,
+    #[serde(default, alias = "Sc2DisplayMode")]
+    pub sc2_display_mode: Option<String>,
+    #[serde(default, alias = "Sc2Verbose")]
+    pub sc2_verbose: bool,
+    #[serde(default, alias = "Sc2DataVersion")]
+    pub sc2_data_version: Option<String>,
+    #[serde(default, alias = "Sc2BaseBuild")]
+    pub sc2_base_build: u64,
+    #[serde(default, alias = "Sc2Version")]
+    pub sc2_version: Option<String>,
+    #[serde(default, alias = "PortRangeStart")]
+    pub port_range_start: u16,
+    #[serde(default, alias = "PortRangeEnd")]
+    pub port_range_end: u16,
+    #[serde(default, alias = "DryRun")]
+    pub dry_run: bool,
+    #[serde(default, alias = "MapDownloadUrl")]
+    pub map_download_url: Option<String>,
 }
+/// Base build of a full SC2 version such as `4.10.0.75689`
+fn version_base_build(version: &str) -> Option<u64> {
+    let parts: Vec<&str> = version.trim().split('.').collect();
+    if parts.len() != 4 || parts[..3].iter().any(|part| part.parse::<u32>().is_err()) {
+        return None;
+    }
+    parts[3].parse().ok().filter(|build| *build > 0)
+}
+
 impl Config {
     /// New default config
     pub fn new() -> Self {
@@ -65,8 +268,58 @@ impl Config {
         let p: Self = serde_json::from_str(data).expect("Could not load config from JSON");
         p
     }
-    pub fn map(&self) -> &String {
-        &self.map
+    /// Name of the map, empty while a list or `random` has not been resolved
+    pub fn map(&self) -> &str {
+        self.map.name()
+    }
+    /// Published Battle.net map the game is created on instead of a local `Map`
+    pub fn battlenet_map_name(&self) -> Option<&str> {
+        self.battlenet_map_name
+            .as_deref()
+            .filter(|name| !name.trim().is_empty())
+    }
+    /// Map the match is played on as reported in the result, the Battle.net map if set
+    pub fn map_name(&self) -> &str {
+        self.battlenet_map_name().unwrap_or_else(|| self.map())
+    }
+    /// Pick the map of a list or `random` Map from the installed maps, keeping only those
+    /// in `MapPool` if it is set
+    /// An unset `MapSeed` is set to `seed`, so the result tells how to reproduce the pick
+    pub fn resolve_map(&mut self, seed: u64) -> Result<(), ConfigError> {
+        if !self.map.needs_pick() {
+            return Ok(());
+        }
+        let mut pool = MapPool::installed();
+        if let MapChoice::List(names) = &self.map {
+            pool = pool.filter(names);
+        }
+        if !self.map_pool.is_empty() {
+            pool = pool.filter(&self.map_pool);
+        }
+        let seed = *self.map_seed.get_or_insert(seed);
+        match pool.pick(seed) {
+            Some(map) => {
+                log::info!("Picked map {} with seed {}", map, seed);
+                self.map = MapChoice::from(map);
+                Ok(())
+            }
+            None => Err(ConfigError::new(
+                "Map",
+                format!("No installed map matches {}", self.map),
+            )),
+        }
+    }
+    /// Set an unset `RandomSeed` to `seed`, so the result tells how to reproduce the game
+    pub fn resolve_random_seed(&mut self, seed: u64) {
+        self.random_seed.get_or_insert(seed as u32);
+    }
+    /// Seed of the randomness of SC2 in the game, None to let SC2 pick one
+    pub fn random_seed(&self) -> Option<u32> {
+        self.random_seed
+    }
+    /// Let every player see the whole map
+    pub fn disable_fog(&self) -> bool {
+        self.disable_fog
     }
     pub fn disable_debug(&self) -> bool {
         self.disable_debug
@@ -74,24 +327,107 @@ impl Config {
     pub fn realtime(&self) -> bool {
         self.real_time
     }
+    /// Most game loops a bot may miss between two observations in real time, None if unchecked
+    pub fn realtime_max_lag(&self) -> Option<u32> {
+        if self.real_time && self.realtime_max_lag > 0 {
+            Some(self.realtime_max_lag)
+        } else {
+            None
+        }
+    }
+    /// Whether falling more than `RealtimeMaxLag` game loops behind costs a strike
+    pub fn realtime_lag_strikes(&self) -> bool {
+        self.realtime_lag_strikes
+    }
+    /// Whether a bot ahead in game loops waits for the other one, only in step mode
+    pub fn pacing(&self) -> bool {
+        self.pacing && !self.real_time
+    }
+    /// Game loops between the progress messages sent to the supervisor, None to send none
+    pub fn progress_interval(&self) -> Option<u32> {
+        Some(self.progress_interval).filter(|&interval| interval > 0)
+    }
     pub fn player1(&self) -> &str {
         &self.player1
     }
     pub fn player2(&self) -> &str {
         &self.player2
     }
+    /// Whether `Player3` and `Player4` make this a 2v2 game
+    pub fn is_team_game(&self) -> bool {
+        self.player3.is_some() && self.player4.is_some()
+    }
+    /// Names of all players in slot order, the computer included
+    pub fn players(&self) -> Vec<&str> {
+        let mut players = vec![self.player1(), self.player2()];
+        if let (Some(player3), Some(player4)) = (&self.player3, &self.player4) {
+            players.push(player3);
+            players.push(player4);
+        }
+        players
+    }
+    /// Player slots played by bots, each with its own SC2 process
+    pub fn bot_slots(&self) -> usize {
+        if self.computer().is_some() {
+            1
+        } else {
+            self.players().len()
+        }
+    }
+    /// Team of each player in slot order
+    /// Without `Teams`, `Player1` and `Player3` play against `Player2` and `Player4`
+    pub fn teams(&self) -> Vec<u32> {
+        if !self.teams.is_empty() {
+            return self.teams.clone();
+        }
+        (0..self.players().len() as u32)
+            .map(|i| i % 2 + 1)
+            .collect()
+    }
     pub fn max_game_time(&self) -> u32 {
         self.max_game_time
     }
+    /// Wall-clock time a game may take before it is ended as `TimedOut`, None if unlimited
+    pub fn max_real_time(&self) -> Option<Duration> {
+        if self.max_real_time_seconds == 0 {
+            None
+        } else {
+            Some(Duration::from_secs(self.max_real_time_seconds))
+        }
+    }
+    /// Log level the proxy is raised to while the match runs, None if not set or invalid
+    pub fn log_level(&self) -> Option<log::LevelFilter> {
+        self.log_level.as_deref()?.parse().ok()
+    }
     pub fn replay_path(&self) -> &str {
         &self.replay_path
     }
     pub fn light_mode(&self) -> bool {
         self.light_mode
     }
+    /// Whether SC2 renders the game in a window for spectators
+    pub fn visualize(&self) -> bool {
+        self.visualize
+    }
+    /// Whether joins requesting the render interface are rejected
+    pub fn forbid_render(&self) -> bool {
+        self.forbid_render
+    }
+    /// Largest width and height of feature layers and renders bots get, None if unlimited
+    pub fn max_spatial_resolution(&self) -> Option<i32> {
+        if self.max_spatial_resolution == 0 {
+            None
+        } else {
+            Some(self.max_spatial_resolution.min(i32::MAX as u32) as i32)
+        }
+    }
     pub fn validate_race(&self) -> bool {
         self.validate_race
     }
+    /// Reject bots joining as another race than configured, instead of overriding their race
+    pub fn strict_race(&self) -> bool {
+        self.validate_race && self.strict_race
+    }
     pub fn player1_race(&self) -> &Option<String> {
         &self.player1_race
     }
@@ -108,9 +444,355 @@ impl Config {
             .as_ref()
             .map(|string| BotRace::from_str(string).to_race())
     }
+    /// Race of the bot in slot `index`, counted from 0
+    pub fn player_bot_race(&self, index: usize) -> Option<Race> {
+        match index {
+            0 => self.player1_race.as_ref(),
+            1 => self.player2_race.as_ref(),
+            2 => self.player3_race.as_ref(),
+            _ => self.player4_race.as_ref(),
+        }
+        .map(|string| BotRace::from_str(string).to_race())
+    }
+    /// Memory and CPU caps of the SC2 process of player 1
+    pub fn player1_limits(&self) -> ResourceLimits {
+        self.player1_limits.unwrap_or_default()
+    }
+    pub fn player2_limits(&self) -> ResourceLimits {
+        self.player2_limits.unwrap_or_default()
+    }
+    /// Memory and CPU caps of the SC2 process of the bot in slot `index`, counted from 0
+    pub fn player_limits(&self, index: usize) -> ResourceLimits {
+        match index {
+            0 => self.player1_limits,
+            1 => self.player2_limits,
+            2 => self.player3_limits,
+            _ => self.player4_limits,
+        }
+        .unwrap_or_default()
+    }
+    /// Built-in AI playing as player 2, if `Player2` is "Computer"
+    pub fn computer(&self) -> Option<BuiltinAI> {
+        if !self.player2.eq_ignore_ascii_case("computer") {
+            return None;
+        }
+        Some(BuiltinAI {
+            race: self.player2_bot_race().unwrap_or_default(),
+            difficulty: self
+                .computer_difficulty
+                .as_deref()
+                .and_then(Difficulty::parse)
+                .unwrap_or_default(),
+        })
+    }
+    /// Key to encrypt replays and other stored artifacts with, if one was provided
+    pub fn artifact_key(&self) -> Result<Option<ArtifactKey>, String> {
+        self.artifact_key
+            .as_ref()
+            .map(|hex| {
+                ArtifactKey::from_hex(self.artifact_key_id.as_deref().unwrap_or_default(), hex)
+            })
+            .transpose()
+    }
     pub fn archon(&self) -> bool {
         self.archon
     }
+    pub fn require_probe(&self) -> bool {
+        self.require_probe
+    }
+    /// Bots must complete the `GameInfo` capability exchange before joining
+    pub fn require_capabilities(&self) -> bool {
+        self.require_capabilities
+    }
+    /// Time a bot has to complete the required probes after connecting, defaults to 30 seconds
+    pub fn probe_timeout(&self) -> Duration {
+        if self.probe_timeout == 0 {
+            Duration::from_secs(30)
+        } else {
+            Duration::from_secs(self.probe_timeout)
+        }
+    }
+    /// Keepalive Pings to the bots, None if `HeartbeatSeconds` is not set
+    /// A bot is dropped after `MissedPongs` unanswered Pings, 3 by default
+    pub fn heartbeat(&self) -> Option<Heartbeat> {
+        if self.heartbeat_seconds == 0 {
+            return None;
+        }
+        Some(Heartbeat {
+            interval: Duration::from_secs(self.heartbeat_seconds),
+            max_missed: if self.missed_pongs == 0 {
+                3
+            } else {
+                self.missed_pongs
+            },
+        })
+    }
+    /// Most actions forwarded from a single request, None if unlimited
+    pub fn max_actions_per_frame(&self) -> Option<usize> {
+        if self.max_actions_per_frame == 0 {
+            None
+        } else {
+            Some(self.max_actions_per_frame as usize)
+        }
+    }
+    /// Handling of requests over `MaxActionsPerFrame`, defaults to truncating them
+    pub fn action_limit_mode(&self) -> ActionLimitMode {
+        self.action_limit_mode
+            .as_deref()
+            .and_then(ActionLimitMode::parse)
+            .unwrap_or_default()
+    }
+    /// Most bytes of a request forwarded to SC2, None if unlimited
+    pub fn max_request_size(&self) -> Option<usize> {
+        if self.max_request_size == 0 {
+            None
+        } else {
+            Some(self.max_request_size)
+        }
+    }
+    /// Most game loops a single step may advance, None if unlimited
+    pub fn max_step_size(&self) -> Option<u32> {
+        if self.max_step_size == 0 {
+            None
+        } else {
+            Some(self.max_step_size)
+        }
+    }
+    /// Answer to debug requests blocked by `DisableDebug`, silent by default
+    pub fn debug_request_policy(&self) -> DebugRequestPolicy {
+        self.debug_request_policy
+            .as_deref()
+            .and_then(DebugRequestPolicy::parse)
+            .unwrap_or_default()
+    }
+    /// Blocked debug requests that make a bot forfeit with `Kick`, 1 by default
+    pub fn max_debug_violations(&self) -> u32 {
+        self.max_debug_violations.max(1)
+    }
+    /// Check of the request order of the bots, off by default
+    pub fn request_ordering(&self) -> RequestOrdering {
+        self.request_ordering
+            .as_deref()
+            .and_then(RequestOrdering::parse)
+            .unwrap_or_default()
+    }
+    /// Hiding of the actual race of random opponents, defaults to always hiding it
+    pub fn race_masking(&self) -> RaceMasking {
+        self.race_masking
+            .as_deref()
+            .and_then(RaceMasking::parse)
+            .unwrap_or_default()
+    }
+    /// Hiding of the actual race of random players from observers, defaults to never hiding it
+    pub fn observer_race_masking(&self) -> RaceMasking {
+        self.observer_race_masking
+            .as_deref()
+            .and_then(RaceMasking::parse)
+            .unwrap_or(RaceMasking::Never)
+    }
+    /// Most distinct tags kept per bot, 32 by default
+    pub fn max_tags(&self) -> usize {
+        if self.max_tags == 0 {
+            32
+        } else {
+            self.max_tags as usize
+        }
+    }
+    /// Most characters of a tag, 64 by default
+    pub fn max_tag_length(&self) -> usize {
+        if self.max_tag_length == 0 {
+            64
+        } else {
+            self.max_tag_length as usize
+        }
+    }
+    /// Send the supervisor the new tags of the bots while the game runs
+    pub fn live_tags(&self) -> bool {
+        self.live_tags
+    }
+    /// Violations that make the bot forfeit the game the first time it commits them
+    pub fn forfeit_violations(&self) -> Vec<Violation> {
+        self.forfeit_violations
+            .iter()
+            .filter_map(|name| Violation::parse(name))
+            .collect()
+    }
+    /// Frame time budget after the warmup
+    pub fn max_frame_time(&self) -> Duration {
+        Duration::from_secs(self.max_frame_time.max(0) as u64)
+    }
+    pub fn has_warmup(&self) -> bool {
+        self.warmup_loops > 0 || self.warmup_seconds > 0
+    }
+    /// Whether a bot is still warming up, given its game loop and time since its first request
+    pub fn in_warmup(&self, game_loop: u32, elapsed: Duration) -> bool {
+        (self.warmup_loops > 0 && game_loop < self.warmup_loops)
+            || (self.warmup_seconds > 0 && elapsed < Duration::from_secs(self.warmup_seconds))
+    }
+    /// Frame time budget during the warmup, defaults to ten times `max_frame_time`
+    pub fn warmup_frame_time(&self) -> Duration {
+        if self.warmup_frame_time == 0 {
+            self.max_frame_time() * 10
+        } else {
+            Duration::from_secs(self.warmup_frame_time)
+        }
+    }
+    /// Speed the game runs at. The API only creates Faster games, so other
+    /// speeds only come from mods or replays and have to be configured
+    pub fn game_speed(&self) -> GameSpeed {
+        self.game_speed
+            .as_deref()
+            .and_then(GameSpeed::parse)
+            .unwrap_or_default()
+    }
+    /// Game loops per real second, `LoopsPerSecond` overrides the game speed
+    pub fn loops_per_second(&self) -> f64 {
+        if self.loops_per_second > 0.0 {
+            self.loops_per_second
+        } else {
+            self.game_speed().loops_per_second()
+        }
+    }
+    /// Directory for per-match artifact bundles, None if bundles are disabled
+    pub fn bundle_dir(&self) -> Option<PathBuf> {
+        self.bundle_dir.as_ref().map(PathBuf::from)
+    }
+    /// Time a lobby waits for the remaining bots before it is aborted, defaults to 5 minutes
+    pub fn lobby_max_age(&self) -> Duration {
+        if self.lobby_max_age_secs == 0 {
+            Duration::from_secs(300)
+        } else {
+            Duration::from_secs(self.lobby_max_age_secs)
+        }
+    }
+    /// Relay frames without parsing them, see `Player::run_transparent`
+    pub fn transparent(&self) -> bool {
+        self.transparent
+    }
+    /// Remove protocol fields unknown to the proxy before forwarding, instead of passing them on
+    pub fn strip_unknown_fields(&self) -> bool {
+        self.strip_unknown_fields
+    }
+    /// Largest supervisor message sent as a single frame
+    pub fn max_message_size(&self) -> usize {
+        if self.max_message_size == 0 {
+            DEFAULT_MAX_MESSAGE_SIZE
+        } else {
+            self.max_message_size
+        }
+    }
+    /// CSV file for the per-frame timing trace, None to trace nothing
+    pub fn frame_trace(&self) -> Option<PathBuf> {
+        self.frame_trace.as_ref().map(PathBuf::from)
+    }
+    /// Size cap of the frame trace, defaults to 16 MiB
+    pub fn frame_trace_max_bytes(&self) -> u64 {
+        if self.frame_trace_max_bytes == 0 {
+            16 * 1024 * 1024
+        } else {
+            self.frame_trace_max_bytes
+        }
+    }
+    /// Keep only the time, direction, type and size of the messages in protocol traces
+    pub fn protocol_trace_compact(&self) -> bool {
+        self.protocol_trace_compact
+    }
+    /// Directory for oversized messages, defaults to `TempDir` or the system temp dir
+    pub fn spill_dir(&self) -> PathBuf {
+        self.spill_dir
+            .as_ref()
+            .map(PathBuf::from)
+            .or_else(|| self.temp_dir())
+            .unwrap_or_else(std::env::temp_dir)
+    }
+    /// Play the match against a mock SC2, see `mock_sc2`
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+    /// Map repository a missing map is downloaded from, see `maps::ensure_map`
+    pub fn map_download_url(&self) -> Option<&str> {
+        self.map_download_url
+            .as_deref()
+            .filter(|url| !url.is_empty())
+    }
+    /// Base build the match runs on, from `Sc2BaseBuild` or the last part of `Sc2Version`,
+    /// None for the newest installed
+    pub fn base_build(&self) -> Option<u64> {
+        if self.sc2_base_build > 0 {
+            Some(self.sc2_base_build)
+        } else {
+            self.sc2_version.as_deref().and_then(version_base_build)
+        }
+    }
+    /// Ports SC2 is given for the match, None to use the range of the proxy
+    pub fn port_range(&self) -> Option<RangeInclusive<u16>> {
+        if self.port_range_start == 0 {
+            None
+        } else {
+            Some(self.port_range_start..=self.port_range_end)
+        }
+    }
+    /// How SC2 is launched for the match
+    pub fn launch_options(&self) -> LaunchOptions {
+        LaunchOptions {
+            display_mode: self
+                .sc2_display_mode
+                .as_deref()
+                .and_then(DisplayMode::parse)
+                .unwrap_or_default(),
+            verbose: self.sc2_verbose,
+            data_version: self.sc2_data_version.clone(),
+            base_build: self.base_build(),
+            args: self.sc2_args.clone(),
+            env: self.sc2_env.clone(),
+            dry_run: self.dry_run,
+            port_range: self.port_range(),
+            window: None,
+        }
+    }
+    /// How SC2 is launched for player `index`, in a window if the game is visualized for it
+    pub fn launch_options_for(&self, index: usize) -> LaunchOptions {
+        LaunchOptions {
+            window: self.visualize_window(index),
+            ..self.launch_options()
+        }
+    }
+    /// Window of the SC2 process of player `index` with `Visualize`, 1024x768 by default
+    /// Only the player of `VisualizePlayer` is shown if it is set, otherwise all side by side
+    #[cfg(feature = "visualization")]
+    pub fn visualize_window(&self, index: usize) -> Option<Window> {
+        if !self.visualize {
+            return None;
+        }
+        let width = if self.visualize_width == 0 {
+            1024
+        } else {
+            self.visualize_width
+        };
+        let height = if self.visualize_height == 0 {
+            768
+        } else {
+            self.visualize_height
+        };
+        match self.visualize_player as usize {
+            0 => Some(Window {
+                width,
+                height,
+                x: width * index as u32,
+            }),
+            player if player == index + 1 => Some(Window {
+                width,
+                height,
+                x: 0,
+            }),
+            _ => None,
+        }
+    }
+
+    #[cfg(not(feature = "visualization"))]
+    pub fn visualize_window(&self, _index: usize) -> Option<Window> {
+        None
+    }
 }
 
 #[cfg(test)]
@@ -133,6 +815,157 @@ mod tests {
         \"Player2Race\":\"t\"}"
     }
     #[test]
+    fn test_warmup() {
+        let mut config = Config::load_from_str(string_config());
+        assert!(!config.in_warmup(0, Duration::from_secs(0)));
+        config.warmup_loops = 100;
+        assert!(config.in_warmup(99, Duration::from_secs(1000)));
+        assert!(!config.in_warmup(100, Duration::from_secs(0)));
+        assert_eq!(config.warmup_frame_time(), Duration::from_secs(200));
+        config.warmup_loops = 0;
+        config.warmup_seconds = 5;
+        assert!(config.in_warmup(1000, Duration::from_secs(4)));
+        assert!(!config.in_warmup(0, Duration::from_secs(5)));
+    }
+    #[test]
+    fn test_loops_per_second() {
+        let mut config = Config::load_from_str(string_config());
+        assert!((config.loops_per_second() - 22.4).abs() < 1e-9);
+        config.game_speed = Some("Normal".to_string());
+        assert!((config.loops_per_second() - 16.0).abs() < 1e-9);
+        config.loops_per_second = 30.0;
+        assert!((config.loops_per_second() - 30.0).abs() < 1e-9);
+    }
+    #[test]
+    fn test_launch_options() {
+        let mut config = Config::load_from_str(string_config());
+        assert!(config.launch_options().is_default());
+        config.sc2_display_mode = Some("Fullscreen".to_string());
+        config.sc2_base_build = 81009;
+        config.sc2_args = vec!["-eglpath".to_string(), "libEGL.so".to_string()];
+        let options = config.launch_options();
+        assert_eq!(options.display_mode, DisplayMode::Fullscreen);
+        assert_eq!(options.base_build, Some(81009));
+        assert_eq!(options.args.len(), 2);
+        assert!(!options.is_default());
+
+        let mut config = Config::load_from_str(string_config());
+        config
+            .sc2_env
+            .insert("MESA_GL_VERSION_OVERRIDE".to_string(), "4.5".to_string());
+        let options = config.launch_options();
+        assert_eq!(options.env["MESA_GL_VERSION_OVERRIDE"], "4.5");
+        assert!(!options.is_default());
+
+        let mut config = Config::load_from_str(string_config());
+        config.port_range_start = 20000;
+        config.port_range_end = 20099;
+        let options = config.launch_options();
+        assert_eq!(options.port_range, Some(20000..=20099));
+        assert!(!options.is_default());
+    }
+    #[test]
+    #[cfg(feature = "visualization")]
+    fn test_visualize_window() {
+        let mut config = Config::load_from_str(string_config());
+        assert_eq!(config.launch_options_for(1).window, None);
+        config.visualize = true;
+        assert_eq!(
+            config.visualize_window(1),
+            Some(Window {
+                width: 1024,
+                height: 768,
+                x: 1024,
+            })
+        );
+        config.visualize_player = 1;
+        config.visualize_width = 800;
+        assert_eq!(config.visualize_window(1), None);
+        let options = config.launch_options_for(0);
+        assert_eq!(options.window.map(|w| (w.width, w.x)), Some((800, 0)));
+        assert!(!options.is_default());
+    }
+    #[test]
+    fn test_base_build() {
+        let mut config = Config::load_from_str(string_config());
+        assert_eq!(config.base_build(), None);
+        config.sc2_version = Some("4.10.0.75689".to_string());
+        assert_eq!(config.base_build(), Some(75689));
+        assert_eq!(config.launch_options().base_build, Some(75689));
+        config.sc2_version = Some("4.10".to_string());
+        assert_eq!(config.base_build(), None);
+        config.sc2_base_build = 81009;
+        assert_eq!(config.base_build(), Some(81009));
+    }
+    #[test]
+    fn test_map_choice() {
+        let mut config = Config::load_from_str(string_config());
+        config.resolve_map(7).unwrap();
+        assert_eq!(config.map(), "AutomatonLE");
+        assert_eq!(config.map_seed, None);
+
+        let config = Config::load_from_str(
+            "{\"Map\": [\"AutomatonLE\", \"EverDreamLE\"], \"MapPool\": [\"EverDreamLE\"], \"MapSeed\": 3}",
+        );
+        assert!(config.map.needs_pick());
+        assert_eq!(config.map(), "");
+        assert_eq!(config.map_pool, vec!["EverDreamLE".to_string()]);
+        assert_eq!(config.map_seed, Some(3));
+    }
+    #[test]
+    fn test_random_seed() {
+        let mut config = Config::load_from_str(string_config());
+        assert_eq!(config.random_seed(), None);
+        config.resolve_random_seed((1 << 32) + 11);
+        assert_eq!(config.random_seed(), Some(11));
+
+        let mut config = Config::load_from_str("{\"RandomSeed\": 5, \"DisableFog\": true}");
+        config.resolve_random_seed(11);
+        assert_eq!(config.random_seed(), Some(5));
+        assert!(config.disable_fog());
+    }
+    #[test]
+    fn test_computer() {
+        let mut config = Config::load_from_str(string_config());
+        assert_eq!(config.computer(), None);
+        config.player2 = "Computer".to_string();
+        config.computer_difficulty = Some("VeryHard".to_string());
+        assert_eq!(
+            config.computer(),
+            Some(BuiltinAI {
+                race: Race::Terran,
+                difficulty: Difficulty::VeryHard
+            })
+        );
+    }
+    #[test]
+    fn test_lobby_max_age() {
+        let mut config = Config::load_from_str(string_config());
+        assert_eq!(config.lobby_max_age(), Duration::from_secs(300));
+        config.lobby_max_age_secs = 20;
+        assert_eq!(config.lobby_max_age(), Duration::from_secs(20));
+    }
+    #[test]
+    fn test_heartbeat() {
+        let mut config = Config::load_from_str(string_config());
+        assert_eq!(config.heartbeat(), None);
+        config.heartbeat_seconds = 10;
+        assert_eq!(
+            config.heartbeat(),
+            Some(Heartbeat {
+                interval: Duration::from_secs(10),
+                max_missed: 3
+            })
+        );
+    }
+    #[test]
+    fn test_max_real_time() {
+        let mut config = Config::load_from_str(string_config());
+        assert_eq!(config.max_real_time(), None);
+        config.max_real_time_seconds = 3600;
+        assert_eq!(config.max_real_time(), Some(Duration::from_secs(3600)));
+    }
+    #[test]
     fn test_load_from_str() {
         let str_config = string_config();
         let config = Config::load_from_str(&*str_config);
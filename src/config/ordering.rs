@@ -0,0 +1,25 @@
+/// Check of the order of the requests of a bot in step mode, see `RequestOrdering`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestOrdering {
+    /// Requests are forwarded in any order
+    Off,
+    /// Out-of-order requests are forwarded, logged and counted in the result
+    Flag,
+    /// Like `Flag`, but the proxy observes for the bot before forwarding them
+    Enforce,
+}
+impl Default for RequestOrdering {
+    fn default() -> Self {
+        RequestOrdering::Off
+    }
+}
+impl RequestOrdering {
+    pub fn parse(mode: &str) -> Option<Self> {
+        match &mode.to_lowercase()[..] {
+            "off" => Some(Self::Off),
+            "flag" => Some(Self::Flag),
+            "enforce" => Some(Self::Enforce),
+            _ => None,
+        }
+    }
+}
@@ -29,6 +29,33 @@ impl BotRace {
     }
 }
 
+/// When the actual race of a random player is hidden in `GameInfo` responses,
+/// set for bots and observers on their own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RaceMasking {
+    /// For the whole game
+    Always,
+    /// Until the bot saw an enemy unit
+    UntilScouted,
+    /// Never, e.g. for post-game analysis
+    Never,
+}
+impl Default for RaceMasking {
+    fn default() -> Self {
+        RaceMasking::Always
+    }
+}
+impl RaceMasking {
+    pub fn parse(mode: &str) -> Option<Self> {
+        match &mode.to_lowercase()[..] {
+            "always" => Some(Self::Always),
+            "untilscouted" => Some(Self::UntilScouted),
+            "never" => Some(Self::Never),
+            _ => None,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -106,4 +133,14 @@ mod tests {
         assert_eq!(Race::Zerg, BotRace::Zerg.to_race());
         assert_eq!(Race::Protoss, BotRace::Protoss.to_race());
     }
+    #[test]
+    pub fn test_race_masking() {
+        assert_eq!(
+            RaceMasking::parse("UntilScouted"),
+            Some(RaceMasking::UntilScouted)
+        );
+        assert_eq!(RaceMasking::parse("never"), Some(RaceMasking::Never));
+        assert_eq!(RaceMasking::parse("Sometimes"), None);
+        assert_eq!(RaceMasking::default(), RaceMasking::Always);
+    }
 }
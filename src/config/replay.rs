@@ -0,0 +1,145 @@
+//! Where replays are saved, so a new match never overwrites an older replay
+
+use crate::config::Config;
+use chrono::{DateTime, Local};
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Keep names usable on every file system
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Whether a replay, plain or encrypted, was already saved at `path`
+fn is_taken(path: &Path) -> bool {
+    let mut encrypted = path.as_os_str().to_os_string();
+    encrypted.push(".enc");
+    path.exists() || Path::new(&encrypted).exists()
+}
+
+/// `path`, or the first of `name_1.ext`, `name_2.ext`... that is still free
+fn unique_path(path: PathBuf) -> PathBuf {
+    if !is_taken(&path) {
+        return path;
+    }
+    let stem = path.file_stem().map(OsString::from).unwrap_or_default();
+    let extension = path.extension().map(OsString::from);
+    (1..)
+        .map(|n| {
+            let mut name = stem.clone();
+            name.push(format!("_{}", n));
+            if let Some(extension) = &extension {
+                name.push(".");
+                name.push(extension);
+            }
+            path.with_file_name(name)
+        })
+        .find(|candidate| !is_taken(candidate))
+        .expect("Ran out of replay names")
+}
+
+impl Config {
+    /// Directory replays are saved to under generated names, None to use `ReplayPath`
+    pub fn replay_dir(&self) -> Option<PathBuf> {
+        self.replay_dir.as_ref().map(PathBuf::from)
+    }
+
+    /// Replay path reported in the result: the file it was saved to,
+    /// or the supervisor's `ReplayName` when the proxy saves no replay
+    pub fn reported_replay_path(&self) -> &str {
+        if self.replay_path.is_empty() {
+            &self.replay_name
+        } else {
+            &self.replay_path
+        }
+    }
+
+    /// `{match_id}_{player1}_vs_{player2}_{timestamp}.SC2Replay`
+    pub fn replay_file_name(&self, timestamp: DateTime<Local>) -> String {
+        format!(
+            "{}_{}_vs_{}_{}.SC2Replay",
+            self.match_id,
+            sanitize(&self.player1),
+            sanitize(&self.player2),
+            timestamp.format("%Y%m%dT%H%M%S")
+        )
+    }
+
+    /// Pick the file the replay of the match is saved to and store it in `replay_path`
+    /// With `ReplayDir` the name is generated and missing directories are created,
+    /// otherwise an existing file at `ReplayPath` gets a numbered name next to it
+    pub fn resolve_replay_path(&mut self, timestamp: DateTime<Local>) -> io::Result<()> {
+        let path = match self.replay_dir() {
+            Some(dir) => {
+                std::fs::create_dir_all(&dir)?;
+                dir.join(self.replay_file_name(timestamp))
+            }
+            None if self.replay_path.is_empty() => return Ok(()),
+            None => PathBuf::from(&self.replay_path),
+        };
+        self.replay_path = unique_path(path).to_string_lossy().into_owned();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(dir: &Path) -> Config {
+        Config {
+            match_id: 7,
+            player1: "Bot 1".to_string(),
+            player2: "Bot2".to_string(),
+            replay_dir: Some(dir.join("replays").to_string_lossy().into_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_replay_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let timestamp = Local::now();
+        let stamp = timestamp.format("%Y%m%dT%H%M%S");
+        let mut config = config(dir.path());
+        config.resolve_replay_path(timestamp).unwrap();
+        let first = dir
+            .path()
+            .join(format!("replays/7_Bot_1_vs_Bot2_{}.SC2Replay", stamp));
+        assert_eq!(Path::new(&config.replay_path), first);
+
+        std::fs::write(&first, b"replay").unwrap();
+        let mut config = self::config(dir.path());
+        config.resolve_replay_path(timestamp).unwrap();
+        assert_eq!(
+            Path::new(&config.replay_path),
+            dir.path()
+                .join(format!("replays/7_Bot_1_vs_Bot2_{}_1.SC2Replay", stamp))
+        );
+    }
+
+    #[test]
+    fn test_replay_path_not_overwritten() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("replay.SC2Replay");
+        std::fs::write(path.with_extension("SC2Replay.enc"), b"replay").unwrap();
+        let mut config = Config {
+            replay_path: path.to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+        config.resolve_replay_path(Local::now()).unwrap();
+        assert_eq!(
+            Path::new(&config.replay_path),
+            dir.path().join("replay_1.SC2Replay")
+        );
+    }
+}
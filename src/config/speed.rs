@@ -0,0 +1,66 @@
+/// Game loops per game second, independent of the game speed
+const LOOPS_PER_GAME_SECOND: f64 = 16.0;
+
+/// SC2 game speed, which scales how many loops pass per real second
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameSpeed {
+    Slower,
+    Slow,
+    Normal,
+    Fast,
+    Faster,
+}
+impl Default for GameSpeed {
+    fn default() -> Self {
+        // Ladder games and the API always run on Faster
+        GameSpeed::Faster
+    }
+}
+impl GameSpeed {
+    pub fn parse(speed: &str) -> Option<Self> {
+        match &speed.to_lowercase()[..] {
+            "slower" => Some(Self::Slower),
+            "slow" => Some(Self::Slow),
+            "normal" => Some(Self::Normal),
+            "fast" => Some(Self::Fast),
+            "faster" => Some(Self::Faster),
+            _ => None,
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Slower => "Slower",
+            Self::Slow => "Slow",
+            Self::Normal => "Normal",
+            Self::Fast => "Fast",
+            Self::Faster => "Faster",
+        }
+    }
+    pub fn loops_per_second(&self) -> f64 {
+        let multiplier = match self {
+            Self::Slower => 0.6,
+            Self::Slow => 0.8,
+            Self::Normal => 1.0,
+            Self::Fast => 1.2,
+            Self::Faster => 1.4,
+        };
+        LOOPS_PER_GAME_SECOND * multiplier
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    pub fn test_parse() {
+        assert_eq!(GameSpeed::parse("Faster"), Some(GameSpeed::Faster));
+        assert_eq!(GameSpeed::parse("normal"), Some(GameSpeed::Normal));
+        assert_eq!(GameSpeed::parse("ludicrous"), None);
+    }
+    #[test]
+    pub fn test_loops_per_second() {
+        assert!((GameSpeed::Faster.loops_per_second() - 22.4).abs() < 1e-9);
+        assert!((GameSpeed::Normal.loops_per_second() - 16.0).abs() < 1e-9);
+        assert!((GameSpeed::Slower.loops_per_second() - 9.6).abs() < 1e-9);
+    }
+}
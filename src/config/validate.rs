@@ -0,0 +1,620 @@
+//! Config checks run before a match is accepted, so a bad config is reported
+//! to the supervisor instead of crashing the proxy later
+
+use crate::config::actions::ActionLimitMode;
+use crate::config::debug::DebugRequestPolicy;
+use crate::config::ordering::RequestOrdering;
+use crate::config::race::BotRace;
+use crate::config::race::RaceMasking;
+use crate::config::speed::GameSpeed;
+use crate::config::{version_base_build, Config, Violation};
+use crate::maps::find_map;
+use crate::paths;
+use crate::sc2::Difficulty;
+use crate::sc2process::{DisplayMode, MANAGED_ARGS};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+
+/// A problem with a config, sent to the supervisor as part of
+/// `{"Config": "Rejected", "Reasons": [...]}`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    /// Config key the problem is about, None if it concerns the whole config
+    #[serde(rename = "Field", skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    #[serde(rename = "Message")]
+    pub message: String,
+}
+impl ConfigError {
+    pub fn new(field: &str, message: impl Into<String>) -> Self {
+        Self {
+            field: Some(field.to_string()),
+            message: message.into(),
+        }
+    }
+    /// Error about the config as a whole, e.g. invalid JSON
+    pub fn general(message: impl Into<String>) -> Self {
+        Self {
+            field: None,
+            message: message.into(),
+        }
+    }
+}
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.field {
+            Some(field) => write!(f, "{}: {}", field, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Answer to a config that was not accepted, with every reason found
+pub fn rejected_message(reasons: &[ConfigError]) -> String {
+    serde_json::json!({ "Config": "Rejected", "Reasons": reasons }).to_string()
+}
+
+/// Whether files can be created in `dir`
+fn is_writable_dir(dir: &Path) -> bool {
+    dir.is_dir() && tempfile::tempfile_in(dir).is_ok()
+}
+
+/// Whether a replay can be written to `path`, i.e. its directory exists and is writable
+fn is_writable(path: &Path) -> bool {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => is_writable_dir(dir),
+        _ => is_writable_dir(Path::new(".")),
+    }
+}
+
+fn check_race(errors: &mut Vec<ConfigError>, field: &str, race: &Option<String>) {
+    if let Some(race) = race {
+        if BotRace::from_str(race) == BotRace::NoRace {
+            errors.push(ConfigError::new(field, format!("Unknown race {:?}", race)));
+        }
+    }
+}
+
+impl Config {
+    /// Load a config, reporting invalid JSON instead of panicking
+    pub fn try_load_from_str(data: &str) -> Result<Self, ConfigError> {
+        serde_json::from_str(data)
+            .map_err(|e| ConfigError::general(format!("Invalid config: {}", e)))
+    }
+
+    /// Check everything the proxy needs to run the match, returning all problems found
+    pub fn validate(&self) -> Result<(), Vec<ConfigError>> {
+        let mut errors = self.validate_fields();
+        // A dry run needs no SC2 installation
+        if !self.dry_run && !self.map().is_empty() && find_map(self.map().to_string()).is_none() {
+            errors.push(ConfigError::new(
+                "Map",
+                format!("Map {:?} not found", self.map()),
+            ));
+        }
+        if !self.replay_path.is_empty() && !is_writable(Path::new(&self.replay_path)) {
+            errors.push(ConfigError::new(
+                "ReplayPath",
+                format!(
+                    "Directory of {:?} is missing or not writable",
+                    self.replay_path
+                ),
+            ));
+        }
+        if let Some(path) = self.frame_trace() {
+            if !is_writable(&path) {
+                errors.push(ConfigError::new(
+                    "FrameTrace",
+                    format!("Directory of {:?} is missing or not writable", path),
+                ));
+            }
+        }
+        if let Some(build) = self.base_build().filter(|_| !self.dry_run) {
+            let installed = paths::versions_in(&paths::versions_dir());
+            let message = match installed.iter().find(|version| version.base_build == build) {
+                Some(version) if version.complete => None,
+                Some(version) => Some(format!(
+                    "Base{} is incomplete, {:?} is missing",
+                    build,
+                    version.executable()
+                )),
+                None => {
+                    let builds: Vec<String> = installed
+                        .iter()
+                        .filter(|version| version.complete)
+                        .map(|version| version.base_build.to_string())
+                        .collect();
+                    Some(format!(
+                        "Base{} is not installed, installed builds: {}",
+                        build,
+                        if builds.is_empty() {
+                            "none".to_string()
+                        } else {
+                            builds.join(", ")
+                        }
+                    ))
+                }
+            };
+            if let Some(message) = message {
+                let field = if self.sc2_base_build > 0 {
+                    "Sc2BaseBuild"
+                } else {
+                    "Sc2Version"
+                };
+                errors.push(ConfigError::new(field, message));
+            }
+        }
+        for (field, dir) in [
+            ("ReplayDir", self.replay_dir()),
+            ("LogDir", self.log_dir()),
+            ("TempDir", self.temp_dir()),
+            ("ProtocolTrace", self.protocol_trace()),
+        ] {
+            if let Some(dir) = dir {
+                // Missing directories are created by `prepare_dirs`
+                if dir.exists() && !is_writable_dir(&dir) {
+                    errors.push(ConfigError::new(
+                        field,
+                        format!("{:?} is not a writable directory", dir),
+                    ));
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Checks that do not touch the file system
+    fn validate_fields(&self) -> Vec<ConfigError> {
+        let mut errors = Vec::new();
+        match (self.map.is_empty(), self.battlenet_map_name()) {
+            (true, None) => errors.push(ConfigError::new("Map", "Map is missing")),
+            (false, Some(_)) => errors.push(ConfigError::new(
+                "BattlenetMapName",
+                "Cannot be combined with Map",
+            )),
+            _ => {}
+        }
+        if self.player1.trim().is_empty() {
+            errors.push(ConfigError::new("Player1", "Player name is missing"));
+        }
+        if self.player2.trim().is_empty() {
+            errors.push(ConfigError::new("Player2", "Player name is missing"));
+        } else if self.player1 == self.player2 {
+            errors.push(ConfigError::new(
+                "Player2",
+                "Player names must differ, results are keyed by name",
+            ));
+        }
+        match (&self.player3, &self.player4) {
+            (Some(_), None) => errors.push(ConfigError::new(
+                "Player4",
+                "Team games need both Player3 and Player4",
+            )),
+            (None, Some(_)) => errors.push(ConfigError::new(
+                "Player3",
+                "Team games need both Player3 and Player4",
+            )),
+            _ => {}
+        }
+        if self.is_team_game() {
+            let players = self.players();
+            for (index, field) in [(2, "Player3"), (3, "Player4")] {
+                if players[index].trim().is_empty() {
+                    errors.push(ConfigError::new(field, "Player name is missing"));
+                } else if players[..index].contains(&players[index]) {
+                    errors.push(ConfigError::new(
+                        field,
+                        "Player names must differ, results are keyed by name",
+                    ));
+                }
+            }
+            if self.computer().is_some() {
+                errors.push(ConfigError::new(
+                    "Player2",
+                    "Team games cannot include the computer",
+                ));
+            }
+            if self.archon {
+                errors.push(ConfigError::new(
+                    "Archon",
+                    "Cannot be combined with team games",
+                ));
+            }
+        }
+        if !self.teams.is_empty() {
+            let players = self.players().len();
+            let first = self.teams[0];
+            let members = self.teams.iter().filter(|team| **team == first).count();
+            let distinct: HashSet<_> = self.teams.iter().collect();
+            if self.teams.len() != players {
+                errors.push(ConfigError::new(
+                    "Teams",
+                    format!("Expected a team for each of the {} players", players),
+                ));
+            } else if distinct.len() != 2 || members * 2 != players {
+                errors.push(ConfigError::new(
+                    "Teams",
+                    "Expected two teams with the same number of players",
+                ));
+            }
+        }
+        if self.replay_dir.is_some() && !self.replay_path.is_empty() {
+            errors.push(ConfigError::new(
+                "ReplayDir",
+                "Cannot be combined with ReplayPath",
+            ));
+        }
+        check_race(&mut errors, "Player1Race", &self.player1_race);
+        check_race(&mut errors, "Player2Race", &self.player2_race);
+        check_race(&mut errors, "Player3Race", &self.player3_race);
+        check_race(&mut errors, "Player4Race", &self.player4_race);
+        for (field, limits) in [
+            ("Player1Limits", self.player_limits(0)),
+            ("Player2Limits", self.player_limits(1)),
+            ("Player3Limits", self.player_limits(2)),
+            ("Player4Limits", self.player_limits(3)),
+        ] {
+            if limits.cpus < 0.0 || !limits.cpus.is_finite() {
+                errors.push(ConfigError::new(field, "Cpus must not be negative"));
+            }
+        }
+        if self.max_game_time == 0 {
+            errors.push(ConfigError::new(
+                "MaxGameTime",
+                "Must be a positive number of game loops",
+            ));
+        }
+        if self.max_frame_time < 0 {
+            errors.push(ConfigError::new("MaxFrameTime", "Must not be negative"));
+        }
+        if self.strikes < 0 {
+            errors.push(ConfigError::new("Strikes", "Must not be negative"));
+        }
+        if self.loops_per_second < 0.0 {
+            errors.push(ConfigError::new("LoopsPerSecond", "Must not be negative"));
+        }
+        if let Some(speed) = &self.game_speed {
+            if GameSpeed::parse(speed).is_none() {
+                errors.push(ConfigError::new(
+                    "GameSpeed",
+                    format!("Unknown game speed {:?}", speed),
+                ));
+            }
+        }
+        if let Some(mode) = &self.sc2_display_mode {
+            if DisplayMode::parse(mode).is_none() {
+                errors.push(ConfigError::new(
+                    "Sc2DisplayMode",
+                    format!(
+                        "Unknown display mode {:?}, expected Windowed or Fullscreen",
+                        mode
+                    ),
+                ));
+            }
+        }
+        if let Some(version) = &self.sc2_version {
+            match version_base_build(version) {
+                None => errors.push(ConfigError::new(
+                    "Sc2Version",
+                    format!(
+                        "Unknown version {:?}, expected a full version such as 4.10.0.75689",
+                        version
+                    ),
+                )),
+                Some(build) if self.sc2_base_build > 0 && build != self.sc2_base_build => errors
+                    .push(ConfigError::new(
+                        "Sc2Version",
+                        "Does not match Sc2BaseBuild",
+                    )),
+                Some(_) => {}
+            }
+        }
+        if let Some(url) = self.map_download_url() {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                errors.push(ConfigError::new(
+                    "MapDownloadUrl",
+                    format!("Expected an http or https URL, got {:?}", url),
+                ));
+            }
+        }
+        for arg in &self.sc2_args {
+            if MANAGED_ARGS.contains(&arg.as_str()) {
+                errors.push(ConfigError::new(
+                    "Sc2Args",
+                    format!("{} is set by the proxy", arg),
+                ));
+            }
+        }
+        for (name, value) in &self.sc2_env {
+            if name.is_empty() || name.contains('=') || name.contains('\0') || value.contains('\0')
+            {
+                errors.push(ConfigError::new(
+                    "Sc2Env",
+                    format!("Invalid environment variable {:?}", name),
+                ));
+            }
+        }
+        if let Some(mode) = &self.action_limit_mode {
+            if ActionLimitMode::parse(mode).is_none() {
+                errors.push(ConfigError::new(
+                    "ActionLimitMode",
+                    format!("Unknown mode {:?}, expected Truncate or Reject", mode),
+                ));
+            }
+        }
+        if let Some(policy) = &self.debug_request_policy {
+            if DebugRequestPolicy::parse(policy).is_none() {
+                errors.push(ConfigError::new(
+                    "DebugRequestPolicy",
+                    format!("Unknown policy {:?}, expected Silent, Deny or Kick", policy),
+                ));
+            }
+        }
+        if let Some(mode) = &self.request_ordering {
+            if RequestOrdering::parse(mode).is_none() {
+                errors.push(ConfigError::new(
+                    "RequestOrdering",
+                    format!("Unknown mode {:?}, expected Off, Flag or Enforce", mode),
+                ));
+            }
+        }
+        for name in &self.forfeit_violations {
+            if Violation::parse(name).is_none() {
+                errors.push(ConfigError::new(
+                    "ForfeitViolations",
+                    format!(
+                        "Unknown violation {:?}, expected one of {:?}",
+                        name,
+                        Violation::ALL
+                    ),
+                ));
+            }
+        }
+        if let Some(mode) = &self.race_masking {
+            if RaceMasking::parse(mode).is_none() {
+                errors.push(ConfigError::new(
+                    "RaceMasking",
+                    format!(
+                        "Unknown mode {:?}, expected Always, UntilScouted or Never",
+                        mode
+                    ),
+                ));
+            }
+        }
+        if let Some(mode) = &self.observer_race_masking {
+            if RaceMasking::parse(mode).is_none() {
+                errors.push(ConfigError::new(
+                    "ObserverRaceMasking",
+                    format!(
+                        "Unknown mode {:?}, expected Always, UntilScouted or Never",
+                        mode
+                    ),
+                ));
+            }
+        }
+        if let Some(difficulty) = &self.computer_difficulty {
+            if Difficulty::parse(difficulty).is_none() {
+                errors.push(ConfigError::new(
+                    "ComputerDifficulty",
+                    format!("Unknown difficulty {:?}", difficulty),
+                ));
+            }
+        }
+        if let Err(e) = self.artifact_key() {
+            errors.push(ConfigError::new("ArtifactKey", e));
+        }
+        if self.artifact_key.is_some() {
+            // Traces are streamed to disk while the game runs, so they can't be encrypted
+            for (field, set) in [
+                ("FrameTrace", self.frame_trace.is_some()),
+                ("ProtocolTrace", self.protocol_trace.is_some()),
+            ] {
+                if set {
+                    errors.push(ConfigError::new(
+                        field,
+                        "Traces are written unencrypted, not allowed with ArtifactKey",
+                    ));
+                }
+            }
+        }
+        if let Some(level) = &self.log_level {
+            if self.log_level().is_none() {
+                errors.push(ConfigError::new(
+                    "LogLevel",
+                    format!(
+                        "Unknown level {:?}, expected Error, Warn, Info, Debug or Trace",
+                        level
+                    ),
+                ));
+            }
+        }
+        match (self.port_range_start, self.port_range_end) {
+            (0, 0) => {}
+            (0, _) => errors.push(ConfigError::new(
+                "PortRangeStart",
+                "PortRangeEnd needs a PortRangeStart",
+            )),
+            (start, end) if start > end => errors.push(ConfigError::new(
+                "PortRangeEnd",
+                format!("Port range {}-{} is empty", start, end),
+            )),
+            _ => {}
+        }
+        if self.strict_race && !self.validate_race {
+            errors.push(ConfigError::new(
+                "StrictRace",
+                "Only rejects races with ValidateRace, which sets the races to check",
+            ));
+        }
+        if self.realtime_max_lag > 0 && !self.real_time {
+            errors.push(ConfigError::new(
+                "RealtimeMaxLag",
+                "Only checked in RealTime games",
+            ));
+        }
+        if self.visualize && !cfg!(feature = "visualization") {
+            errors.push(ConfigError::new(
+                "Visualize",
+                "Built without the visualization feature",
+            ));
+        }
+        if self.visualize_player as usize > self.players().len() {
+            errors.push(ConfigError::new(
+                "VisualizePlayer",
+                format!("There is no player {}", self.visualize_player),
+            ));
+        }
+        if self.archon && self.transparent {
+            errors.push(ConfigError::new(
+                "Archon",
+                "Archon partners need the proxy to parse their requests, not with Transparent",
+            ));
+        }
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn valid_config() -> Config {
+        Config::try_load_from_str(
+            "{\"Map\": \"AutomatonLE\",\
+            \"MaxGameTime\": 60486,\
+            \"Player1\": \"Bot1\",\
+            \"Player2\": \"Bot2\",\
+            \"Player1Race\": \"Zerg\"}",
+        )
+        .unwrap()
+    }
+
+    fn fields(errors: Vec<ConfigError>) -> Vec<String> {
+        errors.into_iter().filter_map(|e| e.field).collect()
+    }
+
+    #[test]
+    fn test_valid_fields() {
+        assert!(valid_config().validate_fields().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_fields() {
+        let mut config = valid_config();
+        config.player2 = "Bot1".to_string();
+        config.player1_race = Some("Human".to_string());
+        config.max_game_time = 0;
+        config.strikes = -1;
+        config.action_limit_mode = Some("Drop".to_string());
+        config.debug_request_policy = Some("Ban".to_string());
+        config.request_ordering = Some("Strict".to_string());
+        config.forfeit_violations = vec!["ActionRate".to_string(), "Spam".to_string()];
+        config.race_masking = Some("Sometimes".to_string());
+        config.observer_race_masking = Some("Later".to_string());
+        config.sc2_version = Some("4.10".to_string());
+        config.sc2_args = vec!["-port".to_string(), "5000".to_string()];
+        config.sc2_env.insert("LC_ALL=C".to_string(), String::new());
+        config.map_download_url = Some("ftp://maps.example.com".to_string());
+        config.log_level = Some("verbose".to_string());
+        config.port_range_start = 20100;
+        config.port_range_end = 20000;
+        config.strict_race = true;
+        config.realtime_max_lag = 22;
+        config.visualize_player = 3;
+        config.archon = true;
+        config.transparent = true;
+        assert_eq!(
+            fields(config.validate_fields()),
+            vec![
+                "Player2",
+                "Player1Race",
+                "MaxGameTime",
+                "Strikes",
+                "Sc2Version",
+                "MapDownloadUrl",
+                "Sc2Args",
+                "Sc2Env",
+                "ActionLimitMode",
+                "DebugRequestPolicy",
+                "RequestOrdering",
+                "ForfeitViolations",
+                "RaceMasking",
+                "ObserverRaceMasking",
+                "LogLevel",
+                "PortRangeEnd",
+                "StrictRace",
+                "RealtimeMaxLag",
+                "VisualizePlayer",
+                "Archon"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_artifact_key_traces() {
+        let mut config = valid_config();
+        config.artifact_key = Some("ab".repeat(32));
+        assert!(config.validate_fields().is_empty());
+        config.frame_trace = Some("trace.bin".to_string());
+        config.protocol_trace = Some("traces".to_string());
+        assert_eq!(
+            fields(config.validate_fields()),
+            vec!["FrameTrace", "ProtocolTrace"]
+        );
+    }
+
+    #[test]
+    fn test_team_game() {
+        let mut config = valid_config();
+        config.player3 = Some("Bot3".to_string());
+        assert_eq!(fields(config.validate_fields()), vec!["Player4"]);
+        config.player4 = Some("Bot4".to_string());
+        assert!(config.validate_fields().is_empty());
+        assert_eq!(config.players(), vec!["Bot1", "Bot2", "Bot3", "Bot4"]);
+        assert_eq!(config.teams(), vec![1, 2, 1, 2]);
+        config.teams = vec![1, 1, 2, 2];
+        assert!(config.validate_fields().is_empty());
+        config.teams = vec![1, 1, 1, 2];
+        assert_eq!(fields(config.validate_fields()), vec!["Teams"]);
+        config.teams = vec![1, 2];
+        config.player4 = Some("Bot1".to_string());
+        config.archon = true;
+        assert_eq!(
+            fields(config.validate_fields()),
+            vec!["Player4", "Archon", "Teams"]
+        );
+    }
+
+    #[test]
+    fn test_battlenet_map() {
+        let mut config = valid_config();
+        config.battlenet_map_name = Some("Automaton LE".to_string());
+        assert_eq!(fields(config.validate_fields()), vec!["BattlenetMapName"]);
+        config.map = Default::default();
+        assert!(config.validate_fields().is_empty());
+        assert_eq!(config.map_name(), "Automaton LE");
+    }
+
+    #[test]
+    fn test_invalid_json() {
+        let error = Config::try_load_from_str("{\"Map\": ").unwrap_err();
+        assert_eq!(error.field, None);
+        assert_eq!(
+            rejected_message(&[ConfigError::new("Map", "Map is missing")]),
+            "{\"Config\":\"Rejected\",\"Reasons\":[{\"Field\":\"Map\",\"Message\":\"Map is missing\"}]}"
+        );
+    }
+
+    #[test]
+    fn test_replay_path_writable() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(is_writable(&dir.path().join("replay.SC2Replay")));
+        assert!(!is_writable(&dir.path().join("missing/replay.SC2Replay")));
+    }
+}
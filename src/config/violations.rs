@@ -0,0 +1,58 @@
+#[cfg(feature = "supervisor")]
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Rule a bot broke that the proxy caught, counted per bot in the result
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "supervisor", derive(JsonSchema))]
+pub enum Violation {
+    /// Debug request while `DisableDebug` is set
+    DebugBlocked,
+    /// Request larger than `MaxRequestSize`
+    OversizedFrame,
+    /// Request with more than `MaxActionsPerFrame` actions
+    ActionRate,
+    /// Step of more than `MaxStepSize` game loops
+    StepSize,
+    /// Tag dropped for breaking `MaxTags`, `MaxTagLength` or the allowed characters
+    TagSpam,
+    /// Request naming another player, e.g. an observer action for their perspective
+    IdSpoofing,
+}
+impl Violation {
+    pub const ALL: [Violation; 6] = [
+        Violation::DebugBlocked,
+        Violation::OversizedFrame,
+        Violation::ActionRate,
+        Violation::StepSize,
+        Violation::TagSpam,
+        Violation::IdSpoofing,
+    ];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match &name.to_lowercase()[..] {
+            "debugblocked" => Some(Self::DebugBlocked),
+            "oversizedframe" => Some(Self::OversizedFrame),
+            "actionrate" => Some(Self::ActionRate),
+            "stepsize" => Some(Self::StepSize),
+            "tagspam" => Some(Self::TagSpam),
+            "idspoofing" => Some(Self::IdSpoofing),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        for violation in Violation::ALL {
+            let name = serde_json::to_string(&violation).unwrap();
+            assert_eq!(Violation::parse(name.trim_matches('"')), Some(violation));
+        }
+        assert_eq!(Violation::parse("tagspam"), Some(Violation::TagSpam));
+        assert_eq!(Violation::parse("Spam"), None);
+    }
+}
@@ -0,0 +1,198 @@
+//! Registry of the open client connections, listed in the status
+//!
+//! The listeners wrap every accepted socket in a `Counted` stream before the websocket
+//! handshake, and register it with its role once the handshake passed. A connection is
+//! listed until its socket is dropped.
+
+use crate::proxy::{ClientType, Connection};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Bytes moved over a socket, including websocket framing
+#[derive(Debug, Default)]
+pub struct ByteCounters {
+    sent: AtomicU64,
+    received: AtomicU64,
+}
+
+/// Socket counting the bytes it moves
+pub struct Counted {
+    inner: Box<dyn Connection>,
+    counters: Arc<ByteCounters>,
+}
+impl Counted {
+    pub fn new(inner: Box<dyn Connection>) -> Self {
+        Self {
+            inner,
+            counters: Arc::default(),
+        }
+    }
+    pub fn counters(&self) -> Arc<ByteCounters> {
+        self.counters.clone()
+    }
+}
+impl AsyncRead for Counted {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let read = buf.filled().len() - before;
+        self.counters
+            .received
+            .fetch_add(read as u64, Ordering::Relaxed);
+        poll
+    }
+}
+impl AsyncWrite for Counted {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = &poll {
+            self.counters
+                .sent
+                .fetch_add(*written as u64, Ordering::Relaxed);
+        }
+        poll
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Options a connection was accepted with
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectionOptions {
+    /// Connected through the supervisor TLS listener
+    #[serde(rename = "Tls")]
+    pub tls: bool,
+    /// Frames without a mask are accepted from the client
+    #[serde(rename = "UnmaskedFrames")]
+    pub unmasked_frames: bool,
+    /// Session the client joined, None for the default session
+    #[serde(rename = "Session", skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+}
+
+/// Open connection, as listed in the status
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConnectionInfo {
+    #[serde(rename = "Role")]
+    pub role: ClientType,
+    #[serde(rename = "Peer")]
+    pub peer: SocketAddr,
+    #[serde(rename = "Options")]
+    pub options: ConnectionOptions,
+    #[serde(rename = "AgeSeconds")]
+    pub age_seconds: f64,
+    #[serde(rename = "BytesSent")]
+    pub bytes_sent: u64,
+    #[serde(rename = "BytesReceived")]
+    pub bytes_received: u64,
+}
+
+struct Entry {
+    role: ClientType,
+    peer: SocketAddr,
+    options: ConnectionOptions,
+    opened: Instant,
+    /// Dropped with the socket
+    counters: Weak<ByteCounters>,
+}
+
+/// Connections accepted by the listeners
+#[derive(Default)]
+pub struct Connections {
+    entries: Mutex<Vec<Entry>>,
+}
+
+/// Registry shared by the listeners, the controller and the server
+pub type SharedConnections = Arc<Connections>;
+
+impl Connections {
+    /// List a connection that passed the handshake
+    pub fn register(
+        &self,
+        role: ClientType,
+        peer: SocketAddr,
+        options: ConnectionOptions,
+        counters: &Arc<ByteCounters>,
+    ) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|entry| entry.counters.strong_count() > 0);
+        entries.push(Entry {
+            role,
+            peer,
+            options,
+            opened: Instant::now(),
+            counters: Arc::downgrade(counters),
+        });
+    }
+
+    /// Connections whose socket is still open, oldest first
+    pub fn list(&self) -> Vec<ConnectionInfo> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|entry| entry.counters.strong_count() > 0);
+        entries
+            .iter()
+            .filter_map(|entry| {
+                let counters = entry.counters.upgrade()?;
+                Some(ConnectionInfo {
+                    role: entry.role,
+                    peer: entry.peer,
+                    options: entry.options.clone(),
+                    age_seconds: entry.opened.elapsed().as_secs_f64(),
+                    bytes_sent: counters.sent.load(Ordering::Relaxed),
+                    bytes_received: counters.received.load(Ordering::Relaxed),
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_counted() {
+        let (proxy_side, mut peer_side) = tokio::io::duplex(64);
+        let mut counted = Counted::new(Box::new(proxy_side));
+        let connections = Connections::default();
+        let peer = "127.0.0.1:4000".parse().unwrap();
+        connections.register(
+            ClientType::Bot,
+            peer,
+            ConnectionOptions::default(),
+            &counted.counters(),
+        );
+        counted.write_all(b"hello").await.unwrap();
+        peer_side.write_all(b"hi").await.unwrap();
+        let mut buf = [0; 2];
+        counted.read_exact(&mut buf).await.unwrap();
+
+        let listed = connections.list();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].role, ClientType::Bot);
+        assert_eq!(listed[0].bytes_sent, 5);
+        assert_eq!(listed[0].bytes_received, 2);
+        drop(counted);
+        assert!(connections.list().is_empty());
+    }
+}
@@ -2,26 +2,53 @@
 
 #![allow(dead_code)]
 
-use log::{debug, error, info, trace};
+use log::{debug, error, info, trace, warn};
 use serde::{Deserialize, Serialize};
 
-use crate::build_info::BuildInfo;
-use crate::config::Config;
-use crate::handler::{spawn_game, FromSupervisor, GameLobby, Handle as GameHandle, PlayerNum};
-use crate::proxy::Client;
-use crate::result::JsonResult;
-use crate::sc2::Race;
-use crossbeam::channel::{Receiver, Sender};
+use crate::auth::{parse_register_tokens, random_token, SharedAuth};
+use crate::bundle::{bundle_path, Bundle};
+use crate::channels::{ChannelDepth, ChannelGauge, PLAYLIST_CAPACITY};
+use crate::chunk::{spill_to_disk, split_message, DEFAULT_MAX_MESSAGE_SIZE};
+use crate::clock::{self, SharedClock, Stopwatch};
+use crate::config::{rejected_message, Config, ConfigError, Violation};
+use crate::connections::SharedConnections;
+use crate::crypto::ENCRYPTED_EXTENSION;
+use crate::errors::ArenaError;
+use crate::handler::middleware::Middlewares;
+use crate::handler::{
+    parse_bot_pid, spawn_game, BotPids, ChatLine, Disconnect, FromSupervisor, Game, GameEndReason,
+    GameLobby, Handle as GameHandle, LagReport, OutOfOrder, PlayerNum, PlayerStats, SizeWindow,
+    StateSnapshot, ToSupervisor, UnknownFieldCounts,
+};
+use crate::limits::{CpuTime, MemoryPeak};
+use crate::log_level::{LogLevelGuard, MatchLog};
+use crate::maps::{ensure_map, find_map, smallest_map};
+use crate::paths;
+use crate::playlist::{spawn_client, ClientId, PlaylistEvent, PlaylistTask, ProbeSettings};
+use crate::pool::{self, PoolCommand, PoolStatus, ProcessPool, SharedPool};
+use crate::portconfig::PortConfig;
+use crate::proxy::{Client, ClientStream};
+use crate::result::{format_game_time, BotCapabilities, JsonResult};
+use crate::sc2::{PlayerResult, Race};
+use crate::schema::{self, PROTOCOL_VERSION};
+use crate::shutdown::{ShutdownReason, ShutdownReport};
+use crate::smoke_test::{self, SmokeTestReport, SMOKE_TEST_MATCH_ID, SMOKE_TEST_TIMEOUT};
+use crate::status::{parse_abort, MatchStatus, ProxyStatus};
+use crate::trace::trace_path;
+use crate::update::{UpdateChecker, CURRENT_VERSION};
+use crossbeam::channel::{self, Receiver, Sender};
+use futures_util::future::join_all;
 use futures_util::stream::{SplitSink, SplitStream};
 use futures_util::{SinkExt, StreamExt};
-use protobuf::Message;
 use sc2_proto::{self, sc2api::RequestJoinGame};
-use std::collections::HashMap;
-use tokio::net::TcpStream;
-use tokio::runtime::Runtime;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
 use tokio_tungstenite::tungstenite::error::Error;
 use tokio_tungstenite::tungstenite::Message as TMessage;
-use tokio_tungstenite::WebSocketStream;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum SupervisorAction {
@@ -31,57 +58,147 @@ pub enum SupervisorAction {
     Received,
     Config(String),
     Ping(Vec<u8>),
+    Status,
+    Pool(PoolCommand),
+    /// Run a smoke test match, on the given map or the smallest installed one
+    SmokeTest(Option<String>),
+    /// The launcher started the bot of a player with this PID
+    BotPid(String, u32),
+    /// Tokens the bots of the next match connect with
+    RegisterTokens(Vec<String>),
+    /// Abort the pending or running match with this ID
+    Abort(i64),
+    /// Supervisor closed its connection
+    Closed,
 }
 
-enum PlaylistAction {
-    Respond(TMessage),
-    RespondQuit(TMessage),
-    JoinGame(RequestJoinGame),
-    Kick,
-}
+pub type BotData = (String, Option<Race>);
 
-impl PlaylistAction {
-    pub fn respond(r: sc2_proto::sc2api::Response) -> Self {
-        let m = TMessage::Binary(r.write_to_bytes().expect("Invalid protobuf message"));
-        PlaylistAction::Respond(m)
-    }
-    pub fn respond_quit(r: sc2_proto::sc2api::Response) -> Self {
-        let m = TMessage::Binary(r.write_to_bytes().expect("Invalid protobuf message"));
-        PlaylistAction::RespondQuit(m)
-    }
+/// Bot waiting in the playlist, served by its own task
+struct PlaylistClient {
+    id: ClientId,
+    bot: BotData,
+    task: PlaylistTask,
 }
 
-/// Unique identifier for lobby and running games
-/// Game keeps same id from lobby creation until all clients leave the handler
-#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct GameId(u64);
+/// Time running games get to finish during a shutdown
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(60);
 
-impl GameId {
-    fn next(self) -> Self {
-        Self(self.0 + 1)
-    }
-}
+/// Bytes of SC2 stderr kept per player in artifact bundles
+const SC2_STDERR_SNIPPET: usize = 64 * 1024;
 
-pub type BotData = (String, Option<Race>);
+/// Longest an observer may take to accept a message before it is dropped
+const OBSERVER_SEND_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Finished matches whose outcome is kept for status queries, older ones are forgotten
+const MAX_FINISHED_MATCHES: usize = 1000;
+
+/// Identifier of a game in the controller pool
+pub type GameId = u64;
+
+/// Game in the pool, with the match data needed to report its result
+struct RunningGame {
+    handle: GameHandle,
+    stopwatch: Stopwatch,
+    config: Config,
+    capabilities: HashMap<String, BotCapabilities>,
+    /// Raised log level of the match, lowered once its result is reported
+    log_level: Option<LogLevelGuard>,
+    /// Proxy log of the match for its bundle, only with `BundleDir`
+    match_log: Option<MatchLog>,
+}
 
 /// Controller manages a pool of games and client waiting for games
 pub struct Controller {
-    /// Connections (in non-blocking mode) waiting for a handler
-    /// If a handler join is requested is pending (with remote), then also contains that
-    clients: Vec<(BotData, Client, Option<RequestJoinGame>)>,
+    /// Bots waiting for a handler, each served by a playlist task
+    clients: Vec<PlaylistClient>,
+    /// Identifier of the next client added to the playlist
+    next_client_id: ClientId,
+    /// Joins and drops reported by the playlist tasks
+    playlist_send: Sender<PlaylistEvent>,
+    playlist_recv: Receiver<PlaylistEvent>,
     /// Supervisor channel writer
-    supervisor: Option<SplitSink<WebSocketStream<TcpStream>, TMessage>>,
+    supervisor: Option<SplitSink<ClientStream, TMessage>>,
+    /// Read-only supervisors, sent every message the supervisor is sent
+    observers: Vec<SplitSink<ClientStream, TMessage>>,
     /// Supervisor channel receiver
     super_recv: Option<Receiver<SupervisorAction>>,
+    /// Task reading the supervisor connection
+    listener: Option<SupervisorListener>,
     /// Game config received from supervisor
     config: Option<Config>,
+    /// Game ports picked when the config was accepted
+    ports: Option<PortConfig>,
     /// Pre-game lobby
     lobby: Option<GameLobby>,
+    /// When the lobby was created
+    lobby_created: Option<Instant>,
     /// Running games
-    game: Option<GameHandle>,
+    games: HashMap<GameId, RunningGame>,
+    /// Identifier of the next game started
+    next_game_id: GameId,
+    /// Games allowed to run at the same time
+    max_games: usize,
     /// Connected Clients
     pub connected_clients: usize,
     light_mode: bool,
+    /// Capabilities of the clients that joined the game
+    capabilities: HashMap<String, BotCapabilities>,
+    /// Identifier of the last chunked supervisor message
+    chunk_id: u64,
+    /// Matches whose result was collected
+    matches_completed: u32,
+    /// CPU time sampled for the bots and SC2 of all collected matches, in seconds
+    cpu_seconds: f64,
+    /// Matches closed before they produced a result
+    matches_aborted: u32,
+    /// SC2 processes killed while closing lobbies
+    processes_killed: u32,
+    /// Results reported while no supervisor was attached, sent to the next one
+    unsent_results: Vec<String>,
+    /// Opt-in check for newer releases
+    update_checker: Option<UpdateChecker>,
+    /// Time source for timeouts and report timestamps
+    clock: SharedClock,
+    /// Matches are submitted through the HTTP API instead of a supervisor
+    external_control: bool,
+    /// Outcome of finished and aborted matches, by MatchID
+    finished_matches: HashMap<i64, MatchStatus>,
+    /// MatchIDs of `finished_matches`, oldest first
+    finished_order: VecDeque<i64>,
+    /// Warm SC2 processes
+    pool: SharedPool,
+    /// Status of the pool once a detached warm-up finished, sent to the supervisor
+    pool_send: Sender<PoolStatus>,
+    pool_recv: Receiver<PoolStatus>,
+    /// Shutting down, results are still reported but the supervisor is kept
+    draining: bool,
+    /// Time running games get to finish during a shutdown
+    drain_timeout: Duration,
+    /// Channels reported in the status
+    channel_gauges: Vec<ChannelGauge>,
+    /// Play every match against a mock SC2, regardless of its `DryRun`
+    dry_run: bool,
+    /// Environment variables added to the `Sc2Env` of every match
+    sc2_env: BTreeMap<String, String>,
+    /// Supervisor secret and bot tokens checked by the listener
+    auth: SharedAuth,
+    /// Address bots connect to, used by the smoke test bot
+    proxy_addr: Option<SocketAddr>,
+    /// Start of the running smoke test
+    smoke_test: Option<Stopwatch>,
+    /// Bots of the match being set up, registered by the launcher
+    bot_pids: BotPids,
+    /// Middlewares registered by library users, for every game
+    middlewares: Middlewares,
+    /// Raised log level of the match being set up
+    log_level: Option<LogLevelGuard>,
+    /// Proxy log of the match being set up, only with `BundleDir`
+    match_log: Option<MatchLog>,
+    /// Token bots send to join this session, None for the default session
+    session: Option<String>,
+    /// Connections accepted by the listeners
+    connections: SharedConnections,
 }
 
 impl Default for Controller {
@@ -93,32 +210,211 @@ impl Default for Controller {
 impl Controller {
     /// Create new empty controller from config
     pub fn new() -> Self {
+        let (playlist_send, playlist_recv) = channel::bounded(PLAYLIST_CAPACITY);
+        let (pool_send, pool_recv) = channel::unbounded();
         Self {
             clients: Vec::with_capacity(2),
+            next_client_id: 0,
+            channel_gauges: vec![ChannelGauge::new("Playlist", &playlist_recv)],
+            playlist_send,
+            playlist_recv,
             supervisor: None,
+            observers: Vec::new(),
             super_recv: None,
+            listener: None,
             config: None,
+            ports: None,
             lobby: None,
-            game: None,
+            lobby_created: None,
+            games: HashMap::new(),
+            next_game_id: 0,
+            max_games: 1,
             connected_clients: 0,
             light_mode: false,
+            capabilities: HashMap::new(),
+            chunk_id: 0,
+            matches_completed: 0,
+            cpu_seconds: 0.0,
+            matches_aborted: 0,
+            processes_killed: 0,
+            unsent_results: Vec::new(),
+            update_checker: None,
+            clock: clock::system(),
+            external_control: false,
+            finished_matches: HashMap::new(),
+            finished_order: VecDeque::new(),
+            pool: ProcessPool::shared(),
+            pool_send,
+            pool_recv,
+            draining: false,
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            dry_run: false,
+            sc2_env: BTreeMap::new(),
+            auth: SharedAuth::default(),
+            proxy_addr: None,
+            smoke_test: None,
+            bot_pids: BotPids::default(),
+            middlewares: Middlewares::default(),
+            log_level: None,
+            match_log: None,
+            session: None,
+            connections: SharedConnections::default(),
         }
     }
     /// Reset Controller for new handler
     pub fn reset(&mut self) {
+        self.reset_playlist();
+        self.games.clear();
+    }
+    /// Forget the config and clients of the match being set up, keeping running games
+    fn reset_playlist(&mut self) {
         self.clients = Vec::with_capacity(2);
         self.config = None;
+        self.ports = None;
         self.lobby = None;
-        self.game = None;
+        self.lobby_created = None;
         self.connected_clients = 0;
+        self.capabilities.clear();
+        self.log_level = None;
+        self.match_log = None;
+    }
+    /// Allow up to `max_games` games to run at the same time
+    pub fn set_max_games(&mut self, max_games: usize) {
+        self.max_games = max_games.max(1);
+    }
+    /// Play every match against a mock SC2 instead of launching SC2
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+    /// Environment variables for the SC2 processes of every match,
+    /// the `Sc2Env` of a match takes precedence
+    pub fn set_sc2_env(&mut self, sc2_env: BTreeMap<String, String>) {
+        self.sc2_env = sc2_env;
+    }
+    /// Run `middlewares` for the players of every game, after the built-in ones
+    pub fn set_middlewares(&mut self, middlewares: Middlewares) {
+        self.middlewares = middlewares;
+    }
+    /// Check connections against `auth`, shared with the listener
+    pub fn set_auth(&mut self, auth: SharedAuth) {
+        self.auth = auth;
+    }
+    /// Let bots connect with `tokens`, replacing the tokens registered before
+    pub fn register_tokens(&mut self, tokens: Vec<String>) {
+        debug!("Registered {} bot tokens", tokens.len());
+        self.auth.register_tokens(self.session_key(), tokens);
+    }
+    /// Sample the memory of the bot of `player` while its game runs
+    pub fn register_bot_pid(&mut self, player: String, pid: u32) {
+        debug!("Bot of {} runs as process {}", player, pid);
+        self.bot_pids.lock().unwrap().insert(player, pid);
+    }
+    /// Address the proxy accepts bots on
+    pub fn set_proxy_addr(&mut self, addr: SocketAddr) {
+        self.proxy_addr = Some(addr);
+    }
+    /// List the connections of `connections` in the status
+    pub fn set_connections(&mut self, connections: SharedConnections) {
+        self.connections = connections;
+    }
+    /// Serve the session bots join with `token`, instead of the default session
+    pub fn set_session(&mut self, token: String) {
+        self.session = Some(token);
+    }
+    /// Token of the session, None for the default session
+    pub fn session(&self) -> Option<&str> {
+        self.session.as_deref()
+    }
+    /// Key of the session in the registered bot tokens
+    fn session_key(&self) -> &str {
+        self.session.as_deref().unwrap_or_default()
+    }
+    /// Status message for a supervisor that just connected
+    pub fn connected_message(&self) -> String {
+        schema::connected_message(self.proxy_addr.map(|addr| addr.port()), self.session())
+    }
+    /// Whether another game can be started
+    pub fn has_capacity(&self) -> bool {
+        self.games.len() < self.max_games
+    }
+    /// Report the depth of a channel in the status
+    pub fn add_channel_gauge(&mut self, gauge: ChannelGauge) {
+        self.channel_gauges.push(gauge);
+    }
+    /// Time running games get to finish during a shutdown
+    pub fn set_drain_timeout(&mut self, drain_timeout: Duration) {
+        self.drain_timeout = drain_timeout;
+    }
+    /// Use `clock` for timeouts and timestamps instead of the system clock
+    pub fn set_clock(&mut self, clock: SharedClock) {
+        self.clock = clock;
+    }
+    /// Report newer releases in status queries and results
+    pub fn set_update_checker(&mut self, update_checker: UpdateChecker) {
+        self.update_checker = Some(update_checker);
+    }
+    /// Depth of the registered channels, with the telemetry of all running games summed up
+    fn channel_depths(&self) -> HashMap<String, ChannelDepth> {
+        let mut depths: HashMap<String, ChannelDepth> = self
+            .channel_gauges
+            .iter()
+            .map(|gauge| (gauge.name().to_string(), gauge.depth()))
+            .collect();
+        let mut telemetry = ChannelDepth::default();
+        for game in self.games.values() {
+            let depth = game.handle.telemetry_depth();
+            telemetry.depth += depth.depth;
+            telemetry.capacity += depth.capacity;
+            telemetry.dropped += depth.dropped;
+        }
+        depths.insert("Telemetry".to_string(), telemetry);
+        depths
+    }
+    /// Snapshot of the proxy state, a session only lists its own connections
+    pub fn status(&self) -> ProxyStatus {
+        let connections = self
+            .connections
+            .list()
+            .into_iter()
+            .filter(|c| self.session.is_none() || c.options.session == self.session)
+            .collect();
+        ProxyStatus {
+            version: CURRENT_VERSION.to_string(),
+            protocol_version: PROTOCOL_VERSION,
+            connections,
+            game_running: !self.games.is_empty(),
+            running_games: self.games.len(),
+            matches_completed: self.matches_completed,
+            cpu_seconds: self.cpu_seconds,
+            channels: self.channel_depths(),
+            installed_versions: paths::installed_versions(),
+            update: self.update_checker.as_ref().and_then(UpdateChecker::status),
+        }
+    }
+    /// Answer a supervisor `Status` query
+    pub async fn send_status(&mut self) {
+        let status = self.status();
+        self.send_message(&status.to_message()).await;
+    }
+    /// Apply a pool command and report the pool status to the supervisor
+    /// `PoolWarm` is answered once its processes launched, see `update_games`
+    pub async fn manage_pool(&mut self, command: PoolCommand) {
+        let status = self.pool.lock().unwrap().apply(command);
+        match command {
+            PoolCommand::Warm(_) => self.fill_pool(),
+            _ => self.send_message(&status.to_message()).await,
+        }
+    }
+    /// Cap the target of `PoolWarm` at `max_size` processes
+    pub fn set_max_pool_size(&mut self, max_size: usize) {
+        self.pool.lock().unwrap().set_max_size(max_size);
     }
     pub async fn send_pong(&mut self, payload: Vec<u8>) {
         match &mut self.supervisor {
             Some(sender) => {
-                sender
-                    .send(TMessage::Pong(payload))
-                    .await
-                    .expect("Could not send message to supervisor");
+                if let Err(e) = sender.send(TMessage::Pong(payload)).await {
+                    error!("Could not send message to supervisor: {}", e);
+                }
             }
             None => {
                 error!("send_message: Supervisor not set");
@@ -126,23 +422,88 @@ impl Controller {
         }
     }
     /// Sends a message to the supervisor
+    /// Messages over the configured size are spilled to disk and sent in chunks
     pub async fn send_message(&mut self, message: &str) {
+        let max_size = self
+            .config
+            .as_ref()
+            .map(|c| c.max_message_size())
+            .unwrap_or(DEFAULT_MAX_MESSAGE_SIZE);
+        if message.len() > max_size {
+            self.send_chunked(message, max_size).await;
+        } else {
+            self.send_frame(message).await;
+        }
+    }
+    async fn send_chunked(&mut self, message: &str, max_size: usize) {
+        self.chunk_id += 1;
+        let spill_path = self.config.as_ref().and_then(|config| {
+            let name = format!("{}_{}_message.json", config.match_id, self.chunk_id);
+            match spill_to_disk(message, &config.spill_dir(), &name) {
+                Ok(path) => Some(path),
+                Err(e) => {
+                    error!("Could not spill message to disk: {:?}", e);
+                    None
+                }
+            }
+        });
+        let chunks = split_message(message, max_size, self.chunk_id, spill_path.as_deref());
+        info!(
+            "Message of {} bytes split into {} chunks (spilled to {:?})",
+            message.len(),
+            chunks.len(),
+            spill_path
+        );
+        for chunk in chunks {
+            self.send_frame(&chunk).await;
+        }
+    }
+    async fn send_frame(&mut self, message: &str) {
         match &mut self.supervisor {
             Some(sender) => {
-                sender
-                    .send(TMessage::text(message))
-                    .await
-                    .expect("Could not send message to supervisor");
+                if let Err(e) = sender.send(TMessage::text(message)).await {
+                    error!("Could not send message to supervisor: {}", e);
+                }
             }
             None => {
                 error!("send_message: Supervisor not set");
             }
         }
+        self.broadcast(message).await;
+    }
+    /// Send a message to the observers, dropping those that are gone or too slow
+    async fn broadcast(&mut self, message: &str) {
+        let mut kept = Vec::with_capacity(self.observers.len());
+        for mut observer in std::mem::take(&mut self.observers) {
+            match timeout(
+                OBSERVER_SEND_TIMEOUT,
+                observer.send(TMessage::text(message)),
+            )
+            .await
+            {
+                Ok(Ok(())) => kept.push(observer),
+                Ok(Err(e)) => debug!("Dropping observer: {}", e),
+                Err(_) => warn!("Dropping observer, it did not keep up"),
+            }
+        }
+        self.observers = kept;
     }
     /// Create new lobby
     fn create_lobby(&mut self) -> bool {
-        if let Some(config) = &self.config {
-            self.lobby = Some(GameLobby::new(config.clone()));
+        if let Some(config) = &mut self.config {
+            if let Err(e) = config.resolve_replay_path(self.clock.wall()) {
+                error!(
+                    "Could not create replay directory, replay will not be saved: {}",
+                    e
+                );
+                config.replay_path.clear();
+            }
+            let mut lobby = GameLobby::new(config.clone()).with_pool(self.pool.clone());
+            if let Some(ports) = self.ports.take() {
+                lobby = lobby.with_ports(ports);
+            }
+            self.lobby = Some(lobby);
+            self.lobby_created = Some(self.clock.now());
             true
         } else {
             error!("Did not receive config from supervisor");
@@ -152,51 +513,63 @@ impl Controller {
     pub fn has_supervisor(&self) -> bool {
         self.supervisor.is_some()
     }
-    /// Add a new client socket to playlist
-    pub fn add_client(&mut self, client: Client) {
+    /// Whether a supervisor or the HTTP API can receive results, so bots are accepted
+    pub fn is_controlled(&self) -> bool {
+        self.supervisor.is_some() || self.external_control
+    }
+    /// Accept bots without a supervisor, for matches submitted through the HTTP API
+    pub fn set_external_control(&mut self, external_control: bool) {
+        self.external_control = external_control;
+    }
+    pub fn has_config(&self) -> bool {
+        self.config.is_some()
+    }
+    /// Add a new client socket to playlist, serving it in its own task
+    pub fn add_client(&mut self, mut client: Client) {
         info!("Added client {:?}", client.peer_addr());
-        debug_assert!(self.clients.len() < 2);
-        match self.config.clone() {
-            Some(config) => {
-                if self.connected_clients == 0 {
-                    debug!("Adding {}", config.player1());
-                    self.clients.push((
-                        (config.player1().to_string(), config.player1_bot_race()),
-                        client,
-                        None,
-                    ));
-                    info!(
-                        "{:?} playing {:?}",
-                        config.player1(),
-                        config.player1_bot_race()
-                    );
-                    self.connected_clients += 1;
-                } else {
-                    debug!("Adding {}", config.player2());
-                    self.clients.push((
-                        (config.player2().to_string(), config.player2_bot_race()),
-                        client,
-                        None,
-                    ));
-                    self.connected_clients += 1;
-                    info!(
-                        "{:?} playing {:?}",
-                        config.player2(),
-                        config.player2_bot_race()
-                    );
-                }
-            }
-            None => {
-                panic!("Config not set");
-            }
-        }
+        let config = self.config.as_ref().expect("Config not set");
+        let slots = config.bot_slots();
+        debug_assert!(self.clients.len() < if config.archon() { 2 * slots } else { slots });
+        client.set_heartbeat(config.heartbeat());
+        // In an Archon match the clients take turns between the slots, two per slot
+        let slot = if config.archon() {
+            self.connected_clients % slots
+        } else {
+            self.connected_clients.min(slots - 1)
+        };
+        let bot = (
+            config.players()[slot].to_string(),
+            config.player_bot_race(slot),
+        );
+        info!("{:?} playing {:?}", bot.0, bot.1);
+        let probe = ProbeSettings {
+            required: config.require_probe(),
+            require_game_info: config.require_capabilities(),
+            map_name: config.map_name().to_string(),
+            timeout: config.probe_timeout(),
+            pinned_build: config
+                .base_build()
+                .map(|build| (config.sc2_version.clone().unwrap_or_default(), build as u32)),
+        };
+        let id = self.next_client_id;
+        self.next_client_id += 1;
+        let task = spawn_client(
+            id,
+            client,
+            probe,
+            self.clock.clone(),
+            self.playlist_send.clone(),
+        );
+        self.clients.push(PlaylistClient { id, bot, task });
+        self.connected_clients += 1;
     }
 
     /// Add a new supervisor client socket
     pub fn add_supervisor(
         &mut self,
-        client: SplitSink<WebSocketStream<TcpStream>, TMessage>,
+        client: SplitSink<ClientStream, TMessage>,
         recv: Receiver<SupervisorAction>,
+        listener: SupervisorListener,
     ) {
         if self.supervisor.is_some() {
             error!("Supervisor already set - Resetting supervisor");
@@ -204,6 +577,26 @@ impl Controller {
         debug!("Added supervisor");
         self.supervisor = Some(client);
         self.super_recv = Some(recv);
+        self.listener = Some(listener);
+    }
+
+    /// Add a read-only supervisor, whose messages are read but ignored
+    pub async fn add_observer(&mut self, client: Client) {
+        info!("Added observer {:?}", client.peer_addr());
+        let (mut sink, mut stream) = client.stream.split();
+        // Reading answers its Pings and notices when it closes
+        tokio::spawn(async move {
+            while let Some(Ok(message)) = stream.next().await {
+                if message.is_close() {
+                    break;
+                }
+            }
+        });
+        let connected = TMessage::text(self.connected_message());
+        match timeout(OBSERVER_SEND_TIMEOUT, sink.send(connected)).await {
+            Ok(Ok(())) => self.observers.push(sink),
+            _ => warn!("Observer went away while connecting"),
+        }
     }
 
     pub fn recv_msg(&self) -> Option<SupervisorAction> {
@@ -221,28 +614,241 @@ impl Controller {
             None => None,
         }
     }
-    pub fn set_config(&mut self, config: String) {
-        let config = Config::load_from_str(&config);
+    /// Set the config of the next match
+    /// Returns the problems found if the config is invalid
+    pub fn set_config(&mut self, config: String) -> Result<(), Vec<ConfigError>> {
+        let config = Config::try_load_from_str(&config).map_err(|e| vec![e])?;
+        self.set_match_config(config)
+    }
+    /// Set an already loaded config of the next match, e.g. from a config file
+    pub fn set_match_config(&mut self, mut config: Config) -> Result<(), Vec<ConfigError>> {
+        config.dry_run |= self.dry_run;
+        for (name, value) in &self.sc2_env {
+            config
+                .sc2_env
+                .entry(name.clone())
+                .or_insert_with(|| value.clone());
+        }
+        let seed = self.clock.wall().timestamp_nanos() as u64;
+        if let Err(e) = config.resolve_map(seed) {
+            error!("Invalid config, {}", e);
+            return Err(vec![e]);
+        }
+        config.resolve_random_seed(seed);
+        if let Err(errors) = config.validate() {
+            for e in &errors {
+                error!("Invalid config, {}", e);
+            }
+            return Err(errors);
+        }
+        if let Err(e) = config.prepare_dirs() {
+            error!("Invalid config, {}", e);
+            return Err(vec![e]);
+        }
+        let ports = PortConfig::for_players(config.players().len(), config.port_range().as_ref())
+            .ok_or_else(|| {
+            error!("No free ports for the game");
+            match config.port_range() {
+                Some(range) => vec![ConfigError::new(
+                    "PortRangeStart",
+                    format!(
+                        "No free ports left in {}-{} for the game",
+                        range.start(),
+                        range.end()
+                    ),
+                )],
+                None => vec![ConfigError::general("No free ports for the game")],
+            }
+        })?;
+        match config.protocol_version {
+            Some(PROTOCOL_VERSION) => {}
+            Some(version) => warn!(
+                "Supervisor uses protocol version {}, proxy expects {}",
+                version, PROTOCOL_VERSION
+            ),
+            None => debug!("Supervisor did not send a protocol version"),
+        }
         self.light_mode = config.light_mode();
-        self.config = Some(config)
+        let config_log_level = config.log_level();
+        self.match_log = config.bundle_dir().map(|_| MatchLog::start());
+        self.config = Some(config);
+        self.ports = Some(ports);
+        self.bot_pids = BotPids::default();
+        self.log_level = config_log_level.map(LogLevelGuard::raise);
+        Ok(())
+    }
+
+    /// Answer a config from the supervisor, setting it if the pool has room and it is valid
+    /// The answer is `Accepted` once the map is found and the game ports are picked,
+    /// so bots are only started for a match that can be set up
+    pub async fn receive_config(&mut self, config: String) {
+        if !self.has_capacity() {
+            error!("Game pool full, rejecting config");
+            let reason = ConfigError::general("Game pool full");
+            self.send_message(&rejected_message(&[reason])).await;
+            return;
+        }
+        let config = match Config::try_load_from_str(&config) {
+            Ok(config) => config,
+            Err(e) => {
+                self.send_message(&rejected_message(&[e])).await;
+                return;
+            }
+        };
+        if let Err(error) = self.download_map(&config).await {
+            error!("{}", error);
+            let reason = ConfigError::new("Map", error.to_string());
+            self.send_message(&rejected_message(&[reason])).await;
+            return;
+        }
+        match self.set_match_config(config) {
+            Ok(()) => self.send_message("{\"Config\": \"Accepted\"}").await,
+            Err(errors) => self.send_message(&rejected_message(&errors)).await,
+        }
+    }
+
+    /// Download the map of `config` if it is missing and `MapDownloadUrl` is set
+    /// The download runs off the controller task, as maps take a while to fetch
+    pub async fn download_map(&self, config: &Config) -> Result<(), ArenaError> {
+        let url = match config.map_download_url() {
+            Some(url) if !config.dry_run() && !self.dry_run => url.to_string(),
+            _ => return Ok(()),
+        };
+        let map = config.map().to_string();
+        if map.is_empty() || find_map(map.clone()).is_some() {
+            return Ok(());
+        }
+        let name = map.clone();
+        tokio::task::spawn_blocking(move || ensure_map(&name, &url))
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|downloaded| downloaded)
+            .map(|_| ())
+            .map_err(|reason| ArenaError::MapDownload(map, reason))
     }
 
     /// Remove client from playlist, closing the connection
     async fn drop_client(&mut self, index: usize) {
-        let (_, client, _) = &mut self.clients[index];
-        debug!("Removing client {:?} from playlist", client.peer_addr());
-        client.shutdown().await.expect("Connection shutdown failed");
-        self.clients.remove(index);
+        let client = self.clients.remove(index);
+        debug!("Removing client {:?} from playlist", client.bot.0);
+        client.task.stop().await;
+    }
+
+    /// Send a result to the supervisor, or keep it for the next one if none is attached
+    async fn send_result(&mut self, result_json: String) {
+        if self.has_supervisor() {
+            self.send_message(&result_json).await;
+        } else {
+            warn!("No supervisor attached, keeping the result until one connects");
+            self.unsent_results.push(result_json);
+        }
+    }
+
+    /// Send the result of a match, or the report of the smoke test it was
+    async fn finish_match(&mut self, config: &Config, result_json: String) {
+        match self.smoke_test.take() {
+            Some(stopwatch) if config.match_id == SMOKE_TEST_MATCH_ID => {
+                let report = SmokeTestReport::from_result(&result_json, stopwatch.elapsed());
+                if report.passed {
+                    info!("Smoke test passed in {:.1}s", report.seconds);
+                } else {
+                    error!("Smoke test failed: {:?}", report.error);
+                }
+                self.send_message(&report.to_message()).await;
+            }
+            smoke_test => {
+                self.smoke_test = smoke_test;
+                self.send_result(result_json).await;
+            }
+        }
+    }
+
+    /// Play a short match of the scripted bot against the built-in AI, reporting
+    /// `{"SmokeTest": ...}` once it is over
+    pub async fn run_smoke_test(&mut self, map: Option<String>) {
+        let stopwatch = Stopwatch::start(self.clock.clone());
+        let failed = |error: &str| SmokeTestReport::failed(error, Duration::ZERO).to_message();
+        if self.smoke_test.is_some() || self.has_config() || !self.has_capacity() {
+            self.send_message(&failed("The proxy is busy")).await;
+            return;
+        }
+        let addr = match self.proxy_addr {
+            Some(addr) => addr.to_string(),
+            None => {
+                self.send_message(&failed("Proxy address unknown")).await;
+                return;
+            }
+        };
+        // The mock SC2 of a dry run loads no map
+        let map = map
+            .or_else(smallest_map)
+            .or_else(|| self.dry_run.then(|| "SmokeTest".to_string()));
+        let map = match map {
+            Some(map) => map,
+            None => {
+                self.send_message(&failed("No map installed")).await;
+                return;
+            }
+        };
+        info!("Running a smoke test on {}", map);
+        if let Err(errors) = self.set_match_config(smoke_test::config(&map)) {
+            let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+            self.send_message(&failed(&errors.join("; "))).await;
+            return;
+        }
+        self.smoke_test = Some(stopwatch);
+        let token = self.auth.is_enabled().then(random_token);
+        if let Some(token) = &token {
+            self.auth
+                .register_tokens(self.session_key(), vec![token.clone()]);
+        }
+        let session = self.session.clone();
+        tokio::spawn(async move {
+            match timeout(
+                SMOKE_TEST_TIMEOUT,
+                smoke_test::run_bot(&addr, token, session),
+            )
+            .await
+            {
+                Ok(Ok(game_loop)) => debug!("Smoke test bot left at game loop {}", game_loop),
+                Ok(Err(e)) => warn!("Smoke test bot failed: {}", e),
+                Err(_) => warn!("Smoke test bot timed out"),
+            }
+        });
+    }
+
+    /// Send the results kept while no supervisor was attached
+    pub async fn send_unsent_results(&mut self) {
+        if !self.has_supervisor() {
+            return;
+        }
+        let results = std::mem::take(&mut self.unsent_results);
+        if !results.is_empty() {
+            info!("Sending {} results kept while detached", results.len());
+        }
+        for result_json in results {
+            self.send_message(&result_json).await;
+        }
+    }
+
+    /// Forget a supervisor that closed its connection
+    /// Results are kept until a supervisor connects again
+    pub async fn supervisor_closed(&mut self) {
+        if let Some(mut client) = self.supervisor.take() {
+            // Completes the close handshake, the peer might already be gone
+            client.close().await.ok();
+        }
+        self.super_recv = None;
+        self.listener = None;
     }
 
     /// Remove supervisor
     pub async fn drop_supervisor(&mut self) {
         match &mut self.supervisor {
             Some(client) => {
-                client
-                    .close()
-                    .await
-                    .expect("Supervisor connection shutdown failed");
+                if let Err(e) = client.close().await {
+                    error!("Supervisor connection shutdown failed: {}", e);
+                }
                 self.supervisor = None;
             }
             None => {
@@ -250,239 +856,790 @@ impl Controller {
             }
         };
         self.super_recv = None;
+        self.listener = None;
     }
 
     /// Join to handler from playlist
-    /// If handler join fails, drops connection
-    async fn client_join_game(&mut self, index: usize, req: RequestJoinGame) -> Option<()> {
-        let ((client_name, client_race), client, old_req) = self.clients.remove(index);
+    /// A bot with a malformed join request loses the match, other failures are returned
+    async fn client_join_game(
+        &mut self,
+        (client_name, client_race): BotData,
+        client: Client,
+        req: RequestJoinGame,
+        mut capabilities: BotCapabilities,
+    ) -> Result<(), ArenaError> {
         debug!("{} client_join_game", client_name);
-        if old_req.is_some() {
-            error!("Client attempted to join a handler twice (dropping connection)");
-            return None;
-        }
-        // TODO: Verify that InterfaceOptions are allowed
+        capabilities.record_join(&req);
+        info!("{} capabilities: {:?}", client_name, capabilities);
+        // The lead of an Archon slot joins first and keeps its capabilities
+        self.capabilities
+            .entry(client_name.clone())
+            .or_insert(capabilities);
         // TODO: Fix this so it works without lobbies
-        let player = match client_name.clone() {
-            n if n == self.config.as_ref().unwrap().player1() => PlayerNum::One,
-            n if n == self.config.as_ref().unwrap().player2() => PlayerNum::Two,
-            _ => panic!(),
+        let config = self
+            .config
+            .as_ref()
+            .ok_or_else(|| ArenaError::Lobby("Config not set".to_string()))?;
+        let player = match config
+            .players()
+            .iter()
+            .position(|name| *name == client_name)
+            .and_then(PlayerNum::from_index)
+        {
+            Some(player) => player,
+            None => {
+                return Err(ArenaError::Lobby(format!(
+                    "{} is not a player of the match",
+                    client_name
+                )))
+            }
         };
-        if self.lobby.is_some() {
-            trace!("Lobby exists");
+        if self.lobby.is_none() {
+            if !self.create_lobby() {
+                return Err(ArenaError::Lobby("Could not create lobby".to_string()));
+            }
+            trace!("Created new lobby");
+        }
+        let lobby = self.lobby.as_mut().unwrap();
+        let joined = lobby
+            .join(
+                client,
+                req,
+                (client_name.clone(), client_race),
+                self.light_mode,
+                player,
+            )
+            .await;
+        if let Err(ArenaError::Protocol(reason)) = &joined {
+            error!("{} could not join: {}", client_name, reason);
+            // Set when the lobby was created, with the resolved replay path
+            let config = self.config.clone().unwrap_or_default();
+            // Teammates lose with the bot, the opponents win
+            let players = config.players();
+            let teams = config.teams();
+            let own_team = players
+                .iter()
+                .position(|name| *name == client_name)
+                .map(|index| teams[index]);
+            let results = players
+                .iter()
+                .zip(&teams)
+                .map(|(name, team)| {
+                    let result = if *name == client_name {
+                        PlayerResult::InitializationError
+                    } else if Some(*team) == own_team {
+                        PlayerResult::Defeat
+                    } else {
+                        PlayerResult::Victory
+                    };
+                    (name.to_string(), result.to_string())
+                })
+                .collect();
+            self.matches_completed += 1;
+            self.close_lobby(&config, "Complete", Some(results)).await;
+            return Ok(());
+        }
+        joined?;
+        let lobby = self.lobby.as_mut().unwrap();
+        if lobby.joined_clients() >= lobby.expected_clients() {
+            trace!("All clients joined the lobby");
             let mut lobby = self.lobby.take().unwrap();
-            lobby
-                .join(
-                    client,
-                    req,
-                    (client_name, client_race),
-                    self.light_mode,
-                    player,
-                )
-                .await;
-            lobby.join_player_handles().await;
-            let game = lobby.start().await?;
-            self.game = Some(spawn_game(game));
-        } else if self.create_lobby() {
-            trace!("Create new lobby");
-            let lobby = self.lobby.as_mut().unwrap();
-            lobby
-                .join(
-                    client,
-                    req,
-                    (client_name, client_race),
-                    self.light_mode,
-                    player,
-                )
-                .await;
-        } else {
-            error!("Could not create lobby");
+            if let Err(e) = lobby.join_player_handles().await {
+                self.processes_killed += lobby.close().await;
+                return Err(e);
+            }
+            match lobby.start().await {
+                Ok(game) => self.start_game(game).await,
+                Err(error @ ArenaError::VersionMismatch(_)) => {
+                    // Neither bot is at fault, but the match cannot be played on this host
+                    error!("{}", error);
+                    self.send_message(&error.to_message()).await;
+                    let config = self.config.clone().unwrap_or_default();
+                    let mut results = HashMap::with_capacity(2);
+                    for player in config.players() {
+                        results.insert(
+                            player.to_string(),
+                            PlayerResult::InitializationError.to_string(),
+                        );
+                    }
+                    self.matches_completed += 1;
+                    self.close_lobby(&config, "Complete", Some(results)).await;
+                }
+                Err(e) => return Err(e),
+            }
         }
 
-        Some(())
+        Ok(())
     }
 
-    /// Process message from a client in the playlist
-    fn process_client_message(&mut self, msg: TMessage) -> PlaylistAction {
-        match msg {
-            TMessage::Binary(bytes) => {
-                let req = sc2_proto::sc2api::Request::parse_from_bytes(&bytes);
-                debug!("Incoming playlist request: {:?}", req);
+    /// Abort a match whose game could not be set up, relaying the error to the supervisor
+    async fn abort_setup(&mut self, error: ArenaError) {
+        error!("Game creation / joining failed: {}", error);
+        self.send_message(&error.to_message()).await;
+        if let Some(config) = self.config.clone() {
+            self.matches_aborted += 1;
+            self.close_lobby(&config, "Error", None).await;
+        }
+    }
 
-                match req {
-                    Ok(ref m) if m.has_quit() => {
-                        info!("Client quit");
-                        let mut resp = sc2_proto::sc2api::Response::new();
-                        let quit = sc2_proto::sc2api::ResponseQuit::new();
-                        resp.set_quit(quit);
-                        PlaylistAction::respond_quit(resp)
-                    }
-                    Ok(ref m) if m.has_ping() => {
-                        trace!("Ping => Pong");
-                        let mut resp = sc2_proto::sc2api::Response::new();
-                        let mut pong = sc2_proto::sc2api::ResponsePing::new();
-                        let b = BuildInfo::get_build_info_from_file();
-                        pong.set_game_version(b.version);
-                        pong.set_base_build(b.base_build);
-                        pong.set_data_build(b.data_build);
-                        pong.set_data_version("".to_string());
-                        resp.set_ping(pong);
-                        PlaylistAction::respond(resp)
+    /// Run a game in the pool
+    async fn start_game(&mut self, game: Game) {
+        let game_id = self.next_game_id;
+        self.next_game_id += 1;
+        let config = self.config.clone().expect("Config not set");
+        info!("Starting game {} (match {})", game_id, config.match_id);
+        let match_id = config.match_id;
+        self.games.insert(
+            game_id,
+            RunningGame {
+                handle: spawn_game(
+                    game.with_bot_pids(self.bot_pids.clone())
+                        .with_middlewares(self.middlewares.clone()),
+                ),
+                stopwatch: Stopwatch::start(self.clock.clone()),
+                config,
+                capabilities: std::mem::take(&mut self.capabilities),
+                log_level: self.log_level.take(),
+                match_log: self.match_log.take(),
+            },
+        );
+        if self.max_games > 1 {
+            // Free the playlist so the supervisor can set up the next match
+            self.reset_playlist();
+            self.send_message(&format!(
+                "{{\"Game\": {{\"GameId\": {}, \"MatchID\": {}, \"Status\": \"Started\"}}}}",
+                game_id, match_id
+            ))
+            .await;
+        }
+    }
+
+    /// Handle the playlist clients that asked to join the game or left
+    /// Their sockets are served by playlist tasks, so this never waits on a client
+    pub async fn update_clients(&mut self) {
+        while let Ok(event) = self.playlist_recv.try_recv() {
+            match event {
+                PlaylistEvent::Join {
+                    client_id,
+                    mut client,
+                    req,
+                    capabilities,
+                } => match self.clients.iter().position(|c| c.id == client_id) {
+                    Some(index) => {
+                        let PlaylistClient { bot, .. } = self.clients.remove(index);
+                        debug!("JoinGame from {:?}", bot);
+                        if let Err(e) = self.client_join_game(bot, client, req, capabilities).await
+                        {
+                            self.abort_setup(e).await;
+                        }
                     }
-                    Ok(ref m) if m.has_join_game() => {
-                        info!("Game join");
-                        PlaylistAction::JoinGame(m.join_game().clone())
+                    None => {
+                        // Dropped from the playlist while its join was underway
+                        client.shutdown().await.ok();
                     }
-                    Ok(other) => {
-                        error!("Unsupported message in playlist {:?}", other);
-                        PlaylistAction::Kick
+                },
+                PlaylistEvent::Dropped { client_id } => {
+                    self.clients.retain(|c| c.id != client_id);
+                }
+            }
+        }
+    }
+
+    /// Update handler handles to see if they are still running
+    pub async fn update_games(&mut self) {
+        let mut messages = Vec::new();
+        for (game_id, game) in self.games.iter_mut() {
+            while let Some(telemetry) = game.handle.try_recv_telemetry() {
+                match telemetry {
+                    ToSupervisor::Progress(progress) => {
+                        messages.push(progress.to_message(*game_id, game.config.match_id))
                     }
-                    Err(err) => {
-                        error!("Invalid message {:?}", err);
-                        PlaylistAction::Kick
+                    ToSupervisor::NewTags(tags) => {
+                        messages.push(tags.to_message(*game_id, game.config.match_id))
                     }
                 }
             }
-            other => {
-                error!("Unsupported message type {:?}", other);
-                PlaylistAction::Kick
-            }
+        }
+        for message in messages {
+            self.send_message(&message).await;
+        }
+        let finished: Vec<GameId> = self
+            .games
+            .iter_mut()
+            .filter_map(|(id, game)| if game.handle.check() { Some(*id) } else { None })
+            .collect();
+        let any_finished = !finished.is_empty();
+        for game_id in finished {
+            let game = self.games.remove(&game_id).unwrap();
+            self.report_game(game_id, game).await;
+        }
+        if any_finished && self.pool.lock().unwrap().missing() > 0 {
+            // Replace the warm processes used by finished games
+            self.fill_pool();
+        }
+        while let Ok(status) = self.pool_recv.try_recv() {
+            self.send_message(&status.to_message()).await;
         }
     }
 
-    /// Update clients in playlist to see if they join a handler or disconnect
-    pub async fn update_clients(&mut self) {
-        for i in (0..self.clients.len()).rev() {
-            match self.clients[i].1.recv_message().await {
-                Some(Ok(msg)) => match self.process_client_message(msg) {
-                    PlaylistAction::Kick => {
-                        debug!("Kick client");
-                        self.drop_client(i).await
+    /// Launch the missing warm processes in the background, the server loop stays responsive
+    /// The status is sent to the supervisor by `update_games` once the pool is warm
+    fn fill_pool(&self) {
+        let shared = self.pool.clone();
+        let pool_send = self.pool_send.clone();
+        tokio::task::spawn_blocking(move || {
+            pool_send.send(pool::fill(&shared)).ok();
+        });
+    }
+
+    /// Send the result of a finished game to the supervisor
+    async fn report_game(&mut self, game_id: GameId, game: RunningGame) {
+        let RunningGame {
+            handle,
+            stopwatch,
+            config,
+            capabilities,
+            log_level: _log_level,
+            match_log,
+        } = game;
+        match handle.collect_result().await {
+            Ok((result, _)) if result.end_reason == GameEndReason::QuitRequest => {
+                info!("Game {} aborted", game_id);
+                let mut j_result = JsonResult::from(
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    Some("Aborted".to_string()),
+                    Some(numbered_bots(&config)),
+                    Some(config.map_name().to_string()),
+                    Some(config.reported_replay_path().to_string()),
+                    Some(config.match_id),
+                    None,
+                );
+                j_result.game_id = game_id;
+                j_result.started_at = stopwatch.started_at();
+                j_result.finished_at = stopwatch.now_wall();
+                j_result.duration = stopwatch.elapsed().as_secs_f64();
+                j_result.set_dirs(&config);
+                j_result.replay_saved = result.replay_saved;
+                self.record_finished(config.match_id, MatchStatus::Aborted);
+                self.send_result(j_result.serialize()).await;
+            }
+            Ok((result, players)) => {
+                // Without the players, the details they kept are missing from the result
+                let cleanup_timed_out = players.is_none();
+                let mut players = players.unwrap_or_default();
+                let mut avg_hash: HashMap<String, f32> = HashMap::with_capacity(2);
+                let mut tags_hash: HashMap<String, Vec<String>> = HashMap::with_capacity(2);
+                let mut warmup_hash: HashMap<String, f32> = HashMap::with_capacity(2);
+                let mut strikes_hash: HashMap<String, u32> = HashMap::new();
+                let mut action_strikes_hash: HashMap<String, u32> = HashMap::new();
+                let mut violations_hash: HashMap<String, BTreeMap<Violation, u32>> =
+                    HashMap::with_capacity(2);
+                let mut disconnect_hash: HashMap<String, Disconnect> = HashMap::new();
+                let mut observation_hash: HashMap<String, StateSnapshot> = HashMap::new();
+                let mut stats_hash: HashMap<String, PlayerStats> = HashMap::new();
+                let mut chat_hash: HashMap<String, Vec<ChatLine>> = HashMap::new();
+                let mut obs_sizes_hash: HashMap<String, Vec<SizeWindow>> = HashMap::new();
+                let mut out_of_order_hash: HashMap<String, BTreeMap<OutOfOrder, u32>> =
+                    HashMap::new();
+                let mut realtime_lag_hash: HashMap<String, LagReport> = HashMap::new();
+                let mut unknown_fields_hash: HashMap<String, UnknownFieldCounts> = HashMap::new();
+                let peak_memory_hash: HashMap<String, MemoryPeak> = config
+                    .players()
+                    .iter()
+                    .zip(result.peak_memory.iter())
+                    .filter(|(_, peak)| !peak.is_empty())
+                    .map(|(name, peak)| (name.to_string(), *peak))
+                    .collect();
+                let cpu_time_hash: HashMap<String, CpuTime> = config
+                    .players()
+                    .iter()
+                    .zip(result.cpu_time.iter())
+                    .filter(|(_, cpu)| !cpu.is_empty())
+                    .map(|(name, cpu)| (name.to_string(), *cpu))
+                    .collect();
+                self.cpu_seconds += result.cpu_time.iter().map(CpuTime::total).sum::<f64>();
+                let loop_skew_hash: HashMap<String, u32> = config
+                    .players()
+                    .iter()
+                    .zip(result.loop_skew.iter())
+                    .map(|(name, skew)| (name.to_string(), *skew))
+                    .collect();
+                for p in players.iter() {
+                    let player_name = match p.player_name() {
+                        Some(name) => name.to_string(),
+                        None => {
+                            warn!("Game {}: a player without a name left no details", game_id);
+                            continue;
+                        }
+                    };
+                    avg_hash.insert(player_name.clone(), p.frame_time);
+                    tags_hash.insert(player_name.clone(), p.tags.iter().cloned().collect());
+                    warmup_hash.insert(player_name.clone(), p.warmup_duration);
+                    if p.strikes > 0 {
+                        strikes_hash.insert(player_name.clone(), p.strikes);
+                    }
+                    if p.action_strikes > 0 {
+                        action_strikes_hash.insert(player_name.clone(), p.action_strikes);
+                    }
+                    violations_hash.insert(
+                        player_name.clone(),
+                        Violation::ALL
+                            .iter()
+                            .map(|v| (*v, p.violations.get(v).copied().unwrap_or_default()))
+                            .collect(),
+                    );
+                    if let Some(disconnect) = &p.disconnect {
+                        disconnect_hash.insert(player_name.clone(), disconnect.clone());
+                    }
+                    if let Some(snapshot) = p.last_observation {
+                        observation_hash.insert(player_name.clone(), snapshot);
                     }
-                    PlaylistAction::Respond(resp) => {
-                        debug!("Respond to {:?}", self.clients[i].0);
-                        self.clients[i]
-                            .1
-                            .stream
-                            .send(resp)
-                            .await
-                            .expect("Could not respond");
+                    if let Some(stats) = p.stats {
+                        stats_hash.insert(player_name.clone(), stats);
                     }
-                    PlaylistAction::RespondQuit(resp) => {
-                        self.clients[i]
-                            .1
-                            .stream
-                            .send(resp)
-                            .await
-                            .expect("Could not respond");
-                        debug!("RespondQuit");
-                        self.drop_client(i).await;
+                    if !p.chat.is_empty() {
+                        chat_hash.insert(player_name.clone(), p.chat.lines.clone());
                     }
-                    PlaylistAction::JoinGame(req) => {
-                        debug!("JoinGame from {:?}", self.clients[i].0);
-                        let join_response = self.client_join_game(i, req).await;
+                    if !p.ordering.is_empty() {
+                        out_of_order_hash.insert(player_name.clone(), p.ordering.counts.clone());
+                    }
+                    if let Some(realtime) = p.realtime.as_ref().filter(|r| r.report.lags > 0) {
+                        realtime_lag_hash.insert(player_name.clone(), realtime.report);
+                    }
+                    if !p.observation_sizes.is_empty() {
+                        obs_sizes_hash
+                            .insert(player_name.clone(), p.observation_sizes.windows.clone());
+                    }
+                    if !p.unknown_fields.is_empty() {
+                        unknown_fields_hash.insert(player_name.clone(), p.unknown_fields);
+                    }
+                }
+                let tags: Option<HashMap<String, Vec<String>>> = Some(tags_hash);
+                let average_frame_time: Option<HashMap<String, f32>> = Some(avg_hash);
 
-                        if join_response.is_none() {
-                            error!("Game creation / joining failed");
+                let player_results = result.player_results;
+
+                let names: Vec<String> = config.players().iter().map(ToString::to_string).collect();
+                let game_result: HashMap<String, String> = names
+                    .iter()
+                    .zip(player_results.iter())
+                    .map(|(name, result)| (name.clone(), result.to_string()))
+                    .collect();
+                let game_time = Some(result.game_loops);
+                let result_discrepancy: HashMap<String, Vec<String>> = result
+                    .result_discrepancy
+                    .map(|reports| {
+                        names
+                            .iter()
+                            .cloned()
+                            .zip(
+                                reports
+                                    .iter()
+                                    .map(|r| r.iter().map(ToString::to_string).collect()),
+                            )
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let bots: HashMap<u8, String> = names
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, name)| (i as u8 + 1, name))
+                    .collect();
+                let loops_per_second = config.loops_per_second();
+                let game_time_seconds = game_time.map(|loops| loops as f64 / loops_per_second);
+                let game_time_formatted = game_time_seconds.map(format_game_time);
+                info!("Game {}: {:?}", game_id, game_result);
+
+                let mut j_result = JsonResult::from(
+                    Some(game_result),
+                    game_time,
+                    game_time_seconds,
+                    game_time_formatted,
+                    average_frame_time,
+                    Some(
+                        match result.end_reason {
+                            GameEndReason::Drained => "Drained",
+                            GameEndReason::TimedOut => "TimedOut",
+                            _ => "Complete",
                         }
+                        .to_string(),
+                    ),
+                    Some(bots),
+                    Some(config.map_name().to_string()),
+                    Some(config.reported_replay_path().to_string()),
+                    Some(config.match_id),
+                    tags,
+                );
+                j_result.game_id = game_id;
+                j_result.started_at = stopwatch.started_at();
+                j_result.finished_at = stopwatch.now_wall();
+                j_result.duration = stopwatch.elapsed().as_secs_f64();
+                j_result.capabilities = capabilities;
+                j_result.warmup_duration = warmup_hash;
+                j_result.game_speed = config.game_speed().name().to_string();
+                j_result.loops_per_second = loops_per_second;
+                j_result.update = self.update_checker.as_ref().and_then(UpdateChecker::status);
+                if config.artifact_key.is_some() {
+                    j_result.artifact_key_id = config.artifact_key_id.clone();
+                }
+                j_result.set_dirs(&config);
+                j_result.strikes = strikes_hash;
+                j_result.action_strikes = action_strikes_hash;
+                j_result.violations = violations_hash;
+                if cleanup_timed_out {
+                    j_result.warnings.push("CleanupTimedOut".to_string());
+                }
+                j_result.disconnect = disconnect_hash;
+                j_result.unknown_fields = unknown_fields_hash;
+                j_result.peak_memory = peak_memory_hash;
+                j_result.cpu_time = cpu_time_hash;
+                j_result.loop_skew = loop_skew_hash;
+                if result.end_reason != GameEndReason::Normal
+                    || player_results.iter().any(|r| r.is_abnormal())
+                {
+                    j_result.last_observation = observation_hash;
+                }
+                j_result.stats = stats_hash;
+                j_result.chat = chat_hash;
+                j_result.observation_sizes = obs_sizes_hash;
+                j_result.out_of_order = out_of_order_hash;
+                j_result.realtime_lag = realtime_lag_hash;
+                j_result.team_results = result
+                    .team_results
+                    .iter()
+                    .map(|(team, result)| (team.to_string(), result.to_string()))
+                    .collect();
+                j_result.rematch = result.rematch;
+                j_result.result_discrepancy = result_discrepancy;
+                j_result.dry_run = config.dry_run();
+                j_result.sc2_version = result.sc2_version.clone();
+                j_result.sc2_env = config.sc2_env.clone();
+                j_result.map_seed = config.map_seed;
+                j_result.random_seed = config.random_seed();
+                j_result.replay_saved = result.replay_saved;
+                self.matches_completed += 1;
+                let bundle_dir = config.bundle_dir();
+                let artifact_key = config.artifact_key().ok().flatten();
+                if let Some(dir) = &bundle_dir {
+                    let mut path = bundle_path(dir, config.match_id).into_os_string();
+                    if artifact_key.is_some() {
+                        path.push(".");
+                        path.push(ENCRYPTED_EXTENSION);
                     }
-                },
-                None => {
-                    error!("None message");
+                    j_result.bundle = Some(path.to_string_lossy().into_owned());
                 }
-                Some(Err(err)) => {
-                    error!("Invalid message {:?}", err);
-                    self.drop_client(i).await;
+                let result_json = j_result.serialize();
+                let sc2_logs: Vec<(String, String)> = players
+                    .iter_mut()
+                    .filter_map(|p| {
+                        let name = p.player_name().clone().unwrap_or_default();
+                        p.process
+                            .stderr_snippet(SC2_STDERR_SNIPPET)
+                            .map(|text| (format!("sc2_stderr_{}.txt", name), text))
+                    })
+                    .collect();
+                if let Some(dir) = config.log_dir() {
+                    for (name, text) in &sc2_logs {
+                        let path = dir.join(format!("{}_{}", config.match_id, name));
+                        if let Err(e) = std::fs::write(&path, text) {
+                            error!("Game {}: could not write log {:?}: {:?}", game_id, path, e);
+                        }
+                    }
                 }
-            };
-        }
-    }
-
-    /// Update handler handles to see if they are still running
-    pub async fn update_games(&mut self) {
-        let mut game_over = false;
-        if let Some(game) = &mut self.game {
-            if game.check() {
-                game_over = true;
-            }
-        }
-        if game_over {
-            let game = self.game.take().unwrap();
-            match game.collect_result().await {
-                Ok((result, players)) => {
-                    let mut avg_hash: HashMap<String, f32> = HashMap::with_capacity(2);
-                    let mut tags_hash: HashMap<String, Vec<String>> = HashMap::with_capacity(2);
-                    for p in players.iter() {
-                        let player_name = p.player_name().as_ref().unwrap().to_string();
-                        avg_hash.insert(player_name.clone(), p.frame_time);
-                        tags_hash.insert(player_name.clone(), p.tags.iter().cloned().collect());
+                if let Some(dir) = &bundle_dir {
+                    let replay_path = PathBuf::from(config.replay_path());
+                    let mut encrypted_replay = replay_path.clone().into_os_string();
+                    encrypted_replay.push(".enc");
+                    let mut texts = sc2_logs;
+                    if let Some(match_log) = match_log {
+                        texts.push(("proxy.log".to_string(), match_log.finish()));
                     }
-                    let tags: Option<HashMap<String, Vec<String>>> = Some(tags_hash);
-                    let average_frame_time: Option<HashMap<String, f32>> = Some(avg_hash);
-
-                    let player_results = result.player_results;
-
-                    let p1 = self.config.as_ref().unwrap().player1().to_string();
-                    let p2 = self.config.as_ref().unwrap().player2().to_string();
-                    let mut game_result = HashMap::with_capacity(1);
-                    game_result.insert(p1.clone(), player_results[0].to_string());
-                    game_result.insert(p2.clone(), player_results[1].to_string());
-                    let game_time = Some(result.game_loops);
-                    let mut bots: HashMap<u8, String> = HashMap::with_capacity(1);
-                    bots.insert(1, p1);
-                    bots.insert(2, p2);
-                    let game_time_seconds = Some(game_time.unwrap() as f64 / 22.4);
-                    info!("{:?}", game_result);
-
-                    let j_result = JsonResult::from(
-                        Some(game_result),
-                        game_time,
-                        game_time_seconds,
-                        None,
-                        average_frame_time,
-                        Some("Complete".to_string()),
-                        Some(bots),
-                        self.config.as_ref().map(|x| x.map.clone()),
-                        self.config.as_ref().map(|x| x.replay_name.clone()),
-                        self.config.as_ref().map(|x| x.match_id),
-                        tags,
-                    );
-                    self.send_message(j_result.serialize().as_ref()).await;
+                    let mut recordings: Vec<PathBuf> = config.frame_trace().into_iter().collect();
+                    if let Some(trace_dir) = config.protocol_trace() {
+                        recordings.extend(
+                            (0..config.players().len())
+                                .map(|index| trace_path(&trace_dir, config.match_id, index)),
+                        );
+                    }
+                    let bundle = Bundle {
+                        match_id: config.match_id,
+                        result_json: result_json.clone(),
+                        files: vec![replay_path, PathBuf::from(encrypted_replay)],
+                        texts,
+                        recordings,
+                    };
+                    let written = bundle.write(dir).and_then(|path| match &artifact_key {
+                        Some(key) => key.encrypt_file(&path),
+                        None => Ok(path),
+                    });
+                    match written {
+                        Ok(path) => {
+                            info!("Game {}: artifact bundle written to {:?}", game_id, path)
+                        }
+                        Err(e) => {
+                            error!("Game {}: could not write artifact bundle: {:?}", game_id, e)
+                        }
+                    }
+                }
+                self.record_finished(
+                    config.match_id,
+                    MatchStatus::Finished {
+                        result: serde_json::from_str(&result_json).unwrap_or_default(),
+                    },
+                );
+                let smoke_test = config.match_id == SMOKE_TEST_MATCH_ID;
+                self.finish_match(&config, result_json).await;
 
+                // A single match owns the supervisor connection, a pool keeps it for the next ones
+                // A smoke test precedes the matches of the supervisor, which keeps its connection
+                if self.max_games <= 1 && !self.draining {
                     for i in (0..self.clients.len()).rev() {
                         self.drop_client(i).await
                     }
-                    self.drop_supervisor().await;
+                    if self.has_supervisor() && !smoke_test {
+                        self.drop_supervisor().await;
+                    }
                     self.reset();
                 }
-                Err(msg) => {
-                    error!("Game thread panicked with: {:?}", msg);
+            }
+            Err(e) => {
+                error!("Game {} failed: {}", game_id, e);
+                self.send_message(&e.to_message()).await;
+                self.matches_aborted += 1;
+                self.record_finished(config.match_id, MatchStatus::Aborted);
+            }
+        }
+    }
+
+    /// Tear down a lobby whose remaining bots did not join within `LobbyMaxAgeSecs`,
+    /// reporting the match as aborted
+    pub async fn collect_stale_lobby(&mut self) {
+        let (created, config) = match (self.lobby_created, &self.config) {
+            (Some(created), Some(config)) => (created, config.clone()),
+            _ => return,
+        };
+        if self.clock.now().saturating_duration_since(created) < config.lobby_max_age() {
+            return;
+        }
+        error!(
+            "Lobby of match {} timed out waiting for bots, aborting",
+            config.match_id
+        );
+        self.matches_aborted += 1;
+        self.close_lobby(&config, "Aborted", None).await;
+    }
+
+    /// Tear down the lobby and the bots waiting for the match, reporting it with `status`
+    /// The match counts as finished if `results` are given, as aborted otherwise
+    async fn close_lobby(
+        &mut self,
+        config: &Config,
+        status: &str,
+        results: Option<HashMap<String, String>>,
+    ) {
+        if let Some(lobby) = &mut self.lobby {
+            self.processes_killed += lobby.close().await;
+        }
+        for i in (0..self.clients.len()).rev() {
+            self.drop_client(i).await
+        }
+
+        let finished = results.is_some();
+        let mut j_result = JsonResult::from(
+            results,
+            None,
+            None,
+            None,
+            None,
+            Some(status.to_string()),
+            Some(numbered_bots(config)),
+            Some(config.map_name().to_string()),
+            Some(config.reported_replay_path().to_string()),
+            Some(config.match_id),
+            None,
+        );
+        j_result.set_dirs(config);
+        j_result.map_seed = config.map_seed;
+        j_result.random_seed = config.random_seed();
+        let result_json = j_result.serialize();
+        self.record_finished(
+            config.match_id,
+            if finished {
+                MatchStatus::Finished {
+                    result: serde_json::from_str(&result_json).unwrap_or_default(),
                 }
+            } else {
+                MatchStatus::Aborted
+            },
+        );
+        self.finish_match(config, result_json).await;
+        self.reset_playlist();
+        if self.max_games <= 1 && self.has_supervisor() && config.match_id != SMOKE_TEST_MATCH_ID {
+            self.drop_supervisor().await;
+        }
+    }
+
+    /// State of a match, None if it is unknown
+    pub fn match_status(&self, match_id: i64) -> Option<MatchStatus> {
+        if let Some((game_id, _)) = self
+            .games
+            .iter()
+            .find(|(_, game)| game.config.match_id == match_id)
+        {
+            return Some(MatchStatus::Running { game_id: *game_id });
+        }
+        match &self.config {
+            Some(config) if config.match_id == match_id => Some(MatchStatus::Pending),
+            _ => self.finished_matches.get(&match_id).cloned(),
+        }
+    }
+
+    /// Remember the outcome of a match, forgetting the oldest beyond `MAX_FINISHED_MATCHES`
+    fn record_finished(&mut self, match_id: i64, status: MatchStatus) {
+        if self.finished_matches.insert(match_id, status).is_none() {
+            self.finished_order.push_back(match_id);
+        }
+        while self.finished_order.len() > MAX_FINISHED_MATCHES {
+            if let Some(oldest) = self.finished_order.pop_front() {
+                self.finished_matches.remove(&oldest);
             }
         }
     }
 
+    /// Abort a pending or running match
+    /// Returns false if the match is unknown or already over
+    pub async fn abort_match(&mut self, match_id: i64) -> bool {
+        if let Some(game) = self
+            .games
+            .values_mut()
+            .find(|game| game.config.match_id == match_id)
+        {
+            // A game that already ended is reported by `update_games`
+            if !game.handle.try_send(FromSupervisor::Quit) {
+                return false;
+            }
+            self.matches_aborted += 1;
+            return true;
+        }
+        match self.config.clone() {
+            Some(config) if config.match_id == match_id => {
+                self.matches_aborted += 1;
+                self.close_lobby(&config, "Aborted", None).await;
+                true
+            }
+            _ => false,
+        }
+    }
+
     /// Destroys the controller, ending all games,
     /// and closing all connections and threads
     pub async fn close(&mut self) {
         debug!("Closing Controller");
 
-        // Tell game to quit
-        if let Some(game) = &mut self.game {
-            game.send(FromSupervisor::Quit);
-        }
+        // Tell games to quit, the SC2 processes of games that hang are killed
+        let games = std::mem::take(&mut self.games);
+        self.matches_aborted += games.len() as u32;
+        join_all(games.into_values().map(|game| game.handle.quit())).await;
         // Destroy lobby
         if let Some(lobby) = &mut self.lobby {
-            lobby.close().await;
+            self.processes_killed += lobby.close().await;
+            self.matches_aborted += 1;
         }
 
-        for (_, client, _) in &mut self.clients {
-            client.shutdown().await.expect("Could not close connection");
+        for client in std::mem::take(&mut self.clients) {
+            client.task.stop().await;
         }
         self.reset()
 
         // Close all handler list connections by drop
     }
+
+    /// End a session whose supervisor left, aborting its matches and closing its connections
+    pub async fn end_session(&mut self) {
+        info!("Ending session {:?}", self.session);
+        self.close().await;
+        {
+            let mut pool = self.pool.lock().unwrap();
+            self.processes_killed += pool.status().idle as u32;
+            pool.drain();
+        }
+        if self.supervisor.is_some() {
+            self.drop_supervisor().await;
+        }
+        for mut observer in std::mem::take(&mut self.observers) {
+            observer.close().await.ok();
+        }
+        self.auth.forget_session(self.session_key());
+    }
+
+    /// Ask running games to save their replays and end, reporting their results,
+    /// until all are over or the drain timeout passed
+    async fn drain_games(&mut self) {
+        if self.games.is_empty() {
+            return;
+        }
+        info!("Draining {} running games", self.games.len());
+        self.draining = true;
+        for game in self.games.values_mut() {
+            // A game that already ended is collected below
+            game.handle.try_send(FromSupervisor::Drain);
+        }
+        let started = self.clock.now();
+        while !self.games.is_empty()
+            && self.clock.now().saturating_duration_since(started) < self.drain_timeout
+        {
+            self.update_games().await;
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        if !self.games.is_empty() {
+            warn!(
+                "{} games did not finish within {:?}, killing them",
+                self.games.len(),
+                self.drain_timeout
+            );
+            let games = std::mem::take(&mut self.games);
+            self.matches_aborted += games.len() as u32;
+            for killed in join_all(games.into_values().map(|game| game.handle.kill())).await {
+                self.processes_killed += killed;
+            }
+        }
+    }
+
+    /// Close everything and report the work done to the supervisor and to disk
+    pub async fn shutdown(&mut self, reason: ShutdownReason) -> ShutdownReport {
+        info!("Shutting down: {:?}", reason);
+        self.drain_games().await;
+        self.close().await;
+        {
+            let mut pool = self.pool.lock().unwrap();
+            self.processes_killed += pool.status().idle as u32;
+            pool.drain();
+        }
+        let report = ShutdownReport {
+            reason,
+            matches_completed: self.matches_completed,
+            matches_aborted: self.matches_aborted,
+            processes_killed: self.processes_killed,
+            unsent_results: self.unsent_results.len() as u32,
+            timestamp: self.clock.wall().to_rfc3339(),
+        };
+        // The supervisor connection might already be broken, so don't panic on failure
+        if let Some(sender) = &mut self.supervisor {
+            if let Err(e) = sender.send(TMessage::text(report.to_message())).await {
+                error!("Could not send shutdown report: {:?}", e);
+            }
+        }
+        self.broadcast(&report.to_message()).await;
+        match report.write_to_disk() {
+            Ok(path) => info!("Shutdown report written to {:?}", path),
+            Err(e) => error!("Could not write shutdown report: {:?}", e),
+        }
+        report
+    }
 }
 
 /// Return type of Controller.update_remote
@@ -496,67 +1653,100 @@ pub enum RemoteUpdateStatus {
     NoAction,
 }
 
+/// Task reading the messages of a supervisor, stopped when it is dropped
+#[derive(Debug)]
+pub struct SupervisorListener(JoinHandle<()>);
+impl Drop for SupervisorListener {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Read the messages of a supervisor into `sender` on a task of the current runtime
+/// The task ends with the connection, or when the listener is dropped
 pub fn create_supervisor_listener(
-    mut client_recv: SplitStream<WebSocketStream<TcpStream>>,
+    mut client_recv: SplitStream<ClientStream>,
     sender: Sender<SupervisorAction>,
-) {
-    std::thread::spawn(move || {
-        let rt = Runtime::new().unwrap();
-        rt.block_on(async {
-            while let Some(r_msg) = client_recv.next().await {
-                trace!("Message received from supervisor client");
-                match r_msg {
-                    Ok(msg) => match msg {
-                        TMessage::Text(data) => {
-                            if data == "Reset" {
-                                sender
-                                    .send(SupervisorAction::Quit)
-                                    .expect("Could not send SupervisorAction");
-                                break;
-                            } else if data == "Received" {
-                                sender
-                                    .send(SupervisorAction::Received)
-                                    .expect("Could not send SupervisorAction");
-                            } else if data.contains("Map") || data.contains("map") {
-                                sender
-                                    .send(SupervisorAction::Config(data))
-                                    .expect("Could not send config");
-                            } else if data == "Quit" {
-                                sender
-                                    .send(SupervisorAction::ForceQuit)
-                                    .expect("Could not send ForceQuit");
-                            }
-                        }
-                        TMessage::Ping(payload) => {
-                            sender
-                                .send(SupervisorAction::Ping(payload))
-                                .expect("Could not send SupervisorAction");
-                        }
-                        _ => {}
-                    },
-                    Err(Error::AlreadyClosed) => {
-                        error!("Supervisor Error::AlreadyClosed");
-                        sender
-                            .send(SupervisorAction::ForceQuit)
-                            .expect("Could not send ForceQuit");
-                        break;
-                    }
-                    Err(Error::Capacity(e)) => {
-                        error!("{:?}", e);
-                        sender
-                            .send(SupervisorAction::ForceQuit)
-                            .expect("Could not send ForceQuit");
-                        break;
-                    }
-                    Err(e) => {
-                        error!("{:?}", e);
-                        sender
-                            .send(SupervisorAction::ForceQuit)
-                            .expect("Could not send ForceQuit");
-                        break;
-                    }
-                }
+) -> SupervisorListener {
+    SupervisorListener(tokio::spawn(async move {
+        while let Some(r_msg) = client_recv.next().await {
+            trace!("Message received from supervisor client");
+            let action = match supervisor_action(r_msg) {
+                Some(action) => action,
+                None => continue,
+            };
+            let last = matches!(
+                action,
+                SupervisorAction::Quit | SupervisorAction::ForceQuit | SupervisorAction::Closed
+            );
+            if sender.send(action).is_err() {
+                debug!("Supervisor messages are no longer read");
+                break;
             }
-        });
-    });
+            if last {
+                break;
+            }
+        }
+    }))
+}
+
+/// Bots of a match by player number, as listed in its result
+fn numbered_bots(config: &Config) -> HashMap<u8, String> {
+    config
+        .players()
+        .iter()
+        .enumerate()
+        .map(|(i, name)| (i as u8 + 1, name.to_string()))
+        .collect()
+}
+
+/// Action requested by a message of the supervisor, None if it requests nothing
+fn supervisor_action(r_msg: Result<TMessage, Error>) -> Option<SupervisorAction> {
+    match r_msg {
+        Ok(TMessage::Text(data)) => {
+            if data == "Reset" {
+                Some(SupervisorAction::Quit)
+            } else if data == "Received" {
+                Some(SupervisorAction::Received)
+            } else if let Some(map) = smoke_test::parse_command(&data) {
+                Some(SupervisorAction::SmokeTest(map))
+            } else if let Some((player, pid)) = parse_bot_pid(&data) {
+                Some(SupervisorAction::BotPid(player, pid))
+            } else if let Some(tokens) = parse_register_tokens(&data) {
+                Some(SupervisorAction::RegisterTokens(tokens))
+            } else if let Some(match_id) = parse_abort(&data) {
+                Some(SupervisorAction::Abort(match_id))
+            } else if data.contains("Map") || data.contains("map") {
+                Some(SupervisorAction::Config(data))
+            } else if let Some(command) = PoolCommand::parse(&data) {
+                Some(SupervisorAction::Pool(command))
+            } else if data == "Status" {
+                Some(SupervisorAction::Status)
+            } else if data == "Quit" {
+                Some(SupervisorAction::ForceQuit)
+            } else {
+                None
+            }
+        }
+        Ok(TMessage::Ping(payload)) => Some(SupervisorAction::Ping(payload)),
+        Ok(TMessage::Close(frame)) => {
+            match frame {
+                Some(frame) => info!(
+                    "Supervisor closed the connection: {} {:?}",
+                    frame.code, frame.reason
+                ),
+                None => info!("Supervisor closed the connection"),
+            }
+            Some(SupervisorAction::Closed)
+        }
+        Ok(_) => None,
+        Err(Error::AlreadyClosed) => {
+            error!("Supervisor Error::AlreadyClosed");
+            Some(SupervisorAction::ForceQuit)
+        }
+        Err(e) => {
+            error!("{:?}", e);
+            Some(SupervisorAction::ForceQuit)
+        }
+    }
 }
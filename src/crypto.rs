@@ -0,0 +1,145 @@
+//! At-rest encryption of match artifacts with a supervisor-provided key
+//!
+//! Encrypted files start with `MAGIC`, followed by the 12 byte nonce and the
+//! ChaCha20-Poly1305 ciphertext.
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Header of encrypted artifacts
+const MAGIC: &[u8] = b"RACENC1";
+/// Length of the ChaCha20-Poly1305 nonce
+const NONCE_LEN: usize = 12;
+/// Extension appended to encrypted artifacts
+pub const ENCRYPTED_EXTENSION: &str = "enc";
+
+/// Key used to encrypt the artifacts of a match
+#[derive(Clone)]
+pub struct ArtifactKey {
+    /// Identifier chosen by the supervisor, recorded in the result
+    pub id: String,
+    key: [u8; 32],
+}
+
+impl std::fmt::Debug for ArtifactKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ArtifactKey").field("id", &self.id).finish()
+    }
+}
+
+impl ArtifactKey {
+    /// Parse a key given as 64 hex characters
+    pub fn from_hex(id: &str, hex: &str) -> Result<Self, String> {
+        let hex = hex.trim();
+        if !hex.is_ascii() {
+            return Err("Artifact key is not valid hex".to_string());
+        }
+        if hex.len() != 64 {
+            return Err(format!(
+                "Artifact key must be 64 hex characters, got {}",
+                hex.len()
+            ));
+        }
+        let mut key = [0_u8; 32];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16)
+                .map_err(|_| "Artifact key is not valid hex".to_string())?;
+        }
+        Ok(Self {
+            id: id.to_string(),
+            key,
+        })
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(Key::from_slice(&self.key))
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> Vec<u8> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher()
+            .encrypt(&nonce, plaintext)
+            .expect("Could not encrypt artifact");
+        let mut data = Vec::with_capacity(MAGIC.len() + NONCE_LEN + ciphertext.len());
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&nonce);
+        data.extend_from_slice(&ciphertext);
+        data
+    }
+
+    /// Returns None if the data was not encrypted with this key
+    pub fn decrypt(&self, data: &[u8]) -> Option<Vec<u8>> {
+        let data = data.strip_prefix(MAGIC)?;
+        if data.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        self.cipher()
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .ok()
+    }
+
+    /// Write `data` encrypted to `path` with the `.enc` extension appended
+    pub fn write_encrypted(&self, path: &Path, data: &[u8]) -> io::Result<PathBuf> {
+        let mut name = path.as_os_str().to_owned();
+        name.push(".");
+        name.push(ENCRYPTED_EXTENSION);
+        let encrypted_path = PathBuf::from(name);
+        fs::write(&encrypted_path, self.encrypt(data))?;
+        Ok(encrypted_path)
+    }
+
+    /// Replace a plaintext artifact with its encrypted version
+    pub fn encrypt_file(&self, path: &Path) -> io::Result<PathBuf> {
+        let data = fs::read(path)?;
+        let encrypted_path = self.write_encrypted(path, &data)?;
+        fs::remove_file(path)?;
+        Ok(encrypted_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    #[test]
+    fn test_roundtrip() {
+        let key = ArtifactKey::from_hex("k1", KEY).unwrap();
+        let data = key.encrypt(b"replay data");
+        assert!(data.starts_with(MAGIC));
+        assert_eq!(key.decrypt(&data).unwrap(), b"replay data");
+
+        let other = ArtifactKey::from_hex("k2", &KEY.replace("00", "ff")).unwrap();
+        assert_eq!(other.decrypt(&data), None);
+        assert_eq!(key.decrypt(b"replay data"), None);
+    }
+
+    #[test]
+    fn test_invalid_key() {
+        assert!(ArtifactKey::from_hex("k", "abcd").is_err());
+        assert!(ArtifactKey::from_hex("k", &"zz".repeat(32)).is_err());
+        // 64 bytes, but slicing at two byte steps would split the multi-byte chars
+        assert!(ArtifactKey::from_hex("k", &"a\u{e9}b".repeat(16)).is_err());
+    }
+
+    #[test]
+    fn test_encrypt_file() {
+        let key = ArtifactKey::from_hex("k1", KEY).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("match.SC2Replay");
+        fs::write(&path, b"replay data").unwrap();
+        let encrypted = key.encrypt_file(&path).unwrap();
+        assert!(!path.exists());
+        assert_eq!(encrypted, dir.path().join("match.SC2Replay.enc"));
+        assert_eq!(
+            key.decrypt(&fs::read(encrypted).unwrap()).unwrap(),
+            b"replay data"
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use crate::config::{Config, ConfigError};
+
+/// Errors reported to users of the crate, e.g. mapped to Python exceptions by the bindings
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ArenaError {
+    /// The proxy address is already bound by another process
+    #[error("Address {0} is already in use")]
+    AddressInUse(String),
+    /// The proxy address could not be bound for another reason
+    #[error("Could not bind {0}: {1}")]
+    Bind(String, String),
+    /// No usable SC2 installation
+    #[error("SC2 not found: {0}")]
+    Sc2NotFound(String),
+    /// The map of the config is not installed
+    #[error("Map {0:?} not found")]
+    MapNotFound(String),
+    /// The map of the config could not be downloaded from `MapDownloadUrl`
+    #[error("Could not download map {0:?}: {1}")]
+    MapDownload(String, String),
+    /// The config failed validation
+    #[error("Invalid config: {}", join_errors(.0))]
+    InvalidConfig(Vec<ConfigError>),
+    /// The match did not finish, e.g. a bot never connected or it was aborted
+    #[error("Match {0} did not finish")]
+    MatchAborted(i64),
+    /// An SC2 process could not be launched or connected to
+    #[error("Could not launch SC2: {0}")]
+    Sc2Launch(String),
+    /// A bot or SC2 sent a message the proxy cannot handle
+    #[error("Protocol error: {0}")]
+    Protocol(String),
+    /// The game could not be set up after the bots joined
+    #[error("Could not set up the game: {0}")]
+    Lobby(String),
+    /// The SC2 processes of a match run different versions
+    #[error("SC2 processes run different versions: {0}")]
+    VersionMismatch(String),
+    /// The game handler or one of its players failed while the game ran
+    #[error("Game failed: {0}")]
+    Game(String),
+}
+
+fn join_errors(errors: &[ConfigError]) -> String {
+    let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+    errors.join("; ")
+}
+
+impl ArenaError {
+    /// Error for a config rejected by `Config::validate`
+    /// A missing map gets its own variant, as it is the usual setup problem
+    pub fn from_config_errors(config: &Config, errors: Vec<ConfigError>) -> Self {
+        let map_missing =
+            !config.map().is_empty() && errors.iter().any(|e| e.field.as_deref() == Some("Map"));
+        if map_missing {
+            Self::MapNotFound(config.map().to_string())
+        } else {
+            Self::InvalidConfig(errors)
+        }
+    }
+    /// The error as the supervisor message `{"Error": [...]}`, e.g. for a game that could not be set up
+    pub fn to_message(&self) -> String {
+        serde_json::json!({ "Error": [{ "Message": self.to_string() }] }).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_config_errors() {
+        let mut config = Config::new();
+        config.map = "AutomatonLE".into();
+        let errors = vec![
+            ConfigError::new("Map", "Map \"AutomatonLE\" not found"),
+            ConfigError::new("Strikes", "Must not be negative"),
+        ];
+        assert_eq!(
+            ArenaError::from_config_errors(&config, errors.clone()),
+            ArenaError::MapNotFound("AutomatonLE".to_string())
+        );
+        assert_eq!(
+            ArenaError::from_config_errors(&config, errors[1..].to_vec()).to_string(),
+            "Invalid config: Strikes: Must not be negative"
+        );
+    }
+
+    #[test]
+    fn test_to_message() {
+        assert_eq!(
+            ArenaError::Lobby("No free ports".to_string()).to_message(),
+            "{\"Error\":[{\"Message\":\"Could not set up the game: No free ports\"}]}"
+        );
+    }
+}
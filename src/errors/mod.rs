@@ -1 +1,6 @@
+pub mod arena_error;
 pub mod proxy_error;
+#[cfg(feature = "python")]
+pub mod python;
+
+pub use self::arena_error::ArenaError;
@@ -1,5 +1,9 @@
+use std::net::SocketAddr;
+
 #[derive(Debug, Clone)]
 pub enum ProxyError {
     ShutdownRequest,
     AcceptError,
+    /// Connection refused during the handshake, the proxy keeps running
+    Rejected(SocketAddr, String),
 }
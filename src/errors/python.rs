@@ -0,0 +1,45 @@
+//! Python exceptions for `ArenaError`, exposed as `rust_ac.errors`
+
+use super::arena_error::ArenaError;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyOSError};
+use pyo3::prelude::*;
+
+create_exception!(rust_ac, ArenaClientError, PyException);
+create_exception!(rust_ac, AddressInUse, ArenaClientError);
+create_exception!(rust_ac, Sc2NotFound, ArenaClientError);
+create_exception!(rust_ac, MapNotFound, ArenaClientError);
+create_exception!(rust_ac, InvalidConfig, ArenaClientError);
+create_exception!(rust_ac, MatchAborted, ArenaClientError);
+
+impl From<ArenaError> for PyErr {
+    fn from(e: ArenaError) -> Self {
+        let message = e.to_string();
+        match e {
+            ArenaError::AddressInUse(_) => AddressInUse::new_err(message),
+            ArenaError::Bind(_, _) => PyOSError::new_err(message),
+            ArenaError::Sc2NotFound(_) => Sc2NotFound::new_err(message),
+            ArenaError::MapNotFound(_) | ArenaError::MapDownload(_, _) => {
+                MapNotFound::new_err(message)
+            }
+            ArenaError::InvalidConfig(_) => InvalidConfig::new_err(message),
+            ArenaError::MatchAborted(_) => MatchAborted::new_err(message),
+            ArenaError::Sc2Launch(_)
+            | ArenaError::Protocol(_)
+            | ArenaError::Lobby(_)
+            | ArenaError::VersionMismatch(_)
+            | ArenaError::Game(_) => ArenaClientError::new_err(message),
+        }
+    }
+}
+
+/// Register the exception types in the extension module
+pub fn register(py: Python, m: &PyModule) -> PyResult<()> {
+    m.add("ArenaClientError", py.get_type::<ArenaClientError>())?;
+    m.add("AddressInUse", py.get_type::<AddressInUse>())?;
+    m.add("Sc2NotFound", py.get_type::<Sc2NotFound>())?;
+    m.add("MapNotFound", py.get_type::<MapNotFound>())?;
+    m.add("InvalidConfig", py.get_type::<InvalidConfig>())?;
+    m.add("MatchAborted", py.get_type::<MatchAborted>())?;
+    Ok(())
+}
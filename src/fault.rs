@@ -0,0 +1,154 @@
+//! Fault injection for robustness testing of bots and supervisors
+//!
+//! Only active when built with the `fault-injection` feature and the
+//! `SC2_PROXY_FAULT_SCENARIO` environment variable points to a scenario file, e.g.
+//! `{"LatencyMs": 50, "DropEveryNth": 100, "CloseProbability": 0.001, "Seed": 1}`
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Faults to inject into the frames a bot sends
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct FaultScenario {
+    /// Artificial latency added to every frame
+    #[serde(default, rename = "LatencyMs")]
+    pub latency_ms: u64,
+    /// Drop every Nth frame, 0 disables dropping
+    #[serde(default, rename = "DropEveryNth")]
+    pub drop_every_nth: u64,
+    /// Probability to close the connection on each frame
+    #[serde(default, rename = "CloseProbability")]
+    pub close_probability: f64,
+    /// Seed for the close decisions, so runs are reproducible
+    #[serde(default, rename = "Seed")]
+    pub seed: u64,
+}
+
+/// What to do with a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultAction {
+    Forward,
+    Drop,
+    Close,
+}
+
+/// Applies a `FaultScenario` frame by frame
+#[derive(Debug, Clone)]
+pub struct FaultInjector {
+    scenario: FaultScenario,
+    frames: u64,
+    rng_state: u64,
+}
+
+impl FaultInjector {
+    pub fn new(scenario: FaultScenario) -> Self {
+        let rng_state = scenario.seed.max(1);
+        Self {
+            scenario,
+            frames: 0,
+            rng_state,
+        }
+    }
+
+    /// Injector from the scenario file in `SC2_PROXY_FAULT_SCENARIO`
+    #[cfg(feature = "fault-injection")]
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var_os("SC2_PROXY_FAULT_SCENARIO")?;
+        let data = match std::fs::read_to_string(&path) {
+            Ok(data) => data,
+            Err(e) => {
+                log::error!("Could not read fault scenario {:?}: {:?}", path, e);
+                return None;
+            }
+        };
+        match serde_json::from_str(&data) {
+            Ok(scenario) => {
+                log::warn!("Fault injection enabled: {:?}", scenario);
+                Some(Self::new(scenario))
+            }
+            Err(e) => {
+                log::error!("Invalid fault scenario {:?}: {:?}", path, e);
+                None
+            }
+        }
+    }
+
+    /// Fault injection is compiled out without the `fault-injection` feature
+    #[cfg(not(feature = "fault-injection"))]
+    pub fn from_env() -> Option<Self> {
+        None
+    }
+
+    /// Decide the fate of the next frame
+    pub fn next_action(&mut self) -> FaultAction {
+        self.frames += 1;
+        if self.scenario.close_probability > 0_f64
+            && self.next_random() < self.scenario.close_probability
+        {
+            FaultAction::Close
+        } else if self.scenario.drop_every_nth > 0
+            && self.frames % self.scenario.drop_every_nth == 0
+        {
+            FaultAction::Drop
+        } else {
+            FaultAction::Forward
+        }
+    }
+
+    /// Delay the frame and decide its fate
+    pub async fn inject(&mut self) -> FaultAction {
+        if self.scenario.latency_ms > 0 {
+            tokio::time::sleep(Duration::from_millis(self.scenario.latency_ms)).await;
+        }
+        self.next_action()
+    }
+
+    /// Xorshift, uniform in [0, 1)
+    fn next_random(&mut self) -> f64 {
+        self.rng_state ^= self.rng_state << 13;
+        self.rng_state ^= self.rng_state >> 7;
+        self.rng_state ^= self.rng_state << 17;
+        (self.rng_state >> 11) as f64 / (1_u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drop_every_nth() {
+        let mut injector = FaultInjector::new(FaultScenario {
+            drop_every_nth: 3,
+            ..Default::default()
+        });
+        let actions: Vec<_> = (0..6).map(|_| injector.next_action()).collect();
+        assert_eq!(
+            actions,
+            vec![
+                FaultAction::Forward,
+                FaultAction::Forward,
+                FaultAction::Drop,
+                FaultAction::Forward,
+                FaultAction::Forward,
+                FaultAction::Drop
+            ]
+        );
+    }
+
+    #[test]
+    fn test_close_is_reproducible() {
+        let scenario = FaultScenario {
+            close_probability: 0.5,
+            seed: 42,
+            ..Default::default()
+        };
+        let mut first = FaultInjector::new(scenario.clone());
+        let mut second = FaultInjector::new(scenario);
+        let first: Vec<_> = (0..32).map(|_| first.next_action()).collect();
+        let second: Vec<_> = (0..32).map(|_| second.next_action()).collect();
+        assert_eq!(first, second);
+        assert!(first.contains(&FaultAction::Close));
+        assert!(first.contains(&FaultAction::Forward));
+    }
+}
@@ -0,0 +1,104 @@
+//! Generators for supervisor protocol fixtures
+//!
+//! Produces valid Config JSON, expected result JSON and canned message sequences,
+//! so supervisor implementations can be contract tested without running SC2.
+
+use crate::config::Config;
+use crate::result::JsonResult;
+use crate::sc2::PlayerResult;
+use crate::schema::{connected_message, PROTOCOL_VERSION};
+use serde_json::json;
+use std::collections::HashMap;
+
+/// Direction of a message on the supervisor socket
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SupervisorMessage {
+    /// Sent by the supervisor to the proxy
+    ToProxy(String),
+    /// Sent by the proxy to the supervisor
+    FromProxy(String),
+}
+
+/// Config JSON as sent by the Python supervisor
+pub fn config_json(map: &str, player1: &str, player2: &str) -> String {
+    json!({
+        "Map": map,
+        "MaxGameTime": 60486,
+        "Player1": player1,
+        "Player2": player2,
+        "ReplayPath": "",
+        "MatchID": 1,
+        "DisableDebug": true,
+        "RealTime": false,
+        "LightMode": false,
+        "ValidateRace": false,
+        "Player1Race": null,
+        "Player2Race": null,
+        "Archon": false,
+        "ProtocolVersion": PROTOCOL_VERSION
+    })
+    .to_string()
+}
+
+/// Config as the proxy loads it from `config_json`
+pub fn config(map: &str, player1: &str, player2: &str) -> Config {
+    Config::load_from_str(&config_json(map, player1, player2))
+}
+
+/// Result JSON the proxy sends after a game ended with `results`
+pub fn result_json(config: &Config, results: [PlayerResult; 2], game_loops: u32) -> String {
+    let mut game_result = HashMap::with_capacity(2);
+    game_result.insert(config.player1().to_string(), results[0].to_string());
+    game_result.insert(config.player2().to_string(), results[1].to_string());
+    let mut bots = HashMap::with_capacity(2);
+    bots.insert(1, config.player1().to_string());
+    bots.insert(2, config.player2().to_string());
+    let mut average_frame_time = HashMap::with_capacity(2);
+    average_frame_time.insert(config.player1().to_string(), 0_f32);
+    average_frame_time.insert(config.player2().to_string(), 0_f32);
+    let mut tags = HashMap::with_capacity(2);
+    tags.insert(config.player1().to_string(), vec![]);
+    tags.insert(config.player2().to_string(), vec![]);
+    JsonResult::from(
+        Some(game_result),
+        Some(game_loops),
+        Some(game_loops as f64 / 22.4),
+        None,
+        Some(average_frame_time),
+        Some("Complete".to_string()),
+        Some(bots),
+        Some(config.map().to_string()),
+        Some(config.reported_replay_path().to_string()),
+        Some(config.match_id),
+        Some(tags),
+    )
+    .serialize()
+}
+
+/// Full message sequence of a successful match, in order
+pub fn match_session(config: &Config, results: [PlayerResult; 2]) -> Vec<SupervisorMessage> {
+    use SupervisorMessage::*;
+    let config_json = serde_json::to_string(config).expect("Could not serialize Config");
+    vec![
+        FromProxy(connected_message(None, None)),
+        ToProxy(config_json),
+        FromProxy("{\"Config\": \"Accepted\"}".to_string()),
+        FromProxy("{\"Bot\": \"Connected\"}".to_string()),
+        FromProxy("{\"Bot\": \"Connected\"}".to_string()),
+        FromProxy(result_json(config, results, 1000)),
+        ToProxy("Received".to_string()),
+    ]
+}
+
+/// Message sequence of a supervisor resetting the proxy before a match started
+pub fn reset_session(config: &Config) -> Vec<SupervisorMessage> {
+    use SupervisorMessage::*;
+    let config_json = serde_json::to_string(config).expect("Could not serialize Config");
+    vec![
+        FromProxy(connected_message(None, None)),
+        ToProxy(config_json),
+        FromProxy("{\"Config\": \"Accepted\"}".to_string()),
+        ToProxy("Reset".to_string()),
+        FromProxy("Reset".to_string()),
+    ]
+}
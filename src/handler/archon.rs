@@ -0,0 +1,114 @@
+//! Archon matches, where two clients control the army of one player
+//!
+//! The first client of a player slot to join is its lead, it has the SC2 process of the slot
+//! and steps the game. The second one, its partner, shares the connection of the lead: its
+//! requests are sent to SC2 while the lead thinks, so it sees the same game and can send
+//! actions, but it cannot step, restart or leave the game. Both clients share the result
+//! of the slot.
+
+use futures_util::future::pending;
+use sc2_proto::sc2api::{Request, Response, ResponseStep, Status};
+use tokio_tungstenite::tungstenite::Message as TMessage;
+
+use crate::errors::ArenaError;
+use crate::proxy::Client;
+
+/// What the proxy does with a request of a partner
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartnerRequest {
+    /// Sent to SC2, the response goes back to the partner
+    Forward,
+    /// Answered without stepping, only the lead steps the game
+    Step,
+    /// Answered with an empty response, as with `DisableDebug`
+    Debug,
+    /// Answered with the error
+    Blocked(&'static str),
+}
+impl PartnerRequest {
+    pub fn of(request: &Request, disable_debug: bool) -> Self {
+        if request.has_step() {
+            Self::Step
+        } else if request.has_debug() && disable_debug {
+            Self::Debug
+        } else if request.has_leave_game() || request.has_quit() {
+            Self::Blocked("Only the lead of an Archon team can leave the game")
+        } else if request.has_create_game()
+            || request.has_join_game()
+            || request.has_restart_game()
+            || request.has_start_replay()
+        {
+            Self::Blocked("The game was already set up by the proxy")
+        } else {
+            Self::Forward
+        }
+    }
+    /// Response to a request not sent to SC2, with the game loop of the lead
+    pub fn response(self, request: &Request, game_loop: u32) -> Response {
+        let mut response = Response::new();
+        response.set_id(request.id());
+        response.set_status(Status::in_game);
+        match self {
+            Self::Step => {
+                let mut step = ResponseStep::new();
+                step.set_simulation_loop(game_loop);
+                response.set_step(step);
+            }
+            Self::Blocked(reason) => response.error.push(reason.to_string()),
+            Self::Forward | Self::Debug => {}
+        }
+        response
+    }
+}
+
+/// Next request of the partner, never ready without one
+pub async fn partner_request(partner: &mut Option<Client>) -> anyhow::Result<Vec<u8>> {
+    let partner = match partner {
+        Some(partner) => partner,
+        None => pending().await,
+    };
+    match partner.recv_message().await {
+        Some(Ok(TMessage::Binary(bytes))) => Ok(bytes),
+        Some(Ok(TMessage::Close(e))) => Err(anyhow::Error::msg(format!("{:?}", e))),
+        Some(Ok(other)) => Err(ArenaError::Protocol(format!(
+            "Expected a binary message from the partner, got {:?}",
+            other
+        ))
+        .into()),
+        Some(Err(e)) => Err(e.into()),
+        None => Err(anyhow::Error::msg("Message is None")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sc2_proto::sc2api::{RequestDebug, RequestLeaveGame, RequestObservation, RequestStep};
+
+    #[test]
+    fn test_partner_requests() {
+        let mut observe = Request::new();
+        observe.set_observation(RequestObservation::new());
+        assert_eq!(PartnerRequest::of(&observe, true), PartnerRequest::Forward);
+
+        let mut step = Request::new();
+        step.set_id(7);
+        step.set_step(RequestStep::new());
+        let kind = PartnerRequest::of(&step, false);
+        assert_eq!(kind, PartnerRequest::Step);
+        let response = kind.response(&step, 224);
+        assert_eq!(response.id(), 7);
+        assert_eq!(response.step().simulation_loop(), 224);
+
+        let mut debug = Request::new();
+        debug.set_debug(RequestDebug::new());
+        assert_eq!(PartnerRequest::of(&debug, true), PartnerRequest::Debug);
+        assert_eq!(PartnerRequest::of(&debug, false), PartnerRequest::Forward);
+
+        let mut leave = Request::new();
+        leave.set_leave_game(RequestLeaveGame::new());
+        let kind = PartnerRequest::of(&leave, false);
+        assert!(matches!(kind, PartnerRequest::Blocked(_)));
+        assert_eq!(kind.response(&leave, 0).error.len(), 1);
+    }
+}
@@ -0,0 +1,59 @@
+//! Time budget a bot can query while it thinks, so it can speed up before it
+//! collects strikes instead of being surprised by a `Timeout`
+//!
+//! A bot sends the text message `Budget` on its game connection between two requests
+//! and is answered with a text message, the frame time keeps running meanwhile.
+
+use serde::Serialize;
+use std::time::Duration;
+
+/// Text message a bot sends to query its budget
+pub const BUDGET_QUERY: &str = "Budget";
+
+/// Budget of a bot for the frame it is working on
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+pub struct Budget {
+    /// Time budget of the frame, larger during the warmup
+    #[serde(rename = "FrameSeconds")]
+    pub frame_seconds: f32,
+    /// Time left of the frame budget
+    #[serde(rename = "SecondsLeft")]
+    pub seconds_left: f32,
+    /// Strikes the bot collected so far
+    #[serde(rename = "Strikes")]
+    pub strikes: u32,
+    /// Strikes that make the bot lose with `Timeout`
+    #[serde(rename = "MaxStrikes")]
+    pub max_strikes: u32,
+}
+impl Budget {
+    pub fn new(frame: Duration, elapsed: Duration, strikes: u32, max_strikes: u32) -> Self {
+        Self {
+            frame_seconds: frame.as_secs_f32(),
+            seconds_left: frame.saturating_sub(elapsed).as_secs_f32(),
+            strikes,
+            max_strikes,
+        }
+    }
+    /// Answer sent to the bot
+    pub fn to_message(&self) -> String {
+        serde_json::json!({ "Budget": self }).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget() {
+        let budget = Budget::new(Duration::from_secs(2), Duration::from_millis(500), 1, 3);
+        assert!((budget.seconds_left - 1.5).abs() < 1e-6);
+        let message: serde_json::Value = serde_json::from_str(&budget.to_message()).unwrap();
+        assert_eq!(message["Budget"]["FrameSeconds"], 2.0);
+        assert_eq!(message["Budget"]["Strikes"], 1);
+        assert_eq!(message["Budget"]["MaxStrikes"], 3);
+        let late = Budget::new(Duration::from_secs(2), Duration::from_secs(3), 2, 3);
+        assert_eq!(late.seconds_left, 0.0);
+    }
+}
@@ -0,0 +1,77 @@
+//! Chat messages the bots sent during a game, kept for the result
+//!
+//! SC2 itself delivers chat to the other players through their observations, the proxy
+//! only records what each bot said and when. `Tag:` messages are recorded as well.
+
+#[cfg(feature = "supervisor")]
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::warn_limited;
+
+/// Lines kept per player, later ones are only counted
+const MAX_LINES: usize = 500;
+
+/// Longest message kept, longer ones are cut
+const MAX_MESSAGE_CHARS: usize = 512;
+
+/// A chat message of a bot
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "supervisor", derive(JsonSchema))]
+pub struct ChatLine {
+    /// Game loop of the observation the bot answered with the message
+    #[serde(rename = "GameLoop")]
+    pub game_loop: u32,
+    #[serde(rename = "Message")]
+    pub message: String,
+}
+
+/// Chat of one player
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChatLog {
+    pub lines: Vec<ChatLine>,
+    /// Messages not kept as the log was full
+    pub dropped: u32,
+}
+impl ChatLog {
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+    /// Record a message of the player at `game_loop`
+    pub fn record(&mut self, player_id: Option<u32>, game_loop: u32, message: &str) {
+        if self.lines.len() >= MAX_LINES {
+            // Only the first dropped message is logged
+            warn_limited(
+                &mut self.dropped,
+                1,
+                format_args!("{:?}: More than {} chat messages", player_id, MAX_LINES),
+            );
+            return;
+        }
+        self.lines.push(ChatLine {
+            game_loop,
+            message: message.chars().take(MAX_MESSAGE_CHARS).collect(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let mut chat = ChatLog::default();
+        assert!(chat.is_empty());
+        chat.record(Some(1), 24, "gl hf");
+        chat.record(Some(1), 25, &"a".repeat(MAX_MESSAGE_CHARS + 10));
+        assert_eq!(chat.lines[0].game_loop, 24);
+        assert_eq!(chat.lines[0].message, "gl hf");
+        assert_eq!(chat.lines[1].message.len(), MAX_MESSAGE_CHARS);
+        for game_loop in 0..MAX_LINES as u32 {
+            chat.record(Some(1), game_loop, "spam");
+        }
+        assert_eq!(chat.lines.len(), MAX_LINES);
+        assert_eq!(chat.dropped, 2);
+    }
+}
@@ -0,0 +1,74 @@
+//! Details of a broken connection, so crashes on the ladder can be triaged from
+//! the result instead of the logs
+
+use crate::errors::ArenaError;
+#[cfg(feature = "supervisor")]
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::io;
+use tokio_tungstenite::tungstenite::error::ProtocolError;
+use tokio_tungstenite::tungstenite::Error;
+
+/// Side of the player whose connection broke
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "supervisor", derive(JsonSchema))]
+pub enum Peer {
+    Bot,
+    SC2,
+}
+
+/// How and where the connection of a player broke
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "supervisor", derive(JsonSchema))]
+pub struct Disconnect {
+    #[serde(rename = "Peer")]
+    pub peer: Peer,
+    /// I/O error kind, e.g. `ConnectionReset`, the websocket protocol error,
+    /// `Protocol` for a message the proxy could not handle,
+    /// or `Closed` if the peer closed the connection without an error
+    #[serde(rename = "Error")]
+    pub error: String,
+    /// Game loop of the last observation forwarded to the bot
+    #[serde(rename = "LastGameLoop")]
+    pub last_game_loop: u32,
+    /// Size of the last message sent to the peer, which it never answered
+    #[serde(rename = "BytesOutstanding")]
+    pub bytes_outstanding: usize,
+}
+
+/// Name of the error that broke a connection
+pub fn error_kind(error: &anyhow::Error) -> String {
+    if let Some(e) = error.downcast_ref::<io::Error>() {
+        format!("{:?}", e.kind())
+    } else if let Some(e) = error.downcast_ref::<ProtocolError>() {
+        format!("{:?}", e)
+    } else if let Some(e) = error.downcast_ref::<Error>() {
+        match e {
+            Error::Io(e) => format!("{:?}", e.kind()),
+            Error::Protocol(e) => format!("{:?}", e),
+            _ => "Closed".to_string(),
+        }
+    } else if let Some(ArenaError::Protocol(_)) = error.downcast_ref::<ArenaError>() {
+        "Protocol".to_string()
+    } else {
+        "Closed".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_kind() {
+        let reset = anyhow::Error::new(io::Error::from(io::ErrorKind::ConnectionReset));
+        assert_eq!(error_kind(&reset), "ConnectionReset");
+        let handshake = anyhow::Error::new(ProtocolError::ResetWithoutClosingHandshake);
+        assert_eq!(error_kind(&handshake), "ResetWithoutClosingHandshake");
+        let send = anyhow::Error::new(Error::Io(io::Error::from(io::ErrorKind::BrokenPipe)));
+        assert_eq!(error_kind(&send), "BrokenPipe");
+        let invalid = anyhow::Error::new(ArenaError::Protocol("Invalid request".to_string()));
+        assert_eq!(error_kind(&invalid), "Protocol");
+        assert_eq!(error_kind(&anyhow::Error::msg("Message is None")), "Closed");
+    }
+}
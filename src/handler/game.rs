@@ -1,31 +1,86 @@
 //! Game manages a single handler, including configuration and result gathering
 
+use crate::channels::TelemetrySender;
 use crate::config::Config;
+use crate::errors::ArenaError;
+use crate::limits::{CpuTime, MemoryPeak};
 use crate::sc2::PlayerResult;
-use crossbeam::channel::{select, Receiver, Sender};
-use log::{debug, info};
+use crossbeam::channel::{after, never, select, tick, Receiver, Sender};
+use log::{debug, error, info, warn};
+use std::collections::BTreeMap;
+use std::time::Duration;
 
 use super::any_panic_to_string;
-use super::messaging::{create_channels, FromSupervisor, ToGame, ToGameContent, ToSupervisor};
+use super::memory::{BotPids, MemoryMonitor, Offender, Watched, SAMPLE_INTERVAL};
+use super::messaging::{
+    create_channels, FromSupervisor, ToGame, ToGameContent, ToPlayer, ToSupervisor,
+};
+use super::middleware::Middlewares;
+use super::pacing::Pacing;
 use super::player::Player;
-// use crate::handler::messaging::ToPlayer;
+use super::policy::{policy_from_config, reconcile, team_results, ResultPolicy};
+use super::progress::Progress;
+use super::realtime::RealtimeLag;
+use super::replay::ReplayLock;
+use super::tags::TagFeed;
+use super::trace::{FrameTrace, SharedTrace};
+use super::version::Sc2Version;
 
 /// Game result data
 #[derive(Debug, Clone)]
 pub struct GameResult {
     pub end_reason: GameEndReason,
     pub player_results: Vec<PlayerResult>,
-    pub average_frame_time: Option<[f32; 2]>,
+    pub average_frame_time: Option<Vec<f32>>,
     pub game_loops: u32,
+    /// The result policy asks for the match to be replayed
+    pub rematch: bool,
+    /// Raw `GameOver` reports of each player, set only when they disagreed
+    pub result_discrepancy: Option<Vec<Vec<PlayerResult>>>,
+    /// Version the SC2 processes of the game ran
+    pub sc2_version: Option<Sc2Version>,
+    /// Whether any player wrote a well-formed replay, `None` if no save was attempted
+    pub replay_saved: Option<bool>,
+    /// Peak memory sampled for each player, in player order
+    pub peak_memory: Vec<MemoryPeak>,
+    /// CPU time sampled for each player, in player order
+    pub cpu_time: Vec<CpuTime>,
+    /// Most game loops each player was ahead of the other, empty in transparent mode
+    pub loop_skew: Vec<u32>,
+    /// Outcome of each team, only for team games
+    pub team_results: BTreeMap<u32, PlayerResult>,
+}
+
+/// Time the players get to save their replays once `MaxRealTimeSeconds` passed,
+/// longer than `SAVE_REPLAY_TIMEOUT`
+const WATCHDOG_GRACE: Duration = Duration::from_secs(90);
+
+/// Result of the built-in AI when SC2 did not report it
+fn computer_result(bot_result: PlayerResult) -> PlayerResult {
+    match bot_result {
+        PlayerResult::Victory => PlayerResult::Defeat,
+        PlayerResult::Tie => PlayerResult::Tie,
+        PlayerResult::SC2Crash => PlayerResult::SC2Crash,
+        PlayerResult::Defeat
+        | PlayerResult::Crash
+        | PlayerResult::Timeout
+        | PlayerResult::InitializationError
+        | PlayerResult::ResourceLimit
+        | PlayerResult::Forfeit => PlayerResult::Victory,
+    }
 }
 
 /// Why this handler ended
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum GameEndReason {
     /// Game ended naturally
     Normal,
-    /// Supervisor requested handler quit
+    /// Supervisor requested handler quit, replays were saved
     QuitRequest,
+    /// Ended early by a graceful shutdown, replays were saved
+    Drained,
+    /// Ended by the `MaxRealTimeSeconds` watchdog
+    TimedOut,
 }
 
 /// A running handler
@@ -35,76 +90,150 @@ pub struct Game {
     pub(super) config: Config,
     /// Player participants
     pub(super) players: Vec<Player>,
+    /// Version all SC2 processes reported before the game was created
+    pub(super) sc2_version: Option<Sc2Version>,
+    /// Bots registered by the launcher, whose memory is sampled
+    pub(super) bot_pids: BotPids,
+    /// Middlewares registered by library users, run after the built-in ones
+    pub(super) middlewares: Middlewares,
 }
 impl Game {
+    /// Sample the memory of the bots registered in `bot_pids`
+    pub fn with_bot_pids(mut self, bot_pids: BotPids) -> Self {
+        self.bot_pids = bot_pids;
+        self
+    }
+    /// Run the registered `middlewares` for every player
+    pub fn with_middlewares(mut self, middlewares: Middlewares) -> Self {
+        self.middlewares = middlewares;
+        self
+    }
+
+    /// SC2 processes of the players
+    pub(super) fn sc2_pids(&self) -> Vec<u32> {
+        self.players
+            .iter()
+            .filter_map(|p| p.process.pid())
+            .collect()
+    }
+
     /// Process a message from player thread
     fn process_msg(
+        policy: &dyn ResultPolicy,
         msg: ToGame,
         player_results: &mut [Option<PlayerResult>],
+        reports: &mut [Option<Vec<PlayerResult>>],
         game_loops: &mut u32,
-        frame_times: &mut [f32; 2],
-        tags: &mut [Vec<String>; 2],
+        frame_times: &mut [f32],
+        tags: &mut [Vec<String>],
     ) {
         let ToGame {
             player_index,
             content,
         } = msg;
-        match content {
-            ToGameContent::GameOver(game_over) => {
-                for (i, item) in player_results.iter_mut().enumerate() {
-                    if item.is_none() {
-                        *item = Some(game_over.results[i])
-                    }
-                }
-                *game_loops = game_over.game_loops;
-                frame_times[player_index] = game_over.frame_time;
-                tags[player_index] = game_over.tags;
-            }
-            ToGameContent::LeftGame => {
-                info!("Player left handler before it was over");
-                player_results[player_index] = Some(PlayerResult::Defeat);
-            }
-            ToGameContent::QuitBeforeLeave => {
-                info!("Client quit without leaving the handler");
-                player_results[player_index] = Some(PlayerResult::Defeat);
-            }
-            ToGameContent::SC2UnexpectedConnectionClose => {
-                info!("SC2 process closed connection unexpectedly");
-                player_results[player_index] = Some(PlayerResult::SC2Crash);
-            }
-            ToGameContent::UnexpectedConnectionClose => {
-                info!("Unexpected connection close");
-                if !player_results
-                    .iter()
-                    .enumerate()
-                    .any(|(idx, r)| idx != player_index && matches!(r, Some(PlayerResult::Crash)))
-                {
-                    debug!("Bot {:?} crashed", player_index);
-                    player_results[player_index] = Some(PlayerResult::Crash);
-                } else {
-                    player_results[player_index] = Some(PlayerResult::Victory);
-                }
-            }
+        policy.apply(player_index, &content, player_results);
+        if let ToGameContent::GameOver(game_over) = content {
+            reports[player_index] = Some(game_over.results);
+            *game_loops = game_over.game_loops;
+            frame_times[player_index] = game_over.frame_time;
+            tags[player_index] = game_over.tags;
         }
     }
 
     /// Run the handler, spawns thread for each participant player
     /// Returns the non-disconnected player instances, so they can be returned to the playlist
+    /// Fails without sending a result if the players end without their results or a player panics
     pub async fn run(
         self,
         result_tx: Sender<GameResult>,
-        from_sv: Receiver<FromSupervisor>,
-        _to_sv: Sender<ToSupervisor>,
-    ) -> Vec<Player> {
+        mut from_sv: Receiver<FromSupervisor>,
+        to_sv: TelemetrySender<ToSupervisor>,
+    ) -> Result<Vec<Player>, ArenaError> {
         let mut handles: Vec<tokio::task::JoinHandle<Option<Player>>> = Vec::new();
         let mut game_loops = 0_u32;
-        let mut frame_times: [f32; 2] = [0_f32, 0_f32];
-        let mut tags: [Vec<String>; 2] = [vec![], vec![]];
-        let (rx, mut _to_player_channels, player_channels) = create_channels(self.players.len());
+        let mut frame_times: Vec<f32> = vec![0_f32; self.players.len().max(2)];
+        let mut tags: Vec<Vec<String>> = vec![vec![]; self.players.len().max(2)];
+        let (rx, mut to_player_channels, player_channels) = create_channels(self.players.len());
+        let mut drained = false;
+        let mut quit = false;
+        // Set by a quit request, with whether the controller dropped the handle
+        let mut quit_requested: Option<bool> = None;
+        let mut timed_out = false;
         let mut player_results: Vec<Option<PlayerResult>> = vec![None; self.players.len()];
+        let mut reports: Vec<Option<Vec<PlayerResult>>> = vec![None; self.players.len()];
+        let policy = policy_from_config(&self.config);
+        let computer = self.config.computer();
+        let sc2_version = self.sc2_version.clone();
+
+        let trace: Option<SharedTrace> = self.config.frame_trace().and_then(|path| {
+            match FrameTrace::create(&path, self.config.frame_trace_max_bytes()) {
+                Ok(trace) => Some(trace.shared()),
+                Err(e) => {
+                    error!("Could not create frame trace {:?}: {:?}", path, e);
+                    None
+                }
+            }
+        });
+
+        let replay_lock = ReplayLock::default();
+        let names = self.config.players();
+        let teams = self.config.teams();
+        let watched = self
+            .players
+            .iter()
+            .enumerate()
+            .map(|(i, p)| Watched {
+                name: names[i].to_string(),
+                sc2_pid: p.process.pid(),
+                limits: self.config.player_limits(i),
+            })
+            .collect();
+        let mut monitor = MemoryMonitor::new(watched, self.bot_pids.clone());
+        let sample = tick(SAMPLE_INTERVAL);
+        let mut deadline = self.config.max_real_time().map(after).unwrap_or_else(never);
+
+        let pacings = if self.config.transparent() {
+            Vec::new()
+        } else {
+            Pacing::for_players(self.players.len(), self.config.pacing())
+        };
+        let pacing = pacings.first().cloned();
+        let realtime = RealtimeLag::for_players(&self.config, self.players.len());
+        let progress = match self.config.progress_interval() {
+            Some(interval) if !self.config.transparent() => {
+                let names = names
+                    .iter()
+                    .take(self.players.len())
+                    .map(ToString::to_string)
+                    .collect();
+                Progress::for_players(names, interval, to_sv.clone())
+            }
+            _ => Vec::new(),
+        };
+
+        let tag_feeds: Vec<_> = if self.config.live_tags() && !self.config.transparent() {
+            names
+                .iter()
+                .take(self.players.len())
+                .map(|name| TagFeed::new(name.to_string(), to_sv.clone()))
+                .collect()
+        } else {
+            Vec::new()
+        };
 
         // Run games
-        for (p, c) in self.players.into_iter().zip(player_channels) {
+        let mut pacings = pacings.into_iter();
+        let mut realtime = realtime.into_iter();
+        let mut tag_feeds = tag_feeds.into_iter();
+        let mut progress = progress.into_iter();
+        for (index, (mut p, c)) in self.players.into_iter().zip(player_channels).enumerate() {
+            p.middlewares = self.middlewares.for_player(&self.config, index);
+            p.trace = trace.clone();
+            p.pacing = pacings.next();
+            p.realtime = realtime.next();
+            p.progress = progress.next();
+            p.tag_feed = tag_feeds.next();
+            p.replay_lock = replay_lock.clone();
             let thread_config: Config = self.config.clone();
             handles.push(tokio::spawn(async move { p.run(thread_config, c).await }));
         }
@@ -114,59 +243,195 @@ impl Game {
                 // A client ended the handler
                 recv(rx) -> r => match r {
                     Ok(msg) => {
-                        Self::process_msg(msg, &mut player_results, &mut game_loops, &mut frame_times, &mut tags);
+                        // The player stopped stepping, nobody waits for it anymore
+                        if let Some(pacing) = &pacing {
+                            pacing.finish(msg.player_index);
+                        }
+                        Self::process_msg(policy.as_ref(), msg, &mut player_results, &mut reports, &mut game_loops, &mut frame_times, &mut tags);
                     },
-                    Err(e) => panic!("Player channel closed without sending results {:?}",e),
+                    Err(_) => return Err(ArenaError::Game("Players ended without sending their results".to_string())),
                 },
                 recv(from_sv) -> r => match r {
-                    Ok(FromSupervisor::Quit) => {
-                        // Game quit requested
+                    Ok(FromSupervisor::Quit) | Err(_) => {
+                        // The players save their replays and kill SC2, like for a drain
+                        // The controller drops the handle of games it closed without waiting for them
                         debug!("Supervisor requested handler quit");
-
-                        result_tx
-                            .send(GameResult {
-                                end_reason: GameEndReason::QuitRequest,
-                                player_results: Vec::new(),
-                                game_loops: 0,
-                                average_frame_time: None
-                            })
-                            .expect("Could not send results to the supervisor");
-
-                        unimplemented!(); // TODO
+                        quit_requested = Some(r.is_err());
+                        quit = true;
+                        for channel in to_player_channels.iter_mut() {
+                            channel.send(ToPlayer::Quit);
+                        }
                     },
-                    Err(e) => panic!("Supervisor channel closed unexpectedly: {}", e),
+                    Ok(FromSupervisor::Drain) => {
+                        debug!("Supervisor requested handler drain");
+                        drained = true;
+                        for channel in to_player_channels.iter_mut() {
+                            channel.send(ToPlayer::Quit);
+                        }
+                    },
+                },
+                // A player over its memory limit loses, ending the game for everyone
+                recv(sample) -> _ => {
+                    if let Some((index, offender)) = monitor.sample() {
+                        policy.apply(index, &ToGameContent::ResourceLimit, &mut player_results);
+                        // Teammates of the offender lose with it
+                        for (i, result) in player_results.iter_mut().enumerate() {
+                            if i != index && result.is_none() {
+                                *result = Some(if teams.get(i) == teams.get(index) {
+                                    PlayerResult::Defeat
+                                } else {
+                                    PlayerResult::Victory
+                                });
+                            }
+                        }
+                        if offender == Offender::Bot {
+                            monitor.kill_bot(index);
+                        }
+                        for channel in to_player_channels.iter_mut() {
+                            channel.send(ToPlayer::Quit);
+                        }
+                    }
+                },
+                // The game took too long, e.g. a bot never sent its next request
+                recv(deadline) -> _ => {
+                    if quit {
+                        error!("Players did not stop within {:?} of the quit request", WATCHDOG_GRACE);
+                    } else {
+                        error!("Game exceeded MaxRealTimeSeconds, ending it");
+                    }
+                    timed_out = true;
+                    for result in player_results.iter_mut().filter(|r| r.is_none()) {
+                        *result = Some(PlayerResult::Tie);
+                    }
+                    for channel in to_player_channels.iter_mut() {
+                        channel.send(ToPlayer::Quit);
+                    }
+                }
+            }
+            // The receivers are replaced once select! no longer borrows them
+            if let Some(supervisor_left) = quit_requested.take() {
+                deadline = after(WATCHDOG_GRACE);
+                if supervisor_left {
+                    // A closed channel is always ready, stop selecting it
+                    from_sv = never();
                 }
             }
         }
 
         info!("Game ready, results collected");
+        if let Some(pacing) = &pacing {
+            for index in 0..player_results.len() {
+                pacing.finish(index);
+            }
+        }
+        if let Some(trace) = &trace {
+            let dropped = trace.lock().unwrap().dropped();
+            if dropped > 0 {
+                warn!(
+                    "Frame trace is missing {} frames past its size cap",
+                    dropped
+                );
+            }
+        }
 
         // Wait until the games are ready
+        // After a timeout, players that do not finish in time are aborted, which kills their SC2
+        let grace_end = tokio::time::Instant::now() + WATCHDOG_GRACE;
         let mut result_players: Vec<Player> = Vec::new();
-        for handle in handles {
-            match handle.await {
+        let mut player_panic = None;
+        for mut handle in handles {
+            let joined = if timed_out || quit {
+                match tokio::time::timeout_at(grace_end, &mut handle).await {
+                    Ok(joined) => joined,
+                    Err(_) => {
+                        error!(
+                            "Player did not end within {:?}, killing its SC2 process",
+                            WATCHDOG_GRACE
+                        );
+                        handle.abort();
+                        continue;
+                    }
+                }
+            } else {
+                handle.await
+            };
+            match joined {
                 Ok(Some(player)) => {
                     result_players.push(player);
                 }
                 Ok(None) => {}
-                Err(panic_msg) => {
-                    panic!(
-                        "Could not join handler-client thread: {:?}",
-                        any_panic_to_string(panic_msg.into_panic())
-                    );
+                Err(e) if e.is_panic() => {
+                    // The other players still end, so their SC2 processes are not left behind
+                    player_panic = Some(any_panic_to_string(e.into_panic()));
                 }
+                Err(_) => {}
             }
         }
+        if let Some(panic_msg) = player_panic {
+            return Err(ArenaError::Game(format!("Player panicked: {}", panic_msg)));
+        }
+        if timed_out {
+            // The players ended the game after the watchdog collected the results
+            game_loops = result_players
+                .iter()
+                .map(|p| p.game_loops)
+                .max()
+                .unwrap_or_default();
+        }
         // Send handler result to the supervisor
-        result_tx
-            .send(GameResult {
-                end_reason: GameEndReason::Normal,
-                player_results: player_results.into_iter().map(Option::unwrap).collect(),
-                average_frame_time: Some(frame_times),
-                game_loops,
-            })
-            .expect("Could not send results to the supervisor");
+        let mut player_results: Vec<PlayerResult> =
+            player_results.into_iter().map(Option::unwrap).collect();
+        if computer.is_some() {
+            // The built-in AI is player 2, SC2 reports its result alongside the bot's
+            let ai_result = reports[0]
+                .as_ref()
+                .and_then(|report| report.get(1).copied())
+                .unwrap_or_else(|| computer_result(player_results[0]));
+            player_results.push(ai_result);
+        }
+        let mut result_discrepancy = None;
+        if let Some(reconciliation) = reconcile(&reports) {
+            player_results = reconciliation.results;
+            if reconciliation.discrepancy {
+                result_discrepancy = Some(reports.into_iter().flatten().collect());
+            }
+        }
+        let rematch = policy.rematch(&player_results);
+        let team_results = if self.config.is_team_game() {
+            team_results(&teams, &player_results)
+        } else {
+            BTreeMap::new()
+        };
+        let replay_saved = result_players
+            .iter()
+            .filter_map(|p| p.replay_saved)
+            .reduce(|a, b| a || b);
+        let sent = result_tx.send(GameResult {
+            end_reason: if quit {
+                GameEndReason::QuitRequest
+            } else if timed_out {
+                GameEndReason::TimedOut
+            } else if drained {
+                GameEndReason::Drained
+            } else {
+                GameEndReason::Normal
+            },
+            player_results,
+            average_frame_time: Some(frame_times),
+            game_loops,
+            rematch,
+            result_discrepancy,
+            sc2_version,
+            replay_saved,
+            cpu_time: monitor.cpu_time(),
+            peak_memory: monitor.peaks(),
+            loop_skew: pacing.map(|pacing| pacing.skew()).unwrap_or_default(),
+            team_results,
+        });
+        if sent.is_err() {
+            debug!("Result not collected, the controller dropped the game");
+        }
 
-        result_players
+        Ok(result_players)
     }
 }
@@ -0,0 +1,107 @@
+//! Interfaces bots may request when they join
+//!
+//! Rendered and feature layer interfaces cost SC2 time every frame, on the budget of both
+//! bots, and the score interface is of no use in light mode. The lobby checks the interface
+//! options of every join against the policy of the match: forbidden interfaces reject the
+//! join, spatial resolutions over the cap are lowered to it.
+
+use sc2_proto::common::Size2DI;
+use sc2_proto::sc2api::{InterfaceOptions, SpatialCameraSetup};
+
+use crate::config::Config;
+
+/// Interfaces allowed in a match
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterfacePolicy {
+    /// Reject joins requesting the render interface
+    pub forbid_render: bool,
+    /// Largest width and height of feature layers and renders, None if unlimited
+    pub max_resolution: Option<i32>,
+    /// Keep the score interface, it is turned off in light mode
+    pub score: bool,
+}
+impl InterfacePolicy {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            forbid_render: config.forbid_render(),
+            max_resolution: config.max_spatial_resolution(),
+            score: !config.light_mode(),
+        }
+    }
+    /// Apply the policy to the interface options of a join, or why the join is rejected
+    pub fn apply(&self, options: &mut InterfaceOptions) -> Result<(), String> {
+        if self.forbid_render && options.render.is_some() {
+            return Err("The render interface is not allowed in this match".to_string());
+        }
+        if let Some(max) = self.max_resolution {
+            if let Some(setup) = options.feature_layer.as_mut() {
+                cap_setup(setup, max);
+            }
+            if let Some(setup) = options.render.as_mut() {
+                cap_setup(setup, max);
+            }
+        }
+        if !self.score {
+            options.set_score(false);
+        }
+        Ok(())
+    }
+}
+
+fn cap_setup(setup: &mut SpatialCameraSetup, max: i32) {
+    if let Some(size) = setup.resolution.as_mut() {
+        cap_size(size, max);
+    }
+    if let Some(size) = setup.minimap_resolution.as_mut() {
+        cap_size(size, max);
+    }
+}
+
+fn cap_size(size: &mut Size2DI, max: i32) {
+    size.set_x(size.x().min(max));
+    size.set_y(size.y().min(max));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn size(x: i32, y: i32) -> Size2DI {
+        let mut size = Size2DI::new();
+        size.set_x(x);
+        size.set_y(y);
+        size
+    }
+
+    #[test]
+    fn test_apply() {
+        let policy = InterfacePolicy {
+            forbid_render: true,
+            max_resolution: Some(64),
+            score: false,
+        };
+        let mut options = InterfaceOptions::new();
+        options.set_raw(true);
+        options.set_score(true);
+        let mut setup = SpatialCameraSetup::new();
+        setup.resolution = Some(size(84, 32)).into();
+        setup.minimap_resolution = Some(size(64, 64)).into();
+        options.feature_layer = Some(setup).into();
+        assert_eq!(policy.apply(&mut options), Ok(()));
+        assert!(!options.score());
+        let setup = options.feature_layer.as_ref().unwrap();
+        assert_eq!(setup.resolution.as_ref(), Some(&size(64, 32)));
+        assert_eq!(setup.minimap_resolution.as_ref(), Some(&size(64, 64)));
+
+        options.render = Some(SpatialCameraSetup::new()).into();
+        assert!(policy.apply(&mut options).is_err());
+        let policy = InterfacePolicy {
+            forbid_render: false,
+            max_resolution: None,
+            score: true,
+        };
+        options.set_score(true);
+        assert_eq!(policy.apply(&mut options), Ok(()));
+        assert!(options.score());
+    }
+}
@@ -0,0 +1,55 @@
+//! Light mode, for workers with little bandwidth or CPU
+//!
+//! Besides starting the players without threads and turning the score interface off, light
+//! mode strips the spatial data of observations before they are forwarded: the feature layers
+//! and rendered images, which make up most of an observation, and the score. Bots get the raw
+//! data and the UI data as usual.
+
+use sc2_proto::sc2api::Response;
+
+/// Drop the heavyweight parts of an observation, returning whether anything was dropped
+pub fn strip_observation(response: &mut Response) -> bool {
+    if !response.has_observation() {
+        return false;
+    }
+    let observation = &mut response.mut_observation().observation;
+    let observation = match observation.as_mut() {
+        Some(observation) => observation,
+        None => return false,
+    };
+    let stripped = observation.feature_layer_data.is_some()
+        || observation.render_data.is_some()
+        || observation.score.is_some();
+    observation.feature_layer_data.clear();
+    observation.render_data.clear();
+    observation.score.clear();
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sc2_proto::raw::ObservationRaw;
+    use sc2_proto::sc2api::{Observation, ResponseObservation};
+    use sc2_proto::spatial::ObservationRender;
+
+    #[test]
+    fn test_strip_observation() {
+        let mut response = Response::new();
+        assert!(!strip_observation(&mut response));
+
+        let mut observation = Observation::new();
+        observation.set_game_loop(224);
+        observation.raw_data = Some(ObservationRaw::new()).into();
+        observation.render_data = Some(ObservationRender::new()).into();
+        let mut response_observation = ResponseObservation::new();
+        response_observation.observation = Some(observation).into();
+        response.set_observation(response_observation);
+        assert!(strip_observation(&mut response));
+        let observation = response.observation().observation.as_ref().unwrap();
+        assert!(observation.render_data.is_none());
+        assert!(observation.raw_data.is_some());
+        assert_eq!(observation.game_loop(), 224);
+        assert!(!strip_observation(&mut response));
+    }
+}
@@ -1,19 +1,30 @@
 //! Game manages a single unstarted handler, including its configuration
 
-use log::{error, info, trace};
-use protobuf::{EnumOrUnknown, MessageField};
+use futures_util::future::join_all;
+use futures_util::SinkExt;
+use log::{error, info, trace, warn};
+use protobuf::{EnumOrUnknown, Message, MessageField};
 
-use sc2_proto::sc2api::RequestJoinGame;
+use sc2_proto::sc2api::{response_join_game, RequestJoinGame, Response, ResponseJoinGame};
 use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message as TMessage;
 
+use crate::errors::ArenaError;
 use crate::maps::find_map;
+use crate::pool::{take_or_launch, SharedPool};
 use crate::portconfig::PortConfig;
 use crate::proxy::Client;
+use crate::sc2process::Process;
+use crate::trace::{trace_path, ProtocolTrace};
 
 use super::game::Game;
+use super::interface::InterfacePolicy;
+use super::memory::BotPids;
+use super::middleware::Middlewares;
 use super::player::{Player, PlayerData};
+use super::version::Sc2Version;
 use crate::config::Config;
-use crate::sc2::Race;
+use crate::sc2::{Difficulty, Race};
 
 /// An unstarted handler
 #[derive(Debug)]
@@ -23,7 +34,17 @@ pub struct GameLobby {
     /// Player participants
     pub players: Vec<Player>,
     //Player handles
-    player_handles: Vec<JoinHandle<Player>>,
+    player_handles: Vec<JoinHandle<Result<Player, ArenaError>>>,
+    /// Warm SC2 processes to use before launching new ones
+    pool: Option<SharedPool>,
+    /// SC2 process launched ahead for the bot that did not join yet, so both boot in parallel
+    spare_process: Option<Process>,
+    /// Game ports picked when the config was accepted, new ones are picked if None
+    ports: Option<PortConfig>,
+    /// Slots whose lead joined, partners of an Archon match join these
+    leads: Vec<PlayerNum>,
+    /// Second clients of the slots in an Archon match, they share the SC2 process of the lead
+    partners: Vec<(PlayerNum, Client)>,
 }
 impl GameLobby {
     /// Create new empty handler lobby from config
@@ -32,20 +53,77 @@ impl GameLobby {
             config,
             players: Vec::new(),
             player_handles: Vec::new(),
+            pool: None,
+            spare_process: None,
+            ports: None,
+            leads: Vec::new(),
+            partners: Vec::new(),
         }
     }
-    pub async fn join_player_handles(&mut self) {
-        while let Some(handle) = self.player_handles.pop() {
-            // self.players.push(handle.join().unwrap());
-            self.players.insert(0, handle.await.unwrap());
+    /// Take SC2 processes from `pool` while it has idle ones
+    pub fn with_pool(mut self, pool: SharedPool) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+    /// Join the game on `ports` instead of picking them when the game starts
+    pub fn with_ports(mut self, ports: PortConfig) -> Self {
+        self.ports = Some(ports);
+        self
+    }
+    /// Wait for the players still connecting to their SC2 processes, all at once
+    /// Players that connected are kept even if another one failed, so `close` kills their processes
+    pub async fn join_player_handles(&mut self) -> Result<(), ArenaError> {
+        let handles = std::mem::take(&mut self.player_handles);
+        let mut connected = Vec::with_capacity(handles.len());
+        let mut error = None;
+        for result in join_all(handles).await {
+            match result {
+                Ok(Ok(player)) => connected.push(player),
+                Ok(Err(e)) => {
+                    error.get_or_insert(e);
+                }
+                Err(e) => {
+                    error.get_or_insert(ArenaError::Lobby(format!("Player task failed: {}", e)));
+                }
+            }
+        }
+        connected.append(&mut self.players);
+        self.players = connected;
+        self.sort_players();
+        error.map_or(Ok(()), Err)
+    }
+    /// Order the players by slot, SC2 assigns the player ids in join order
+    fn sort_players(&mut self) {
+        let names = self.config.players();
+        self.players.sort_by_key(|p| {
+            p.player_name()
+                .as_deref()
+                .and_then(|name| names.iter().position(|n| *n == name))
+        });
+    }
+    /// Player slots of bots, each with its own SC2 process
+    fn expected_leads(&self) -> usize {
+        self.config.bot_slots()
+    }
+    /// Clients that have to join before the game can start, two per slot in an Archon match
+    pub fn expected_clients(&self) -> usize {
+        if self.config.archon() {
+            2 * self.expected_leads()
+        } else {
+            self.expected_leads()
         }
     }
+    /// Clients that joined the lobby
+    pub fn joined_clients(&self) -> usize {
+        self.leads.len() + self.partners.len()
+    }
     /// Checks if this lobby has any player participants
     pub fn is_valid(&self) -> bool {
         !self.players.is_empty()
     }
 
     /// Add a new client to the handler
+    /// A malformed join request is answered with an error and returned as `ArenaError::Protocol`
     pub async fn join(
         &mut self,
         connection: Client,
@@ -53,79 +131,163 @@ impl GameLobby {
         client_data: (String, Option<Race>),
         must_join: bool,
         player: PlayerNum,
-    ) {
-        let mut pd = PlayerData::from_join_request(join_req, self.config.archon());
-        if self.config.validate_race() && client_data.1.is_some() {
-            pd.race = client_data.1.unwrap();
+    ) -> Result<(), ArenaError> {
+        if self.config.strict_race() {
+            if let Err(reason) = check_race(&join_req, client_data.1) {
+                reject_join(connection, &reason).await;
+                return Err(ArenaError::Protocol(reason));
+            }
+        }
+        let race = if self.config.validate_race() {
+            client_data.1
+        } else {
+            None
+        };
+        let mut pd = match PlayerData::from_join_request(join_req, self.config.archon(), race) {
+            Ok(pd) => pd,
+            Err(reason) => {
+                reject_join(connection, &reason).await;
+                return Err(ArenaError::Protocol(reason));
+            }
+        };
+        if let Err(reason) =
+            InterfacePolicy::from_config(&self.config).apply(&mut pd.interface_options)
+        {
+            reject_join(connection, &reason).await;
+            return Err(ArenaError::Protocol(reason));
         }
         pd.name = Some(client_data.0);
+        if self.config.archon() && self.leads.contains(&player) {
+            info!(
+                "{:?} joined as the Archon partner of {:?}",
+                connection.peer_addr(),
+                pd.name
+            );
+            self.partners.push((player, connection));
+            return Ok(());
+        }
         trace!(
             "Player {:?} with peer addr {:?} is player {:?}",
             &pd.name,
             connection.peer_addr(),
             player
         );
-        if must_join {
-            match player {
-                PlayerNum::One => self
-                    .players
-                    .insert(0, Player::new_no_thread(connection, pd).await),
-                PlayerNum::Two => self
-                    .players
-                    .push(Player::new_no_thread(connection, pd).await),
+        let process = match self.spare_process.take() {
+            Some(process) => Ok(process),
+            None => take_or_launch(
+                self.pool.as_ref(),
+                self.config.temp_dir().as_deref(),
+                &self.config.launch_options_for(player.index()),
+            ),
+        };
+        let mut process = match process {
+            Ok(process) => process,
+            Err(e) => {
+                reject_join(connection, &e.to_string()).await;
+                return Err(e);
             }
+        };
+        process.apply_limits(&self.config.player_limits(player.index()));
+        self.leads.push(player);
+        if must_join {
+            let joined = Player::new_no_thread(connection, pd, process).await?;
+            self.players.push(joined);
+            self.sort_players();
         } else {
-            match player {
-                PlayerNum::One => self
-                    .player_handles
-                    .insert(0, Player::new(connection, pd).await),
-                PlayerNum::Two => self.player_handles.push(Player::new(connection, pd).await),
+            self.player_handles
+                .push(Player::new(connection, pd, process).await);
+        }
+        // The window of a visualized game depends on the player, so it is launched on join
+        if self.leads.len() < self.expected_leads()
+            && self.spare_process.is_none()
+            && !self.config.visualize()
+        {
+            match take_or_launch(
+                self.pool.as_ref(),
+                self.config.temp_dir().as_deref(),
+                &self.config.launch_options(),
+            ) {
+                Ok(process) => self.spare_process = Some(process),
+                // Launched again once the other bot joins
+                Err(e) => warn!("Could not launch SC2 ahead for the other bot: {}", e),
             }
         }
+        Ok(())
     }
 
     /// Protobuf to create a new handler
-    fn proto_create_game(&self, players: Vec<CreateGamePlayer>) -> sc2_proto::sc2api::Request {
+    fn proto_create_game(
+        &self,
+        players: Vec<CreateGamePlayer>,
+    ) -> Result<sc2_proto::sc2api::Request, ArenaError> {
         use sc2_proto::sc2api::{LocalMap, Request, RequestCreateGame};
 
-        let mut r_local_map = LocalMap::new();
-        let map = self.config.clone().map().clone();
-        r_local_map.set_map_path(find_map(map).expect("Map not found (Config::check?)"));
-
         let mut r_create_game = RequestCreateGame::new();
-        r_create_game.set_local_map(r_local_map);
+        if let Some(name) = self.config.battlenet_map_name() {
+            // SC2 downloads published maps itself, no local file is needed
+            r_create_game.set_battlenet_map_name(name.to_string());
+        } else {
+            let mut r_local_map = LocalMap::new();
+            let map = self.config.map().to_string();
+            let map_path = match find_map(map.clone()) {
+                Some(path) => path,
+                // The mock SC2 does not load the map
+                None if self.config.dry_run() => map,
+                None => return Err(ArenaError::MapNotFound(map)),
+            };
+            r_local_map.set_map_path(map_path);
+            r_create_game.set_local_map(r_local_map);
+        }
         r_create_game.set_realtime(self.config.realtime());
+        if let Some(seed) = self.config.random_seed() {
+            r_create_game.set_random_seed(seed);
+        }
+        r_create_game.set_disable_fog(self.config.disable_fog());
 
         r_create_game.player_setup = players.iter().map(CreateGamePlayer::as_proto).collect();
 
         let mut request = Request::new();
         request.set_create_game(r_create_game);
-        request
+        Ok(request)
     }
 
     /// Create the handler using the first client
-    /// Returns None if handler join fails (connection close or sc2 process close)
-    pub async fn create_game(&mut self) -> Option<()> {
+    /// Fails if SC2 closes the connection or rejects the game
+    pub async fn create_game(&mut self) -> Result<(), ArenaError> {
         assert!(!self.players.is_empty());
 
         // Craft CrateGame request
-        let player_configs: Vec<CreateGamePlayer> =
+        let mut player_configs: Vec<CreateGamePlayer> =
             vec![CreateGamePlayer::Participant; self.players.len()];
+        if let Some(ai) = self.config.computer() {
+            player_configs.push(CreateGamePlayer::Computer(ai.race, ai.difficulty));
+        }
 
         // Send CreateGame request to first process
-        let proto = self.proto_create_game(player_configs);
-        let response = self.players[0].sc2_query(&proto).await?;
-
-        assert!(response.has_create_game());
+        let proto = self.proto_create_game(player_configs)?;
+        let response = self.players[0].sc2_query(&proto).await.ok_or_else(|| {
+            ArenaError::Lobby("SC2 closed the connection while creating the game".to_string())
+        })?;
+
+        if !response.has_create_game() {
+            return Err(ArenaError::Protocol(format!(
+                "Expected a CreateGame response, got {:?}",
+                response
+            )));
+        }
         let resp_create_game = response.create_game();
         if resp_create_game.has_error() {
             error!("Could not create handler: {:?}", resp_create_game.error());
-            return None;
+            return Err(ArenaError::Lobby(format!(
+                "SC2 could not create the game: {:?} {}",
+                resp_create_game.error(),
+                resp_create_game.error_details()
+            )));
         } else {
             info!("Game created successfully");
         }
 
-        Some(())
+        Ok(())
     }
 
     /// Protobuf to join a handler
@@ -151,29 +313,57 @@ impl GameLobby {
         request
     }
 
-    /// Joins all participants to games
-    /// Returns None iff handler join fails (connection close or sc2 process close)
-    pub async fn join_all_game(&mut self) -> Option<()> {
-        let pc = PortConfig::new().expect("Unable to find free ports");
+    /// Joins all participants to games, sending the joins and awaiting SC2 for all players at once
+    /// Fails if a connection closes or SC2 rejects a join
+    pub async fn join_all_game(&mut self) -> Result<(), ArenaError> {
+        let pc = match self.ports.take() {
+            Some(ports) => ports,
+            None => PortConfig::for_players(
+                self.config.players().len(),
+                self.config.port_range().as_ref(),
+            )
+            .ok_or_else(|| ArenaError::Lobby("No free ports".to_string()))?,
+        };
 
+        for player in self.players.iter_mut() {
+            player.process.hold_ports(pc.reservation());
+        }
         let protos: Vec<_> = self
             .players
             .iter()
             .map(|p| self.proto_join_game_participant(pc.clone(), p.data.clone()))
             .collect();
 
-        for (player, proto) in self.players.iter_mut().zip(protos) {
-            player.sc2_request(&proto).await?;
-        }
-
-        for player in self.players.iter_mut() {
-            let response = player.sc2_recv().await?;
-            assert!(response.has_join_game());
+        // SC2 answers a join only once every player joined
+        let responses = join_all(self.players.iter_mut().zip(protos).map(
+            |(player, proto)| async move {
+                player.sc2_request(&proto).await?;
+                player.sc2_recv().await
+            },
+        ))
+        .await;
+
+        let mut joined = Vec::with_capacity(self.players.len());
+        for (player, response) in self.players.iter_mut().zip(responses) {
+            let response = response.ok_or_else(|| {
+                ArenaError::Lobby("SC2 closed the connection while joining the game".to_string())
+            })?;
+            if !response.has_join_game() {
+                return Err(ArenaError::Protocol(format!(
+                    "Expected a JoinGame response, got {:?}",
+                    response
+                )));
+            }
             let resp_join_game = response.join_game();
             player.player_id = Some(resp_join_game.player_id());
             if resp_join_game.has_error() {
                 error!("Could not join handler: {:?}", resp_join_game.error());
-                return None;
+                return Err(ArenaError::Lobby(format!(
+                    "SC2 could not join {:?}: {:?} {}",
+                    player.player_name(),
+                    resp_join_game.error(),
+                    resp_join_game.error_details()
+                )));
             } else {
                 info!("Game join successful");
             }
@@ -184,42 +374,182 @@ impl GameLobby {
                 player.player_id,
                 &response
             );
-            player.client_respond(&response).await;
+            player.client_respond(&response).await.map_err(|e| {
+                ArenaError::Lobby(format!(
+                    "{:?} disconnected while joining: {}",
+                    player.player_name(),
+                    e
+                ))
+            })?;
+            joined.push(response);
+        }
+
+        // Team of every SC2 player id, for the results a player derives on its own
+        let teams = self.config.teams();
+        let mut teams_by_id = vec![0; teams.len()];
+        for (slot, player) in self.players.iter().enumerate() {
+            let id = player.player_id.and_then(|id| id.checked_sub(1));
+            if let Some(team) = id.and_then(|id| teams_by_id.get_mut(id as usize)) {
+                *team = teams[slot];
+            }
+        }
+        // The built-in AI takes the id left
+        let mut others = teams.iter().skip(self.players.len());
+        for team in teams_by_id.iter_mut().filter(|team| **team == 0) {
+            *team = others.next().copied().unwrap_or_default();
+        }
+        for player in self.players.iter_mut() {
+            player.teams = teams_by_id.clone();
+        }
+
+        // Partners get the response of their lead, the players are ordered by slot
+        for (slot, mut partner) in self.partners.drain(..) {
+            let index = slot.index();
+            let data = joined[index]
+                .write_to_bytes()
+                .expect("Invalid protobuf message");
+            partner
+                .send_message(TMessage::binary(data))
+                .await
+                .map_err(|e| {
+                    ArenaError::Lobby(format!(
+                        "Archon partner of {:?} disconnected while joining: {}",
+                        self.players[index].player_name(),
+                        e
+                    ))
+                })?;
+            self.players[index].partner = Some(partner);
         }
 
         // TODO: Human players?
         // TODO: Observers?
 
-        Some(())
+        Ok(())
+    }
+
+    /// Start the protocol trace of every player, only with `ProtocolTrace`
+    /// A trace that cannot be created is logged, the match is played without it
+    fn open_protocol_traces(&mut self) {
+        let dir = match self.config.protocol_trace() {
+            Some(dir) => dir,
+            None => return,
+        };
+        let match_id = self.config.match_id;
+        let compact = self.config.protocol_trace_compact();
+        for (index, player) in self.players.iter_mut().enumerate() {
+            let path = trace_path(&dir, match_id, index);
+            match ProtocolTrace::create(&path, compact) {
+                Ok(protocol_trace) => player.protocol_trace = Some(protocol_trace),
+                Err(e) => error!("Could not create protocol trace {:?}: {:?}", path, e),
+            }
+        }
+    }
+
+    /// Ping every SC2 process, failing with `VersionMismatch` unless all run the same version
+    pub async fn check_versions(&mut self) -> Result<Sc2Version, ArenaError> {
+        use sc2_proto::sc2api::{Request, RequestPing};
+
+        let mut request = Request::new();
+        request.set_ping(RequestPing::new());
+        let mut versions = Vec::with_capacity(self.players.len());
+        for player in self.players.iter_mut() {
+            let response = player.sc2_query(&request).await.ok_or_else(|| {
+                ArenaError::Lobby(
+                    "SC2 closed the connection while checking its version".to_string(),
+                )
+            })?;
+            if !response.has_ping() {
+                return Err(ArenaError::Protocol(format!(
+                    "Expected a Ping response, got {:?}",
+                    response
+                )));
+            }
+            versions.push(Sc2Version::from_ping(response.ping()));
+        }
+        let version = Sc2Version::common(&versions).map_err(ArenaError::VersionMismatch)?;
+        info!("SC2 version {:?}", version);
+        Ok(version)
     }
 
     /// Start the handler, and send responses to join requests
-    /// Returns None if handler create or join fails (connection close or sc2 process close)
+    /// Fails if the SC2 versions differ, or handler create or join fails (connection close or sc2 process close)
     /// In that case, the connections are dropped (closed).
-    pub async fn start(mut self) -> Option<Game> {
+    pub async fn start(mut self) -> Result<Game, ArenaError> {
+        self.open_protocol_traces();
+        let sc2_version = self.check_versions().await?;
         self.create_game().await?;
         self.join_all_game().await?;
-        Some(Game {
+        Ok(Game {
             config: self.config,
             players: self.players,
+            sc2_version: Some(sc2_version),
+            bot_pids: BotPids::default(),
+            middlewares: Middlewares::default(),
         })
     }
 
     /// Destroy the lobby, closing all the connections
-    pub async fn close(&mut self) {
+    /// Returns the number of SC2 processes killed
+    pub async fn close(&mut self) -> u32 {
+        let mut killed = 0;
+        if let Some(mut process) = self.spare_process.take() {
+            process.kill();
+            killed += 1;
+        }
         while let Some(handle) = self.player_handles.pop() {
-            if let Ok(mut p) = handle.await {
-                p.process.kill()
+            if let Ok(Ok(mut p)) = handle.await {
+                p.process.kill();
+                killed += 1;
             }
         }
+        for p in self.players.iter_mut() {
+            p.process.kill();
+            killed += 1;
+        }
+        for (_, mut partner) in self.partners.drain(..) {
+            partner.shutdown().await.ok();
+        }
+        killed
     }
 }
 
+/// Check that a bot joins as the race it is configured to play, if one is configured
+fn check_race(join_req: &RequestJoinGame, expected: Option<Race>) -> Result<(), String> {
+    let expected = match expected {
+        Some(expected) => expected,
+        None => return Ok(()),
+    };
+    if Race::from_proto(join_req.race()) == Some(expected) {
+        Ok(())
+    } else {
+        Err(format!(
+            "Joined as {:?}, the match is configured for {:?}",
+            join_req.race(),
+            expected
+        ))
+    }
+}
+
+/// Answer a join request that cannot be accepted and close the connection
+async fn reject_join(mut connection: Client, reason: &str) {
+    error!("Rejecting join of {:?}: {}", connection.peer_addr(), reason);
+    let mut join = ResponseJoinGame::new();
+    join.set_error(response_join_game::Error::OtherError);
+    join.set_error_details(reason.to_string());
+    let mut response = Response::new();
+    response.set_join_game(join);
+    response.error.push(reason.to_string());
+    let data = response.write_to_bytes().expect("Invalid protobuf message");
+    connection.stream.send(TMessage::binary(data)).await.ok();
+    connection.shutdown().await.ok();
+}
+
 /// Used to pass player setup info to CreateGame
 #[allow(dead_code)]
 #[derive(Clone, Copy)]
 enum CreateGamePlayer {
     Participant,
+    Computer(Race, Difficulty),
     Observer,
 }
 impl CreateGamePlayer {
@@ -230,6 +560,11 @@ impl CreateGamePlayer {
             Self::Participant => {
                 ps.type_ = Some(EnumOrUnknown::new(PlayerType::Participant));
             }
+            Self::Computer(race, difficulty) => {
+                ps.type_ = Some(EnumOrUnknown::new(PlayerType::Computer));
+                ps.set_race(race.to_proto());
+                ps.set_difficulty(difficulty.to_proto());
+            }
             Self::Observer => {
                 ps.type_ = Some(EnumOrUnknown::new(PlayerType::Observer));
             }
@@ -238,8 +573,72 @@ impl CreateGamePlayer {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum PlayerNum {
     One,
     Two,
+    Three,
+    Four,
+}
+impl PlayerNum {
+    /// Slot of the player, counted from 0
+    pub fn index(self) -> usize {
+        match self {
+            Self::One => 0,
+            Self::Two => 1,
+            Self::Three => 2,
+            Self::Four => 3,
+        }
+    }
+    pub fn from_index(index: usize) -> Option<Self> {
+        match index {
+            0 => Some(Self::One),
+            1 => Some(Self::Two),
+            2 => Some(Self::Three),
+            3 => Some(Self::Four),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_race() {
+        let mut join_req = RequestJoinGame::new();
+        join_req.set_race(sc2_proto::common::Race::Zerg);
+        assert!(check_race(&join_req, None).is_ok());
+        assert!(check_race(&join_req, Some(Race::Zerg)).is_ok());
+        assert!(check_race(&join_req, Some(Race::Random)).is_err());
+        join_req.set_race(sc2_proto::common::Race::NoRace);
+        assert!(check_race(&join_req, Some(Race::Terran)).is_err());
+    }
+
+    #[test]
+    fn test_proto_create_game() {
+        let config = Config {
+            battlenet_map_name: Some("Equilibrium LE".to_string()),
+            random_seed: Some(42),
+            disable_fog: true,
+            ..Default::default()
+        };
+        let lobby = GameLobby::new(config);
+        let request = lobby
+            .proto_create_game(vec![CreateGamePlayer::Participant])
+            .unwrap();
+        let create_game = request.create_game();
+        assert_eq!(create_game.random_seed(), 42);
+        assert!(create_game.disable_fog());
+
+        let lobby = GameLobby::new(Config {
+            battlenet_map_name: Some("Equilibrium LE".to_string()),
+            ..Default::default()
+        });
+        let request = lobby
+            .proto_create_game(vec![CreateGamePlayer::Participant])
+            .unwrap();
+        assert!(!request.create_game().has_random_seed());
+    }
 }
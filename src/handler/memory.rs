@@ -0,0 +1,204 @@
+//! Sampling the memory of the bots and SC2 processes of a running game
+//!
+//! The bot launcher registers each bot it starts with `BotPid <Player> <pid>`.
+//! While the game runs, the bot process tree, and SC2 with `SampleSc2Memory`, is sampled
+//! every `SAMPLE_INTERVAL`. A player over its `BotMemoryMb`, or `MemoryMb` for SC2,
+//! loses with `ResourceLimit`.
+//!
+//! The CPU time of the bot process tree and of SC2 is sampled at the same interval.
+//! SC2 processes can come from the warm pool, so only the time since the game started counts.
+//! The processes are killed before the game ends, so up to one interval is missing.
+
+use crate::limits::{
+    kill_tree, tree_cpu_seconds, tree_rss_bytes, CpuTime, MemoryPeak, ResourceLimits,
+};
+use log::{debug, error};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Time between two samples of a running game
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// PIDs of the bots launched for a match, by player name
+pub type BotPids = Arc<Mutex<HashMap<String, u32>>>;
+
+/// Player and PID of a `BotPid <Player> <pid>` command, None if `data` is another message
+pub fn parse_bot_pid(data: &str) -> Option<(String, u32)> {
+    let (name, pid) = data.strip_prefix("BotPid ")?.trim().rsplit_once(' ')?;
+    let name = name.trim();
+    if name.is_empty() {
+        return None;
+    }
+    Some((name.to_string(), pid.parse().ok()?))
+}
+
+/// Process of a player that went over its limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Offender {
+    Bot,
+    #[allow(clippy::upper_case_acronyms)]
+    SC2,
+}
+
+/// Player of a game as seen by the monitor
+#[derive(Debug, Clone)]
+pub struct Watched {
+    pub name: String,
+    pub sc2_pid: Option<u32>,
+    pub limits: ResourceLimits,
+}
+
+/// Samples the processes of each player, keeping the peaks and the CPU time
+#[derive(Debug)]
+pub struct MemoryMonitor {
+    players: Vec<Watched>,
+    bot_pids: BotPids,
+    peaks: Vec<MemoryPeak>,
+    cpu: Vec<CpuTime>,
+    /// CPU time SC2 used before the game
+    sc2_cpu_baseline: Vec<Option<f64>>,
+}
+impl MemoryMonitor {
+    pub fn new(players: Vec<Watched>, bot_pids: BotPids) -> Self {
+        let peaks = vec![MemoryPeak::default(); players.len()];
+        let cpu = vec![CpuTime::default(); players.len()];
+        let sc2_cpu_baseline = players
+            .iter()
+            .map(|p| p.sc2_pid.and_then(tree_cpu_seconds))
+            .collect();
+        Self {
+            players,
+            bot_pids,
+            peaks,
+            cpu,
+            sc2_cpu_baseline,
+        }
+    }
+
+    fn bot_pid(&self, index: usize) -> Option<u32> {
+        let name = &self.players[index].name;
+        self.bot_pids.lock().unwrap().get(name).copied()
+    }
+
+    /// Record a sample of `bytes`, returning whether it is over the limit
+    fn record(&mut self, index: usize, offender: Offender, bytes: u64) -> bool {
+        let limits = &self.players[index].limits;
+        let (peak, limit) = match offender {
+            Offender::Bot => (&mut self.peaks[index].bot_mb, limits.bot_memory_bytes()),
+            Offender::SC2 => (&mut self.peaks[index].sc2_mb, limits.memory_bytes()),
+        };
+        let mb = bytes / (1024 * 1024);
+        *peak = Some(peak.map_or(mb, |peak| peak.max(mb)));
+        limit.map_or(false, |limit| bytes > limit)
+    }
+
+    /// Record the CPU time of the processes of player `index` that are still running
+    fn sample_cpu(&mut self, index: usize) {
+        if let Some(seconds) = self.bot_pid(index).and_then(tree_cpu_seconds) {
+            self.cpu[index].bot_seconds = Some(seconds);
+        }
+        if let Some(seconds) = self.players[index].sc2_pid.and_then(tree_cpu_seconds) {
+            let baseline = self.sc2_cpu_baseline[index].unwrap_or_default();
+            self.cpu[index].sc2_seconds = Some((seconds - baseline).max(0.0));
+        }
+    }
+
+    /// Sample every process, returning the first player over its limit
+    pub fn sample(&mut self) -> Option<(usize, Offender)> {
+        for index in 0..self.players.len() {
+            self.sample_cpu(index);
+            let bot = self.bot_pid(index).and_then(tree_rss_bytes);
+            let sc2 = match self.players[index].sc2_pid {
+                Some(pid) if self.players[index].limits.sample_sc2_memory => tree_rss_bytes(pid),
+                _ => None,
+            };
+            for (offender, bytes) in [(Offender::Bot, bot), (Offender::SC2, sc2)] {
+                if let Some(bytes) = bytes {
+                    if self.record(index, offender, bytes) {
+                        error!(
+                            "{}: {:?} uses {} MiB, more than its limit",
+                            self.players[index].name,
+                            offender,
+                            bytes / (1024 * 1024)
+                        );
+                        return Some((index, offender));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Kill the bot of player `index`, if it was registered
+    pub fn kill_bot(&self, index: usize) {
+        if let Some(pid) = self.bot_pid(index) {
+            debug!("{}: Killing bot process {}", self.players[index].name, pid);
+            kill_tree(pid);
+        }
+    }
+
+    /// CPU time of each player, in player order
+    pub fn cpu_time(&self) -> Vec<CpuTime> {
+        self.cpu.clone()
+    }
+
+    /// Peak memory of each player, in player order
+    pub fn peaks(self) -> Vec<MemoryPeak> {
+        self.peaks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bot_pid() {
+        assert_eq!(
+            parse_bot_pid("BotPid basic_bot 4242"),
+            Some(("basic_bot".to_string(), 4242))
+        );
+        assert_eq!(
+            parse_bot_pid("BotPid My Bot 17"),
+            Some(("My Bot".to_string(), 17))
+        );
+        assert_eq!(parse_bot_pid("BotPid 17"), None);
+        assert_eq!(parse_bot_pid("BotPid basic_bot x"), None);
+        assert_eq!(parse_bot_pid("{\"Map\": \"BotPid\"}"), None);
+    }
+
+    #[test]
+    fn test_record() {
+        let limits = ResourceLimits {
+            memory_mb: 1024,
+            bot_memory_mb: 256,
+            ..Default::default()
+        };
+        let players = vec![
+            Watched {
+                name: "Bot1".to_string(),
+                sc2_pid: None,
+                limits,
+            },
+            Watched {
+                name: "Bot2".to_string(),
+                sc2_pid: None,
+                limits: ResourceLimits::default(),
+            },
+        ];
+        let mut monitor = MemoryMonitor::new(players, BotPids::default());
+        let mib = 1024 * 1024;
+        assert!(!monitor.record(0, Offender::Bot, 200 * mib));
+        assert!(!monitor.record(0, Offender::Bot, 100 * mib));
+        assert!(monitor.record(0, Offender::Bot, 300 * mib));
+        assert!(!monitor.record(0, Offender::SC2, 900 * mib));
+        assert!(!monitor.record(1, Offender::Bot, 4096 * mib));
+        assert_eq!(monitor.sample(), None);
+        let peaks = monitor.peaks();
+        assert_eq!(peaks[0].bot_mb, Some(300));
+        assert_eq!(peaks[0].sc2_mb, Some(900));
+        assert_eq!(peaks[1].bot_mb, Some(4096));
+        assert_eq!(peaks[1].sc2_mb, None);
+    }
+}
@@ -1,16 +1,31 @@
 #![allow(dead_code)]
 
 use crossbeam::channel::{self, Receiver, Sender, TryRecvError};
+use log::debug;
+use std::sync::Arc;
+use tokio::sync::Notify;
 
+use crate::channels::GAME_CAPACITY;
+use crate::errors::ArenaError;
 use crate::sc2::PlayerResult;
 
+use super::progress::GameProgress;
+use super::tags::NewTags;
+
 /// Request from the supervisor
 pub enum FromSupervisor {
     Quit,
+    /// Let the players save their replays and end the game, for a graceful shutdown
+    Drain,
 }
 
-/// Response to the supervisor
-pub enum ToSupervisor {}
+/// Telemetry for the supervisor, dropped oldest first when the controller falls behind
+pub enum ToSupervisor {
+    /// Sent every `ProgressInterval` game loops
+    Progress(GameProgress),
+    /// Tags a bot sent, with `LiveTags`
+    NewTags(NewTags),
+}
 
 /// Create one receiver for the handler, send connections to players,
 /// and corresponding two-way connections to players
@@ -20,16 +35,21 @@ pub fn create_channels(
     let mut to_player_channels = Vec::new();
     let mut to_game_channels = Vec::new();
 
-    let (tx_to_game, rx_game) = channel::unbounded();
+    let (tx_to_game, rx_game) = channel::bounded(GAME_CAPACITY);
     for player_index in 0..count {
-        let (tx, rx) = channel::unbounded();
+        let (tx, rx) = channel::bounded(GAME_CAPACITY);
+        let wake = Arc::new(Notify::new());
 
-        to_player_channels.push(ChannelToPlayer { tx });
+        to_player_channels.push(ChannelToPlayer {
+            tx,
+            wake: wake.clone(),
+        });
 
         to_game_channels.push(ChannelToGame {
             player_index,
             tx: tx_to_game.clone(),
             rx,
+            wake,
         });
     }
 
@@ -41,26 +61,41 @@ pub struct ChannelToGame {
     player_index: usize,
     tx: Sender<ToGame>,
     rx: Receiver<ToPlayer>,
+    /// Notified for every message from the handler
+    wake: Arc<Notify>,
 }
 impl ChannelToGame {
-    /// Sends a message to the handler
-    pub fn send(&mut self, content: ToGameContent) {
+    /// Sends a message to the handler, fails if the handler already ended
+    pub fn send(&mut self, content: ToGameContent) -> Result<(), ArenaError> {
         self.tx
             .send(ToGame {
                 player_index: self.player_index,
                 content,
             })
-            .expect("Unable to send to the handler");
+            .map_err(|_| self.handler_ended())
     }
 
     /// Receives message from handler, nonblocking: None if not available
-    pub fn recv(&mut self) -> Option<ToPlayer> {
+    /// Fails if the handler already ended
+    pub fn recv(&mut self) -> Result<Option<ToPlayer>, ArenaError> {
         match self.rx.try_recv() {
-            Ok(msg) => Some(msg),
-            Err(TryRecvError::Empty) => None,
-            Err(TryRecvError::Disconnected) => panic!("Disconnected"),
+            Ok(msg) => Ok(Some(msg)),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(self.handler_ended()),
         }
     }
+
+    fn handler_ended(&self) -> ArenaError {
+        ArenaError::Game(format!(
+            "Handler of player {} ended before the player",
+            self.player_index + 1
+        ))
+    }
+
+    /// Wait until the handler sent a message, e.g. to interrupt a player waiting for its bot
+    pub async fn notified(&self) {
+        self.wake.notified().await
+    }
 }
 
 /// Message from a player to the handler
@@ -82,6 +117,12 @@ pub enum ToGameContent {
     /// SC2 unexpectedly closed connection, usually user clicking the window close button
     #[allow(clippy::upper_case_acronyms)]
     SC2UnexpectedConnectionClose,
+    /// SC2 was killed for exceeding the resource limits of the player
+    ResourceLimit,
+    /// Client exceeded the frame budget more often than its strikes allow
+    Timeout,
+    /// Client committed a violation listed in `ForfeitViolations`
+    Forfeit,
     /// Client unexpectedly closed connection
     UnexpectedConnectionClose,
 }
@@ -96,19 +137,22 @@ pub struct GameOver {
 #[derive(Clone)]
 pub struct ChannelToPlayer {
     tx: Sender<ToPlayer>,
+    wake: Arc<Notify>,
 }
 impl ChannelToPlayer {
-    /// Sends a message to the player
+    /// Sends a message to the player, waking it if it waits for its bot
+    /// The player might already have finished, which is not an error
     pub fn send(&mut self, content: ToPlayer) {
-        self.tx
-            .send(content)
-            .expect("Unable to send to the handler");
+        if self.tx.send(content).is_err() {
+            debug!("Player already finished");
+        }
+        self.wake.notify_one();
     }
 }
 
-/// Message from a player to the handler
+/// Message from the handler to a player
 #[derive(Debug, Clone)]
 pub enum ToPlayer {
-    /// Game over, kill the client
+    /// Game over, save the replay and kill the client
     Quit,
 }
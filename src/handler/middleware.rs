@@ -0,0 +1,339 @@
+//! Middlewares that see every request of a bot and every response of SC2 in `Player::run`
+//!
+//! Blocking debug requests, hiding the race of random opponents and extracting tags from chat
+//! are built-in middlewares. Library users register their own with `Middlewares::add`, e.g.
+//! to log or rewrite messages; they run after the built-in ones, in the order they were
+//! added. Transparent mode does not parse messages and runs no middlewares, and requests of
+//! Archon partners do not pass through them.
+
+use log::error;
+use sc2_proto::raw::Alliance;
+use sc2_proto::sc2api::{Request, Response, Status};
+use std::fmt;
+use std::sync::Arc;
+
+use super::tags::TAG_PREFIX;
+use crate::config::{Config, DebugRequestPolicy, RaceMasking, Violation};
+use crate::proxy::ClientType;
+
+/// Error of debug requests answered without reaching SC2, unless `DebugRequestPolicy` is `Silent`
+pub const DEBUG_DENIED: &str = "Proxy: Request denied";
+
+/// What the proxy does with a request after a middleware saw it
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    /// Pass the request, as it is now, to the next middleware and then to SC2
+    Forward,
+    /// Answer the bot with the response instead of forwarding the request
+    Respond(Box<Response>),
+    /// Count the violation, then answer with the response unless the bot forfeits for it
+    Violation(Violation, Box<Response>),
+    /// Count the violation and end the game for the bot, which forfeits
+    Forfeit(Violation),
+}
+
+/// The player a message belongs to, and what middlewares report back to it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MessageContext {
+    pub player_id: Option<u32>,
+    /// Game loop of the last observation of the bot
+    pub game_loop: u32,
+    /// Set by a middleware that changed the message, so the proxy serializes it again
+    pub modified: bool,
+    /// Tags of the bot found in the request, kept within `MaxTags` and `MaxTagLength`
+    pub tags: Vec<String>,
+}
+impl MessageContext {
+    pub fn new(player_id: Option<u32>, game_loop: u32) -> Self {
+        Self {
+            player_id,
+            game_loop,
+            ..Self::default()
+        }
+    }
+}
+
+/// Sees the messages of one bot during a game
+pub trait ProxyMiddleware: Send {
+    /// Called for every request of the bot, before it is forwarded to SC2
+    fn on_request(&mut self, _context: &mut MessageContext, _request: &mut Request) -> Action {
+        Action::Forward
+    }
+    /// Called for every response of SC2, before it is sent to the bot
+    fn on_response(&mut self, _context: &mut MessageContext, _response: &mut Response) {}
+}
+
+/// Creates the middleware of the player with the given index for each game
+pub type MiddlewareFactory = Arc<dyn Fn(usize) -> Box<dyn ProxyMiddleware> + Send + Sync>;
+
+/// Middlewares registered by library users
+#[derive(Clone, Default)]
+pub struct Middlewares(Vec<MiddlewareFactory>);
+impl Middlewares {
+    /// Run a new middleware from `factory` for every player of every later game
+    pub fn add(&mut self, factory: MiddlewareFactory) {
+        self.0.push(factory);
+    }
+    /// Built-in and registered middlewares of player `index`, in the order they run
+    pub fn for_player(&self, config: &Config, index: usize) -> Vec<Box<dyn ProxyMiddleware>> {
+        let mut middlewares: Vec<Box<dyn ProxyMiddleware>> = Vec::new();
+        if config.disable_debug() {
+            middlewares.push(Box::new(DebugBlocker::from_config(config)));
+        }
+        if config.race_masking() != RaceMasking::Never {
+            middlewares.push(Box::new(RaceHider::for_connection(config, ClientType::Bot)));
+        }
+        middlewares.push(Box::new(TagExtractor));
+        middlewares.extend(self.0.iter().map(|factory| factory(index)));
+        middlewares
+    }
+}
+impl fmt::Debug for Middlewares {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Middlewares({})", self.0.len())
+    }
+}
+
+/// Answers debug requests without forwarding them, with `DisableDebug`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebugBlocker {
+    policy: DebugRequestPolicy,
+    max_violations: u32,
+    blocked: u32,
+}
+impl DebugBlocker {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            policy: config.debug_request_policy(),
+            max_violations: config.max_debug_violations(),
+            blocked: 0,
+        }
+    }
+}
+impl ProxyMiddleware for DebugBlocker {
+    fn on_request(&mut self, context: &mut MessageContext, request: &mut Request) -> Action {
+        if !request.has_debug() {
+            return Action::Forward;
+        }
+        self.blocked += 1;
+        if self.policy == DebugRequestPolicy::Kick && self.blocked >= self.max_violations {
+            error!(
+                "{:?}: Kicked after {} debug requests",
+                context.player_id, self.blocked
+            );
+            return Action::Forfeit(Violation::DebugBlocked);
+        }
+        let mut response = Response::new();
+        response.set_id(request.id());
+        response.set_status(Status::in_game);
+        if self.policy != DebugRequestPolicy::Silent {
+            response.error.push(DEBUG_DENIED.to_string());
+        }
+        Action::Violation(Violation::DebugBlocked, Box::new(response))
+    }
+}
+
+/// Hides the actual race of random opponents in `GameInfo` responses, see `RaceMasking`
+/// Observers have no player of their own, the race of every player is hidden from them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RaceHider {
+    masking: RaceMasking,
+    /// The bot saw an enemy unit, ending `UntilScouted`
+    enemy_seen: bool,
+}
+impl RaceHider {
+    pub fn new(masking: RaceMasking) -> Self {
+        Self {
+            masking,
+            enemy_seen: false,
+        }
+    }
+    /// Masking of the kind of connection the responses go to, `RaceMasking` for bots and
+    /// `ObserverRaceMasking` for observers; supervisors never see `GameInfo` responses
+    pub fn for_connection(config: &Config, connection: ClientType) -> Self {
+        Self::new(match connection {
+            ClientType::Bot => config.race_masking(),
+            ClientType::Observer => config.observer_race_masking(),
+            ClientType::Controller => RaceMasking::Never,
+        })
+    }
+    fn masks(&self) -> bool {
+        match self.masking {
+            RaceMasking::Always => true,
+            RaceMasking::UntilScouted => !self.enemy_seen,
+            RaceMasking::Never => false,
+        }
+    }
+}
+impl ProxyMiddleware for RaceHider {
+    fn on_response(&mut self, context: &mut MessageContext, response: &mut Response) {
+        if response.has_observation() {
+            self.enemy_seen |= response
+                .observation()
+                .observation
+                .raw_data
+                .units
+                .iter()
+                .any(|unit| unit.alliance() == Alliance::Enemy);
+        }
+        if response.has_game_info() && self.masks() {
+            for pi in response.mut_game_info().player_info.iter_mut() {
+                if Some(pi.player_id()) != context.player_id {
+                    pi.race_actual = pi.race_requested;
+                }
+            }
+            context.modified = true;
+        }
+    }
+}
+
+/// Collects the tags bots send as `Tag:<tag>` chat messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TagExtractor;
+impl ProxyMiddleware for TagExtractor {
+    fn on_request(&mut self, context: &mut MessageContext, request: &mut Request) -> Action {
+        for action in &request.action().actions {
+            if let Some(tag) = action.action_chat.message().strip_prefix(TAG_PREFIX) {
+                context.tags.push(tag.to_string());
+            }
+        }
+        Action::Forward
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sc2_proto::sc2api::{
+        Action as Sc2Action, ActionChat, PlayerInfo, RequestAction, RequestDebug, ResponseGameInfo,
+    };
+
+    #[test]
+    fn test_debug_blocker() {
+        let config = Config {
+            disable_debug: true,
+            debug_request_policy: Some("Kick".to_string()),
+            max_debug_violations: 2,
+            ..Default::default()
+        };
+        let mut blocker = DebugBlocker::from_config(&config);
+        let mut context = MessageContext::new(Some(1), 0);
+        let mut request = Request::new();
+        assert_eq!(
+            blocker.on_request(&mut context, &mut request),
+            Action::Forward
+        );
+        request.set_id(3);
+        request.set_debug(RequestDebug::new());
+        match blocker.on_request(&mut context, &mut request) {
+            Action::Violation(Violation::DebugBlocked, response) => {
+                assert_eq!(response.id(), 3);
+                assert_eq!(response.error, vec![DEBUG_DENIED.to_string()]);
+            }
+            other => panic!("Unexpected {:?}", other),
+        }
+        assert_eq!(
+            blocker.on_request(&mut context, &mut request),
+            Action::Forfeit(Violation::DebugBlocked)
+        );
+    }
+
+    #[test]
+    fn test_race_hider() {
+        let mut hider = RaceHider::new(RaceMasking::Always);
+        let mut context = MessageContext::new(Some(1), 0);
+        let mut game_info = ResponseGameInfo::new();
+        for player_id in 1..=2 {
+            let mut pi = PlayerInfo::new();
+            pi.set_player_id(player_id);
+            pi.set_race_requested(sc2_proto::common::Race::Random);
+            pi.set_race_actual(sc2_proto::common::Race::Zerg);
+            game_info.player_info.push(pi);
+        }
+        let mut response = Response::new();
+        response.set_game_info(game_info);
+        hider.on_response(&mut context, &mut response);
+        assert!(context.modified);
+        let races: Vec<_> = response
+            .game_info()
+            .player_info
+            .iter()
+            .map(|pi| pi.race_actual())
+            .collect();
+        assert_eq!(
+            races,
+            vec![
+                sc2_proto::common::Race::Zerg,
+                sc2_proto::common::Race::Random
+            ]
+        );
+    }
+
+    #[test]
+    fn test_race_hider_by_connection() {
+        let config = Config {
+            observer_race_masking: Some("Always".to_string()),
+            race_masking: Some("Never".to_string()),
+            ..Default::default()
+        };
+        let mut game_info = ResponseGameInfo::new();
+        for player_id in 1..=2 {
+            let mut pi = PlayerInfo::new();
+            pi.set_player_id(player_id);
+            pi.set_race_requested(sc2_proto::common::Race::Random);
+            pi.set_race_actual(sc2_proto::common::Race::Protoss);
+            game_info.player_info.push(pi);
+        }
+        let mut response = Response::new();
+        response.set_game_info(game_info);
+        let races = |response: &Response| -> Vec<_> {
+            response
+                .game_info()
+                .player_info
+                .iter()
+                .map(|pi| pi.race_actual())
+                .collect()
+        };
+
+        let mut bot = RaceHider::for_connection(&config, ClientType::Bot);
+        let mut context = MessageContext::new(Some(1), 0);
+        let mut bot_response = response.clone();
+        bot.on_response(&mut context, &mut bot_response);
+        assert!(!context.modified);
+        assert_eq!(
+            races(&bot_response),
+            vec![sc2_proto::common::Race::Protoss; 2]
+        );
+
+        let mut observer = RaceHider::for_connection(&config, ClientType::Observer);
+        let mut context = MessageContext::new(None, 0);
+        observer.on_response(&mut context, &mut response);
+        assert!(context.modified);
+        assert_eq!(races(&response), vec![sc2_proto::common::Race::Random; 2]);
+        assert_eq!(
+            RaceHider::for_connection(&Config::default(), ClientType::Observer),
+            RaceHider::new(RaceMasking::Never)
+        );
+    }
+
+    #[test]
+    fn test_tag_extractor() {
+        let mut context = MessageContext::new(Some(1), 0);
+        let mut request = Request::new();
+        let mut action = RequestAction::new();
+        for message in ["gl hf", "Tag:proxy_rax"] {
+            let mut chat = ActionChat::new();
+            chat.set_message(message.to_string());
+            let mut sc2_action = Sc2Action::new();
+            sc2_action.action_chat = Some(chat).into();
+            action.actions.push(sc2_action);
+        }
+        request.set_action(action);
+        assert_eq!(
+            TagExtractor.on_request(&mut context, &mut request),
+            Action::Forward
+        );
+        assert_eq!(context.tags, vec!["proxy_rax".to_string()]);
+        assert!(!context.modified);
+    }
+}
@@ -1,20 +1,78 @@
 //! Games run in their own threads,
 //! which in turn run own thread for each client
 
+mod archon;
+mod budget;
+mod chat;
+mod disconnect;
 mod game;
+mod interface;
+mod light;
 mod lobby;
+mod memory;
 mod messaging;
+pub mod middleware;
+mod obs_sizes;
+mod ordering;
+mod pacing;
 pub mod player;
+pub mod policy;
+mod progress;
+mod realtime;
+mod replay;
+mod snapshot;
+mod sniffer;
+mod stats;
+mod tags;
+mod trace;
+mod unknown_fields;
+mod version;
 
+use crate::channels::{
+    telemetry, ChannelDepth, TelemetryReceiver, GAME_CAPACITY, TELEMETRY_CAPACITY,
+};
+use crate::errors::ArenaError;
+use crate::limits::kill_tree;
 use crossbeam::channel::{self, Receiver, Sender, TryRecvError};
+use log::{error, warn};
 use std::any::Any;
+use std::fmt;
+use std::time::Duration;
 use tokio::runtime::Runtime;
+use tokio::time::timeout;
 
 use self::player::Player;
 
-pub use self::game::{Game, GameResult};
+pub use self::chat::ChatLine;
+pub use self::disconnect::{Disconnect, Peer};
+pub use self::game::{Game, GameEndReason, GameResult};
 pub use self::lobby::{GameLobby, PlayerNum};
+pub use self::memory::{parse_bot_pid, BotPids};
 pub use self::messaging::{FromSupervisor, ToSupervisor};
+pub use self::obs_sizes::SizeWindow;
+pub use self::ordering::OutOfOrder;
+pub use self::realtime::LagReport;
+pub use self::snapshot::StateSnapshot;
+pub use self::stats::PlayerStats;
+pub use self::unknown_fields::UnknownFieldCounts;
+pub use self::version::Sc2Version;
+
+/// Longest wait for the handler thread to finish after it sent its result
+pub const JOIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Warnings logged per bot about a repeated problem, later ones are only counted
+pub(crate) const WARNING_LIMIT: u32 = 5;
+
+/// Count one more occurrence of a problem in `counter`, logging `message` for the first `limit`
+/// The last one logged says that later ones are only counted
+pub(crate) fn warn_limited(counter: &mut u32, limit: u32, message: fmt::Arguments) {
+    *counter += 1;
+    if *counter < limit {
+        warn!("{}", message);
+    } else if *counter == limit {
+        warn!("{}, later ones are only counted", message);
+    }
+}
 
 fn any_panic_to_string(panic_msg: Box<dyn Any>) -> String {
     panic_msg
@@ -26,16 +84,18 @@ fn any_panic_to_string(panic_msg: Box<dyn Any>) -> String {
 /// Game thread handle
 pub struct Handle {
     /// Handle for the handler thread
-    handle: std::thread::JoinHandle<Vec<Player>>,
+    handle: std::thread::JoinHandle<Result<Vec<Player>, ArenaError>>,
     /// Result connection receiver
     result_rx: Receiver<GameResult>,
     /// Message connection sender
     msg_tx: Sender<FromSupervisor>,
-    /// Message connection receiver
-    _msg_rx: Receiver<ToSupervisor>,
+    /// Telemetry connection receiver
+    telemetry_rx: TelemetryReceiver<ToSupervisor>,
     /// Result or error, if the handler is over
     /// Updated by `poll`
     result: Option<Result<GameResult, ()>>,
+    /// SC2 processes of the game, killed if the handler thread hangs
+    sc2_pids: Vec<u32>,
 }
 impl Handle {
     /// Send message to the handler
@@ -44,6 +104,21 @@ impl Handle {
         self.msg_tx.send(msg).expect("Could not send");
     }
 
+    /// Send message to the handler, if it is still running
+    pub fn try_send(&mut self, msg: FromSupervisor) -> bool {
+        self.msg_tx.send(msg).is_ok()
+    }
+
+    /// Next telemetry message of the handler, None if there is none
+    pub fn try_recv_telemetry(&mut self) -> Option<ToSupervisor> {
+        self.telemetry_rx.try_recv()
+    }
+
+    /// Fill level of the telemetry channel
+    pub fn telemetry_depth(&self) -> ChannelDepth {
+        self.telemetry_rx.depth()
+    }
+
     /// Checks if the handler is over
     pub fn check(&mut self) -> bool {
         match self.result_rx.try_recv() {
@@ -59,35 +134,93 @@ impl Handle {
         }
     }
 
-    /// Read result after the handler is over, and clean up the handler
-    /// Also returns the handler result and a list of non-disconnected players
-    /// Panics if handler is still running, i.e. `update` hasn't returned true yet
-    pub async fn collect_result(self) -> Result<(GameResult, Vec<Player>), String> {
-        if let Some(r) = self.result {
-            match r {
-                Ok(result) => {
-                    let players = self
-                        .handle
-                        .join()
-                        .expect("Game crashed after sending a result");
-                    Ok((result, players))
+    /// Join the handler thread, waiting at most `JOIN_TIMEOUT`
+    /// If it does not finish in time, its SC2 processes are killed and None is returned
+    async fn join(self) -> Option<std::thread::Result<Result<Vec<Player>, ArenaError>>> {
+        let handle = self.handle;
+        match timeout(
+            JOIN_TIMEOUT,
+            tokio::task::spawn_blocking(move || handle.join()),
+        )
+        .await
+        {
+            Ok(joined) => Some(joined.expect("Join task failed")),
+            Err(_) => {
+                error!(
+                    "Game thread did not finish within {:?}, killing SC2 processes {:?}",
+                    JOIN_TIMEOUT, self.sc2_pids
+                );
+                for pid in &self.sc2_pids {
+                    kill_tree(*pid);
                 }
-                Err(()) => match self.handle.join() {
-                    Ok(_) => panic!("Game dropped result channel before ending"),
-                    Err(panic_msg) => Err(any_panic_to_string(panic_msg)),
-                },
+                None
+            }
+        }
+    }
+
+    /// Ask the handler to quit and join its thread, see `join`
+    pub async fn quit(mut self) {
+        self.try_send(FromSupervisor::Quit);
+        self.join().await;
+    }
+
+    /// Kill the SC2 processes of a handler that did not end in time and join its thread,
+    /// returning the number of processes killed
+    pub async fn kill(self) -> u32 {
+        for pid in &self.sc2_pids {
+            kill_tree(*pid);
+        }
+        let killed = self.sc2_pids.len() as u32;
+        self.join().await;
+        killed
+    }
+
+    /// Read result after the handler is over, and clean up the handler
+    /// Also returns the handler result and a list of non-disconnected players,
+    /// None if the handler thread did not finish within `JOIN_TIMEOUT` or failed after its result
+    /// Fails if the handler ended without a result, or is still running, i.e. `check` hasn't returned true yet
+    pub async fn collect_result(mut self) -> Result<(GameResult, Option<Vec<Player>>), ArenaError> {
+        match self.result.take() {
+            Some(Ok(result)) => {
+                let players = match self.join().await {
+                    Some(Ok(Ok(players))) => Some(players),
+                    Some(Ok(Err(e))) => {
+                        error!("Game failed after sending a result: {}", e);
+                        None
+                    }
+                    Some(Err(panic_msg)) => {
+                        error!(
+                            "Game crashed after sending a result: {}",
+                            any_panic_to_string(panic_msg)
+                        );
+                        None
+                    }
+                    None => None,
+                };
+                Ok((result, players))
             }
-        } else {
-            panic!("Game still running");
+            Some(Err(())) => match self.join().await {
+                Some(Ok(Ok(_))) => Err(ArenaError::Game(
+                    "Handler dropped its result channel before ending".to_string(),
+                )),
+                Some(Ok(Err(e))) => Err(e),
+                Some(Err(panic_msg)) => Err(ArenaError::Game(any_panic_to_string(panic_msg))),
+                None => Err(ArenaError::Game(
+                    "Handler thread did not finish after dropping its result".to_string(),
+                )),
+            },
+            None => Err(ArenaError::Game("Handler is still running".to_string())),
         }
     }
 }
 
 /// Run handler in a thread, returning handle
 pub fn spawn_game(game: Game) -> Handle {
-    let (result_tx, result_rx) = channel::unbounded::<GameResult>();
-    let (fr_msg_tx, fr_msg_rx) = channel::unbounded::<FromSupervisor>();
-    let (to_msg_tx, to_msg_rx) = channel::unbounded::<ToSupervisor>();
+    // A game sends exactly one result
+    let (result_tx, result_rx) = channel::bounded::<GameResult>(1);
+    let (fr_msg_tx, fr_msg_rx) = channel::bounded::<FromSupervisor>(GAME_CAPACITY);
+    let (to_msg_tx, to_msg_rx) = telemetry::<ToSupervisor>(TELEMETRY_CAPACITY);
+    let sc2_pids = game.sc2_pids();
 
     let handle = std::thread::spawn(move || {
         let rt = Runtime::new().unwrap();
@@ -98,7 +231,8 @@ pub fn spawn_game(game: Game) -> Handle {
         handle,
         result_rx,
         msg_tx: fr_msg_tx,
-        _msg_rx: to_msg_rx,
+        telemetry_rx: to_msg_rx,
         result: None,
+        sc2_pids,
     }
 }
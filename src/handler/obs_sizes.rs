@@ -0,0 +1,95 @@
+//! Size of the observations sent to each bot over the game
+//!
+//! Bots that request huge raw observations, e.g. with many units in view, spend their frame
+//! budget parsing them. The sizes are summarized per window of game loops for the result.
+
+#[cfg(feature = "supervisor")]
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Game loops summarized by a window
+const WINDOW_LOOPS: u32 = 1000;
+
+/// Observations sent to a bot within `WINDOW_LOOPS` game loops
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "supervisor", derive(JsonSchema))]
+pub struct SizeWindow {
+    /// First game loop of the window
+    #[serde(rename = "StartLoop")]
+    pub start_loop: u32,
+    #[serde(rename = "Observations")]
+    pub observations: u32,
+    #[serde(rename = "TotalBytes")]
+    pub total_bytes: u64,
+    /// Largest observation of the window
+    #[serde(rename = "MaxBytes")]
+    pub max_bytes: u32,
+}
+
+/// Observation sizes of one player, a window for every `WINDOW_LOOPS` game loops it observed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ObservationSizes {
+    pub windows: Vec<SizeWindow>,
+}
+impl ObservationSizes {
+    pub fn is_empty(&self) -> bool {
+        self.windows.is_empty()
+    }
+    /// Record an observation of `bytes` at `game_loop`
+    pub fn record(&mut self, game_loop: u32, bytes: usize) {
+        let start_loop = game_loop - game_loop % WINDOW_LOOPS;
+        let bytes = bytes as u32;
+        match self.windows.last_mut() {
+            Some(window) if window.start_loop == start_loop => {
+                window.observations += 1;
+                window.total_bytes += bytes as u64;
+                window.max_bytes = window.max_bytes.max(bytes);
+            }
+            _ => self.windows.push(SizeWindow {
+                start_loop,
+                observations: 1,
+                total_bytes: bytes as u64,
+                max_bytes: bytes,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let mut sizes = ObservationSizes::default();
+        assert!(sizes.is_empty());
+        sizes.record(0, 2000);
+        sizes.record(16, 3000);
+        sizes.record(999, 1000);
+        sizes.record(1000, 50_000);
+        sizes.record(3500, 60_000);
+        assert_eq!(
+            sizes.windows,
+            vec![
+                SizeWindow {
+                    start_loop: 0,
+                    observations: 3,
+                    total_bytes: 6000,
+                    max_bytes: 3000,
+                },
+                SizeWindow {
+                    start_loop: 1000,
+                    observations: 1,
+                    total_bytes: 50_000,
+                    max_bytes: 50_000,
+                },
+                SizeWindow {
+                    start_loop: 3000,
+                    observations: 1,
+                    total_bytes: 60_000,
+                    max_bytes: 60_000,
+                },
+            ]
+        );
+    }
+}
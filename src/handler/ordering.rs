@@ -0,0 +1,105 @@
+//! Order of the requests of a bot in step mode
+//!
+//! The canonical loop of a bot is observation, actions, step. Frameworks that act or step
+//! again without observing the stepped game send actions against a state SC2 already left,
+//! which triggers SC2 edge cases that end up as desync reports. With `RequestOrdering` such
+//! requests are counted per bot, and with `Enforce` the proxy observes for the bot first.
+
+use sc2_proto::sc2api::Request;
+#[cfg(feature = "supervisor")]
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use super::{warn_limited, WARNING_LIMIT};
+
+/// Request of a bot that broke the canonical order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "supervisor", derive(JsonSchema))]
+pub enum OutOfOrder {
+    /// Actions after a step, before observing its result
+    ActionBeforeObservation,
+    /// A step after a step, without observing in between
+    StepBeforeObservation,
+}
+
+/// Order of the requests of one bot
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OrderingCheck {
+    /// The game was stepped since the last observation
+    stepped: bool,
+    /// Out-of-order requests seen so far
+    pub counts: BTreeMap<OutOfOrder, u32>,
+}
+impl OrderingCheck {
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+    /// Check the next request of the bot, counting it if it is out of order
+    pub fn check(&mut self, player_id: Option<u32>, request: &Request) -> Option<OutOfOrder> {
+        let issue = if request.has_observation() {
+            self.stepped = false;
+            None
+        } else if request.has_action() && self.stepped {
+            Some(OutOfOrder::ActionBeforeObservation)
+        } else if request.has_step() {
+            let stepped = std::mem::replace(&mut self.stepped, true);
+            if stepped {
+                Some(OutOfOrder::StepBeforeObservation)
+            } else {
+                None
+            }
+        } else {
+            None
+        };
+        if let Some(issue) = issue {
+            warn_limited(
+                self.counts.entry(issue).or_default(),
+                WARNING_LIMIT,
+                format_args!("{:?}: Out-of-order request, {:?}", player_id, issue),
+            );
+        }
+        issue
+    }
+    /// The proxy observed for the bot, it acts on the stepped game from now on
+    pub fn observed(&mut self) {
+        self.stepped = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sc2_proto::sc2api::{RequestAction, RequestObservation, RequestStep};
+
+    fn request(kind: &str) -> Request {
+        let mut request = Request::new();
+        match kind {
+            "O" => request.set_observation(RequestObservation::new()),
+            "A" => request.set_action(RequestAction::new()),
+            _ => request.set_step(RequestStep::new()),
+        }
+        request
+    }
+
+    #[test]
+    fn test_check() {
+        let mut check = OrderingCheck::default();
+        for kind in ["O", "A", "S", "O", "S", "O"] {
+            assert_eq!(check.check(Some(1), &request(kind)), None);
+        }
+        assert!(check.is_empty());
+        check.check(Some(1), &request("S"));
+        assert_eq!(
+            check.check(Some(1), &request("A")),
+            Some(OutOfOrder::ActionBeforeObservation)
+        );
+        assert_eq!(
+            check.check(Some(1), &request("S")),
+            Some(OutOfOrder::StepBeforeObservation)
+        );
+        check.observed();
+        assert_eq!(check.check(Some(1), &request("A")), None);
+        assert_eq!(check.counts.len(), 2);
+    }
+}
@@ -0,0 +1,137 @@
+//! Keeping the players of a game on the same game loop
+//!
+//! Every observation a player forwards records its game loop. How far a player got ahead
+//! of the slowest other one is its loop skew, reported in the result. With `Pacing`,
+//! a player that is ahead holds back the observation until the others caught up, or
+//! `PACING_TIMEOUT` passed, so a bot stepping in larger increments cannot race ahead.
+
+use log::warn;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::{timeout_at, Instant};
+
+/// Longest an observation is held back for the other players
+pub const PACING_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Game loop of a player that stopped playing, never waited for
+const FINISHED: u32 = u32::MAX;
+
+#[derive(Debug)]
+struct Shared {
+    loops: Vec<AtomicU32>,
+    skew: Vec<AtomicU32>,
+    caught_up: Notify,
+}
+
+/// Position of one player in the shared game loops
+#[derive(Debug, Clone)]
+pub struct Pacing {
+    shared: Arc<Shared>,
+    index: usize,
+    /// Hold back observations of a player that is ahead
+    wait: bool,
+}
+impl Pacing {
+    /// One handle per player of a game
+    pub fn for_players(count: usize, wait: bool) -> Vec<Self> {
+        let shared = Arc::new(Shared {
+            loops: (0..count).map(|_| AtomicU32::new(0)).collect(),
+            skew: (0..count).map(|_| AtomicU32::new(0)).collect(),
+            caught_up: Notify::new(),
+        });
+        (0..count)
+            .map(|index| Self {
+                shared: shared.clone(),
+                index,
+                wait,
+            })
+            .collect()
+    }
+
+    /// Game loop of the slowest other player
+    fn slowest_other(&self) -> u32 {
+        self.shared
+            .loops
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != self.index)
+            .map(|(_, game_loop)| game_loop.load(Ordering::SeqCst))
+            .min()
+            .unwrap_or(FINISHED)
+    }
+
+    /// Record that the player reached `game_loop`, waiting for the others if pacing
+    pub async fn align(&self, game_loop: u32) {
+        self.shared.loops[self.index].store(game_loop, Ordering::SeqCst);
+        self.shared.caught_up.notify_waiters();
+        let slowest = self.slowest_other();
+        if slowest == FINISHED {
+            return;
+        }
+        let skew = game_loop.saturating_sub(slowest);
+        self.shared.skew[self.index].fetch_max(skew, Ordering::SeqCst);
+        if !self.wait {
+            return;
+        }
+        let deadline = Instant::now() + PACING_TIMEOUT;
+        loop {
+            // Created before the check, so a player catching up in between still wakes it
+            let caught_up = self.shared.caught_up.notified();
+            if self.slowest_other() >= game_loop {
+                return;
+            }
+            if timeout_at(deadline, caught_up).await.is_err() {
+                warn!(
+                    "Player {} waited {:?} at game loop {} for the other players",
+                    self.index, PACING_TIMEOUT, game_loop
+                );
+                return;
+            }
+        }
+    }
+
+    /// Stop waiting for player `index`, which no longer plays
+    pub fn finish(&self, index: usize) {
+        self.shared.loops[index].store(FINISHED, Ordering::SeqCst);
+        self.shared.caught_up.notify_waiters();
+    }
+
+    /// Largest number of game loops each player was ahead of the slowest other one
+    pub fn skew(&self) -> Vec<u32> {
+        self.shared
+            .skew
+            .iter()
+            .map(|skew| skew.load(Ordering::SeqCst))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_align() {
+        let pending = Duration::from_millis(50);
+        let players = Pacing::for_players(2, true);
+        let (fast, slow) = (players[0].clone(), players[1].clone());
+        slow.align(8).await;
+        let mut waiting = tokio::spawn(async move { fast.align(24).await });
+        assert!(tokio::time::timeout(pending, &mut waiting).await.is_err());
+        slow.align(16).await;
+        assert!(tokio::time::timeout(pending, &mut waiting).await.is_err());
+        slow.align(24).await;
+        tokio::time::timeout(PACING_TIMEOUT, waiting)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(slow.skew(), vec![16, 8]);
+
+        let players = Pacing::for_players(2, true);
+        players[0].finish(1);
+        players[0].align(100).await;
+        assert_eq!(players[0].skew(), vec![0, 0]);
+    }
+}
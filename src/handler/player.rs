@@ -4,22 +4,50 @@ use std::fmt;
 use std::time::{Duration, Instant};
 
 use protobuf::Message;
-use sc2_proto::sc2api::{Request, RequestJoinGame, RequestSaveReplay, Response, Status};
+use sc2_proto::sc2api::{
+    Request, RequestJoinGame, RequestObservation, RequestSaveReplay, Response, ResponseAction,
+    Status,
+};
 use tokio_tungstenite::tungstenite::Message as TMessage;
 
-use super::messaging::{ChannelToGame, ToGameContent};
-use crate::config::Config;
+use super::archon::{partner_request, PartnerRequest};
+use super::budget::{Budget, BUDGET_QUERY};
+use super::chat::ChatLog;
+use super::disconnect::{error_kind, Disconnect, Peer};
+use super::light::strip_observation;
+use super::messaging::{ChannelToGame, ToGameContent, ToPlayer};
+use super::middleware::{self, MessageContext, ProxyMiddleware, DEBUG_DENIED};
+use super::obs_sizes::ObservationSizes;
+use super::ordering::OrderingCheck;
+use super::pacing::Pacing;
+use super::policy::surrender_results;
+use super::progress::Progress;
+use super::realtime::RealtimeLag;
+use super::replay::{validate_replay, write_replay, ReplayLock, SAVE_REPLAY_TIMEOUT};
+use super::snapshot::StateSnapshot;
+use super::sniffer::{sniff_leave_game, sniff_response};
+use super::stats::PlayerStats;
+use super::tags::{TagFeed, TagLimits};
+use super::trace::SharedTrace;
+use super::unknown_fields::{unknown_field_numbers, UnknownFieldCounts};
+use super::{warn_limited, WARNING_LIMIT};
+use crate::clock::{self, SharedClock};
+use crate::config::{ActionLimitMode, Config, DebugRequestPolicy, RequestOrdering, Violation};
+use crate::crypto::ArtifactKey;
+use crate::errors::ArenaError;
+use crate::fault::{FaultAction, FaultInjector};
 
 use crate::handler::messaging::GameOver;
 use crate::proxy::Client;
 use crate::sc2::{PlayerResult, Race};
 use crate::sc2process::Process;
+use crate::trace::{request_type, Direction, ProtocolTrace};
 use futures_util::{SinkExt, StreamExt};
-use std::collections::HashSet;
-use std::fs::File;
+use std::collections::{BTreeMap, HashSet};
 use std::io::ErrorKind::{ConnectionAborted, ConnectionReset, TimedOut, WouldBlock};
-use std::io::Write;
+use std::path::Path;
 use tokio::net::TcpStream;
+use tokio::time::error::Elapsed;
 use tokio::time::timeout;
 use tokio_tungstenite::tungstenite::error::ProtocolError::ResetWithoutClosingHandshake;
 use tokio_tungstenite::tungstenite::Error;
@@ -45,17 +73,108 @@ pub struct Player {
     pub player_id: Option<u32>,
     /// Tags
     pub tags: HashSet<String>,
+    /// Tags dropped for breaking the limits
+    tags_dropped: u32,
+    /// Chat messages of the bot, not tracked in transparent mode
+    pub chat: ChatLog,
+    /// Seconds from the first request until the warmup ended
+    pub warmup_duration: f32,
+    /// Frame budgets the bot exceeded
+    pub strikes: u32,
+    /// Requests that carried more than `MaxActionsPerFrame` actions
+    pub action_strikes: u32,
+    /// Rule violations the proxy caught, only committed ones are listed
+    pub violations: BTreeMap<Violation, u32>,
+    /// Per-frame timing trace of the match
+    pub(super) trace: Option<SharedTrace>,
+    /// Messages to and from SC2, only with `ProtocolTrace`
+    pub(super) protocol_trace: Option<ProtocolTrace>,
+    /// How the connection broke, if it did
+    pub disconnect: Option<Disconnect>,
+    /// Last observation sent to the bot, not tracked in transparent mode
+    pub last_observation: Option<StateSnapshot>,
+    /// Score of the last observation sent to the bot, not tracked in transparent mode
+    pub stats: Option<PlayerStats>,
+    /// Actions the bot sent to SC2 so far
+    actions: u32,
+    /// Sizes of the observations sent to the bot, not tracked in transparent mode
+    pub observation_sizes: ObservationSizes,
+    /// Order of the requests of the bot, only checked with `RequestOrdering`
+    pub ordering: OrderingCheck,
+    /// Protocol fields unknown to the proxy, not tracked in transparent mode
+    pub unknown_fields: UnknownFieldCounts,
+    /// Size of the last message sent to the bot
+    sent_bytes: usize,
     response: Response,
     request: Request,
+    /// Fault injection for robustness tests
+    fault: Option<FaultInjector>,
+    /// Key to encrypt the saved replay with
+    artifact_key: Option<ArtifactKey>,
+    /// Time source of the frame budgets
+    clock: SharedClock,
+    /// Shared by the players of a game to order replay saves before kills
+    pub(super) replay_lock: ReplayLock,
+    /// Whether a well-formed replay was written, `None` if no save was attempted
+    pub replay_saved: Option<bool>,
+    /// Game loops of all players of the game, not tracked in transparent mode
+    pub(super) pacing: Option<Pacing>,
+    /// Game loops the bot missed in real time, only with `RealtimeMaxLag`
+    pub realtime: Option<RealtimeLag>,
+    /// Built-in and registered middlewares, not run in transparent mode
+    pub(super) middlewares: Vec<Box<dyn ProxyMiddleware>>,
+    /// Live progress of the game, only with `ProgressInterval`
+    pub(super) progress: Option<Progress>,
+    /// New tags for the supervisor, only with `LiveTags`
+    pub(super) tag_feed: Option<TagFeed>,
+    /// Second client of the slot in an Archon match
+    pub(super) partner: Option<Client>,
+    /// Team of every SC2 player id of the game, set once the players joined
+    pub(super) teams: Vec<u32>,
+}
+
+/// Outcome of waiting for the next request of the bot
+enum NextRequest {
+    Request(Vec<u8>),
+    /// The connection was closed or broken
+    Closed,
+    /// The bot used up its strikes
+    TimedOut,
+    /// The handler sent a message while the bot was thinking
+    Interrupted,
+}
+
+/// First of the messages `next_request` waits for
+enum Waited {
+    /// Request of the bot, or the frame budget ran out
+    Lead(Result<anyhow::Result<Vec<u8>>, Elapsed>),
+    /// Request of the Archon partner
+    Partner(anyhow::Result<Vec<u8>>),
+    Interrupted,
+}
+
+/// Outcome of checking a request against the limits of the config
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ActionCheck {
+    Pass,
+    /// The request was cut down to the limits, it has to be encoded again
+    Truncated,
+    /// The request must not reach SC2, the bot is answered with the error instead
+    Rejected(String),
+    /// The bot forfeits for exceeding the limit
+    Forfeit,
 }
 
 impl Player {
     /// Creates new player instance and initializes sc2 process for it
-    pub async fn new(connection: Client, data: PlayerData) -> tokio::task::JoinHandle<Player> {
+    pub async fn new(
+        connection: Client,
+        data: PlayerData,
+        process: Process,
+    ) -> tokio::task::JoinHandle<Result<Player, ArenaError>> {
         tokio::task::spawn(async {
-            let process = Process::new();
-            let sc2_ws = process.connect().await.expect("Could not connect");
-            Self {
+            let sc2_ws = process.connect().await?;
+            Ok(Self {
                 process,
                 sc2_ws,
                 sc2_status: None,
@@ -64,16 +183,47 @@ impl Player {
                 frame_time: 0_f32,
                 player_id: None,
                 tags: Default::default(),
+                tags_dropped: 0,
+                chat: Default::default(),
+                warmup_duration: 0_f32,
+                strikes: 0,
+                action_strikes: 0,
+                violations: Default::default(),
+                trace: None,
+                protocol_trace: None,
+                disconnect: None,
+                last_observation: None,
+                stats: None,
+                actions: 0,
+                observation_sizes: Default::default(),
+                ordering: Default::default(),
+                unknown_fields: Default::default(),
+                sent_bytes: 0,
                 response: Default::default(),
                 client_ws: connection,
                 request: Default::default(),
-            }
+                fault: FaultInjector::from_env(),
+                artifact_key: None,
+                clock: clock::system(),
+                replay_lock: Default::default(),
+                replay_saved: None,
+                pacing: None,
+                realtime: None,
+                middlewares: Vec::new(),
+                progress: None,
+                tag_feed: None,
+                partner: None,
+                teams: Vec::new(),
+            })
         })
     }
-    pub async fn new_no_thread(connection: Client, data: PlayerData) -> Self {
-        let process = Process::new();
-        let sc2_ws = process.connect().await.expect("Could not connect");
-        Self {
+    pub async fn new_no_thread(
+        connection: Client,
+        data: PlayerData,
+        process: Process,
+    ) -> Result<Self, ArenaError> {
+        let sc2_ws = process.connect().await?;
+        Ok(Self {
             process,
             sc2_ws,
             client_ws: connection,
@@ -83,24 +233,55 @@ impl Player {
             frame_time: 0_f32,
             player_id: None,
             tags: Default::default(),
+            tags_dropped: 0,
+            chat: Default::default(),
+            warmup_duration: 0_f32,
+            strikes: 0,
+            action_strikes: 0,
+            violations: Default::default(),
+            trace: None,
+            protocol_trace: None,
+            disconnect: None,
+            last_observation: None,
+            stats: None,
+            actions: 0,
+            observation_sizes: Default::default(),
+            ordering: Default::default(),
+            unknown_fields: Default::default(),
+            sent_bytes: 0,
             response: Default::default(),
             request: Default::default(),
-        }
+            fault: FaultInjector::from_env(),
+            artifact_key: None,
+            clock: clock::system(),
+            replay_lock: Default::default(),
+            replay_saved: None,
+            pacing: None,
+            realtime: None,
+            middlewares: Vec::new(),
+            progress: None,
+            tag_feed: None,
+            partner: None,
+            teams: Vec::new(),
+        })
+    }
+    /// Measure frame budgets with `clock` instead of the system clock
+    pub fn set_clock(&mut self, clock: SharedClock) {
+        self.clock = clock;
     }
     pub fn player_name(&self) -> &Option<String> {
         &self.data.name
     }
     /// Send message to the client
-    async fn client_send(&mut self, msg: TMessage) {
+    async fn client_send(&mut self, msg: TMessage) -> anyhow::Result<()> {
         trace!("{:?}: Sending message to client", self.player_id);
-        self.client_ws
-            .send_message(msg)
-            .await
-            .expect("Could not send");
+        self.sent_bytes = msg.len();
+        self.client_ws.send_message(msg).await?;
+        Ok(())
     }
 
     /// Send a protobuf response to the client
-    pub async fn client_respond(&mut self, r: &Response) {
+    pub async fn client_respond(&mut self, r: &Response) -> anyhow::Result<()> {
         trace!(
             "{:?}:
             Response to client: [{}]",
@@ -110,15 +291,15 @@ impl Player {
         self.client_send(TMessage::binary(
             r.write_to_bytes().expect("Invalid protobuf message"),
         ))
-        .await;
+        .await
     }
-    pub async fn client_respond_raw(&mut self, r: &[u8]) {
+    pub async fn client_respond_raw(&mut self, r: &[u8]) -> anyhow::Result<()> {
         trace!(
             "{:?}: Response to client: [{}]",
             self.player_id,
             format!("{:?}", r).chars().take(100).collect::<String>()
         );
-        self.client_send(TMessage::binary(r)).await;
+        self.client_send(TMessage::binary(r)).await
     }
 
     /// Receive a message from the client
@@ -173,13 +354,16 @@ impl Player {
                 );
                     return Err(anyhow::Error::new(e));
                 }
-                Err(err) => panic!(
-                    "Could not receive: Address:{:?}\nPlayerId:{:?}\nName:{:?}\nError:{:?}",
-                    self.client_ws.peer_addr(),
-                    self.player_id,
-                    self.data.name,
-                    &err
-                ),
+                Err(err) => {
+                    error!(
+                        "Could not receive: Address:{:?}\nPlayerId:{:?}\nName:{:?}\nError:{:?}",
+                        self.client_ws.peer_addr(),
+                        self.player_id,
+                        self.data.name,
+                        &err
+                    );
+                    return Err(anyhow::Error::new(err));
+                }
             }
         }
         Err(anyhow::Error::msg("Message is None"))
@@ -190,7 +374,9 @@ impl Player {
     pub async fn client_get_request(&mut self) -> anyhow::Result<Request> {
         match self.client_recv().await? {
             TMessage::Binary(bytes) => {
-                let resp = Message::parse_from_bytes(&bytes).expect("Invalid protobuf message");
+                let resp = Message::parse_from_bytes(&bytes).map_err(|e| {
+                    ArenaError::Protocol(format!("Invalid request from the bot: {}", e))
+                })?;
                 trace!(
                     "{:?} Message from client parsed:\n{:?}",
                     self.player_id,
@@ -199,7 +385,11 @@ impl Player {
                 Ok(resp)
             }
             TMessage::Close(e) => Err(anyhow::Error::msg(format!("{:?}", e))),
-            other => panic!("Expected binary message, got {:?}", other),
+            other => Err(ArenaError::Protocol(format!(
+                "Expected a binary message from the bot, got {:?}",
+                other
+            ))
+            .into()),
         }
     }
 
@@ -207,13 +397,52 @@ impl Player {
         match self.client_recv().await? {
             TMessage::Binary(bytes) => Ok(bytes),
             TMessage::Close(e) => Err(anyhow::Error::msg(format!("{:?}", e))),
-            other => panic!("Expected binary message, got {:?}", other),
+            other => Err(ArenaError::Protocol(format!(
+                "Expected a binary message from the bot, got {:?}",
+                other
+            ))
+            .into()),
+        }
+    }
+
+    /// Get the next request of the bot within a frame budget of `budget` that started at `started`
+    /// Budget queries in between are answered with what is left of it
+    async fn client_get_request_budgeted(
+        &mut self,
+        config: &Config,
+        budget: Duration,
+        started: Instant,
+    ) -> anyhow::Result<Vec<u8>> {
+        loop {
+            match self.client_recv().await? {
+                TMessage::Binary(bytes) => return Ok(bytes),
+                TMessage::Text(text) if text == BUDGET_QUERY => {
+                    let elapsed = self.clock.now().saturating_duration_since(started);
+                    let max_strikes = config.strikes.max(1) as u32;
+                    let answer = Budget::new(budget, elapsed, self.strikes, max_strikes);
+                    // Not a response to a request, so not counted in `sent_bytes`
+                    self.client_ws
+                        .send_message(TMessage::text(answer.to_message()))
+                        .await?;
+                }
+                TMessage::Close(e) => return Err(anyhow::Error::msg(format!("{:?}", e))),
+                other => {
+                    return Err(ArenaError::Protocol(format!(
+                        "Expected a binary message from the bot, got {:?}",
+                        other
+                    ))
+                    .into())
+                }
+            }
         }
     }
 
     /// Send message to sc2
     /// Returns None if the connection is already closed
     async fn sc2_send(&mut self, msg: TMessage) -> Option<()> {
+        if let (Some(protocol_trace), TMessage::Binary(bytes)) = (&mut self.protocol_trace, &msg) {
+            protocol_trace.record(Direction::Request, bytes);
+        }
         self.sc2_ws.send(msg).await.ok()
     }
 
@@ -232,19 +461,31 @@ impl Player {
     /// Wait and receive a protobuf request from sc2
     /// Returns None if the connection is already closed
     pub async fn sc2_recv(&mut self) -> Option<Response> {
-        match self.sc2_ws.next().await?.ok()? {
-            TMessage::Binary(bytes) => {
-                Some(Message::parse_from_bytes(&bytes).expect("Invalid data"))
+        let bytes = self.sc2_recv_raw().await?;
+        match Message::parse_from_bytes(&bytes) {
+            Ok(response) => Some(response),
+            Err(e) => {
+                error!("{:?}: Invalid response from SC2: {}", self.player_id, e);
+                None
             }
-            TMessage::Close(_) => None,
-            other => panic!("Expected binary message, got {:?}", other),
         }
     }
     pub async fn sc2_recv_raw(&mut self) -> Option<Vec<u8>> {
         match self.sc2_ws.next().await?.ok()? {
-            TMessage::Binary(bytes) => Some(bytes),
+            TMessage::Binary(bytes) => {
+                if let Some(protocol_trace) = &mut self.protocol_trace {
+                    protocol_trace.record(Direction::Response, &bytes);
+                }
+                Some(bytes)
+            }
             TMessage::Close(_) => None,
-            other => panic!("Expected binary message, got {:?}", other),
+            other => {
+                error!(
+                    "{:?}: Expected a binary message from SC2, got {:?}",
+                    self.player_id, other
+                );
+                None
+            }
         }
     }
 
@@ -258,91 +499,820 @@ impl Player {
         self.sc2_request_raw(r).await?;
         self.sc2_recv_raw().await
     }
-    /// Saves replay to path
+    /// Saves replay to path, waiting at most `SAVE_REPLAY_TIMEOUT` for SC2
+    /// Records whether a well-formed replay was written in `replay_saved`
     pub async fn save_replay(&mut self, path: &str) -> bool {
         if path.is_empty() {
             return false;
         }
+        let lock = self.replay_lock.clone();
+        let _saving = lock.read().await;
+        let saved = self.write_replay(path).await;
+        self.replay_saved = Some(saved);
+        saved
+    }
+
+    async fn write_replay(&mut self, path: &str) -> bool {
         let mut r = Request::new();
         r.set_save_replay(RequestSaveReplay::new());
-        if let Some(response) = self.sc2_query(&r).await {
-            if response.has_save_replay() {
-                match File::create(path) {
-                    Ok(mut buffer) => {
-                        let data: &[u8] = response.save_replay().data();
-                        buffer
-                            .write_all(data)
-                            .expect("Could not write to replay file");
-                        info!("{:?}: Replay saved to {:?}", self.player_id, &path);
-                        true
-                    }
-                    Err(e) => {
-                        error!(
-                            "{:?}:Failed to create replay file {:?}: {:?}",
-                            self.player_id, &path, e
-                        );
-                        false
-                    }
+        let response = match timeout(SAVE_REPLAY_TIMEOUT, self.sc2_query(&r)).await {
+            Ok(Some(response)) => response,
+            Ok(None) => {
+                error!("{:?}:Could not save replay", self.player_id);
+                return false;
+            }
+            Err(_) => {
+                error!(
+                    "{:?}:SC2 did not save the replay within {:?}",
+                    self.player_id, SAVE_REPLAY_TIMEOUT
+                );
+                return false;
+            }
+        };
+        if !response.has_save_replay() {
+            error!("{:?}:No replay data available", self.player_id);
+            return false;
+        }
+        let data: &[u8] = response.save_replay().data();
+        if let Err(e) = validate_replay(data) {
+            error!("{:?}:Invalid replay: {}", self.player_id, e);
+            return false;
+        }
+        if let Some(key) = &self.artifact_key {
+            return match key.write_encrypted(Path::new(path), data) {
+                Ok(encrypted_path) => {
+                    info!(
+                        "{:?}: Encrypted replay saved to {:?}",
+                        self.player_id, encrypted_path
+                    );
+                    true
                 }
-            } else {
-                error!("{:?}:No replay data available", self.player_id);
+                Err(e) => {
+                    error!(
+                        "{:?}:Failed to write encrypted replay {:?}: {:?}",
+                        self.player_id, &path, e
+                    );
+                    false
+                }
+            };
+        }
+        match write_replay(Path::new(path), self.player_id.unwrap_or(0), data) {
+            Ok(()) => {
+                info!("{:?}: Replay saved to {:?}", self.player_id, &path);
+                true
+            }
+            Err(e) => {
+                error!(
+                    "{:?}:Failed to write replay file {:?}: {:?}",
+                    self.player_id, &path, e
+                );
                 false
             }
+        }
+    }
+
+    /// Kill the SC2 process once no player of the game is saving its replay
+    async fn kill_after_saves(&mut self) {
+        let lock = self.replay_lock.clone();
+        let _saves_done = lock.write().await;
+        self.process.kill();
+    }
+
+    /// Time budget for the next frame, larger while the bot is warming up
+    /// `first_request` is None until the bot sent its first request
+    fn frame_budget(&mut self, config: &Config, first_request: Option<Instant>) -> Duration {
+        let elapsed = first_request
+            .map(|t| self.clock.now().saturating_duration_since(t))
+            .unwrap_or_default();
+        if config.in_warmup(self.game_loops, elapsed) {
+            return config.warmup_frame_time();
+        }
+        if config.has_warmup() && self.warmup_duration == 0_f32 && first_request.is_some() {
+            self.warmup_duration = elapsed.as_secs_f32();
+            debug!(
+                "{:?}: Warmup ended after {:?}s",
+                self.player_id, self.warmup_duration
+            );
+        }
+        config.max_frame_time()
+    }
+
+    /// Wait for the next request, giving a strike for every frame budget the bot exceeds
+    /// Times out once the bot collected `Strikes` strikes, or at the first one if unset
+    async fn next_request(
+        &mut self,
+        config: &Config,
+        gamec: &ChannelToGame,
+        first_request: Option<Instant>,
+    ) -> NextRequest {
+        loop {
+            let budget = self.frame_budget(config, first_request);
+            let started = self.clock.now();
+            let request = loop {
+                let left =
+                    budget.saturating_sub(self.clock.now().saturating_duration_since(started));
+                // Taken out so the partner can be read while the lead is
+                let mut partner = self.partner.take();
+                let waited = tokio::select! {
+                    request = timeout(left, self.client_get_request_budgeted(config, budget, started)) => Waited::Lead(request),
+                    request = partner_request(&mut partner) => Waited::Partner(request),
+                    _ = gamec.notified() => Waited::Interrupted,
+                };
+                self.partner = partner;
+                match waited {
+                    Waited::Lead(request) => break request,
+                    Waited::Partner(request) => self.serve_partner(config, request).await,
+                    Waited::Interrupted => return NextRequest::Interrupted,
+                }
+            };
+            match request {
+                Ok(Ok(req_raw)) => return NextRequest::Request(req_raw),
+                Ok(Err(e)) => {
+                    self.bot_disconnected(&e);
+                    return NextRequest::Closed;
+                }
+                Err(_) => {
+                    self.strikes += 1;
+                    warn!(
+                        "{:?}: Frame took longer than {:?} (strike {})",
+                        self.player_id, budget, self.strikes
+                    );
+                    if self.strikes >= config.strikes.max(1) as u32 {
+                        error!("{:?}: Out of strikes", self.player_id);
+                        return NextRequest::TimedOut;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Answer a request of the Archon partner, on the SC2 connection of the lead
+    /// A partner that disconnects or sends garbage is dropped, the lead plays on
+    async fn serve_partner(&mut self, config: &Config, request: anyhow::Result<Vec<u8>>) {
+        let req_raw = match request {
+            Ok(req_raw) => req_raw,
+            Err(e) => {
+                warn!("{:?}: Archon partner disconnected: {}", self.player_id, e);
+                self.partner = None;
+                return;
+            }
+        };
+        let request: Request = match Message::parse_from_bytes(&req_raw) {
+            Ok(request) => request,
+            Err(e) => {
+                warn!(
+                    "{:?}: Invalid request from the partner: {}",
+                    self.player_id, e
+                );
+                self.partner = None;
+                return;
+            }
+        };
+        let response_raw = match PartnerRequest::of(&request, config.disable_debug()) {
+            PartnerRequest::Forward => {
+                if request.has_action() {
+                    self.actions += request.action().actions.len() as u32;
+                }
+                match self.sc2_query_raw(req_raw).await {
+                    Some(response_raw) => response_raw,
+                    // Noticed by the lead with its next request
+                    None => return,
+                }
+            }
+            kind => {
+                let mut response = kind.response(&request, self.game_loops);
+                if kind == PartnerRequest::Debug
+                    && config.debug_request_policy() != DebugRequestPolicy::Silent
+                {
+                    response.error.push(DEBUG_DENIED.to_string());
+                }
+                response.write_to_bytes().expect("Invalid protobuf message")
+            }
+        };
+        if let Some(partner) = self.partner.as_mut() {
+            if let Err(e) = partner.send_message(TMessage::binary(response_raw)).await {
+                warn!("{:?}: Archon partner disconnected: {}", self.player_id, e);
+                self.partner = None;
+            }
+        }
+    }
+
+    /// End the game for a bot that ran out of strikes or forfeited, keeping the replay
+    async fn finish_penalized(
+        mut self,
+        gamec: &mut ChannelToGame,
+        replay_path: &str,
+        frame_time: f32,
+        content: ToGameContent,
+    ) -> Self {
+        self.save_replay(replay_path).await;
+        self.finalize_frame_time(frame_time);
+        self.send_to_game(gamec, content);
+        self.kill_after_saves().await;
+        self
+    }
+
+    /// Send `content` to the handler, logging instead if the handler already ended
+    fn send_to_game(&self, gamec: &mut ChannelToGame, content: ToGameContent) {
+        if let Err(e) = gamec.send(content) {
+            error!("{:?}: {}", self.player_id, e);
+        }
+    }
+
+    /// Average frame time over the game loops played so far
+    fn finalize_frame_time(&mut self, frame_time: f32) {
+        self.frame_time = frame_time / self.game_loops as f32;
+        if self.frame_time.is_nan() {
+            self.frame_time = 0_f32;
+        }
+    }
+
+    /// Record a frame in the timing trace, if the match has one
+    fn trace_frame(&self, frame_time: f32, request_type: &str, bytes: usize) {
+        if let Some(trace) = &self.trace {
+            trace.lock().unwrap().record(
+                self.game_loops,
+                self.player_name().as_deref().unwrap_or_default(),
+                frame_time as f64 * 1000.0,
+                request_type,
+                bytes,
+            );
+        }
+    }
+
+    /// Enforce the limits of the config on the current request of `request_bytes`
+    fn check_request(&mut self, config: &Config, request_bytes: usize) -> ActionCheck {
+        let checked = self.limit_size(config, request_bytes);
+        if checked != ActionCheck::Pass {
+            return checked;
+        }
+        let checked = self.check_player_ids(config);
+        if checked != ActionCheck::Pass {
+            return checked;
+        }
+        let step = self.limit_step(config);
+        if step == ActionCheck::Forfeit {
+            return step;
+        }
+        match self.limit_actions(config) {
+            ActionCheck::Pass => step,
+            checked => checked,
+        }
+    }
+
+    /// Enforce `MaxRequestSize`, rejecting larger requests
+    fn limit_size(&mut self, config: &Config, request_bytes: usize) -> ActionCheck {
+        let limit = match exceeded_size(request_bytes, config.max_request_size()) {
+            Some(limit) => limit,
+            None => return ActionCheck::Pass,
+        };
+        warn!(
+            "{:?}: Request of {} bytes, the limit is {}",
+            self.player_id, request_bytes, limit
+        );
+        if self.record_violation(config, Violation::OversizedFrame) {
+            return ActionCheck::Forfeit;
+        }
+        ActionCheck::Rejected(format!("Request rejected, more than {} bytes", limit))
+    }
+
+    /// Reject requests naming another player
+    fn check_player_ids(&mut self, config: &Config) -> ActionCheck {
+        let other = match foreign_player_id(&self.request, self.player_id) {
+            Some(other) => other,
+            None => return ActionCheck::Pass,
+        };
+        warn!("{:?}: Request names player {}", self.player_id, other);
+        if self.record_violation(config, Violation::IdSpoofing) {
+            return ActionCheck::Forfeit;
+        }
+        ActionCheck::Rejected(format!("Request rejected, player {} is not the bot", other))
+    }
+
+    /// Enforce `MaxStepSize`, shortening longer steps to it
+    fn limit_step(&mut self, config: &Config) -> ActionCheck {
+        let count = match clamp_step(&mut self.request, config.max_step_size()) {
+            Some(count) => count,
+            None => return ActionCheck::Pass,
+        };
+        warn!(
+            "{:?}: Step of {} game loops, the limit is {}",
+            self.player_id,
+            count,
+            config.max_step_size().unwrap_or_default()
+        );
+        if self.record_violation(config, Violation::StepSize) {
+            return ActionCheck::Forfeit;
+        }
+        ActionCheck::Truncated
+    }
+
+    /// Enforce `MaxActionsPerFrame` on the current request, counting a strike if it is over
+    fn limit_actions(&mut self, config: &Config) -> ActionCheck {
+        let limit = match config.max_actions_per_frame() {
+            Some(limit) if self.request.has_action() => limit,
+            _ => return ActionCheck::Pass,
+        };
+        let count = self.request.action().actions.len();
+        if count <= limit {
+            return ActionCheck::Pass;
+        }
+        self.action_strikes += 1;
+        warn!(
+            "{:?}: {} actions in one frame, the limit is {} (strike {})",
+            self.player_id, count, limit, self.action_strikes
+        );
+        if self.record_violation(config, Violation::ActionRate) {
+            return ActionCheck::Forfeit;
+        }
+        match config.action_limit_mode() {
+            ActionLimitMode::Truncate => {
+                self.request.mut_action().actions.truncate(limit);
+                ActionCheck::Truncated
+            }
+            ActionLimitMode::Reject => {
+                ActionCheck::Rejected(format!("Request rejected, more than {} actions", limit))
+            }
+        }
+    }
+
+    /// Keep a tag of the bot, dropping it with a warning if it breaks the limits
+    /// Returns whether the dropped tag makes the bot forfeit
+    fn add_tag(&mut self, config: &Config, limits: TagLimits, tag: &str) -> bool {
+        let rejection = match limits.admit(&self.tags, tag) {
+            Ok(None) => return false,
+            Ok(Some(tag)) => {
+                if let Some(feed) = &mut self.tag_feed {
+                    feed.push(tag.clone());
+                }
+                self.tags.insert(tag);
+                return false;
+            }
+            Err(rejection) => rejection,
+        };
+        warn_limited(
+            &mut self.tags_dropped,
+            WARNING_LIMIT,
+            format_args!(
+                "{:?}: Dropping tag {:?}: {}",
+                self.player_id,
+                tag.chars().take(limits.max_length).collect::<String>(),
+                rejection
+            ),
+        );
+        self.record_violation(config, Violation::TagSpam)
+    }
+
+    /// Count a violation, returning whether it makes the bot forfeit
+    fn record_violation(&mut self, config: &Config, violation: Violation) -> bool {
+        *self.violations.entry(violation).or_insert(0) += 1;
+        if config.forfeit_violations().contains(&violation) {
+            error!("{:?}: Forfeits for {:?}", self.player_id, violation);
+            true
         } else {
-            error!("{:?}:Could not save replay", self.player_id);
             false
         }
     }
 
+    /// Keep the details of a broken connection for the result
+    fn record_disconnect(&mut self, peer: Peer, error: String, bytes_outstanding: usize) {
+        self.disconnect = Some(Disconnect {
+            peer,
+            error,
+            last_game_loop: self.game_loops,
+            bytes_outstanding,
+        });
+    }
+
+    /// Keep the details of a bot connection that broke, e.g. while sending it a response
+    fn bot_disconnected(&mut self, error: &anyhow::Error) {
+        let bytes = self.sent_bytes;
+        self.record_disconnect(Peer::Bot, error_kind(error), bytes);
+    }
+
+    /// Why SC2 closed the connection while answering a request of `request_bytes`:
+    /// a resource limit, or a crash
+    fn sc2_closed(&mut self, request_bytes: usize) -> ToGameContent {
+        self.record_disconnect(Peer::SC2, "Closed".to_string(), request_bytes);
+        match self.process.limit_violation() {
+            Some(violation) => {
+                error!(
+                    "{:?}: SC2 exceeded the {:?} limit",
+                    self.player_id, violation
+                );
+                ToGameContent::ResourceLimit
+            }
+            None => ToGameContent::SC2UnexpectedConnectionClose,
+        }
+    }
+
+    /// End the game early when the handler asks, e.g. for a graceful shutdown, keeping the replay
+    async fn finish_drained(
+        mut self,
+        gamec: &mut ChannelToGame,
+        replay_path: &str,
+        frame_time: f32,
+    ) -> Self {
+        info!("{:?}: Game ended early by the handler", self.player_id);
+        self.save_replay(replay_path).await;
+        self.finalize_frame_time(frame_time);
+        self.send_to_game(
+            gamec,
+            ToGameContent::GameOver(GameOver {
+                results: vec![PlayerResult::Tie; self.teams.len().max(2)],
+                game_loops: self.game_loops,
+                frame_time: self.frame_time,
+                tags: self.tags.iter().cloned().collect(),
+            }),
+        );
+        self.kill_after_saves().await;
+        self
+    }
+
+    /// End the game for a bot whose connection broke, still trying to keep the replay
+    /// SC2 is alive, only the bot is gone, so the save usually succeeds
+    async fn finish_disconnected(
+        mut self,
+        gamec: &mut ChannelToGame,
+        replay_path: &str,
+        frame_time: f32,
+    ) -> Self {
+        info!(
+            "{:?}: Saving the replay after unexpected connection close",
+            self.player_id
+        );
+        self.save_replay(replay_path).await;
+        self.finalize_frame_time(frame_time);
+        self.send_to_game(gamec, ToGameContent::UnexpectedConnectionClose);
+        debug!("{:?}: Killing the process", self.player_id);
+        self.kill_after_saves().await;
+        self
+    }
+
+    /// Run handler communication loop without parsing the frames
+    /// Debug requests are not filtered and races are not masked, only the
+    /// end of the game is detected by sniffing the responses
+    pub async fn run_transparent(
+        mut self,
+        config: Config,
+        mut gamec: ChannelToGame,
+    ) -> Option<Self> {
+        let replay_path = config.replay_path();
+        let mut frame_time = 0_f32;
+        let mut start_time: Option<Instant> = None;
+        let mut first_request: Option<Instant> = None;
+        let mut surrender = false;
+        let mut malformed_frames = 0_u32;
+
+        loop {
+            let req_raw = match self.next_request(&config, &gamec, first_request).await {
+                NextRequest::Request(req_raw) => req_raw,
+                NextRequest::Closed => break,
+                NextRequest::TimedOut => {
+                    return Some(
+                        self.finish_penalized(
+                            &mut gamec,
+                            replay_path,
+                            frame_time,
+                            ToGameContent::Timeout,
+                        )
+                        .await,
+                    )
+                }
+                NextRequest::Interrupted => match gamec.recv() {
+                    Ok(None) => continue,
+                    // Without its handler, nobody waits for the game anymore
+                    Ok(Some(ToPlayer::Quit)) | Err(_) => {
+                        return Some(
+                            self.finish_drained(&mut gamec, replay_path, frame_time)
+                                .await,
+                        )
+                    }
+                },
+            };
+            first_request.get_or_insert(self.clock.now());
+            if let Some(start) = start_time {
+                let elapsed = self
+                    .clock
+                    .now()
+                    .saturating_duration_since(start)
+                    .as_secs_f32();
+                frame_time += elapsed;
+                // Frames are not parsed in transparent mode
+                self.trace_frame(elapsed, "Unparsed", req_raw.len());
+            }
+            match sniff_leave_game(&req_raw) {
+                Ok(leave_game) => surrender = leave_game,
+                Err(_) => malformed_frames += 1,
+            }
+            let request_bytes = req_raw.len();
+
+            let response_raw = match self.sc2_query_raw(req_raw).await {
+                Some(d) => d,
+                None => {
+                    error!(
+                        "{:?}: SC2 unexpectedly closed the connection",
+                        self.player_id
+                    );
+                    let content = self.sc2_closed(request_bytes);
+                    self.send_to_game(&mut gamec, content);
+                    self.process.kill();
+                    return Some(self);
+                }
+            };
+            let sniffed = sniff_response(&response_raw).unwrap_or_else(|_| {
+                malformed_frames += 1;
+                Default::default()
+            });
+            if let Err(e) = self.client_respond_raw(&response_raw).await {
+                self.bot_disconnected(&e);
+                break;
+            }
+            start_time = Some(self.clock.now());
+            if let Ok(Some(ToPlayer::Quit)) | Err(_) = gamec.recv() {
+                return Some(
+                    self.finish_drained(&mut gamec, replay_path, frame_time)
+                        .await,
+                );
+            }
+
+            if let Some(game_loop) = sniffed.game_loop {
+                self.game_loops = game_loop;
+            }
+            if sniffed.quit {
+                self.save_replay(replay_path).await;
+                self.finalize_frame_time(frame_time);
+                self.send_to_game(&mut gamec, ToGameContent::QuitBeforeLeave);
+                self.process.wait();
+                return Some(self);
+            } else if surrender {
+                self.save_replay(replay_path).await;
+                break;
+            } else if !sniffed.results.is_empty() {
+                self.finalize_frame_time(frame_time);
+                let mut results_by_id = sniffed.results;
+                results_by_id.sort();
+                self.save_replay(replay_path).await;
+                self.send_to_game(
+                    &mut gamec,
+                    ToGameContent::GameOver(GameOver {
+                        results: results_by_id.into_iter().map(|(_, r)| r).collect(),
+                        game_loops: self.game_loops,
+                        frame_time: self.frame_time,
+                        tags: vec![],
+                    }),
+                );
+                self.kill_after_saves().await;
+                return Some(self);
+            } else if sniffed.observation && self.game_loops > config.max_game_time() {
+                self.save_replay(replay_path).await;
+                self.finalize_frame_time(frame_time);
+                debug!("{:?}: Max time reached", self.player_id);
+                self.send_to_game(
+                    &mut gamec,
+                    ToGameContent::GameOver(GameOver {
+                        results: vec![PlayerResult::Tie; self.teams.len().max(2)],
+                        game_loops: self.game_loops,
+                        frame_time: self.frame_time,
+                        tags: vec![],
+                    }),
+                );
+                self.kill_after_saves().await;
+                return Some(self);
+            }
+        }
+        if malformed_frames > 0 {
+            warn!(
+                "{:?}: {} frames failed the integrity check",
+                self.player_id, malformed_frames
+            );
+        }
+        if !surrender {
+            return Some(
+                self.finish_disconnected(&mut gamec, replay_path, frame_time)
+                    .await,
+            );
+        }
+        self.finalize_frame_time(frame_time);
+        let results = surrender_results(&self.teams, self.player_id.unwrap());
+        self.send_to_game(
+            &mut gamec,
+            ToGameContent::GameOver(GameOver {
+                results,
+                game_loops: self.game_loops,
+                frame_time: self.frame_time,
+                tags: vec![],
+            }),
+        );
+        self.kill_after_saves().await;
+        Some(self)
+    }
+
     /// Run handler communication loop
     pub async fn run(mut self, config: Config, mut gamec: ChannelToGame) -> Option<Self> {
-        let mut debug_response = Response::new();
-        debug_response.set_id(0);
-        debug_response.set_status(Status::in_game);
-        let timeout_secs = Duration::from_secs(config.max_frame_time as u64);
+        // The key was validated when the supervisor sent the config
+        self.artifact_key = config.artifact_key().ok().flatten();
+        if config.transparent() {
+            return self.run_transparent(config, gamec).await;
+        }
         let replay_path = config.replay_path();
+        let tag_limits = TagLimits::from_config(&config);
+        let ordering = config.request_ordering();
+        let mut first_request: Option<Instant> = None;
         let mut start_timer = false;
         let mut frame_time = 0_f32;
-        let mut start_time: Instant = Instant::now();
+        let mut start_time: Instant = self.clock.now();
         let mut surrender = false;
         let mut response_raw: Vec<u8>;
 
-        // Get request
-        while let Ok(Ok(req_raw)) = timeout(timeout_secs, self.client_get_request_raw()).await {
+        loop {
+            // Get request
+            let req_raw = match self.next_request(&config, &gamec, first_request).await {
+                NextRequest::Request(req_raw) => req_raw,
+                NextRequest::Closed => break,
+                NextRequest::TimedOut => {
+                    return Some(
+                        self.finish_penalized(
+                            &mut gamec,
+                            replay_path,
+                            frame_time,
+                            ToGameContent::Timeout,
+                        )
+                        .await,
+                    )
+                }
+                NextRequest::Interrupted => match gamec.recv() {
+                    Ok(None) => continue,
+                    // Without its handler, nobody waits for the game anymore
+                    Ok(Some(ToPlayer::Quit)) | Err(_) => {
+                        return Some(
+                            self.finish_drained(&mut gamec, replay_path, frame_time)
+                                .await,
+                        )
+                    }
+                },
+            };
+            first_request.get_or_insert(self.clock.now());
+            if let Some(fault) = self.fault.as_mut() {
+                match fault.inject().await {
+                    FaultAction::Forward => {}
+                    FaultAction::Drop => {
+                        warn!("{:?}: Fault injection dropped a frame", self.player_id);
+                        continue;
+                    }
+                    FaultAction::Close => {
+                        warn!(
+                            "{:?}: Fault injection closed the connection",
+                            self.player_id
+                        );
+                        self.client_ws.shutdown().await.ok();
+                        let bytes = self.sent_bytes;
+                        self.record_disconnect(Peer::Bot, "FaultInjection".to_string(), bytes);
+                        break;
+                    }
+                }
+            }
             self.request.merge_from_bytes(&req_raw).ok()?;
+            let unknown = unknown_field_numbers(&self.request);
+            self.unknown_fields.record_request(self.player_id, &unknown);
+            let mut strip_request = config.strip_unknown_fields() && !unknown.is_empty();
+            if strip_request {
+                self.request.mut_unknown_fields().clear();
+            }
             if start_timer {
-                frame_time += start_time.elapsed().as_secs_f32();
+                let elapsed = self
+                    .clock
+                    .now()
+                    .saturating_duration_since(start_time)
+                    .as_secs_f32();
+                frame_time += elapsed;
+                self.trace_frame(elapsed, request_type(&self.request), req_raw.len());
             }
-            // Check for debug requests
-            if config.disable_debug() && self.request.has_debug() {
-                debug_response.set_id(self.request.id());
-                self.client_respond(&debug_response).await;
-
+            // The middlewares run in order, until one answers the request itself
+            let mut context = MessageContext::new(self.player_id, self.game_loops);
+            let mut action = middleware::Action::Forward;
+            for layer in self.middlewares.iter_mut() {
+                action = layer.on_request(&mut context, &mut self.request);
+                if action != middleware::Action::Forward {
+                    break;
+                }
+            }
+            strip_request |= context.modified;
+            let mut forfeit = false;
+            for tag in &context.tags {
+                forfeit |= self.add_tag(&config, tag_limits, tag);
+            }
+            if forfeit {
+                return Some(
+                    self.finish_penalized(
+                        &mut gamec,
+                        replay_path,
+                        frame_time,
+                        ToGameContent::Forfeit,
+                    )
+                    .await,
+                );
+            }
+            let (violation, response) = match action {
+                middleware::Action::Forward => (None, None),
+                middleware::Action::Respond(response) => (None, Some(response)),
+                middleware::Action::Violation(violation, response) => {
+                    (Some(violation), Some(response))
+                }
+                middleware::Action::Forfeit(violation) => (Some(violation), None),
+            };
+            if let Some(violation) = violation {
+                if self.record_violation(&config, violation) || response.is_none() {
+                    return Some(
+                        self.finish_penalized(
+                            &mut gamec,
+                            replay_path,
+                            frame_time,
+                            ToGameContent::Forfeit,
+                        )
+                        .await,
+                    );
+                }
+            }
+            if let Some(response) = response {
+                if let Err(e) = self.client_respond(&response).await {
+                    self.bot_disconnected(&e);
+                    break;
+                }
+                clear_request(&mut self.request);
                 continue;
             } else if self.request.has_leave_game() {
                 surrender = true;
                 break;
             }
 
-            for tag in self
+            let messages: Vec<String> = self
                 .request
                 .action()
                 .actions
                 .iter()
                 .filter(|a| a.action_chat.has_message())
-                .filter_map(|x| {
-                    let msg = x.action_chat.message();
-                    if msg.contains("Tag:") {
-                        msg.strip_prefix("Tag:").map(String::from)
-                    } else {
-                        None
+                .map(|x| x.action_chat.message().to_string())
+                .collect();
+            for msg in &messages {
+                self.chat.record(self.player_id, self.game_loops, msg);
+            }
+            if let Some(feed) = &mut self.tag_feed {
+                feed.flush(self.clock.now(), self.game_loops);
+            }
+
+            let req_raw = match self.check_request(&config, req_raw.len()) {
+                ActionCheck::Pass if !strip_request => req_raw,
+                ActionCheck::Pass | ActionCheck::Truncated => self
+                    .request
+                    .write_to_bytes()
+                    .expect("Invalid protobuf message"),
+                ActionCheck::Rejected(error) => {
+                    let mut response = Response::new();
+                    response.set_id(self.request.id());
+                    response.set_status(self.sc2_status.unwrap_or(Status::in_game));
+                    if self.request.has_action() {
+                        response.set_action(ResponseAction::new());
                     }
-                })
+                    response.error.push(error);
+                    if let Err(e) = self.client_respond(&response).await {
+                        self.bot_disconnected(&e);
+                        break;
+                    }
+                    clear_request(&mut self.request);
+                    continue;
+                }
+                ActionCheck::Forfeit => {
+                    return Some(
+                        self.finish_penalized(
+                            &mut gamec,
+                            replay_path,
+                            frame_time,
+                            ToGameContent::Forfeit,
+                        )
+                        .await,
+                    )
+                }
+            };
+
+            if ordering != RequestOrdering::Off
+                && self.ordering.check(self.player_id, &self.request).is_some()
+                && ordering == RequestOrdering::Enforce
             {
-                self.tags.insert(tag);
+                // The request then applies to the stepped game, as if the bot had observed it
+                let mut observe = Request::new();
+                observe.set_observation(RequestObservation::new());
+                if self.sc2_query(&observe).await.is_some() {
+                    self.ordering.observed();
+                }
+            }
+
+            if self.request.has_action() {
+                self.actions += self.request.action().actions.len() as u32;
             }
 
             // Send request to SC2 and get response
+            let request_bytes = req_raw.len();
             response_raw = match self.sc2_query_raw(req_raw).await {
                 Some(d) => d,
                 None => {
@@ -350,7 +1320,8 @@ impl Player {
                         "{:?}: SC2 unexpectedly closed the connection",
                         self.player_id
                     );
-                    gamec.send(ToGameContent::SC2UnexpectedConnectionClose);
+                    let content = self.sc2_closed(request_bytes);
+                    self.send_to_game(&mut gamec, content);
                     debug!("{:?}: Killing the process", self.player_id);
                     self.process.kill();
                     return Some(self);
@@ -358,20 +1329,72 @@ impl Player {
             };
 
             self.response.merge_from_bytes(&response_raw).ok()?;
+            let unknown = unknown_field_numbers(&self.response);
+            self.unknown_fields
+                .record_response(self.player_id, &unknown);
+            if config.strip_unknown_fields() && !unknown.is_empty() {
+                self.response.mut_unknown_fields().clear();
+                response_raw = self.response.write_to_bytes().unwrap();
+            }
             self.sc2_status = Some(self.response.status());
-            if self.response.has_game_info() {
-                for pi in self.response.mut_game_info().player_info.iter_mut() {
-                    if pi.player_id() != self.player_id.unwrap() {
-                        pi.race_actual = pi.race_requested;
+            let mut context = MessageContext::new(self.player_id, self.game_loops);
+            for layer in self.middlewares.iter_mut() {
+                layer.on_response(&mut context, &mut self.response);
+            }
+            if context.modified {
+                response_raw = self.response.write_to_bytes().unwrap();
+            }
+            if config.light_mode() && strip_observation(&mut self.response) {
+                response_raw = self.response.write_to_bytes().unwrap();
+            }
+
+            if let Some(realtime) = self.realtime.as_mut() {
+                if self.response.has_observation() {
+                    let game_loop = self.response.observation().observation.game_loop();
+                    if realtime.observe(self.player_id, game_loop).is_some()
+                        && config.realtime_lag_strikes()
+                    {
+                        self.strikes += 1;
+                        if self.strikes >= config.strikes.max(1) as u32 {
+                            error!("{:?}: Out of strikes", self.player_id);
+                            return Some(
+                                self.finish_penalized(
+                                    &mut gamec,
+                                    replay_path,
+                                    frame_time,
+                                    ToGameContent::Timeout,
+                                )
+                                .await,
+                            );
+                        }
+                    }
+                }
+            }
+            if let Some(pacing) = &self.pacing {
+                if self.response.has_observation() {
+                    let game_loop = self.response.observation().observation.game_loop();
+                    // A quit from the game ends the wait, it is handled once the response is sent
+                    tokio::select! {
+                        biased;
+                        _ = pacing.align(game_loop) => {}
+                        _ = gamec.notified() => {}
                     }
                 }
-                response_raw = self.response.write_to_bytes().unwrap();
             }
 
             // Send SC2 response to client
-            self.client_respond_raw(&response_raw).await;
+            if let Err(e) = self.client_respond_raw(&response_raw).await {
+                self.bot_disconnected(&e);
+                break;
+            }
             start_timer = true;
-            start_time = Instant::now();
+            start_time = self.clock.now();
+            if let Ok(Some(ToPlayer::Quit)) | Err(_) = gamec.recv() {
+                return Some(
+                    self.finish_drained(&mut gamec, replay_path, frame_time)
+                        .await,
+                );
+            }
 
             if self.response.has_quit() {
                 self.save_replay(replay_path).await;
@@ -382,7 +1405,7 @@ impl Player {
                     self.frame_time
                 };
                 debug!("{:?}: SC2 is shutting down", self.player_id);
-                gamec.send(ToGameContent::QuitBeforeLeave);
+                self.send_to_game(&mut gamec, ToGameContent::QuitBeforeLeave);
                 debug!("{:?}: Waiting for the process", self.player_id);
                 self.process.wait();
                 return Some(self);
@@ -395,8 +1418,16 @@ impl Player {
                 };
 
                 let obs = self.response.observation();
+                let snapshot = StateSnapshot::from_observation(obs);
+                if let Some(progress) = &self.progress {
+                    progress.record(&snapshot, self.frame_time);
+                }
+                self.last_observation = Some(snapshot);
+                self.stats = Some(PlayerStats::from_observation(obs, self.actions));
                 let obs_results = &obs.player_result;
                 self.game_loops = obs.observation.game_loop();
+                self.observation_sizes
+                    .record(self.game_loops, response_raw.len());
 
                 if !obs_results.is_empty() {
                     // Game is over and results available
@@ -406,14 +1437,17 @@ impl Player {
                         .collect();
                     results_by_id.sort();
                     let results: Vec<_> = results_by_id.into_iter().map(|(_, v)| v).collect();
-                    gamec.send(ToGameContent::GameOver(GameOver {
-                        results,
-                        game_loops: self.game_loops,
-                        frame_time: self.frame_time,
-                        tags: self.tags.iter().cloned().collect(),
-                    }));
                     self.save_replay(replay_path).await;
-                    self.process.kill();
+                    self.send_to_game(
+                        &mut gamec,
+                        ToGameContent::GameOver(GameOver {
+                            results,
+                            game_loops: self.game_loops,
+                            frame_time: self.frame_time,
+                            tags: self.tags.iter().cloned().collect(),
+                        }),
+                    );
+                    self.kill_after_saves().await;
                     return Some(self);
                 }
                 if self.game_loops > config.max_game_time() {
@@ -425,13 +1459,16 @@ impl Player {
                         self.frame_time
                     };
                     debug!("{:?}: Max time reached", self.player_id);
-                    gamec.send(ToGameContent::GameOver(GameOver {
-                        results: vec![PlayerResult::Tie, PlayerResult::Tie],
-                        game_loops: self.game_loops,
-                        frame_time: self.frame_time,
-                        tags: self.tags.iter().cloned().collect(),
-                    }));
-                    self.process.kill();
+                    self.send_to_game(
+                        &mut gamec,
+                        ToGameContent::GameOver(GameOver {
+                            results: vec![PlayerResult::Tie; self.teams.len().max(2)],
+                            game_loops: self.game_loops,
+                            frame_time: self.frame_time,
+                            tags: self.tags.iter().cloned().collect(),
+                        }),
+                    );
+                    self.kill_after_saves().await;
                     return Some(self);
                 }
             } else if surrender {
@@ -443,24 +1480,23 @@ impl Player {
             response_raw.clear();
         }
         if surrender {
-            let mut results: Vec<PlayerResult> = vec![PlayerResult::Victory; 2];
-            results[(self.player_id.unwrap() - 1) as usize] = PlayerResult::Defeat;
-            gamec.send(ToGameContent::GameOver(GameOver {
-                results,
-                game_loops: self.game_loops,
-                frame_time: self.frame_time,
-                tags: self.tags.iter().cloned().collect(),
-            }));
-            self.process.kill();
+            let results = surrender_results(&self.teams, self.player_id.unwrap());
+            self.send_to_game(
+                &mut gamec,
+                ToGameContent::GameOver(GameOver {
+                    results,
+                    game_loops: self.game_loops,
+                    frame_time: self.frame_time,
+                    tags: self.tags.iter().cloned().collect(),
+                }),
+            );
+            self.kill_after_saves().await;
             return Some(self);
         }
-        gamec.send(ToGameContent::UnexpectedConnectionClose);
-        info!(
-            "{:?}: Killing process after unexpected connection close (Crash or Timeout)",
-            self.player_id
-        );
-        self.process.kill();
-        Some(self)
+        Some(
+            self.finish_disconnected(&mut gamec, replay_path, frame_time)
+                .await,
+        )
     }
 }
 
@@ -479,9 +1515,18 @@ pub struct PlayerData {
 }
 
 impl PlayerData {
-    pub fn from_join_request(req: RequestJoinGame, archon: bool) -> Self {
-        Self {
-            race: Race::from_proto(req.race()),
+    /// Player data of a join request, `race` overrides the race the bot asked for
+    /// Fails if the request has no playable race or no interface options
+    pub fn from_join_request(
+        req: RequestJoinGame,
+        archon: bool,
+        race: Option<Race>,
+    ) -> Result<Self, String> {
+        let race = race
+            .or_else(|| Race::from_proto(req.race()))
+            .ok_or_else(|| format!("Cannot join as {:?}", req.race()))?;
+        Ok(Self {
+            race,
             name: if req.has_player_name() {
                 Some(req.player_name().to_owned())
             } else {
@@ -489,12 +1534,15 @@ impl PlayerData {
             },
 
             interface_options: {
-                let mut ifopts = req.options.unwrap();
+                let mut ifopts = req
+                    .options
+                    .into_option()
+                    .ok_or_else(|| "Join request has no interface options".to_string())?;
 
                 ifopts.set_raw_affects_selection(!archon);
                 ifopts
             },
-        }
+        })
     }
 }
 
@@ -512,12 +1560,52 @@ impl Default for Visibility {
     }
 }
 
+/// The size limit a request of `request_bytes` is over, if any
+fn exceeded_size(request_bytes: usize, limit: Option<usize>) -> Option<usize> {
+    limit.filter(|limit| request_bytes > *limit)
+}
+
+/// Shorten a step of the request to `limit` game loops, returning its count if it was longer
+fn clamp_step(request: &mut Request, limit: Option<u32>) -> Option<u32> {
+    let limit = limit?;
+    if !request.has_step() || request.step().count() <= limit {
+        return None;
+    }
+    let count = request.step().count();
+    request.mut_step().set_count(limit);
+    Some(count)
+}
+
+/// Another player an observer action of the request is for, a bot may only act as itself
+/// The perspective of player 0 shows everyone, it names no player
+fn foreign_player_id(request: &Request, player_id: Option<u32>) -> Option<u32> {
+    if !request.has_obs_action() {
+        return None;
+    }
+    request
+        .obs_action()
+        .actions
+        .iter()
+        .filter_map(|action| {
+            if action.has_player_perspective() {
+                Some(action.player_perspective().player_id())
+            } else if action.has_camera_follow_player() {
+                Some(action.camera_follow_player().player_id())
+            } else {
+                None
+            }
+        })
+        .find(|id| *id != 0 && Some(*id) != player_id)
+}
+
+/// Reset the request buffer for the next frame, its unknown fields were already counted
 pub fn clear_request(req: &mut Request) {
     req.request = None;
     req.id = None;
     req.mut_unknown_fields().clear();
 }
 
+/// Reset the response buffer for the next frame, its unknown fields were already counted
 pub fn clear_response(response: &mut Response) {
     response.response = None;
     response.id = None;
@@ -525,3 +1613,75 @@ pub fn clear_response(response: &mut Response) {
     response.status = None;
     response.mut_unknown_fields().clear();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protobuf::MessageField;
+    use sc2_proto::sc2api::{
+        ActionObserverCameraFollowPlayer, ActionObserverPlayerPerspective, InterfaceOptions,
+        ObserverAction, RequestObserverAction, RequestStep,
+    };
+
+    #[test]
+    fn test_join_request_without_race() {
+        let mut req = RequestJoinGame::new();
+        req.set_race(sc2_proto::common::Race::NoRace);
+        req.options = MessageField::some(InterfaceOptions::new());
+        assert!(PlayerData::from_join_request(req.clone(), false, None).is_err());
+        let data = PlayerData::from_join_request(req.clone(), false, Some(Race::Zerg)).unwrap();
+        assert_eq!(data.race, Race::Zerg);
+
+        req.set_race(sc2_proto::common::Race::Terran);
+        req.options = MessageField::none();
+        assert!(PlayerData::from_join_request(req, false, None).is_err());
+    }
+
+    #[test]
+    fn test_exceeded_size() {
+        assert_eq!(exceeded_size(1 << 20, None), None);
+        assert_eq!(exceeded_size(1024, Some(1024)), None);
+        assert_eq!(exceeded_size(1025, Some(1024)), Some(1024));
+    }
+
+    #[test]
+    fn test_clamp_step() {
+        let mut request = Request::new();
+        assert_eq!(clamp_step(&mut request, Some(8)), None);
+        let mut step = RequestStep::new();
+        step.set_count(22);
+        request.set_step(step);
+        assert_eq!(clamp_step(&mut request, None), None);
+        assert_eq!(clamp_step(&mut request, Some(22)), None);
+        assert_eq!(clamp_step(&mut request, Some(8)), Some(22));
+        assert_eq!(request.step().count(), 8);
+    }
+
+    #[test]
+    fn test_foreign_player_id() {
+        let mut request = Request::new();
+        assert_eq!(foreign_player_id(&request, Some(1)), None);
+        let mut perspective = ActionObserverPlayerPerspective::new();
+        perspective.set_player_id(0);
+        let mut action = ObserverAction::new();
+        action.set_player_perspective(perspective);
+        let mut obs_action = RequestObserverAction::new();
+        obs_action.actions.push(action);
+        request.set_obs_action(obs_action.clone());
+        assert_eq!(foreign_player_id(&request, Some(1)), None);
+
+        let mut follow = ActionObserverCameraFollowPlayer::new();
+        follow.set_player_id(1);
+        let mut action = ObserverAction::new();
+        action.set_camera_follow_player(follow.clone());
+        obs_action.actions.push(action.clone());
+        request.set_obs_action(obs_action.clone());
+        assert_eq!(foreign_player_id(&request, Some(1)), None);
+
+        follow.set_player_id(2);
+        action.set_camera_follow_player(follow);
+        obs_action.actions.push(action);
+        request.set_obs_action(obs_action);
+        assert_eq!(foreign_player_id(&request, Some(1)), Some(2));
+    }
+}
@@ -0,0 +1,395 @@
+//! Derivation of player results from the messages of the player loops
+
+use log::{debug, info, warn};
+use std::collections::BTreeMap;
+
+use super::messaging::ToGameContent;
+use crate::config::Config;
+use crate::sc2::PlayerResult;
+
+/// Decides how player messages translate into results
+pub trait ResultPolicy: Send {
+    /// Update the results with a message from player `player_index`
+    fn apply(
+        &self,
+        player_index: usize,
+        content: &ToGameContent,
+        player_results: &mut [Option<PlayerResult>],
+    );
+
+    /// Whether the supervisor should replay the match instead of scoring it
+    fn rematch(&self, _player_results: &[PlayerResult]) -> bool {
+        false
+    }
+}
+
+/// Select the policy named by `Config::result_policy`
+pub fn policy_from_config(config: &Config) -> Box<dyn ResultPolicy> {
+    match config.result_policy.as_deref() {
+        Some("RematchOnSC2Crash") => Box::new(RematchOnSC2Crash),
+        Some("Default") | None => Box::new(DefaultPolicy),
+        Some(other) => {
+            log::error!("Unknown result policy {:?}, using Default", other);
+            Box::new(DefaultPolicy)
+        }
+    }
+}
+
+/// Crashes lose, SC2 crashes are reported as such
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultPolicy;
+
+impl ResultPolicy for DefaultPolicy {
+    fn apply(
+        &self,
+        player_index: usize,
+        content: &ToGameContent,
+        player_results: &mut [Option<PlayerResult>],
+    ) {
+        match content {
+            ToGameContent::GameOver(game_over) => {
+                for (i, item) in player_results.iter_mut().enumerate() {
+                    if item.is_none() {
+                        *item = Some(game_over.results[i])
+                    }
+                }
+            }
+            ToGameContent::LeftGame => {
+                info!("Player left handler before it was over");
+                player_results[player_index] = Some(PlayerResult::Defeat);
+            }
+            ToGameContent::QuitBeforeLeave => {
+                info!("Client quit without leaving the handler");
+                player_results[player_index] = Some(PlayerResult::Defeat);
+            }
+            ToGameContent::SC2UnexpectedConnectionClose => {
+                info!("SC2 process closed connection unexpectedly");
+                player_results[player_index] = Some(PlayerResult::SC2Crash);
+            }
+            ToGameContent::Timeout => {
+                info!("Bot ran out of strikes");
+                player_results[player_index] = Some(PlayerResult::Timeout);
+            }
+            ToGameContent::Forfeit => {
+                info!("Bot forfeited by a rule violation");
+                player_results[player_index] = Some(PlayerResult::Forfeit);
+            }
+            ToGameContent::ResourceLimit => {
+                info!("Player exceeded its resource limits");
+                player_results[player_index] = Some(PlayerResult::ResourceLimit);
+            }
+            ToGameContent::UnexpectedConnectionClose => {
+                info!("Unexpected connection close");
+                if !player_results
+                    .iter()
+                    .enumerate()
+                    .any(|(idx, r)| idx != player_index && matches!(r, Some(PlayerResult::Crash)))
+                {
+                    debug!("Bot {:?} crashed", player_index);
+                    player_results[player_index] = Some(PlayerResult::Crash);
+                } else {
+                    player_results[player_index] = Some(PlayerResult::Victory);
+                }
+            }
+        }
+    }
+}
+
+/// Like `DefaultPolicy`, but an SC2 crash ends the game for everyone and requests a rematch
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RematchOnSC2Crash;
+
+impl ResultPolicy for RematchOnSC2Crash {
+    fn apply(
+        &self,
+        player_index: usize,
+        content: &ToGameContent,
+        player_results: &mut [Option<PlayerResult>],
+    ) {
+        DefaultPolicy.apply(player_index, content, player_results);
+        if let ToGameContent::SC2UnexpectedConnectionClose = content {
+            for item in player_results.iter_mut().filter(|r| r.is_none()) {
+                *item = Some(PlayerResult::SC2Crash);
+            }
+        }
+    }
+
+    fn rematch(&self, player_results: &[PlayerResult]) -> bool {
+        player_results.contains(&PlayerResult::SC2Crash)
+    }
+}
+
+/// Outcome of comparing the `GameOver` reports of all players
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reconciliation {
+    /// Results to use for the game
+    pub results: Vec<PlayerResult>,
+    /// The reports did not agree
+    pub discrepancy: bool,
+}
+
+/// Whether a report describes an outcome SC2 can actually produce:
+/// a winning team of at most half the players and defeated rest, or a tie for everyone
+fn is_consistent(report: &[PlayerResult]) -> bool {
+    let victories = report
+        .iter()
+        .filter(|r| **r == PlayerResult::Victory)
+        .count();
+    let defeats = report
+        .iter()
+        .filter(|r| **r == PlayerResult::Defeat)
+        .count();
+    (victories > 0 && victories * 2 <= report.len() && defeats == report.len() - victories)
+        || report.iter().all(|r| *r == PlayerResult::Tie)
+}
+
+/// Results by player id when player `player_id` leaves the game, its team loses
+/// `teams` holds the team of every player id, two players on their own teams if empty
+pub fn surrender_results(teams: &[u32], player_id: u32) -> Vec<PlayerResult> {
+    let two_players = [1, 2];
+    let teams: &[u32] = if teams.is_empty() {
+        &two_players
+    } else {
+        teams
+    };
+    let own = (player_id as usize)
+        .checked_sub(1)
+        .and_then(|index| teams.get(index))
+        .copied();
+    teams
+        .iter()
+        .map(|team| {
+            if Some(*team) == own {
+                PlayerResult::Defeat
+            } else {
+                PlayerResult::Victory
+            }
+        })
+        .collect()
+}
+
+/// Outcome of each team: a victory if any member won, a tie if any member tied, a defeat otherwise
+pub fn team_results(teams: &[u32], results: &[PlayerResult]) -> BTreeMap<u32, PlayerResult> {
+    let mut outcomes: BTreeMap<u32, PlayerResult> = BTreeMap::new();
+    for (team, result) in teams.iter().zip(results) {
+        let outcome = outcomes.entry(*team).or_insert(PlayerResult::Defeat);
+        match (*outcome, result) {
+            (PlayerResult::Victory, _) => {}
+            (_, PlayerResult::Victory) | (_, PlayerResult::Tie) => *outcome = *result,
+            _ => {}
+        }
+    }
+    outcomes
+}
+
+/// Compare the `GameOver` reports of every player.
+/// Returns None unless every player sent a report.
+/// On disagreement the first self-consistent report wins, falling back to the first report.
+pub fn reconcile(reports: &[Option<Vec<PlayerResult>>]) -> Option<Reconciliation> {
+    if reports.len() < 2 || reports.iter().any(Option::is_none) {
+        return None;
+    }
+    let reports: Vec<&Vec<PlayerResult>> = reports.iter().flatten().collect();
+    info!("GameOver reports: {:?}", reports);
+    let first = reports[0];
+    if reports.iter().all(|r| *r == first) {
+        return Some(Reconciliation {
+            results: first.clone(),
+            discrepancy: false,
+        });
+    }
+    warn!("Players disagree on the game result: {:?}", reports);
+    let chosen = reports
+        .iter()
+        .find(|r| is_consistent(r))
+        .copied()
+        .unwrap_or(first);
+    Some(Reconciliation {
+        results: chosen.clone(),
+        discrepancy: true,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::messaging::GameOver;
+    use PlayerResult::*;
+
+    fn messages() -> Vec<(&'static str, ToGameContent)> {
+        vec![
+            (
+                "G",
+                ToGameContent::GameOver(GameOver {
+                    results: vec![Victory, Defeat],
+                    game_loops: 100,
+                    frame_time: 0_f32,
+                    tags: vec![],
+                }),
+            ),
+            ("L", ToGameContent::LeftGame),
+            ("Q", ToGameContent::QuitBeforeLeave),
+            ("S", ToGameContent::SC2UnexpectedConnectionClose),
+            ("T", ToGameContent::Timeout),
+            ("U", ToGameContent::UnexpectedConnectionClose),
+        ]
+    }
+
+    fn play(
+        policy: &dyn ResultPolicy,
+        first: &ToGameContent,
+        second: &ToGameContent,
+    ) -> Vec<Option<PlayerResult>> {
+        let mut results = vec![None; 2];
+        policy.apply(0, first, &mut results);
+        policy.apply(1, second, &mut results);
+        results
+    }
+
+    /// Result of player 0 sending the first message, then player 1 the second
+    fn expected_default(first: &str, second: &str) -> [PlayerResult; 2] {
+        match (first, second) {
+            ("G", "S") => [Victory, SC2Crash],
+            ("G", "T") => [Victory, Timeout],
+            ("G", "U") => [Victory, Crash],
+            ("G", _) => [Victory, Defeat],
+            ("L", "S") | ("Q", "S") => [Defeat, SC2Crash],
+            ("L", "T") | ("Q", "T") => [Defeat, Timeout],
+            ("L", "U") | ("Q", "U") => [Defeat, Crash],
+            ("L", _) | ("Q", _) => [Defeat, Defeat],
+            ("S", "S") => [SC2Crash, SC2Crash],
+            ("S", "T") => [SC2Crash, Timeout],
+            ("S", "U") => [SC2Crash, Crash],
+            ("S", _) => [SC2Crash, Defeat],
+            ("T", "S") => [Timeout, SC2Crash],
+            ("T", "T") => [Timeout, Timeout],
+            ("T", "U") => [Timeout, Crash],
+            ("T", _) => [Timeout, Defeat],
+            ("U", "S") => [Crash, SC2Crash],
+            ("U", "T") => [Crash, Timeout],
+            ("U", "U") => [Crash, Victory],
+            ("U", _) => [Crash, Defeat],
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_team_results() {
+        assert_eq!(surrender_results(&[], 2), vec![Victory, Defeat]);
+        assert_eq!(
+            surrender_results(&[1, 2, 1, 2], 3),
+            vec![Defeat, Victory, Defeat, Victory]
+        );
+        let results = [Crash, Defeat, Victory, Defeat];
+        let teams = team_results(&[1, 2, 1, 2], &results);
+        assert_eq!(teams.get(&1), Some(&Victory));
+        assert_eq!(teams.get(&2), Some(&Defeat));
+        assert!(is_consistent(&[Defeat, Victory, Defeat, Victory]));
+        assert!(!is_consistent(&[Victory, Victory]));
+    }
+
+    #[test]
+    fn test_resource_limit() {
+        let mut results = vec![None; 2];
+        DefaultPolicy.apply(1, &ToGameContent::ResourceLimit, &mut results);
+        assert_eq!(results, vec![None, Some(ResourceLimit)]);
+        DefaultPolicy.apply(0, &ToGameContent::Forfeit, &mut results);
+        assert_eq!(results, vec![Some(Forfeit), Some(ResourceLimit)]);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let mut results = vec![None; 2];
+        DefaultPolicy.apply(0, &ToGameContent::Timeout, &mut results);
+        assert_eq!(results, vec![Some(Timeout), None]);
+    }
+
+    #[test]
+    fn test_default_policy_permutations() {
+        for (first_name, first) in messages() {
+            for (second_name, second) in messages() {
+                let results = play(&DefaultPolicy, &first, &second);
+                let expected = expected_default(first_name, second_name);
+                assert_eq!(
+                    results,
+                    vec![Some(expected[0]), Some(expected[1])],
+                    "{} then {}",
+                    first_name,
+                    second_name
+                );
+                assert!(!DefaultPolicy.rematch(&expected));
+            }
+        }
+    }
+
+    #[test]
+    fn test_rematch_policy_permutations() {
+        for (first_name, first) in messages() {
+            for (second_name, second) in messages() {
+                let results: Vec<PlayerResult> = play(&RematchOnSC2Crash, &first, &second)
+                    .into_iter()
+                    .map(Option::unwrap)
+                    .collect();
+                let crashed = first_name == "S" || second_name == "S";
+                assert_eq!(
+                    RematchOnSC2Crash.rematch(&results),
+                    crashed,
+                    "{} then {}",
+                    first_name,
+                    second_name
+                );
+                if !crashed {
+                    let expected = expected_default(first_name, second_name);
+                    assert_eq!(results, expected.to_vec());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_rematch_policy_sc2_crash_ends_game() {
+        let mut results = vec![None; 2];
+        RematchOnSC2Crash.apply(
+            1,
+            &ToGameContent::SC2UnexpectedConnectionClose,
+            &mut results,
+        );
+        assert_eq!(results, vec![Some(SC2Crash), Some(SC2Crash)]);
+    }
+
+    #[test]
+    fn test_reconcile() {
+        assert_eq!(reconcile(&[Some(vec![Victory, Defeat]), None]), None);
+        assert_eq!(
+            reconcile(&[Some(vec![Victory, Defeat]), Some(vec![Victory, Defeat])]),
+            Some(Reconciliation {
+                results: vec![Victory, Defeat],
+                discrepancy: false
+            })
+        );
+        // Inconsistent first report loses to a consistent second one
+        assert_eq!(
+            reconcile(&[Some(vec![Victory, Victory]), Some(vec![Defeat, Victory])]),
+            Some(Reconciliation {
+                results: vec![Defeat, Victory],
+                discrepancy: true
+            })
+        );
+        // Both consistent but different: the first one is kept
+        assert_eq!(
+            reconcile(&[Some(vec![Tie, Tie]), Some(vec![Victory, Defeat])]),
+            Some(Reconciliation {
+                results: vec![Tie, Tie],
+                discrepancy: true
+            })
+        );
+        // Neither consistent: the first one is kept
+        assert_eq!(
+            reconcile(&[Some(vec![Defeat, Defeat]), Some(vec![Victory, Victory])]),
+            Some(Reconciliation {
+                results: vec![Defeat, Defeat],
+                discrepancy: true
+            })
+        );
+    }
+}
@@ -0,0 +1,169 @@
+//! Live progress of a running game, for supervisors that show matches while they play
+//!
+//! With `ProgressInterval`, every player records the game loop, supply and average frame
+//! time of its last observation. The first player to reach the next multiple of the
+//! interval sends the state of all players to the supervisor, so a game reports once per
+//! interval however the bots step. Progress needs parsed observations, it is not tracked
+//! in transparent mode.
+
+use crate::channels::TelemetrySender;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use super::messaging::ToSupervisor;
+use super::snapshot::StateSnapshot;
+
+/// State of one player at its last observation
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub struct PlayerProgress {
+    #[serde(rename = "GameLoop")]
+    pub game_loop: u32,
+    #[serde(rename = "FoodUsed")]
+    pub food_used: u32,
+    #[serde(rename = "FoodCap")]
+    pub food_cap: u32,
+    /// Seconds per game loop the bot took so far, as `AverageFrameTime` in the result
+    #[serde(rename = "AverageFrameTime")]
+    pub average_frame_time: f32,
+}
+
+/// Progress of a game, sent every `ProgressInterval` game loops
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameProgress {
+    /// Game loop of the player that reached the interval
+    pub game_loop: u32,
+    pub players: HashMap<String, PlayerProgress>,
+}
+impl GameProgress {
+    /// Message sent to the supervisor
+    pub fn to_message(&self, game_id: u64, match_id: i64) -> String {
+        serde_json::json!({
+            "Progress": {
+                "GameId": game_id,
+                "MatchID": match_id,
+                "GameLoop": self.game_loop,
+                "Players": self.players,
+            }
+        })
+        .to_string()
+    }
+}
+
+struct State {
+    players: Vec<PlayerProgress>,
+    /// Game loop at which the next progress is sent
+    next_report: u32,
+}
+
+struct Shared {
+    interval: u32,
+    names: Vec<String>,
+    state: Mutex<State>,
+    to_sv: TelemetrySender<ToSupervisor>,
+}
+
+/// Handle of one player on the progress of its game
+#[derive(Clone)]
+pub struct Progress {
+    shared: Arc<Shared>,
+    index: usize,
+}
+impl std::fmt::Debug for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Progress")
+            .field("interval", &self.shared.interval)
+            .field("index", &self.index)
+            .finish()
+    }
+}
+impl Progress {
+    /// One handle per player, named as in the result, reporting to `to_sv`
+    pub fn for_players(
+        names: Vec<String>,
+        interval: u32,
+        to_sv: TelemetrySender<ToSupervisor>,
+    ) -> Vec<Self> {
+        let count = names.len();
+        let shared = Arc::new(Shared {
+            interval: interval.max(1),
+            names,
+            state: Mutex::new(State {
+                players: vec![PlayerProgress::default(); count],
+                next_report: interval.max(1),
+            }),
+            to_sv,
+        });
+        (0..count)
+            .map(|index| Self {
+                shared: shared.clone(),
+                index,
+            })
+            .collect()
+    }
+
+    /// Record the last observation of the player, reporting once the game reached the interval
+    pub fn record(&self, snapshot: &StateSnapshot, average_frame_time: f32) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.players[self.index] = PlayerProgress {
+            game_loop: snapshot.game_loop,
+            food_used: snapshot.food_used,
+            food_cap: snapshot.food_cap,
+            average_frame_time,
+        };
+        if snapshot.game_loop < state.next_report {
+            return;
+        }
+        let interval = self.shared.interval;
+        state.next_report = (snapshot.game_loop / interval + 1) * interval;
+        let players = self
+            .shared
+            .names
+            .iter()
+            .cloned()
+            .zip(state.players.iter().copied())
+            .collect();
+        self.shared.to_sv.send(ToSupervisor::Progress(GameProgress {
+            game_loop: snapshot.game_loop,
+            players,
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channels::telemetry;
+
+    #[test]
+    fn test_record() {
+        let (tx, rx) = telemetry(8);
+        let players = Progress::for_players(vec!["A".to_string(), "B".to_string()], 100, tx);
+        let snapshot = |game_loop, food_used| StateSnapshot {
+            game_loop,
+            food_used,
+            food_cap: 15,
+            ..StateSnapshot::default()
+        };
+        players[0].record(&snapshot(96, 12), 0.01);
+        assert!(rx.try_recv().is_none());
+        players[1].record(&snapshot(104, 13), 0.02);
+        let progress = match rx.try_recv() {
+            Some(ToSupervisor::Progress(progress)) => progress,
+            _ => panic!("Expected Progress"),
+        };
+        assert_eq!(progress.game_loop, 104);
+        assert_eq!(progress.players["A"].game_loop, 96);
+        assert_eq!(progress.players["B"].food_used, 13);
+        // The slower player reaching the same interval does not report it again
+        players[0].record(&snapshot(112, 14), 0.01);
+        assert!(rx.try_recv().is_none());
+        players[0].record(&snapshot(320, 20), 0.01);
+        assert!(rx.try_recv().is_some());
+        players[1].record(&snapshot(392, 21), 0.02);
+        assert!(rx.try_recv().is_none());
+        assert!(progress
+            .to_message(3, 5)
+            .starts_with("{\"Progress\":{\"GameId\":3,"));
+    }
+}
@@ -0,0 +1,91 @@
+//! Bots falling behind a game played in real time
+//!
+//! In real time SC2 runs the game on its own clock, so a bot that thinks too long misses the
+//! game loops in between and sees the game jump ahead. With `RealtimeMaxLag` every observation
+//! of a bot is compared to its previous one: a bot that missed more game loops lagged, which
+//! is logged, counted for the result and, with `RealtimeLagStrikes`, costs it a strike.
+
+#[cfg(feature = "supervisor")]
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::{warn_limited, WARNING_LIMIT};
+use crate::config::Config;
+
+/// Times a bot fell behind the game
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "supervisor", derive(JsonSchema))]
+pub struct LagReport {
+    /// Observations after which the bot missed more than `RealtimeMaxLag` game loops
+    #[serde(rename = "Lags")]
+    pub lags: u32,
+    /// Most game loops missed between two observations
+    #[serde(rename = "MaxLoops")]
+    pub max_loops: u32,
+}
+
+/// Game loops one bot of a real time game observed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RealtimeLag {
+    max_lag: u32,
+    last_loop: Option<u32>,
+    pub report: LagReport,
+}
+impl RealtimeLag {
+    pub fn new(max_lag: u32) -> Self {
+        Self {
+            max_lag,
+            last_loop: None,
+            report: LagReport::default(),
+        }
+    }
+    /// One per player of a real time game with `RealtimeMaxLag`, none otherwise
+    pub fn for_players(config: &Config, count: usize) -> Vec<Self> {
+        match config.realtime_max_lag() {
+            Some(max_lag) if !config.transparent() => {
+                (0..count).map(|_| Self::new(max_lag)).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+    /// Record an observation at `game_loop`, returning the game loops missed if the bot lagged
+    pub fn observe(&mut self, player_id: Option<u32>, game_loop: u32) -> Option<u32> {
+        let last = self.last_loop.replace(game_loop)?;
+        let missed = game_loop.saturating_sub(last);
+        if missed <= self.max_lag {
+            return None;
+        }
+        self.report.max_loops = self.report.max_loops.max(missed);
+        warn_limited(
+            &mut self.report.lags,
+            WARNING_LIMIT,
+            format_args!(
+                "{:?}: Fell {} game loops behind, more than {}",
+                player_id, missed, self.max_lag
+            ),
+        );
+        Some(missed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_observe() {
+        let mut lag = RealtimeLag::new(16);
+        assert_eq!(lag.observe(Some(1), 400), None);
+        assert_eq!(lag.observe(Some(1), 416), None);
+        assert_eq!(lag.observe(Some(1), 440), Some(24));
+        assert_eq!(lag.observe(Some(1), 441), None);
+        assert_eq!(lag.observe(Some(1), 541), Some(100));
+        assert_eq!(
+            lag.report,
+            LagReport {
+                lags: 2,
+                max_loops: 100,
+            }
+        );
+    }
+}
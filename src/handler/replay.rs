@@ -0,0 +1,84 @@
+//! Writing replays so that a save racing the end of the game never leaves a
+//! truncated file behind
+//!
+//! Both players save to the same path. Each writes to its own temporary file
+//! and renames it into place, so the file at the path is always a complete replay.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+/// How long SC2 gets to answer a save replay request
+pub const SAVE_REPLAY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Replays are MPQ archives
+const MPQ_MAGIC: &[u8] = b"MPQ";
+
+/// Held for reading while a player saves its replay, and for writing before
+/// a process is killed, so no process of the game dies during a save
+pub type ReplayLock = Arc<RwLock<()>>;
+
+/// Check that SC2 returned something that looks like a replay
+pub fn validate_replay(data: &[u8]) -> Result<(), String> {
+    if data.is_empty() {
+        Err("Replay data is empty".to_string())
+    } else if !data.starts_with(MPQ_MAGIC) {
+        Err("Replay data is not an MPQ archive".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+fn temp_path(path: &Path, player_id: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}.tmp", player_id));
+    PathBuf::from(name)
+}
+
+/// Write `data` to `path` through a temporary file, checking the size on disk
+pub fn write_replay(path: &Path, player_id: u32, data: &[u8]) -> io::Result<()> {
+    let temp = temp_path(path, player_id);
+    let written = (|| {
+        let mut file = File::create(&temp)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        let len = fs::metadata(&temp)?.len();
+        if len != data.len() as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Wrote {} of {} bytes", len, data.len()),
+            ));
+        }
+        fs::rename(&temp, path)
+    })();
+    if written.is_err() {
+        fs::remove_file(&temp).ok();
+    }
+    written
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_replay() {
+        assert!(validate_replay(b"").is_err());
+        assert!(validate_replay(b"not a replay").is_err());
+        let data = b"MPQ\x1b replay";
+        assert!(validate_replay(data).is_ok());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("game.SC2Replay");
+        write_replay(&path, 1, data).unwrap();
+        write_replay(&path, 2, data).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), data);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        let missing = dir.path().join("missing").join("game.SC2Replay");
+        assert!(write_replay(&missing, 1, data).is_err());
+    }
+}
@@ -0,0 +1,98 @@
+//! What a bot saw in its last observation, so disputes about games that ended
+//! abnormally can be judged from the result
+
+use sc2_proto::raw::Alliance;
+use sc2_proto::sc2api::ResponseObservation;
+#[cfg(feature = "supervisor")]
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Metadata of the last observation sent to a bot
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "supervisor", derive(JsonSchema))]
+pub struct StateSnapshot {
+    #[serde(rename = "GameLoop")]
+    pub game_loop: u32,
+    #[serde(rename = "Minerals")]
+    pub minerals: u32,
+    #[serde(rename = "Vespene")]
+    pub vespene: u32,
+    #[serde(rename = "FoodUsed")]
+    pub food_used: u32,
+    #[serde(rename = "FoodCap")]
+    pub food_cap: u32,
+    /// Units of the bot
+    #[serde(rename = "Units")]
+    pub units: u32,
+    /// Enemy units visible to the bot
+    #[serde(rename = "EnemyUnits")]
+    pub enemy_units: u32,
+}
+impl StateSnapshot {
+    pub fn from_observation(response: &ResponseObservation) -> Self {
+        let obs = &response.observation;
+        let common = &obs.player_common;
+        let count = |alliance: Alliance| {
+            obs.raw_data
+                .units
+                .iter()
+                .filter(|unit| unit.alliance() == alliance)
+                .count() as u32
+        };
+        Self {
+            game_loop: obs.game_loop(),
+            minerals: common.minerals(),
+            vespene: common.vespene(),
+            food_used: common.food_used(),
+            food_cap: common.food_cap(),
+            units: count(Alliance::Self_),
+            enemy_units: count(Alliance::Enemy),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protobuf::MessageField;
+    use sc2_proto::raw::{ObservationRaw, Unit};
+    use sc2_proto::sc2api::{Observation, PlayerCommon};
+
+    #[test]
+    fn test_snapshot() {
+        let mut common = PlayerCommon::new();
+        common.set_minerals(450);
+        common.set_food_used(23);
+        common.set_food_cap(31);
+        let mut raw = ObservationRaw::new();
+        for alliance in vec![
+            Alliance::Self_,
+            Alliance::Self_,
+            Alliance::Enemy,
+            Alliance::Neutral,
+        ] {
+            let mut unit = Unit::new();
+            unit.set_alliance(alliance);
+            raw.units.push(unit);
+        }
+        let mut obs = Observation::new();
+        obs.set_game_loop(1344);
+        obs.player_common = MessageField::some(common);
+        obs.raw_data = MessageField::some(raw);
+        let mut response = ResponseObservation::new();
+        response.observation = MessageField::some(obs);
+
+        assert_eq!(
+            StateSnapshot::from_observation(&response),
+            StateSnapshot {
+                game_loop: 1344,
+                minerals: 450,
+                vespene: 0,
+                food_used: 23,
+                food_cap: 31,
+                units: 2,
+                enemy_units: 1,
+            }
+        );
+    }
+}
@@ -0,0 +1,228 @@
+//! Minimal protobuf wire-format sniffer for transparent mode
+//!
+//! Walks the top level of SC2 API messages without decoding them, extracting only what
+//! the proxy needs to detect the end of a game. Malformed frames are reported as errors.
+
+use crate::sc2::PlayerResult;
+
+const REQUEST_LEAVE_GAME: u32 = 5;
+const RESPONSE_QUIT: u32 = 8;
+const RESPONSE_OBSERVATION: u32 = 10;
+const RESPONSE_STATUS: u32 = 99;
+const OBSERVATION_OBSERVATION: u32 = 3;
+const OBSERVATION_PLAYER_RESULT: u32 = 4;
+const OBSERVATION_GAME_LOOP: u32 = 9;
+const PLAYER_RESULT_PLAYER_ID: u32 = 1;
+const PLAYER_RESULT_RESULT: u32 = 2;
+
+/// Frame is not valid protobuf wire data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MalformedFrame;
+
+/// Facts extracted from a response frame
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SniffedResponse {
+    /// Response to a quit request
+    pub quit: bool,
+    /// Response is an observation
+    pub observation: bool,
+    /// Game loop of the observation
+    pub game_loop: Option<u32>,
+    /// Player results of the observation, by player id
+    pub results: Vec<(u32, PlayerResult)>,
+    /// Raw game status
+    pub status: Option<u64>,
+}
+
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+struct Fields<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn varint(&mut self) -> Result<u64, MalformedFrame> {
+        let mut value = 0_u64;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = self.data.split_first().ok_or(MalformedFrame)?;
+            self.data = rest;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(MalformedFrame)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], MalformedFrame> {
+        if len > self.data.len() {
+            return Err(MalformedFrame);
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    /// Next field number and value, None at the end of the message
+    fn next_field(&mut self) -> Result<Option<(u32, Value<'a>)>, MalformedFrame> {
+        if self.data.is_empty() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let field = (key >> 3) as u32;
+        let value = match key & 0x7 {
+            0 => Value::Varint(self.varint()?),
+            1 => {
+                self.take(8)?;
+                Value::Fixed
+            }
+            2 => {
+                let len = self.varint()? as usize;
+                Value::Bytes(self.take(len)?)
+            }
+            5 => {
+                self.take(4)?;
+                Value::Fixed
+            }
+            _ => return Err(MalformedFrame),
+        };
+        Ok(Some((field, value)))
+    }
+}
+
+/// Whether a request frame asks to leave the game
+pub fn sniff_leave_game(request: &[u8]) -> Result<bool, MalformedFrame> {
+    let mut fields = Fields::new(request);
+    let mut leave_game = false;
+    while let Some((field, _)) = fields.next_field()? {
+        leave_game |= field == REQUEST_LEAVE_GAME;
+    }
+    Ok(leave_game)
+}
+
+/// Extract game end information from a response frame
+pub fn sniff_response(response: &[u8]) -> Result<SniffedResponse, MalformedFrame> {
+    let mut sniffed = SniffedResponse::default();
+    let mut fields = Fields::new(response);
+    while let Some((field, value)) = fields.next_field()? {
+        match (field, value) {
+            (RESPONSE_QUIT, _) => sniffed.quit = true,
+            (RESPONSE_STATUS, Value::Varint(status)) => sniffed.status = Some(status),
+            (RESPONSE_OBSERVATION, Value::Bytes(observation)) => {
+                sniffed.observation = true;
+                sniff_observation(observation, &mut sniffed)?;
+            }
+            _ => {}
+        }
+    }
+    Ok(sniffed)
+}
+
+fn sniff_observation(data: &[u8], sniffed: &mut SniffedResponse) -> Result<(), MalformedFrame> {
+    let mut fields = Fields::new(data);
+    while let Some((field, value)) = fields.next_field()? {
+        match (field, value) {
+            (OBSERVATION_OBSERVATION, Value::Bytes(observation)) => {
+                let mut inner = Fields::new(observation);
+                while let Some((field, value)) = inner.next_field()? {
+                    if let (OBSERVATION_GAME_LOOP, Value::Varint(game_loop)) = (field, value) {
+                        sniffed.game_loop = Some(game_loop as u32);
+                    }
+                }
+            }
+            (OBSERVATION_PLAYER_RESULT, Value::Bytes(player_result)) => {
+                let mut inner = Fields::new(player_result);
+                let mut player_id = None;
+                let mut result = None;
+                while let Some((field, value)) = inner.next_field()? {
+                    match (field, value) {
+                        (PLAYER_RESULT_PLAYER_ID, Value::Varint(id)) => player_id = Some(id as u32),
+                        (PLAYER_RESULT_RESULT, Value::Varint(1)) => {
+                            result = Some(PlayerResult::Victory)
+                        }
+                        (PLAYER_RESULT_RESULT, Value::Varint(2)) => {
+                            result = Some(PlayerResult::Defeat)
+                        }
+                        (PLAYER_RESULT_RESULT, Value::Varint(3)) => {
+                            result = Some(PlayerResult::Tie)
+                        }
+                        _ => {}
+                    }
+                }
+                if let (Some(player_id), Some(result)) = (player_id, result) {
+                    sniffed.results.push((player_id, result));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protobuf::Message;
+    use sc2_proto::sc2api::{
+        PlayerResult as ProtoPlayerResult, Request, RequestLeaveGame, Response,
+        ResponseObservation, ResponseQuit, Result as ProtoResult, Status,
+    };
+
+    #[test]
+    fn test_sniff_observation() {
+        let mut observation = ResponseObservation::new();
+        observation
+            .observation
+            .mut_or_insert_default()
+            .set_game_loop(1234);
+        let mut result = ProtoPlayerResult::new();
+        result.set_player_id(2);
+        result.set_result(ProtoResult::Defeat);
+        observation.player_result.push(result);
+        let mut response = Response::new();
+        response.set_observation(observation);
+        response.set_status(Status::ended);
+
+        let sniffed = sniff_response(&response.write_to_bytes().unwrap()).unwrap();
+        assert!(sniffed.observation);
+        assert!(!sniffed.quit);
+        assert_eq!(sniffed.game_loop, Some(1234));
+        assert_eq!(sniffed.results, vec![(2, PlayerResult::Defeat)]);
+        assert_eq!(sniffed.status, Some(Status::ended as u64));
+    }
+
+    #[test]
+    fn test_sniff_quit() {
+        let mut response = Response::new();
+        response.set_quit(ResponseQuit::new());
+        let sniffed = sniff_response(&response.write_to_bytes().unwrap()).unwrap();
+        assert!(sniffed.quit);
+        assert!(!sniffed.observation);
+    }
+
+    #[test]
+    fn test_sniff_leave_game() {
+        let mut request = Request::new();
+        request.set_leave_game(RequestLeaveGame::new());
+        request.set_id(7);
+        assert_eq!(
+            sniff_leave_game(&request.write_to_bytes().unwrap()),
+            Ok(true)
+        );
+        assert_eq!(sniff_leave_game(&[]), Ok(false));
+    }
+
+    #[test]
+    fn test_malformed_frame() {
+        assert_eq!(sniff_response(&[0x52, 0x05, 0x01]), Err(MalformedFrame));
+        assert_eq!(sniff_leave_game(&[0xff]), Err(MalformedFrame));
+    }
+}
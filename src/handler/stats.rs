@@ -0,0 +1,97 @@
+//! Score of each bot as SC2 reports it in the observations, for ladder analytics
+
+use sc2_proto::sc2api::ResponseObservation;
+#[cfg(feature = "supervisor")]
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Game loops per real minute at Faster, the speed SC2 reports APM at
+const LOOPS_PER_MINUTE: f32 = 22.4 * 60.0;
+
+/// Score details of the last observation sent to a bot
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "supervisor", derive(JsonSchema))]
+pub struct PlayerStats {
+    /// Score shown on the SC2 score screen
+    #[serde(rename = "Score")]
+    pub score: i32,
+    #[serde(rename = "CollectedMinerals")]
+    pub collected_minerals: u32,
+    #[serde(rename = "CollectedVespene")]
+    pub collected_vespene: u32,
+    #[serde(rename = "SpentMinerals")]
+    pub spent_minerals: u32,
+    #[serde(rename = "SpentVespene")]
+    pub spent_vespene: u32,
+    /// Resource value of the enemy units the bot killed
+    #[serde(rename = "KilledValueUnits")]
+    pub killed_value_units: u32,
+    /// Resource value of the enemy structures the bot killed
+    #[serde(rename = "KilledValueStructures")]
+    pub killed_value_structures: u32,
+    /// Actions the bot sent to SC2
+    #[serde(rename = "Actions")]
+    pub actions: u32,
+    /// Actions per minute of game time at Faster
+    #[serde(rename = "APM")]
+    pub apm: f32,
+}
+impl PlayerStats {
+    /// Stats of a bot that sent `actions` actions until this observation
+    pub fn from_observation(response: &ResponseObservation, actions: u32) -> Self {
+        let obs = &response.observation;
+        let details = &obs.score.score_details;
+        let game_loop = obs.game_loop();
+        Self {
+            score: obs.score.score(),
+            collected_minerals: details.collected_minerals() as u32,
+            collected_vespene: details.collected_vespene() as u32,
+            spent_minerals: details.spent_minerals() as u32,
+            spent_vespene: details.spent_vespene() as u32,
+            killed_value_units: details.killed_value_units() as u32,
+            killed_value_structures: details.killed_value_structures() as u32,
+            actions,
+            apm: if game_loop == 0 {
+                0.0
+            } else {
+                actions as f32 * LOOPS_PER_MINUTE / game_loop as f32
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protobuf::MessageField;
+    use sc2_proto::sc2api::Observation;
+    use sc2_proto::score::{Score, ScoreDetails};
+
+    #[test]
+    fn test_stats() {
+        let mut details = ScoreDetails::new();
+        details.set_collected_minerals(4250.0);
+        details.set_collected_vespene(1100.0);
+        details.set_killed_value_units(800.0);
+        let mut score = Score::new();
+        score.set_score(3120);
+        score.score_details = MessageField::some(details);
+        let mut obs = Observation::new();
+        obs.set_game_loop(2688);
+        obs.score = MessageField::some(score);
+        let mut response = ResponseObservation::new();
+        response.observation = MessageField::some(obs);
+
+        let stats = PlayerStats::from_observation(&response, 300);
+        assert_eq!(stats.score, 3120);
+        assert_eq!(stats.collected_minerals, 4250);
+        assert_eq!(stats.collected_vespene, 1100);
+        assert_eq!(stats.killed_value_units, 800);
+        assert_eq!(stats.spent_minerals, 0);
+        assert!((stats.apm - 150.0).abs() < 1e-3);
+        assert_eq!(
+            PlayerStats::from_observation(&ResponseObservation::new(), 0).apm,
+            0.0
+        );
+    }
+}
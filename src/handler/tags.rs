@@ -0,0 +1,200 @@
+//! Tags bots send as `Tag:<tag>` chat messages, e.g. to mark the strategy they play
+//!
+//! Tags end up in the result, so they are limited in number, length and characters
+//! to keep bots from passing arbitrary data through them. With `LiveTags` the supervisor
+//! is also sent the new tags of a bot while the game runs, at most once per second.
+
+use crate::channels::TelemetrySender;
+use crate::config::Config;
+use std::collections::HashSet;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use super::messaging::ToSupervisor;
+
+/// Chat prefix of a tag
+pub const TAG_PREFIX: &str = "Tag:";
+
+/// Least time between two `NewTags` messages of a bot
+const FEED_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Limits of the tags of one bot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TagLimits {
+    /// Most distinct tags kept
+    pub max_tags: usize,
+    /// Most characters of a tag
+    pub max_length: usize,
+}
+impl TagLimits {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            max_tags: config.max_tags(),
+            max_length: config.max_tag_length(),
+        }
+    }
+    /// The tag as it is kept, or why it is dropped
+    /// Tags may only use ASCII letters, digits, `_`, `-` and `.`
+    pub fn sanitize(&self, tag: &str) -> Result<String, String> {
+        let tag = tag.trim();
+        if tag.is_empty() {
+            return Err("Empty tag".to_string());
+        }
+        if tag.chars().count() > self.max_length {
+            return Err(format!("Tag longer than {} characters", self.max_length));
+        }
+        if let Some(c) = tag
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.')))
+        {
+            return Err(format!("Tag contains {:?}", c));
+        }
+        Ok(tag.to_string())
+    }
+    /// Check a tag against the ones a bot kept: the tag to keep, None if it was kept
+    /// before, or why it is dropped, which counts as `TagSpam`
+    pub fn admit(&self, kept: &HashSet<String>, tag: &str) -> Result<Option<String>, String> {
+        match self.sanitize(tag)? {
+            tag if kept.contains(&tag) => Ok(None),
+            _ if kept.len() >= self.max_tags => Err(format!("More than {} tags", self.max_tags)),
+            tag => Ok(Some(tag)),
+        }
+    }
+}
+
+/// Tags a bot sent since the last `NewTags` message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewTags {
+    pub player: String,
+    /// Game loop of the bot when the tags were sent on
+    pub game_loop: u32,
+    pub tags: Vec<String>,
+}
+impl NewTags {
+    /// Message sent to the supervisor
+    pub fn to_message(&self, game_id: u64, match_id: i64) -> String {
+        serde_json::json!({
+            "NewTags": {
+                "GameId": game_id,
+                "MatchID": match_id,
+                "Player": self.player,
+                "GameLoop": self.game_loop,
+                "Tags": self.tags,
+            }
+        })
+        .to_string()
+    }
+}
+
+/// Sends the new tags of one bot to the supervisor, at most once per `FEED_INTERVAL`
+/// Tags still pending when the game ends are only in the result
+pub struct TagFeed {
+    player: String,
+    to_sv: TelemetrySender<ToSupervisor>,
+    pending: Vec<String>,
+    last_sent: Option<Instant>,
+}
+impl fmt::Debug for TagFeed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TagFeed")
+            .field("player", &self.player)
+            .field("pending", &self.pending)
+            .finish()
+    }
+}
+impl TagFeed {
+    pub fn new(player: String, to_sv: TelemetrySender<ToSupervisor>) -> Self {
+        Self {
+            player,
+            to_sv,
+            pending: Vec::new(),
+            last_sent: None,
+        }
+    }
+    /// Queue a tag the bot did not send before
+    pub fn push(&mut self, tag: String) {
+        self.pending.push(tag);
+    }
+    /// Send the pending tags, unless the last message went out less than `FEED_INTERVAL` ago
+    pub fn flush(&mut self, now: Instant, game_loop: u32) {
+        if self.pending.is_empty() {
+            return;
+        }
+        if let Some(last) = self.last_sent {
+            if now.saturating_duration_since(last) < FEED_INTERVAL {
+                return;
+            }
+        }
+        self.last_sent = Some(now);
+        self.to_sv.send(ToSupervisor::NewTags(NewTags {
+            player: self.player.clone(),
+            game_loop,
+            tags: std::mem::take(&mut self.pending),
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channels::telemetry;
+
+    #[test]
+    fn test_sanitize() {
+        let limits = TagLimits {
+            max_tags: 2,
+            max_length: 8,
+        };
+        assert_eq!(limits.sanitize(" proxy_2 "), Ok("proxy_2".to_string()));
+        assert_eq!(limits.sanitize("v1.2-b"), Ok("v1.2-b".to_string()));
+        assert!(limits.sanitize("  ").is_err());
+        assert!(limits.sanitize("cannon_rush").is_err());
+        assert!(limits.sanitize("a b").is_err());
+        assert!(limits.sanitize("{\"x\":1}").is_err());
+    }
+
+    #[test]
+    fn test_admit() {
+        let limits = TagLimits {
+            max_tags: 1,
+            max_length: 8,
+        };
+        let mut kept = HashSet::new();
+        assert_eq!(limits.admit(&kept, "rush"), Ok(Some("rush".to_string())));
+        kept.insert("rush".to_string());
+        assert_eq!(limits.admit(&kept, " rush"), Ok(None));
+        assert!(limits.admit(&kept, "macro").is_err());
+        assert!(limits.admit(&kept, "a b").is_err());
+    }
+
+    #[test]
+    fn test_feed() {
+        let (tx, rx) = telemetry(8);
+        let mut feed = TagFeed::new("Bot1".to_string(), tx);
+        let start = Instant::now();
+        feed.flush(start, 10);
+        assert!(rx.try_recv().is_none());
+        feed.push("proxy_rax".to_string());
+        feed.flush(start, 10);
+        let tags = match rx.try_recv() {
+            Some(ToSupervisor::NewTags(tags)) => tags,
+            _ => panic!("Expected NewTags"),
+        };
+        assert_eq!(tags.tags, vec!["proxy_rax"]);
+        assert_eq!(tags.game_loop, 10);
+        // Tags within the interval wait for the next flush after it
+        feed.push("cannon_rush".to_string());
+        feed.push("all_in".to_string());
+        feed.flush(start + Duration::from_millis(500), 20);
+        assert!(rx.try_recv().is_none());
+        feed.flush(start + Duration::from_secs(1), 30);
+        let tags = match rx.try_recv() {
+            Some(ToSupervisor::NewTags(tags)) => tags,
+            _ => panic!("Expected NewTags"),
+        };
+        assert_eq!(tags.tags, vec!["cannon_rush", "all_in"]);
+        assert!(tags
+            .to_message(3, 5)
+            .starts_with("{\"NewTags\":{\"GameId\":3,"));
+    }
+}
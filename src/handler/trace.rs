@@ -0,0 +1,125 @@
+//! Per-frame timing trace of a match, so bot authors can see which frames
+//! blew their budget instead of only the average frame time
+//!
+//! Both players append to one CSV file with the columns
+//! `game_loop,player,frame_time_ms,request_type,bytes`.
+//! Rows past the size cap are dropped and counted.
+
+use csv::WriterBuilder;
+use log::{error, warn};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Trace shared by the players of a game
+pub type SharedTrace = Arc<Mutex<FrameTrace>>;
+
+#[derive(Serialize)]
+struct TraceRow<'a> {
+    game_loop: u32,
+    player: &'a str,
+    frame_time_ms: f64,
+    request_type: &'a str,
+    bytes: usize,
+}
+
+/// CSV trace file with a size cap
+pub struct FrameTrace {
+    file: BufWriter<File>,
+    written: u64,
+    max_bytes: u64,
+    dropped: u64,
+}
+impl FrameTrace {
+    /// Create the trace file at `path`, writing the header
+    pub fn create(path: &Path, max_bytes: u64) -> io::Result<Self> {
+        let mut trace = Self {
+            file: BufWriter::new(File::create(path)?),
+            written: 0,
+            max_bytes,
+            dropped: 0,
+        };
+        trace.write(b"game_loop,player,frame_time_ms,request_type,bytes\n")?;
+        Ok(trace)
+    }
+
+    pub fn shared(self) -> SharedTrace {
+        Arc::new(Mutex::new(self))
+    }
+
+    fn write(&mut self, line: &[u8]) -> io::Result<()> {
+        self.file.write_all(line)?;
+        self.written += line.len() as u64;
+        Ok(())
+    }
+
+    /// Append a frame, unless the trace reached its size cap
+    pub fn record(
+        &mut self,
+        game_loop: u32,
+        player: &str,
+        frame_time_ms: f64,
+        request_type: &str,
+        bytes: usize,
+    ) {
+        let mut writer = WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(Vec::new());
+        let line = writer
+            .serialize(TraceRow {
+                game_loop,
+                player,
+                frame_time_ms,
+                request_type,
+                bytes,
+            })
+            .ok()
+            .and_then(|_| writer.into_inner().ok())
+            .unwrap_or_default();
+        if self.written + line.len() as u64 > self.max_bytes {
+            if self.dropped == 0 {
+                warn!("Frame trace reached its size cap, dropping further frames");
+            }
+            self.dropped += 1;
+            return;
+        }
+        if let Err(e) = self.write(&line) {
+            error!("Could not write the frame trace: {:?}", e);
+            self.dropped += 1;
+        }
+    }
+
+    /// Frames not written because of the size cap or errors
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::request_type;
+    use sc2_proto::sc2api::{Request, RequestObservation};
+
+    #[test]
+    fn test_trace_size_cap() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trace.csv");
+        let mut trace = FrameTrace::create(&path, 120).unwrap();
+        let mut request = Request::new();
+        request.set_observation(RequestObservation::new());
+        for game_loop in 0..4 {
+            trace.record(game_loop, "Bot, 1", 1.5, request_type(&request), 12);
+        }
+        assert_eq!(trace.dropped(), 2);
+        drop(trace);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "game_loop,player,frame_time_ms,request_type,bytes\n\
+            0,\"Bot, 1\",1.5,Observation,12\n\
+            1,\"Bot, 1\",1.5,Observation,12\n"
+        );
+    }
+}
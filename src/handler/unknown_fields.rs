@@ -0,0 +1,96 @@
+//! Fields of requests and responses the proxy's protocol version does not know,
+//! a sign that a bot framework, the proxy and SC2 disagree on the protocol
+//!
+//! Only the top level of a request or response is inspected, e.g. request types
+//! added by a newer protocol. With `StripUnknownFields` they are removed before
+//! the message is forwarded, otherwise they are passed through.
+
+use protobuf::Message;
+#[cfg(feature = "supervisor")]
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::{warn_limited, WARNING_LIMIT};
+
+/// Numbers of the unknown top level fields of `message`
+pub fn unknown_field_numbers<M: Message>(message: &M) -> Vec<u32> {
+    message
+        .unknown_fields()
+        .iter()
+        .map(|(number, _)| number)
+        .collect()
+}
+
+/// Unknown fields a player's bot sent and received
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "supervisor", derive(JsonSchema))]
+pub struct UnknownFieldCounts {
+    /// Fields in requests of the bot
+    #[serde(rename = "Requests")]
+    pub requests: u32,
+    /// Fields in responses of SC2
+    #[serde(rename = "Responses")]
+    pub responses: u32,
+}
+impl UnknownFieldCounts {
+    pub fn is_empty(&self) -> bool {
+        self.requests == 0 && self.responses == 0
+    }
+    /// Count the unknown `fields` of a request, warning about the first ones
+    pub fn record_request(&mut self, player_id: Option<u32>, fields: &[u32]) {
+        record(&mut self.requests, "request", player_id, fields);
+    }
+    /// Count the unknown `fields` of a response, warning about the first ones
+    pub fn record_response(&mut self, player_id: Option<u32>, fields: &[u32]) {
+        record(&mut self.responses, "response", player_id, fields);
+    }
+}
+
+fn record(count: &mut u32, kind: &str, player_id: Option<u32>, fields: &[u32]) {
+    for field in fields {
+        warn_limited(
+            count,
+            WARNING_LIMIT,
+            format_args!(
+                "{:?}: Unknown field {} in a {}, the protocol versions might not match",
+                player_id, field, kind
+            ),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sc2_proto::sc2api::{Request, RequestPing};
+
+    #[test]
+    fn test_unknown_fields() {
+        let mut request = Request::new();
+        request.set_ping(RequestPing::new());
+        request.mut_unknown_fields().add_varint(1000, 1);
+        let data = request.write_to_bytes().unwrap();
+
+        let mut parsed = Request::new();
+        parsed.merge_from_bytes(&data).unwrap();
+        let fields = unknown_field_numbers(&parsed);
+        assert_eq!(fields, vec![1000]);
+
+        let mut counts = UnknownFieldCounts::default();
+        assert!(counts.is_empty());
+        counts.record_request(Some(1), &fields);
+        counts.record_response(Some(1), &[]);
+        assert_eq!(
+            counts,
+            UnknownFieldCounts {
+                requests: 1,
+                responses: 0
+            }
+        );
+
+        parsed.mut_unknown_fields().clear();
+        let stripped = Request::parse_from_bytes(&parsed.write_to_bytes().unwrap()).unwrap();
+        assert!(unknown_field_numbers(&stripped).is_empty());
+        assert!(stripped.has_ping());
+    }
+}
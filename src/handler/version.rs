@@ -0,0 +1,71 @@
+//! SC2 version each player's process runs, compared before the game is created
+//! since processes on partially installed builds desync without an error
+
+use sc2_proto::sc2api::ResponsePing;
+#[cfg(feature = "supervisor")]
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Version an SC2 process reported in its Ping response
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "supervisor", derive(JsonSchema))]
+pub struct Sc2Version {
+    #[serde(rename = "GameVersion")]
+    pub game_version: String,
+    #[serde(rename = "BaseBuild")]
+    pub base_build: u32,
+    #[serde(rename = "DataVersion")]
+    pub data_version: String,
+}
+impl Sc2Version {
+    pub fn from_ping(ping: &ResponsePing) -> Self {
+        Self {
+            game_version: ping.game_version().to_string(),
+            base_build: ping.base_build(),
+            data_version: ping.data_version().to_string(),
+        }
+    }
+
+    /// The version all processes run, or a description of how they differ
+    pub fn common(versions: &[Self]) -> Result<Self, String> {
+        let first = versions.first().cloned().unwrap_or_default();
+        if versions.iter().all(|version| *version == first) {
+            Ok(first)
+        } else {
+            let described: Vec<String> = versions
+                .iter()
+                .map(|v| {
+                    format!(
+                        "{} (Base{}, data {})",
+                        v.game_version, v.base_build, v.data_version
+                    )
+                })
+                .collect();
+            Err(described.join(" vs "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_common() {
+        let mut ping = ResponsePing::new();
+        ping.set_game_version("4.10.0.75689".to_string());
+        ping.set_base_build(75689);
+        ping.set_data_version("B89B5D6FA7CBF6452E721311BFBC6CB2".to_string());
+        let version = Sc2Version::from_ping(&ping);
+        assert_eq!(
+            Sc2Version::common(&[version.clone(), version.clone()]),
+            Ok(version.clone())
+        );
+
+        ping.set_game_version("5.0.5.81009".to_string());
+        ping.set_base_build(81009);
+        let other = Sc2Version::from_ping(&ping);
+        let error = Sc2Version::common(&[version, other]).unwrap_err();
+        assert!(error.contains("Base75689") && error.contains("Base81009"));
+    }
+}
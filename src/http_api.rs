@@ -0,0 +1,394 @@
+//! Optional HTTP control API, for CI systems that do not implement the supervisor websocket protocol
+//!
+//! - `POST /match` with a Config body starts a match, bots connect with the tokens listed
+//!   next to the Config under `Tokens` if the proxy checks them
+//! - `GET /match/{MatchID}` returns its status, and the result once it finished
+//! - `DELETE /match/{MatchID}` aborts it
+//! - `GET /connections` lists the open client connections
+//!
+//! With a supervisor secret, every request must send it in the `supervisor-secret` header.
+
+use crate::auth::{SharedAuth, SUPERVISOR_SECRET_HEADER};
+use crate::channels::send_with_backpressure;
+use crate::connections::ConnectionInfo;
+use crate::status::MatchStatus;
+use crossbeam::channel::Sender;
+use log::{debug, error, info};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::oneshot;
+
+/// Largest accepted request body
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// Request from the HTTP API to the server loop
+#[derive(Debug)]
+pub enum HttpCommand {
+    /// Config JSON and the bot tokens to register, answered with the MatchID or a rejection reason
+    SubmitMatch(String, Vec<String>, oneshot::Sender<Result<i64, String>>),
+    Query(i64, oneshot::Sender<Option<MatchStatus>>),
+    /// Answered with whether the match was known
+    Abort(i64, oneshot::Sender<bool>),
+    Connections(oneshot::Sender<Vec<ConnectionInfo>>),
+}
+
+/// Parsed HTTP request
+#[derive(Debug, PartialEq, Eq)]
+struct HttpRequest {
+    method: String,
+    path: String,
+    /// Value of the `supervisor-secret` header
+    secret: Option<String>,
+    body: String,
+}
+
+/// Serve the API until the listener fails
+pub async fn run<A: ToSocketAddrs>(addr: A, commands: Sender<HttpCommand>, auth: SharedAuth) {
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Could not bind HTTP API: {:?}", e);
+            return;
+        }
+    };
+    info!("HTTP API listening on {:?}", listener.local_addr());
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                debug!("HTTP API connection from {:?}", peer);
+                let commands = commands.clone();
+                let auth = auth.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(stream, commands, auth).await {
+                        error!("HTTP API connection failed: {:?}", e);
+                    }
+                });
+            }
+            Err(e) => {
+                error!("HTTP API accept failed: {:?}", e);
+                return;
+            }
+        }
+    }
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    commands: Sender<HttpCommand>,
+    auth: SharedAuth,
+) -> std::io::Result<()> {
+    let (status, body) = match read_request(&mut stream).await? {
+        Some(request) => route(request, &commands, &auth).await,
+        None => (400, error_body("Malformed request")),
+    };
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason_phrase(status),
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+async fn read_request(stream: &mut TcpStream) -> std::io::Result<Option<HttpRequest>> {
+    let mut reader = BufReader::new(stream);
+    let mut head = String::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+        if line == "\r\n" || line == "\n" {
+            break;
+        }
+        head.push_str(&line);
+        if head.len() > MAX_BODY_SIZE {
+            return Ok(None);
+        }
+    }
+    let (request, content_length) = match parse_head(&head) {
+        Some(parsed) => parsed,
+        None => return Ok(None),
+    };
+    if content_length > MAX_BODY_SIZE {
+        return Ok(None);
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await?;
+    Ok(String::from_utf8(body)
+        .ok()
+        .map(|body| HttpRequest { body, ..request }))
+}
+
+/// Request without its body, and the content length of a request head
+fn parse_head(head: &str) -> Option<(HttpRequest, usize)> {
+    let mut lines = head.lines();
+    let mut request_line = lines.next()?.split_whitespace();
+    let method = request_line.next()?.to_string();
+    let path = request_line.next()?.to_string();
+    let mut content_length = 0;
+    let mut secret = None;
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            let name = name.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().ok()?;
+            } else if name.eq_ignore_ascii_case(SUPERVISOR_SECRET_HEADER) {
+                secret = Some(value.trim().to_string());
+            }
+        }
+    }
+    let request = HttpRequest {
+        method,
+        path,
+        secret,
+        body: String::new(),
+    };
+    Some((request, content_length))
+}
+
+async fn route(
+    request: HttpRequest,
+    commands: &Sender<HttpCommand>,
+    auth: &SharedAuth,
+) -> (u16, String) {
+    if !auth.check_supervisor(request.secret.as_deref()) {
+        return (401, error_body("Invalid supervisor secret"));
+    }
+    let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+    match (request.method.as_str(), segments.as_slice()) {
+        ("POST", ["match"]) => {
+            let (config, tokens) = split_tokens(request.body);
+            let (tx, rx) = oneshot::channel();
+            if send_with_backpressure(commands, HttpCommand::SubmitMatch(config, tokens, tx))
+                .await
+                .is_err()
+            {
+                return (503, error_body("Server stopped"));
+            }
+            match rx.await {
+                Ok(Ok(match_id)) => (202, format!("{{\"MatchID\": {}}}", match_id)),
+                Ok(Err(reason)) => (409, error_body(&reason)),
+                Err(_) => (503, error_body("Server stopped")),
+            }
+        }
+        ("GET", ["match", id]) => {
+            let match_id = match id.parse() {
+                Ok(match_id) => match_id,
+                Err(_) => return (400, error_body("Invalid MatchID")),
+            };
+            let (tx, rx) = oneshot::channel();
+            if send_with_backpressure(commands, HttpCommand::Query(match_id, tx))
+                .await
+                .is_err()
+            {
+                return (503, error_body("Server stopped"));
+            }
+            match rx.await {
+                Ok(Some(status)) => (
+                    200,
+                    serde_json::to_string(&status).expect("Could not serialize MatchStatus"),
+                ),
+                Ok(None) => (404, error_body("Unknown match")),
+                Err(_) => (503, error_body("Server stopped")),
+            }
+        }
+        ("DELETE", ["match", id]) => {
+            let match_id = match id.parse() {
+                Ok(match_id) => match_id,
+                Err(_) => return (400, error_body("Invalid MatchID")),
+            };
+            let (tx, rx) = oneshot::channel();
+            if send_with_backpressure(commands, HttpCommand::Abort(match_id, tx))
+                .await
+                .is_err()
+            {
+                return (503, error_body("Server stopped"));
+            }
+            match rx.await {
+                Ok(true) => (200, "{\"Status\": \"Aborted\"}".to_string()),
+                Ok(false) => (404, error_body("Unknown match")),
+                Err(_) => (503, error_body("Server stopped")),
+            }
+        }
+        ("GET", ["connections"]) => {
+            let (tx, rx) = oneshot::channel();
+            if send_with_backpressure(commands, HttpCommand::Connections(tx))
+                .await
+                .is_err()
+            {
+                return (503, error_body("Server stopped"));
+            }
+            match rx.await {
+                Ok(connections) => (
+                    200,
+                    serde_json::to_string(&connections).expect("Could not serialize connections"),
+                ),
+                Err(_) => (503, error_body("Server stopped")),
+            }
+        }
+        (_, ["match"]) | (_, ["match", _]) | (_, ["connections"]) => {
+            (405, error_body("Method not allowed"))
+        }
+        _ => (404, error_body("Not found")),
+    }
+}
+
+/// Config of a `POST /match` body and the bot tokens listed in it under `Tokens`
+/// A body that is not a JSON object is passed on as it is, to be rejected as a Config
+fn split_tokens(body: String) -> (String, Vec<String>) {
+    let mut value: serde_json::Value = match serde_json::from_str(&body) {
+        Ok(value) => value,
+        Err(_) => return (body, Vec::new()),
+    };
+    match value
+        .as_object_mut()
+        .and_then(|config| config.remove("Tokens"))
+    {
+        Some(tokens) => {
+            let tokens = tokens
+                .as_array()
+                .map(|tokens| {
+                    tokens
+                        .iter()
+                        .filter_map(|token| token.as_str())
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or_default();
+            (value.to_string(), tokens)
+        }
+        None => (body, Vec::new()),
+    }
+}
+
+fn error_body(message: &str) -> String {
+    serde_json::json!({ "Error": message }).to_string()
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Auth;
+    use crossbeam::channel;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_parse_head() {
+        let head = "POST /match HTTP/1.1\r\nHost: localhost\r\nContent-Length: 12\r\nSupervisor-Secret: s3cret\r\n";
+        let request = HttpRequest {
+            method: "POST".to_string(),
+            path: "/match".to_string(),
+            secret: Some("s3cret".to_string()),
+            body: String::new(),
+        };
+        assert_eq!(parse_head(head), Some((request, 12)));
+        assert_eq!(parse_head("GET\r\n"), None);
+    }
+
+    #[test]
+    fn test_split_tokens() {
+        let (config, tokens) =
+            split_tokens("{\"Map\": \"AutomatonLE\", \"Tokens\": [\"t1\", \"t2\"]}".to_string());
+        assert_eq!(config, "{\"Map\":\"AutomatonLE\"}");
+        assert_eq!(tokens, vec!["t1".to_string(), "t2".to_string()]);
+        let body = "{\"Map\": \"AutomatonLE\"}".to_string();
+        assert_eq!(split_tokens(body.clone()), (body, Vec::new()));
+        assert_eq!(
+            split_tokens("not json".to_string()),
+            ("not json".to_string(), Vec::new())
+        );
+    }
+
+    #[test]
+    fn test_match_status_json() {
+        let status = MatchStatus::Running { game_id: 3 };
+        assert_eq!(
+            serde_json::to_string(&status).unwrap(),
+            "{\"Status\":\"Running\",\"GameId\":3}"
+        );
+    }
+
+    /// Answers commands as if match 5 was the only one
+    fn spawn_commands() -> Sender<HttpCommand> {
+        let (tx, rx) = channel::unbounded();
+        std::thread::spawn(move || {
+            while let Ok(command) = rx.recv() {
+                match command {
+                    HttpCommand::SubmitMatch(_, _, reply) => reply.send(Ok(5)).unwrap(),
+                    HttpCommand::Query(id, reply) => reply
+                        .send(if id == 5 {
+                            Some(MatchStatus::Pending)
+                        } else {
+                            None
+                        })
+                        .unwrap(),
+                    HttpCommand::Abort(id, reply) => reply.send(id == 5).unwrap(),
+                    HttpCommand::Connections(reply) => reply.send(Vec::new()).unwrap(),
+                }
+            }
+        });
+        tx
+    }
+
+    fn request(method: &str, path: &str, secret: Option<&str>) -> HttpRequest {
+        HttpRequest {
+            method: method.to_string(),
+            path: path.to_string(),
+            secret: secret.map(str::to_string),
+            body: "{\"Map\": \"AutomatonLE\", \"MatchID\": 5}".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_route() {
+        let tx = spawn_commands();
+        let auth = Arc::new(Auth::default());
+        let request = |method: &str, path: &str| request(method, path, None);
+        assert_eq!(
+            route(request("POST", "/match"), &tx, &auth).await,
+            (202, "{\"MatchID\": 5}".to_string())
+        );
+        assert_eq!(route(request("GET", "/match/5"), &tx, &auth).await.0, 200);
+        assert_eq!(route(request("GET", "/match/6"), &tx, &auth).await.0, 404);
+        assert_eq!(route(request("GET", "/match/x"), &tx, &auth).await.0, 400);
+        assert_eq!(
+            route(request("DELETE", "/match/5"), &tx, &auth).await.0,
+            200
+        );
+        assert_eq!(
+            route(request("GET", "/connections"), &tx, &auth).await,
+            (200, "[]".to_string())
+        );
+        assert_eq!(route(request("PUT", "/match"), &tx, &auth).await.0, 405);
+        assert_eq!(route(request("GET", "/"), &tx, &auth).await.0, 404);
+    }
+
+    #[tokio::test]
+    async fn test_supervisor_secret() {
+        let tx = spawn_commands();
+        let auth = Arc::new(Auth::new(Some("s3cret".to_string())));
+        for secret in [None, Some("wrong")] {
+            let (status, _) = route(request("DELETE", "/match/5", secret), &tx, &auth).await;
+            assert_eq!(status, 401);
+        }
+        let (status, _) = route(request("GET", "/match/5", Some("s3cret")), &tx, &auth).await;
+        assert_eq!(status, 200);
+    }
+}
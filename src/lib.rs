@@ -1,96 +1,134 @@
-#[cfg(not(feature = "no-pyo3"))]
+#[cfg(feature = "python")]
 use pyo3::prelude::*;
+#[cfg(feature = "python")]
+use pyo3::types::PyDict;
 
+pub mod auth;
 pub mod build_info;
+#[cfg(feature = "supervisor")]
+pub mod bundle;
+pub mod channels;
+pub mod chunk;
+pub mod clock;
 pub mod config;
+pub mod connections;
+#[cfg(feature = "supervisor")]
 pub mod controller;
+pub mod crypto;
 pub mod errors;
+pub mod fault;
+#[cfg(feature = "fixtures")]
+pub mod fixtures;
 pub mod handler;
+#[cfg(feature = "http-api")]
+pub mod http_api;
+pub mod limits;
+#[cfg(feature = "supervisor")]
+pub mod log_level;
 pub mod maps;
+pub mod mock_sc2;
 pub mod paths;
+#[cfg(feature = "supervisor")]
+pub mod playlist;
+pub mod pool;
 pub mod portconfig;
+pub mod ports;
 pub mod proxy;
+#[cfg(feature = "supervisor")]
 pub mod result;
 pub mod sc2;
 pub mod sc2process;
+#[cfg(feature = "supervisor")]
+pub mod schema;
+#[cfg(feature = "supervisor")]
 pub mod server;
+#[cfg(feature = "supervisor")]
+pub mod session;
+#[cfg(feature = "supervisor")]
+pub mod shutdown;
+#[cfg(feature = "supervisor")]
+pub mod smoke_test;
+#[cfg(feature = "supervisor")]
+pub mod status;
+#[cfg(feature = "supervisor-tls")]
+pub mod supervisor_tls;
+pub mod trace;
+pub mod update;
 
-#[cfg(not(feature = "no-pyo3"))]
+/// Version of the installed SC2 client, as read from its .build.info file
+#[cfg(feature = "python")]
+#[pyfunction]
+fn sc2_version(py: Python) -> PyResult<PyObject> {
+    let info = build_info::BuildInfo::from_file().map_err(|e| {
+        errors::ArenaError::Sc2NotFound(format!("Could not read build info: {}", e))
+    })?;
+    let dict = PyDict::new(py);
+    dict.set_item("version", info.version)?;
+    dict.set_item("base_build", info.base_build)?;
+    dict.set_item("data_build", info.data_build)?;
+    Ok(dict.to_object(py))
+}
+
+/// Paths the proxy resolved for the SC2 installation
+/// `executable` is None if no SC2 version is installed
+#[cfg(feature = "python")]
+#[pyfunction]
+fn sc2_paths(py: Python) -> PyResult<PyObject> {
+    let dict = PyDict::new(py);
+    dict.set_item("base_dir", paths::base_dir())?;
+    dict.set_item("cwd", paths::cwd_dir())?;
+    dict.set_item("map_dir", paths::map_dir())?;
+    dict.set_item("replay_dir", paths::replay_dir())?;
+    dict.set_item(
+        "executable",
+        paths::latest_version(&paths::versions_dir()).map(|(_, path)| path.join(paths::bin_path())),
+    )?;
+    Ok(dict.to_object(py))
+}
+
+/// SC2 builds in the `Versions` folder, oldest first
+/// `game_version` is only known for the build described by .build.info
+#[cfg(feature = "python")]
+#[pyfunction]
+fn installed_versions(py: Python) -> PyResult<Vec<PyObject>> {
+    paths::installed_versions()
+        .into_iter()
+        .map(|version| {
+            let dict = PyDict::new(py);
+            dict.set_item("base_build", version.base_build)?;
+            dict.set_item("path", &version.path)?;
+            dict.set_item("game_version", version.game_version)?;
+            dict.set_item("complete", version.complete)?;
+            Ok(dict.to_object(py))
+        })
+        .collect()
+}
+
+/// Path of a map relative to the SC2 map directory, None if it is not installed
+#[cfg(feature = "python")]
+#[pyfunction]
+#[pyo3(name = "find_map")]
+fn py_find_map(name: String) -> Option<String> {
+    maps::find_map(name)
+}
+
+#[cfg(feature = "python")]
 #[pymodule]
-fn rust_ac(_py: Python, m: &PyModule) -> PyResult<()> {
-    pyo3_log::init();
+fn rust_ac(py: Python, m: &PyModule) -> PyResult<()> {
+    // Python's logging configuration still filters what is forwarded to it
+    log_level::init(
+        Box::new(
+            pyo3_log::Logger::new(py, pyo3_log::Caching::LoggersAndLevels)?
+                .filter(log::LevelFilter::Trace),
+        ),
+        log::LevelFilter::Debug,
+    )
+    .expect("Could not install the logger");
+    errors::python::register(py, m)?;
     m.add_class::<server::PServer>()?;
+    m.add_function(wrap_pyfunction!(sc2_version, m)?)?;
+    m.add_function(wrap_pyfunction!(sc2_paths, m)?)?;
+    m.add_function(wrap_pyfunction!(installed_versions, m)?)?;
+    m.add_function(wrap_pyfunction!(py_find_map, m)?)?;
     Ok(())
 }
-#[cfg(test)]
-mod tests {
-    use super::*;
-    // use crate::config::Config;
-    // use crate::server::RustServer;
-    // use std::path::PathBuf;
-    // use std::process::{Command, Stdio};
-    // use websocket::header::Headers;
-    // use websocket::ClientBuilder;
-    // use websocket::Message;
-
-    // fn start_bot(cwd: String) {
-    //     let bot_file = "run.py";
-    //
-    //     let process = (Command::new("python3")
-    //         .stdout(Stdio::piped())
-    //         .stderr(Stdio::piped())
-    //         .arg(bot_file)
-    //         .arg("--GamePort")
-    //         .arg("8642")
-    //         .arg("--LadderServer")
-    //         .arg("127.0.0.1")
-    //         .arg("--StartPort")
-    //         .arg("8642")
-    //         .arg("--OpponentId")
-    //         .arg("123")
-    //         .current_dir(PathBuf::from(&cwd)))
-    //     .spawn()
-    //     .expect("Could not launch Bot");
-    // }
-    //
-    // #[test]
-    // fn test_tie() {
-    //     let server = RustServer::new("127.0.0.1:8642");
-    //     let _t = server.run();
-    //     let mut sup_headers = Headers::new();
-    //     sup_headers.set_raw("supervisor", vec![b"true".to_vec()]);
-    //     let mut supervisor = ClientBuilder::new("ws://127.0.0.1:8642/sc2api")
-    //         .unwrap()
-    //         .custom_headers(&sup_headers)
-    //         .connect_insecure()
-    //         .unwrap();
-    //     let _msg = supervisor.recv_message().expect("Could not receive");
-    //     let config = Config {
-    //         pids: vec![],
-    //         average_frame_time: vec![],
-    //         map: "AutomatonLE".to_string(),
-    //         max_game_time: 100,
-    //         max_frame_time: 1000,
-    //         strikes: 10,
-    //         result: vec![],
-    //         player1: "loser_bot".to_string(),
-    //         player2: "loser_bot".to_string(),
-    //         replay_path: "".to_string(),
-    //         match_id: 1,
-    //         replay_name: "".to_string(),
-    //         game_time: 0.0,
-    //         game_time_seconds: 0.0,
-    //         game_time_formatted: "".to_string(),
-    //         disable_debug: true,
-    //         real_time: false,
-    //         visualize: false,
-    //         light_mode: false,
-    //     };
-    //     supervisor.send_message(&Message::text(serde_json::to_string(&config).unwrap()));
-    //     start_bot("/aiarena-test-bots-master/loser_bot".parse().unwrap());
-    //     supervisor.recv_message().expect("Could not receive");
-    //     start_bot("/aiarena-test-bots-master/loser_bot".parse().unwrap());
-    //     supervisor.recv_message().expect("Could not receive");
-    //     supervisor.recv_message().expect("Could not receive");
-    // }
-}
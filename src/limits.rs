@@ -0,0 +1,564 @@
+//! Memory and CPU caps for the SC2 process of each player
+//!
+//! On Linux the process is moved into its own cgroup (v2) below `SC2_PROXY_CGROUP`,
+//! default `/sys/fs/cgroup/rust_ac`, which has to be writable by the proxy.
+//! On Windows it is assigned to a job object. Elsewhere limits are not enforced.
+//! The CPU cap throttles the process, exceeding the memory cap kills it and is
+//! reported as `PlayerResult::ResourceLimit`.
+//!
+//! The memory of bot processes, and optionally of SC2, is sampled instead: on Linux
+//! the resident memory of a process and its descendants is read from `/proc`.
+//! Elsewhere nothing is sampled. The CPU time of the bots and SC2 is sampled the same way.
+
+#[cfg(feature = "supervisor")]
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::process::Child;
+
+/// Limits of one player, 0 means unlimited
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "supervisor", derive(JsonSchema))]
+#[cfg_attr(feature = "supervisor", schemars(rename_all = "PascalCase"))]
+pub struct ResourceLimits {
+    /// Memory of the SC2 process in MiB
+    #[serde(default, alias = "MemoryMb")]
+    pub memory_mb: u64,
+    /// CPU time in cores, e.g. 1.5 for one and a half cores
+    #[serde(default, alias = "Cpus")]
+    pub cpus: f64,
+    /// Resident memory of the bot process and its children in MiB, checked by sampling
+    #[serde(default, alias = "BotMemoryMb")]
+    pub bot_memory_mb: u64,
+    /// Sample the memory of the SC2 process too, checking it against `memory_mb`
+    #[serde(default, alias = "SampleSc2Memory")]
+    pub sample_sc2_memory: bool,
+}
+impl ResourceLimits {
+    /// Whether no cap is enforced on the SC2 process, sampled limits aside
+    pub fn is_unlimited(&self) -> bool {
+        self.memory_bytes().is_none() && self.cpus().is_none()
+    }
+    pub fn memory_bytes(&self) -> Option<u64> {
+        if self.memory_mb == 0 {
+            None
+        } else {
+            Some(self.memory_mb * 1024 * 1024)
+        }
+    }
+    pub fn bot_memory_bytes(&self) -> Option<u64> {
+        if self.bot_memory_mb == 0 {
+            None
+        } else {
+            Some(self.bot_memory_mb * 1024 * 1024)
+        }
+    }
+    pub fn cpus(&self) -> Option<f64> {
+        if self.cpus > 0.0 {
+            Some(self.cpus)
+        } else {
+            None
+        }
+    }
+}
+
+/// A limit the process ran into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    Memory,
+}
+
+/// Largest resident memory sampled for the processes of a player, in MiB
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "supervisor", derive(JsonSchema))]
+pub struct MemoryPeak {
+    #[serde(default, rename = "BotMb", skip_serializing_if = "Option::is_none")]
+    pub bot_mb: Option<u64>,
+    #[serde(default, rename = "SC2Mb", skip_serializing_if = "Option::is_none")]
+    pub sc2_mb: Option<u64>,
+}
+impl MemoryPeak {
+    pub fn is_empty(&self) -> bool {
+        self.bot_mb.is_none() && self.sc2_mb.is_none()
+    }
+}
+
+/// CPU time used by the processes of a player during a game, in seconds
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "supervisor", derive(JsonSchema))]
+pub struct CpuTime {
+    #[serde(
+        default,
+        rename = "BotSeconds",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub bot_seconds: Option<f64>,
+    #[serde(
+        default,
+        rename = "SC2Seconds",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub sc2_seconds: Option<f64>,
+}
+impl CpuTime {
+    pub fn is_empty(&self) -> bool {
+        self.bot_seconds.is_none() && self.sc2_seconds.is_none()
+    }
+    /// Seconds of all processes
+    pub fn total(&self) -> f64 {
+        self.bot_seconds.unwrap_or_default() + self.sc2_seconds.unwrap_or_default()
+    }
+}
+
+/// User and system CPU time of `pid` and all its descendants in seconds,
+/// including their children that already exited
+/// None if the process is gone or CPU time is not sampled on this platform
+pub fn tree_cpu_seconds(pid: u32) -> Option<f64> {
+    sys::tree_cpu_seconds(pid)
+}
+
+/// Resident memory of `pid` and all its descendants in bytes
+/// None if the process is gone or memory is not sampled on this platform
+pub fn tree_rss_bytes(pid: u32) -> Option<u64> {
+    sys::tree_rss_bytes(pid)
+}
+
+/// Kill `pid` and all its descendants
+pub fn kill_tree(pid: u32) {
+    sys::kill_tree(pid)
+}
+
+/// Limits applied to a running process, lifted when dropped
+pub struct LimitGuard {
+    inner: sys::Guard,
+}
+impl LimitGuard {
+    /// Enforce `limits` on `child`
+    pub fn apply(child: &Child, limits: &ResourceLimits) -> io::Result<Self> {
+        Ok(Self {
+            inner: sys::Guard::apply(child, limits)?,
+        })
+    }
+    /// The limit that ended the process, if any
+    pub fn violation(&self) -> Option<Violation> {
+        self.inner.violation()
+    }
+}
+
+/// Number of OOM kills in a cgroup `memory.events` file
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn oom_kills(events: &str) -> u64 {
+    events
+        .lines()
+        .filter_map(|line| line.strip_prefix("oom_kill "))
+        .filter_map(|count| count.trim().parse().ok())
+        .sum()
+}
+
+/// Resident memory in bytes from a `/proc/<pid>/status` file
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn vm_rss_bytes(status: &str) -> Option<u64> {
+    let line = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?;
+    let kib: u64 = line.trim().strip_suffix("kB")?.trim().parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// Parent PID from a `/proc/<pid>/stat` file
+/// The command name may contain spaces and parentheses, the fields after it do not
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parent_pid(stat: &str) -> Option<u32> {
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// CPU clock ticks of a process and its waited-for children from a `/proc/<pid>/stat` file
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn cpu_ticks(stat: &str) -> Option<u64> {
+    let (_, fields) = stat.rsplit_once(')')?;
+    // utime, stime, cutime and cstime follow the state and nine other fields
+    let ticks: Vec<i64> = fields
+        .split_whitespace()
+        .skip(11)
+        .take(4)
+        .map(|ticks| ticks.parse().ok())
+        .collect::<Option<_>>()?;
+    if ticks.len() == 4 {
+        Some(ticks.into_iter().map(|ticks| ticks.max(0) as u64).sum())
+    } else {
+        None
+    }
+}
+
+/// `root` followed by all its descendants, given the `(pid, parent)` pairs of all processes
+#[cfg_attr(not(any(target_os = "linux", windows)), allow(dead_code))]
+fn process_tree(root: u32, parents: &[(u32, u32)]) -> Vec<u32> {
+    let mut tree = vec![root];
+    let mut next = 0;
+    while next < tree.len() {
+        let parent = tree[next];
+        let children: Vec<u32> = parents
+            .iter()
+            .filter(|(pid, ppid)| *ppid == parent && !tree.contains(pid))
+            .map(|(pid, _)| *pid)
+            .collect();
+        tree.extend(children);
+        next += 1;
+    }
+    tree
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use super::{
+        cpu_ticks, oom_kills, parent_pid, process_tree, vm_rss_bytes, ResourceLimits, Violation,
+    };
+    use log::debug;
+    use std::fs;
+    use std::io;
+    use std::path::PathBuf;
+    use std::process::Child;
+
+    /// `(pid, parent)` of every process
+    fn parents() -> Vec<(u32, u32)> {
+        let entries = match fs::read_dir("/proc") {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+        entries
+            .filter_map(|entry| {
+                let pid: u32 = entry.ok()?.file_name().to_str()?.parse().ok()?;
+                let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+                Some((pid, parent_pid(&stat)?))
+            })
+            .collect()
+    }
+
+    fn rss_bytes(pid: u32) -> Option<u64> {
+        vm_rss_bytes(&fs::read_to_string(format!("/proc/{}/status", pid)).ok()?)
+    }
+
+    pub fn tree_rss_bytes(pid: u32) -> Option<u64> {
+        let root = rss_bytes(pid)?;
+        let children: u64 = process_tree(pid, &parents())
+            .into_iter()
+            .skip(1)
+            .filter_map(rss_bytes)
+            .sum();
+        Some(root + children)
+    }
+
+    pub fn tree_cpu_seconds(pid: u32) -> Option<f64> {
+        let ticks_per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+        if ticks_per_second <= 0 {
+            return None;
+        }
+        let ticks = |pid: u32| cpu_ticks(&fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?);
+        let root = ticks(pid)?;
+        let children: u64 = process_tree(pid, &parents())
+            .into_iter()
+            .skip(1)
+            .filter_map(ticks)
+            .sum();
+        Some((root + children) as f64 / ticks_per_second as f64)
+    }
+
+    pub fn kill_tree(pid: u32) {
+        for pid in process_tree(pid, &parents()) {
+            // Already exited processes are not an error
+            unsafe {
+                libc::kill(pid as libc::pid_t, libc::SIGKILL);
+            }
+        }
+    }
+
+    /// Period of the CFS bandwidth control, in microseconds
+    const CPU_PERIOD: u64 = 100_000;
+
+    fn cgroup_root() -> PathBuf {
+        std::env::var_os("SC2_PROXY_CGROUP")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("/sys/fs/cgroup/rust_ac"))
+    }
+
+    pub struct Guard {
+        dir: PathBuf,
+    }
+    impl Guard {
+        pub fn apply(child: &Child, limits: &ResourceLimits) -> io::Result<Self> {
+            let root = cgroup_root();
+            fs::create_dir_all(&root)?;
+            // Already enabled or managed by the host otherwise
+            fs::write(root.join("cgroup.subtree_control"), "+memory +cpu").ok();
+            let dir = root.join(format!("sc2_{}", child.id()));
+            fs::create_dir_all(&dir)?;
+            let guard = Self { dir };
+            if let Some(bytes) = limits.memory_bytes() {
+                fs::write(guard.dir.join("memory.max"), bytes.to_string())?;
+                // Swapping would only slow the process down instead of enforcing the cap
+                fs::write(guard.dir.join("memory.swap.max"), "0").ok();
+            }
+            if let Some(cpus) = limits.cpus() {
+                let quota = (cpus * CPU_PERIOD as f64).round() as u64;
+                fs::write(
+                    guard.dir.join("cpu.max"),
+                    format!("{} {}", quota, CPU_PERIOD),
+                )?;
+            }
+            fs::write(guard.dir.join("cgroup.procs"), child.id().to_string())?;
+            debug!("SC2 process {} limited by {:?}", child.id(), guard.dir);
+            Ok(guard)
+        }
+        pub fn violation(&self) -> Option<Violation> {
+            let events = fs::read_to_string(self.dir.join("memory.events")).ok()?;
+            if oom_kills(&events) > 0 {
+                Some(Violation::Memory)
+            } else {
+                None
+            }
+        }
+    }
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            // Only succeeds once the process exited, which `Process` ensures
+            fs::remove_dir(&self.dir).ok();
+        }
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use super::{process_tree, ResourceLimits, Violation};
+    use std::ffi::c_void;
+    use std::io;
+    use std::mem::{size_of, zeroed};
+    use std::os::windows::io::AsRawHandle;
+    use std::process::Child;
+    use windows_sys::Win32::Foundation::{CloseHandle, HANDLE, INVALID_HANDLE_VALUE};
+    use windows_sys::Win32::System::Diagnostics::ToolHelp::{
+        CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W,
+        TH32CS_SNAPPROCESS,
+    };
+    use windows_sys::Win32::System::JobObjects::{
+        AssignProcessToJobObject, CreateJobObjectW, JobObjectCpuRateControlInformation,
+        JobObjectExtendedLimitInformation, QueryInformationJobObject, SetInformationJobObject,
+        JOBOBJECT_CPU_RATE_CONTROL_INFORMATION, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+        JOB_OBJECT_CPU_RATE_CONTROL_ENABLE, JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP,
+        JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE, JOB_OBJECT_LIMIT_PROCESS_MEMORY,
+    };
+    use windows_sys::Win32::System::Threading::{OpenProcess, TerminateProcess, PROCESS_TERMINATE};
+
+    fn check(ok: i32) -> io::Result<()> {
+        if ok == 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    pub struct Guard {
+        job: HANDLE,
+        memory: Option<u64>,
+    }
+    // The job handle can be used from any thread
+    unsafe impl Send for Guard {}
+    unsafe impl Sync for Guard {}
+
+    pub fn tree_rss_bytes(_pid: u32) -> Option<u64> {
+        None
+    }
+
+    pub fn tree_cpu_seconds(_pid: u32) -> Option<f64> {
+        None
+    }
+
+    /// `(pid, parent)` of every process, from a ToolHelp snapshot
+    fn parents() -> Vec<(u32, u32)> {
+        let mut parents = Vec::new();
+        unsafe {
+            let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0);
+            if snapshot == INVALID_HANDLE_VALUE {
+                return parents;
+            }
+            let mut entry: PROCESSENTRY32W = zeroed();
+            entry.dwSize = size_of::<PROCESSENTRY32W>() as u32;
+            let mut ok = Process32FirstW(snapshot, &mut entry);
+            while ok != 0 {
+                parents.push((entry.th32ProcessID, entry.th32ParentProcessID));
+                ok = Process32NextW(snapshot, &mut entry);
+            }
+            CloseHandle(snapshot);
+        }
+        parents
+    }
+
+    pub fn kill_tree(pid: u32) {
+        for pid in process_tree(pid, &parents()) {
+            unsafe {
+                let process = OpenProcess(PROCESS_TERMINATE, 0, pid);
+                if process != 0 {
+                    TerminateProcess(process, 1);
+                    CloseHandle(process);
+                }
+            }
+        }
+    }
+
+    impl Guard {
+        pub fn apply(child: &Child, limits: &ResourceLimits) -> io::Result<Self> {
+            unsafe {
+                let job = CreateJobObjectW(std::ptr::null(), std::ptr::null());
+                if job == 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                let guard = Self {
+                    job,
+                    memory: limits.memory_bytes(),
+                };
+                let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = zeroed();
+                info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+                if let Some(bytes) = guard.memory {
+                    info.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_PROCESS_MEMORY;
+                    info.ProcessMemoryLimit = bytes as usize;
+                }
+                check(SetInformationJobObject(
+                    job,
+                    JobObjectExtendedLimitInformation,
+                    &info as *const _ as *const c_void,
+                    size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+                ))?;
+                if let Some(cpus) = limits.cpus() {
+                    let cores = std::thread::available_parallelism()
+                        .map(|n| n.get())
+                        .unwrap_or(1) as f64;
+                    // In 1/100 percent of all cores
+                    let rate = (cpus / cores * 10_000.0).round().clamp(1.0, 10_000.0) as u32;
+                    let mut cpu: JOBOBJECT_CPU_RATE_CONTROL_INFORMATION = zeroed();
+                    cpu.ControlFlags =
+                        JOB_OBJECT_CPU_RATE_CONTROL_ENABLE | JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP;
+                    cpu.Anonymous.CpuRate = rate;
+                    check(SetInformationJobObject(
+                        job,
+                        JobObjectCpuRateControlInformation,
+                        &cpu as *const _ as *const c_void,
+                        size_of::<JOBOBJECT_CPU_RATE_CONTROL_INFORMATION>() as u32,
+                    ))?;
+                }
+                check(AssignProcessToJobObject(
+                    job,
+                    child.as_raw_handle() as HANDLE,
+                ))?;
+                Ok(guard)
+            }
+        }
+        pub fn violation(&self) -> Option<Violation> {
+            let limit = self.memory?;
+            let peak = unsafe {
+                let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = zeroed();
+                check(QueryInformationJobObject(
+                    self.job,
+                    JobObjectExtendedLimitInformation,
+                    &mut info as *mut _ as *mut c_void,
+                    size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+                    std::ptr::null_mut(),
+                ))
+                .ok()?;
+                info.PeakProcessMemoryUsed as u64
+            };
+            if peak >= limit {
+                Some(Violation::Memory)
+            } else {
+                None
+            }
+        }
+    }
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            unsafe {
+                CloseHandle(self.job);
+            }
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+mod sys {
+    use super::{ResourceLimits, Violation};
+    use std::io;
+    use std::process::Child;
+
+    pub fn tree_rss_bytes(_pid: u32) -> Option<u64> {
+        None
+    }
+
+    pub fn tree_cpu_seconds(_pid: u32) -> Option<f64> {
+        None
+    }
+
+    /// Only `pid` itself, its descendants can't be listed without `/proc`
+    #[cfg(unix)]
+    pub fn kill_tree(pid: u32) {
+        unsafe {
+            libc::kill(pid as libc::pid_t, libc::SIGKILL);
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn kill_tree(_pid: u32) {}
+
+    pub struct Guard;
+    impl Guard {
+        pub fn apply(_child: &Child, _limits: &ResourceLimits) -> io::Result<Self> {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Resource limits are only supported on Linux and Windows",
+            ))
+        }
+        pub fn violation(&self) -> Option<Violation> {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits() {
+        assert!(ResourceLimits::default().is_unlimited());
+        let limits: ResourceLimits =
+            serde_json::from_str("{\"MemoryMb\": 2048, \"Cpus\": 1.5}").unwrap();
+        assert_eq!(limits.memory_bytes(), Some(2048 * 1024 * 1024));
+        assert_eq!(limits.cpus(), Some(1.5));
+        assert!(!limits.is_unlimited());
+    }
+
+    #[test]
+    fn test_oom_kills() {
+        let events = "low 0\nhigh 0\nmax 12\noom 1\noom_kill 1\noom_group_kill 0\n";
+        assert_eq!(oom_kills(events), 1);
+        assert_eq!(oom_kills("oom_kill 0\n"), 0);
+    }
+
+    #[test]
+    fn test_process_sampling() {
+        let limits: ResourceLimits = serde_json::from_str("{\"BotMemoryMb\": 512}").unwrap();
+        assert_eq!(limits.bot_memory_bytes(), Some(512 * 1024 * 1024));
+        assert!(limits.is_unlimited());
+
+        let status = "Name:\tpython3\nVmPeak:\t  204800 kB\nVmRSS:\t  102400 kB\n";
+        assert_eq!(vm_rss_bytes(status), Some(100 * 1024 * 1024));
+        assert_eq!(vm_rss_bytes("Name:\tzombie\n"), None);
+        let stat = "4242 (my (bot) main) S 4200 4242 4242 0 -1 4194560";
+        assert_eq!(parent_pid(stat), Some(4200));
+        let stat = "4242 (sc2) R 4200 4242 4242 0 -1 4194560 900 0 0 0 250 50 7 3 20 0 12 0";
+        assert_eq!(cpu_ticks(stat), Some(310));
+        assert_eq!(cpu_ticks("4242 (sc2) Z 4200"), None);
+
+        let parents = [(1, 0), (10, 1), (11, 10), (12, 10), (13, 12), (20, 1)];
+        assert_eq!(process_tree(10, &parents), vec![10, 11, 12, 13]);
+        assert_eq!(process_tree(99, &parents), vec![99]);
+    }
+}
@@ -0,0 +1,193 @@
+//! Log verbosity that single matches can raise without restarting the proxy
+//!
+//! The logger is wrapped in a filter with a base level. A match with `LogLevel` in its
+//! Config holds a `LogLevelGuard` from the moment its config is accepted until its
+//! result is reported, and the proxy logs at the most verbose level of all guards.
+//! Guards only raise the verbosity, never lower it below the base level.
+//!
+//! A `MatchLog` keeps a copy of the records logged while it is held, for the artifact
+//! bundle of its match.
+
+use log::{info, LevelFilter, Log, Metadata, Record, SetLoggerError};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Levels in the order of their `LevelFilter` discriminant
+const LEVELS: [LevelFilter; 6] = [
+    LevelFilter::Off,
+    LevelFilter::Error,
+    LevelFilter::Warn,
+    LevelFilter::Info,
+    LevelFilter::Debug,
+    LevelFilter::Trace,
+];
+
+static BASE: AtomicUsize = AtomicUsize::new(LevelFilter::Error as usize);
+
+/// Live guards per level
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_GUARDS: AtomicUsize = AtomicUsize::new(0);
+static GUARDS: [AtomicUsize; 6] = [NO_GUARDS; 6];
+
+/// Bytes kept per `MatchLog`, later records are dropped
+const MATCH_LOG_MAX_BYTES: usize = 4 * 1024 * 1024;
+
+/// Records of the open match logs, by id
+static MATCH_LOGS: Mutex<Vec<(usize, String)>> = Mutex::new(Vec::new());
+static NEXT_MATCH_LOG: AtomicUsize = AtomicUsize::new(0);
+
+/// Level the proxy currently logs at
+pub fn current() -> LevelFilter {
+    let raised = (0..LEVELS.len())
+        .rev()
+        .find(|&i| GUARDS[i].load(Ordering::SeqCst) > 0)
+        .unwrap_or_default();
+    LEVELS[BASE.load(Ordering::SeqCst).max(raised)]
+}
+
+fn update() {
+    log::set_max_level(current());
+}
+
+/// Filters the records of the logger it wraps by the current level
+struct Reloadable {
+    inner: Box<dyn Log>,
+}
+impl Log for Reloadable {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= current() && self.inner.enabled(metadata)
+    }
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            capture(record);
+            self.inner.log(record)
+        }
+    }
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+/// Install `inner` as the logger, logging at `base` unless a match raised the level
+/// `inner` should accept every level that guards may raise to
+pub fn init(inner: Box<dyn Log>, base: LevelFilter) -> Result<(), SetLoggerError> {
+    BASE.store(base as usize, Ordering::SeqCst);
+    log::set_boxed_logger(Box::new(Reloadable { inner }))?;
+    update();
+    Ok(())
+}
+
+/// Raised log level, held while a match runs and lowered again when dropped
+#[derive(Debug)]
+pub struct LogLevelGuard {
+    level: LevelFilter,
+}
+impl LogLevelGuard {
+    pub fn raise(level: LevelFilter) -> Self {
+        GUARDS[level as usize].fetch_add(1, Ordering::SeqCst);
+        update();
+        info!("Log level raised to {} for a match", level);
+        Self { level }
+    }
+}
+impl Drop for LogLevelGuard {
+    fn drop(&mut self) {
+        GUARDS[self.level as usize].fetch_sub(1, Ordering::SeqCst);
+        update();
+    }
+}
+
+/// Append a record to every open match log
+fn capture(record: &Record) {
+    let mut logs = match MATCH_LOGS.lock() {
+        Ok(logs) if !logs.is_empty() => logs,
+        _ => return,
+    };
+    let line = format!(
+        "{} {} {}: {}\n",
+        chrono::Local::now().to_rfc3339(),
+        record.level(),
+        record.target(),
+        record.args()
+    );
+    for (_, text) in logs.iter_mut() {
+        if text.len() + line.len() <= MATCH_LOG_MAX_BYTES {
+            text.push_str(&line);
+        }
+    }
+}
+
+/// Copy of the proxy log from the moment a match config is accepted until its result is reported
+/// Matches running at the same time see each other's records in their logs
+#[derive(Debug)]
+pub struct MatchLog {
+    id: usize,
+}
+impl MatchLog {
+    pub fn start() -> Self {
+        let id = NEXT_MATCH_LOG.fetch_add(1, Ordering::SeqCst);
+        if let Ok(mut logs) = MATCH_LOGS.lock() {
+            logs.push((id, String::new()));
+        }
+        Self { id }
+    }
+
+    /// Records logged since the start, ending the capture
+    pub fn finish(self) -> String {
+        MATCH_LOGS
+            .lock()
+            .ok()
+            .and_then(|mut logs| {
+                let index = logs.iter().position(|(id, _)| *id == self.id)?;
+                Some(logs.remove(index).1)
+            })
+            .unwrap_or_default()
+    }
+}
+impl Drop for MatchLog {
+    fn drop(&mut self) {
+        if let Ok(mut logs) = MATCH_LOGS.lock() {
+            logs.retain(|(id, _)| *id != self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guards() {
+        let base = current();
+        let debug = LogLevelGuard::raise(LevelFilter::Debug);
+        assert_eq!(current(), LevelFilter::Debug.max(base));
+        let trace = LogLevelGuard::raise(LevelFilter::Trace);
+        assert_eq!(current(), LevelFilter::Trace);
+        drop(trace);
+        assert_eq!(current(), LevelFilter::Debug.max(base));
+        drop(debug);
+        assert_eq!(current(), base);
+        let off = LogLevelGuard::raise(LevelFilter::Off);
+        assert_eq!(current(), base);
+        drop(off);
+    }
+
+    #[test]
+    fn test_match_log() {
+        let log_record = || {
+            capture(
+                &Record::builder()
+                    .level(log::Level::Info)
+                    .target("rust_ac")
+                    .args(format_args!("Game started"))
+                    .build(),
+            )
+        };
+        log_record();
+        let match_log = MatchLog::start();
+        log_record();
+        let text = match_log.finish();
+        assert_eq!(text.lines().count(), 1);
+        assert!(text.ends_with("INFO rust_ac: Game started\n"));
+    }
+}
@@ -1,21 +1,47 @@
+pub mod auth;
 mod build_info;
+pub mod bundle;
+pub mod channels;
+pub mod chunk;
+pub mod clock;
 pub mod config;
+pub mod connections;
 pub mod controller;
+pub mod crypto;
 pub mod handler;
+#[cfg(feature = "http-api")]
+pub mod http_api;
+pub mod limits;
+pub mod log_level;
 pub mod maps;
+mod mock_sc2;
 mod paths;
+pub mod playlist;
+pub mod pool;
 mod portconfig;
+mod ports;
 pub mod proxy;
 mod result;
 pub mod sc2;
 mod sc2process;
+pub mod schema;
 pub mod server;
+pub mod session;
+pub mod shutdown;
+pub mod smoke_test;
+pub mod status;
+#[cfg(feature = "supervisor-tls")]
+pub mod supervisor_tls;
+pub mod trace;
+pub mod update;
 use std::io::Write;
 pub mod errors;
+pub mod fault;
 
 #[tokio::main]
 async fn main() {
-    env_logger::Builder::new()
+    let logger = env_logger::Builder::new()
+        .filter_level(log::LevelFilter::Trace)
         .format(|buf, record| {
             writeln!(
                 buf,
@@ -27,7 +53,163 @@ async fn main() {
                 record.args()
             )
         })
-        .init();
-    let s = server::RustServer::new("127.0.0.1:8642");
-    s.run().await.expect("Could not join");
+        .build();
+    // Errors only, unless the config of a match raises the level
+    log_level::init(Box::new(logger), log::LevelFilter::Error)
+        .expect("Could not install the logger");
+    if std::env::args().any(|arg| arg == "--print-schema") {
+        println!("{}", schema::config_schema());
+        println!("{}", schema::result_schema());
+        return;
+    }
+    if let Some(path) =
+        std::env::args().find_map(|arg| arg.strip_prefix("--replay-trace=").map(String::from))
+    {
+        let options = sc2process::LaunchOptions::default();
+        match trace::replay_file(std::path::Path::new(&path), &options).await {
+            Ok(report) => {
+                println!(
+                    "{}",
+                    serde_json::to_string(&report).expect("Invalid report")
+                );
+                std::process::exit(if report.mismatches.is_empty() { 0 } else { 1 });
+            }
+            Err(e) => {
+                log::error!("Could not replay {:?}: {}", path, e);
+                std::process::exit(2);
+            }
+        }
+    }
+    let mut s = server::RustServer::new("127.0.0.1:8642");
+    if let Some(mode) =
+        std::env::args().find_map(|arg| arg.strip_prefix("--frame-masking=").map(String::from))
+    {
+        match proxy::FrameMasking::parse(&mode) {
+            Some(frame_masking) => s.set_frame_masking(frame_masking),
+            None => log::error!("Unknown frame masking mode {:?}", mode),
+        }
+    }
+    if let Some(max_games) =
+        std::env::args().find_map(|arg| arg.strip_prefix("--max-games=").map(String::from))
+    {
+        match max_games.parse() {
+            Ok(max_games) => s.set_max_games(max_games),
+            Err(_) => log::error!("Invalid --max-games value {:?}", max_games),
+        }
+    }
+    if let Some(max_pool_size) =
+        std::env::args().find_map(|arg| arg.strip_prefix("--max-pool=").map(String::from))
+    {
+        match max_pool_size.parse() {
+            Ok(max_pool_size) => s.set_max_pool_size(max_pool_size),
+            Err(_) => log::error!("Invalid --max-pool value {:?}", max_pool_size),
+        }
+    }
+    if let Some(port_range) =
+        std::env::args().find_map(|arg| arg.strip_prefix("--port-range=").map(String::from))
+    {
+        match ports::parse_range(&port_range) {
+            Some(range) => s.set_port_range(range),
+            None => log::error!("Invalid --port-range value {:?}", port_range),
+        }
+    }
+    if let Some(max_sessions) =
+        std::env::args().find_map(|arg| arg.strip_prefix("--max-sessions=").map(String::from))
+    {
+        match max_sessions.parse() {
+            Ok(max_sessions) => s.set_max_sessions(max_sessions),
+            Err(_) => log::error!("Invalid --max-sessions value {:?}", max_sessions),
+        }
+    }
+    #[cfg(feature = "http-api")]
+    if let Some(http_addr) =
+        std::env::args().find_map(|arg| arg.strip_prefix("--http=").map(String::from))
+    {
+        s.set_http_addr(http_addr);
+    }
+    // Standalone match: the result is printed and reflected in the exit code
+    // `--config=<path>` is an alias of `--match <path>`
+    let args: Vec<String> = std::env::args().collect();
+    let mut standalone_player = None;
+    if let Some(path) = args
+        .iter()
+        .position(|arg| arg == "--match")
+        .and_then(|i| args.get(i + 1))
+        .cloned()
+        .or_else(|| {
+            args.iter()
+                .find_map(|arg| arg.strip_prefix("--config=").map(String::from))
+        })
+    {
+        match config::Config::load_from_path(std::path::Path::new(&path)) {
+            Ok(config) => {
+                standalone_player = Some(config.player1().to_string());
+                s.set_match_config(config);
+            }
+            Err(e) => {
+                log::error!("{}", e);
+                std::process::exit(status::match_exit_code(None, ""));
+            }
+        }
+    }
+    if std::env::args().any(|arg| arg == "--quit-on-supervisor-close") {
+        s.set_quit_on_supervisor_close(true);
+    }
+    #[cfg(feature = "supervisor-tls")]
+    if let Some(tls_addr) =
+        std::env::args().find_map(|arg| arg.strip_prefix("--supervisor-tls=").map(String::from))
+    {
+        let file = |name: &str| {
+            std::env::args()
+                .find_map(|arg| arg.strip_prefix(name).map(std::path::PathBuf::from))
+                .unwrap_or_default()
+        };
+        let tls = supervisor_tls::SupervisorTls {
+            addr: tls_addr,
+            cert: file("--supervisor-tls-cert="),
+            key: file("--supervisor-tls-key="),
+            client_ca: file("--supervisor-tls-ca="),
+        };
+        if let Err(e) = tls.acceptor() {
+            log::error!("Invalid supervisor TLS setup: {}", e);
+            std::process::exit(status::match_exit_code(None, ""));
+        }
+        s.set_supervisor_tls(tls);
+    }
+    if std::env::args().any(|arg| arg == "--dry-run") {
+        s.set_dry_run(true);
+    }
+    // Kept out of the arguments, which other users of the host can read
+    if let Some(secret) = std::env::var("SC2_PROXY_SUPERVISOR_SECRET")
+        .ok()
+        .filter(|secret| !secret.is_empty())
+    {
+        s.set_supervisor_secret(secret);
+    }
+    let sc2_env: std::collections::BTreeMap<String, String> = std::env::args()
+        .filter_map(|arg| {
+            let (name, value) = arg.strip_prefix("--sc2-env=")?.split_once('=')?;
+            Some((name.to_string(), value.to_string()))
+        })
+        .collect();
+    if !sc2_env.is_empty() {
+        s.set_sc2_env(sc2_env);
+    }
+    if std::env::args().any(|arg| arg == "--check-updates") {
+        s.set_check_updates(true);
+    }
+    let server = match s.run(None) {
+        Ok(server) => server,
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(status::match_exit_code(None, ""));
+        }
+    };
+    let outcome = server.await.expect("Could not join");
+    if let Some(player) = standalone_player {
+        if let Some(status::MatchStatus::Finished { result }) = &outcome {
+            println!("{}", result);
+        }
+        std::process::exit(status::match_exit_code(outcome.as_ref(), &player));
+    }
 }
@@ -0,0 +1,278 @@
+//! Map file finder, map pools, and download of missing maps from a map repository
+//!
+//! A repository serves each map as `<url>/<Map>.zip` next to its SHA-256 checksum
+//! `<url>/<Map>.zip.sha256`, the `.SC2Map` files of the archive are extracted into
+//! the map directory.
+
+mod pool;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::paths::map_dir;
+
+pub use self::pool::{MapChoice, MapPool};
+
+/// Find a map file, returning its relative path to the sc2 map directory
+pub fn find_map(name: String) -> Option<String> {
+    let name = map_file_name(&name);
+
+    let mapdir = map_dir();
+    if !mapdir.is_dir() {
+        return None;
+    }
+    for outer in fs::read_dir(mapdir.clone()).expect("Could not iterate map directory") {
+        let outer_path = outer.unwrap().path();
+        if !outer_path.is_dir() {
+            let current = outer_path
+                .file_name()
+                .unwrap()
+                .to_str()
+                .expect("Invalid unicode in path");
+            if current.to_ascii_lowercase() == name.to_ascii_lowercase() {
+                let relative = outer_path.strip_prefix(mapdir).unwrap();
+                let relative_str = relative.to_str().unwrap();
+                return Some(relative_str.to_owned());
+            } else {
+                continue;
+            }
+        }
+
+        for inner in fs::read_dir(outer_path).expect("Could not iterate map subdirectory") {
+            let path = inner.unwrap().path();
+            let current = path
+                .file_name()
+                .unwrap()
+                .to_str()
+                .expect("Invalid unicode in path");
+            if current.to_ascii_lowercase() == name.to_ascii_lowercase() {
+                let relative = path.strip_prefix(mapdir).unwrap();
+                let relative_str = relative.to_str().unwrap();
+                return Some(relative_str.to_owned());
+            }
+        }
+    }
+    None
+}
+
+fn dir_entries(dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(dir)
+        .map(|entries| entries.flatten().map(|entry| entry.path()).collect())
+        .unwrap_or_default()
+}
+
+/// Map files in the map directory and its subdirectories, with their sizes
+fn map_files() -> Vec<(PathBuf, u64)> {
+    let mut files = Vec::new();
+    for outer in dir_entries(&map_dir()) {
+        let paths = if outer.is_dir() {
+            dir_entries(&outer)
+        } else {
+            vec![outer]
+        };
+        for path in paths {
+            let is_map = path
+                .extension()
+                .map_or(false, |ext| ext.eq_ignore_ascii_case("SC2Map"));
+            match fs::metadata(&path) {
+                Ok(metadata) if is_map && metadata.is_file() => files.push((path, metadata.len())),
+                _ => {}
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+/// File name of the smallest installed map, the quickest to load
+pub fn smallest_map() -> Option<String> {
+    map_files()
+        .into_iter()
+        .min_by_key(|(_, size)| *size)
+        .and_then(|(path, _)| Some(path.file_name()?.to_str()?.to_string()))
+}
+
+/// Map file name as `find_map` looks for it
+fn map_file_name(name: &str) -> String {
+    let mut name = name.replace(' ', "");
+    if !name.ends_with(".SC2Map") {
+        name.push_str(".SC2Map");
+    }
+    name
+}
+
+/// URLs of the archive of a map and of its checksum in the repository at `url`
+pub fn archive_urls(name: &str, url: &str) -> (String, String) {
+    let file_name = map_file_name(name);
+    let archive = format!(
+        "{}/{}.zip",
+        url.trim_end_matches('/'),
+        file_name.trim_end_matches(".SC2Map")
+    );
+    let checksum = format!("{}.sha256", archive);
+    (archive, checksum)
+}
+
+/// Find a map file like `find_map`, downloading it from the repository at `url` if it
+/// is not installed
+pub fn ensure_map(name: &str, url: &str) -> Result<String, String> {
+    if let Some(path) = find_map(name.to_string()) {
+        return Ok(path);
+    }
+    download_map(name, url)?;
+    find_map(name.to_string())
+        .ok_or_else(|| format!("Archive does not contain {}", map_file_name(name)))
+}
+
+#[cfg(feature = "map-download")]
+fn download_map(name: &str, url: &str) -> Result<(), String> {
+    let (archive_url, checksum_url) = archive_urls(name, url);
+    log::info!("Downloading map {:?} from {}", name, archive_url);
+    let checksum = fetch(&checksum_url)?;
+    // Same format as the output of sha256sum, the file name is optional
+    let expected = String::from_utf8_lossy(&checksum)
+        .split_whitespace()
+        .next()
+        .map(str::to_string)
+        .ok_or_else(|| format!("{} is empty", checksum_url))?;
+    let archive = fetch(&archive_url)?;
+    verify_checksum(&archive, &expected)?;
+    let extracted = extract_maps(&archive, &map_dir())?;
+    log::info!("Extracted {:?}", extracted);
+    Ok(())
+}
+
+#[cfg(not(feature = "map-download"))]
+fn download_map(_name: &str, _url: &str) -> Result<(), String> {
+    Err("built without the map-download feature".to_string())
+}
+
+/// Largest archive that is downloaded, ladder maps are a few megabytes
+#[cfg(feature = "map-download")]
+const MAX_ARCHIVE_BYTES: u64 = 256 * 1024 * 1024;
+
+#[cfg(feature = "map-download")]
+fn fetch(url: &str) -> Result<Vec<u8>, String> {
+    use std::io::Read;
+    let response = ureq::get(url)
+        .set(
+            "User-Agent",
+            concat!("rust-arenaclient/", env!("CARGO_PKG_VERSION")),
+        )
+        .timeout(std::time::Duration::from_secs(300))
+        .call()
+        .map_err(|e| format!("{}: {}", url, e))?;
+    let mut data = Vec::new();
+    response
+        .into_reader()
+        .take(MAX_ARCHIVE_BYTES)
+        .read_to_end(&mut data)
+        .map_err(|e| format!("{}: {}", url, e))?;
+    Ok(data)
+}
+
+#[cfg(feature = "map-download")]
+fn verify_checksum(data: &[u8], expected: &str) -> Result<(), String> {
+    use sha2::{Digest, Sha256};
+    let actual: String = Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    if actual.eq_ignore_ascii_case(expected.trim()) {
+        Ok(())
+    } else {
+        Err(format!(
+            "Checksum mismatch, expected {} but the archive has {}",
+            expected, actual
+        ))
+    }
+}
+
+/// Extract the maps of a zip archive into `dir`, returning their paths
+/// Directories of the archive are dropped, so no entry can escape `dir`
+#[cfg(feature = "map-download")]
+fn extract_maps(archive: &[u8], dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut zip = zip::ZipArchive::new(std::io::Cursor::new(archive))
+        .map_err(|e| format!("Invalid archive: {}", e))?;
+    fs::create_dir_all(dir).map_err(|e| format!("{:?}: {}", dir, e))?;
+    let mut extracted = Vec::new();
+    for index in 0..zip.len() {
+        let mut entry = zip
+            .by_index(index)
+            .map_err(|e| format!("Invalid archive: {}", e))?;
+        let file_name = match entry.enclosed_name().and_then(|path| path.file_name()) {
+            Some(file_name) => file_name.to_owned(),
+            None => continue,
+        };
+        let is_map = file_name
+            .to_str()
+            .map_or(false, |name| name.to_ascii_lowercase().ends_with(".sc2map"));
+        if !entry.is_file() || !is_map {
+            continue;
+        }
+        // Written next to the target first, so a failed download leaves no partial map
+        let path = dir.join(file_name);
+        let mut temp =
+            tempfile::NamedTempFile::new_in(dir).map_err(|e| format!("{:?}: {}", dir, e))?;
+        std::io::copy(&mut entry, &mut temp).map_err(|e| format!("{:?}: {}", path, e))?;
+        temp.persist(&path)
+            .map_err(|e| format!("{:?}: {}", path, e))?;
+        extracted.push(path);
+    }
+    if extracted.is_empty() {
+        return Err("Archive contains no map".to_string());
+    }
+    Ok(extracted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_archive_urls() {
+        assert_eq!(
+            archive_urls("Automaton LE", "https://maps.example.com/ladder/"),
+            (
+                "https://maps.example.com/ladder/AutomatonLE.zip".to_string(),
+                "https://maps.example.com/ladder/AutomatonLE.zip.sha256".to_string()
+            )
+        );
+        assert_eq!(
+            archive_urls("AutomatonLE.SC2Map", "https://maps.example.com").0,
+            "https://maps.example.com/AutomatonLE.zip"
+        );
+    }
+
+    #[cfg(feature = "map-download")]
+    #[test]
+    fn test_extract_maps() {
+        use std::io::Write;
+        let mut archive = std::io::Cursor::new(Vec::new());
+        {
+            let mut writer = zip::ZipWriter::new(&mut archive);
+            let options = zip::write::FileOptions::default()
+                .compression_method(zip::CompressionMethod::Stored);
+            writer
+                .start_file("Ladder/AutomatonLE.SC2Map", options)
+                .unwrap();
+            writer.write_all(b"map").unwrap();
+            writer.start_file("../readme.txt", options).unwrap();
+            writer.write_all(b"readme").unwrap();
+            writer.finish().unwrap();
+        }
+        let archive = archive.into_inner();
+
+        // sha256 of an empty input
+        let empty = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        assert!(verify_checksum(b"", empty).is_ok());
+        assert!(verify_checksum(&archive, empty).is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let extracted = extract_maps(&archive, dir.path()).unwrap();
+        assert_eq!(extracted, vec![dir.path().join("AutomatonLE.SC2Map")]);
+        assert_eq!(fs::read(&extracted[0]).unwrap(), b"map");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+        assert!(extract_maps(b"not a zip", dir.path()).is_err());
+    }
+}
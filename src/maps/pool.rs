@@ -0,0 +1,167 @@
+//! Selection of the map of a match from several candidates
+//!
+//! `Map` can name one map, list several, or be `"random"` for any installed map.
+//! The pick depends only on the seed and the installed maps, so a match can be
+//! reproduced from the `MapSeed` of its result.
+
+use super::map_files;
+#[cfg(feature = "supervisor")]
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// `Map` of a config
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "supervisor", derive(JsonSchema))]
+#[serde(untagged)]
+pub enum MapChoice {
+    /// A map, or `random` for any installed map
+    Name(String),
+    /// Maps to pick one from
+    List(Vec<String>),
+}
+impl Default for MapChoice {
+    fn default() -> Self {
+        Self::Name(String::new())
+    }
+}
+impl From<&str> for MapChoice {
+    fn from(name: &str) -> Self {
+        Self::Name(name.to_string())
+    }
+}
+impl MapChoice {
+    /// Name of the map, empty until a list or `random` was resolved
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Name(name) if !self.is_random() => name,
+            _ => "",
+        }
+    }
+    pub fn is_random(&self) -> bool {
+        matches!(self, Self::Name(name) if name.eq_ignore_ascii_case("random"))
+    }
+    /// Whether a map still has to be picked
+    pub fn needs_pick(&self) -> bool {
+        self.is_random() || matches!(self, Self::List(_))
+    }
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Self::Name(name) => name.is_empty(),
+            Self::List(names) => names.is_empty(),
+        }
+    }
+}
+impl fmt::Display for MapChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Name(name) => write!(f, "{}", name),
+            Self::List(names) => write!(f, "{}", names.join(", ")),
+        }
+    }
+}
+
+/// Map names compare like `find_map` compares file names
+fn normalize(name: &str) -> String {
+    let name = name.replace(' ', "").to_ascii_lowercase();
+    name.trim_end_matches(".sc2map").to_string()
+}
+
+/// Maps a match can be played on, sorted by name
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MapPool {
+    maps: Vec<String>,
+}
+impl MapPool {
+    pub fn new(names: impl IntoIterator<Item = String>) -> Self {
+        let mut maps: Vec<String> = names.into_iter().collect();
+        maps.sort();
+        maps.dedup();
+        Self { maps }
+    }
+
+    /// Maps of the map directory and its subdirectories, without the extension
+    pub fn installed() -> Self {
+        Self::new(
+            map_files()
+                .into_iter()
+                .filter_map(|(path, _)| Some(path.file_stem()?.to_str()?.to_string())),
+        )
+    }
+
+    /// Keep the maps named in `names`, ignoring spaces, case and the extension
+    pub fn filter(&self, names: &[String]) -> Self {
+        let names: Vec<String> = names.iter().map(|name| normalize(name)).collect();
+        Self::new(
+            self.maps
+                .iter()
+                .filter(|map| names.contains(&normalize(map)))
+                .cloned(),
+        )
+    }
+
+    pub fn maps(&self) -> &[String] {
+        &self.maps
+    }
+
+    /// Pick a map, always the same one for the same seed and pool
+    pub fn pick(&self, seed: u64) -> Option<&str> {
+        if self.maps.is_empty() {
+            return None;
+        }
+        // SplitMix64, so that consecutive seeds spread over the pool
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        Some(&self.maps[(z % self.maps.len() as u64) as usize])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool() -> MapPool {
+        MapPool::new(
+            ["AutomatonLE", "EverDreamLE", "Flat32", "PillarsofGoldLE"]
+                .iter()
+                .map(|name| name.to_string()),
+        )
+    }
+
+    #[test]
+    fn test_map_choice() {
+        let choice: MapChoice = serde_json::from_str("\"AutomatonLE\"").unwrap();
+        assert_eq!(choice.name(), "AutomatonLE");
+        assert!(!choice.needs_pick());
+        let choice: MapChoice = serde_json::from_str("\"Random\"").unwrap();
+        assert!(choice.is_random() && choice.needs_pick());
+        assert_eq!(choice.name(), "");
+        let choice: MapChoice = serde_json::from_str("[\"AutomatonLE\", \"Flat32\"]").unwrap();
+        assert!(choice.needs_pick());
+        assert_eq!(choice.to_string(), "AutomatonLE, Flat32");
+        assert!(MapChoice::List(vec![]).is_empty());
+    }
+
+    #[test]
+    fn test_pick() {
+        let pool = pool();
+        let picked = pool.pick(42).unwrap().to_string();
+        assert_eq!(pool.pick(42), Some(picked.as_str()));
+        let picks: std::collections::HashSet<&str> =
+            (0..64).filter_map(|seed| pool.pick(seed)).collect();
+        assert_eq!(picks.len(), 4);
+        assert_eq!(MapPool::default().pick(42), None);
+
+        let filtered = pool.filter(&[
+            "Automaton LE".to_string(),
+            "flat32.SC2Map".to_string(),
+            "Missing".to_string(),
+        ]);
+        assert_eq!(filtered.maps(), ["AutomatonLE", "Flat32"]);
+        assert!(filtered
+            .maps()
+            .contains(&filtered.pick(7).unwrap().to_string()));
+    }
+}
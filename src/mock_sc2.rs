@@ -0,0 +1,242 @@
+//! Stand-in for SC2 in dry runs, so supervisors and bots can test their
+//! integration on machines without StarCraft II
+//!
+//! Every request gets an empty but well formed response. Steps and observations
+//! advance the game loop, and the game ends in a tie after `GAME_LOOPS` loops.
+
+use std::io;
+use std::net::TcpListener as StdTcpListener;
+
+use futures_util::{SinkExt, StreamExt};
+use log::{debug, warn};
+use protobuf::Message;
+use sc2_proto::common::Race;
+use sc2_proto::sc2api::{
+    PlayerInfo, PlayerResult, PlayerType, Request, Response, ResponseAction, ResponseCreateGame,
+    ResponseData, ResponseDebug, ResponseGameInfo, ResponseJoinGame, ResponseLeaveGame,
+    ResponseObsAction, ResponseObservation, ResponsePing, ResponseQuery, ResponseQuit,
+    ResponseSaveReplay, ResponseStep, Result as GameResult, Status,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message as TMessage;
+use tokio_tungstenite::WebSocketStream;
+
+/// Game loops of a dry run game, ten game seconds
+pub const GAME_LOOPS: u32 = 224;
+
+/// Mock SC2 instance serving one websocket connection
+pub struct MockSc2 {
+    task: JoinHandle<()>,
+}
+impl MockSc2 {
+    /// Listen on `port` of localhost, must be called inside the runtime
+    pub fn launch(port: u16) -> io::Result<Self> {
+        let listener = StdTcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+        let task = tokio::spawn(async move {
+            let accepted = match TcpListener::from_std(listener) {
+                Ok(listener) => listener.accept().await,
+                Err(e) => Err(e),
+            };
+            // Like SC2, a single connection is served
+            let socket = match accepted {
+                Ok((socket, _)) => socket,
+                Err(e) => {
+                    warn!("Mock SC2 could not accept a connection: {}", e);
+                    return;
+                }
+            };
+            match tokio_tungstenite::accept_async(socket).await {
+                Ok(ws) => serve(ws).await,
+                Err(e) => warn!("Mock SC2 websocket handshake failed: {}", e),
+            }
+        });
+        Ok(Self { task })
+    }
+
+    pub fn kill(&self) {
+        self.task.abort();
+    }
+}
+
+async fn serve(mut ws: WebSocketStream<TcpStream>) {
+    let mut game = MockGame::default();
+    while let Some(Ok(message)) = ws.next().await {
+        let data = match message {
+            TMessage::Binary(data) => data,
+            TMessage::Close(_) => break,
+            _ => continue,
+        };
+        let request = match Request::parse_from_bytes(&data) {
+            Ok(request) => request,
+            Err(e) => {
+                warn!("Mock SC2 received an invalid request: {}", e);
+                break;
+            }
+        };
+        let response = game.respond(&request);
+        let data = response.write_to_bytes().expect("Invalid mock response");
+        if ws.send(TMessage::Binary(data)).await.is_err() || response.has_quit() {
+            break;
+        }
+    }
+    debug!("Mock SC2 connection closed");
+}
+
+/// State of the game behind one mock instance
+#[derive(Debug)]
+struct MockGame {
+    /// Whether the game was created on this instance, whose player is then player 1
+    host: bool,
+    player_id: u32,
+    game_loop: u32,
+    status: Status,
+}
+impl Default for MockGame {
+    fn default() -> Self {
+        Self {
+            host: false,
+            player_id: 0,
+            game_loop: 0,
+            status: Status::launched,
+        }
+    }
+}
+impl MockGame {
+    fn respond(&mut self, request: &Request) -> Response {
+        let mut response = Response::new();
+        if request.has_id() {
+            response.set_id(request.id());
+        }
+        if request.has_create_game() {
+            self.host = true;
+            self.status = Status::init_game;
+            response.set_create_game(ResponseCreateGame::new());
+        } else if request.has_join_game() {
+            self.player_id = if self.host { 1 } else { 2 };
+            self.status = Status::in_game;
+            let mut join_game = ResponseJoinGame::new();
+            join_game.set_player_id(self.player_id);
+            response.set_join_game(join_game);
+        } else if request.has_game_info() {
+            response.set_game_info(self.game_info());
+        } else if request.has_observation() {
+            self.advance(1);
+            response.set_observation(self.observation());
+        } else if request.has_step() {
+            self.advance(request.step().count().max(1));
+            let mut step = ResponseStep::new();
+            step.set_simulation_loop(self.game_loop);
+            response.set_step(step);
+        } else if request.has_action() {
+            response.set_action(ResponseAction::new());
+        } else if request.has_obs_action() {
+            response.set_obs_action(ResponseObsAction::new());
+        } else if request.has_query() {
+            response.set_query(ResponseQuery::new());
+        } else if request.has_debug() {
+            response.set_debug(ResponseDebug::new());
+        } else if request.has_data() {
+            response.set_data(ResponseData::new());
+        } else if request.has_ping() {
+            response.set_ping(ResponsePing::new());
+        } else if request.has_save_replay() {
+            response.set_save_replay(ResponseSaveReplay::new());
+        } else if request.has_leave_game() {
+            self.status = Status::launched;
+            response.set_leave_game(ResponseLeaveGame::new());
+        } else if request.has_quit() {
+            self.status = Status::quit;
+            response.set_quit(ResponseQuit::new());
+        } else {
+            response
+                .error
+                .push("Request not supported in a dry run".to_string());
+        }
+        response.set_status(self.status);
+        response
+    }
+
+    fn advance(&mut self, loops: u32) {
+        if self.status == Status::in_game {
+            self.game_loop += loops;
+            if self.game_loop >= GAME_LOOPS {
+                self.status = Status::ended;
+            }
+        }
+    }
+
+    fn game_info(&self) -> ResponseGameInfo {
+        let mut game_info = ResponseGameInfo::new();
+        game_info.set_map_name("Dry run".to_string());
+        for player_id in 1..=2 {
+            let mut info = PlayerInfo::new();
+            info.set_player_id(player_id);
+            info.set_type(PlayerType::Participant);
+            info.set_race_requested(Race::Random);
+            game_info.player_info.push(info);
+        }
+        game_info
+    }
+
+    fn observation(&self) -> ResponseObservation {
+        let mut observation = ResponseObservation::new();
+        observation
+            .observation
+            .mut_or_insert_default()
+            .set_game_loop(self.game_loop);
+        if self.status == Status::ended {
+            for player_id in 1..=2 {
+                let mut result = PlayerResult::new();
+                result.set_player_id(player_id);
+                result.set_result(GameResult::Tie);
+                observation.player_result.push(result);
+            }
+        }
+        observation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sc2_proto::sc2api::{RequestCreateGame, RequestJoinGame, RequestObservation};
+
+    #[test]
+    fn test_mock_game() {
+        let mut host = MockGame::default();
+        let mut request = Request::new();
+        request.set_create_game(RequestCreateGame::new());
+        assert_eq!(host.respond(&request).status(), Status::init_game);
+
+        let mut join = Request::new();
+        join.set_join_game(RequestJoinGame::new());
+        assert_eq!(host.respond(&join).join_game().player_id(), 1);
+        assert_eq!(
+            MockGame::default().respond(&join).join_game().player_id(),
+            2
+        );
+
+        let mut step = Request::new();
+        step.mut_step().set_count(GAME_LOOPS - 2);
+        let response = host.respond(&step);
+        assert_eq!(response.step().simulation_loop(), GAME_LOOPS - 2);
+        assert_eq!(response.status(), Status::in_game);
+
+        let mut observation = Request::new();
+        observation.set_observation(RequestObservation::new());
+        assert!(host
+            .respond(&observation)
+            .observation()
+            .player_result
+            .is_empty());
+        let response = host.respond(&observation);
+        assert_eq!(response.status(), Status::ended);
+        let results = &response.observation().player_result;
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|r| r.result() == GameResult::Tie));
+
+        assert_eq!(host.respond(&Request::new()).error.len(), 1);
+    }
+}
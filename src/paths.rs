@@ -6,10 +6,44 @@ use std::io::prelude::*;
 use std::path::{Path, PathBuf};
 
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 
-pub fn default_base() -> PathBuf {
-    // TODO: Detect Wine and use "~/.wine/drive_c/Program Files (x86)/StarCraft II"
+use crate::build_info::BuildInfo;
+
+/// Windows install of SC2 in the Wine prefix, `WINEPREFIX` or `~/.wine`, None if there is none
+pub fn wine_dir() -> Option<PathBuf> {
+    let prefix = match var_os("WINEPREFIX") {
+        Some(prefix) => PathBuf::from(prefix),
+        None => PathBuf::from(shellexpand::tilde("~/.wine").into_owned()),
+    };
+    let dir = prefix.join("drive_c/Program Files (x86)/StarCraft II");
+    if dir.is_dir() {
+        Some(dir)
+    } else {
+        None
+    }
+}
+
+/// Whether SC2 is the Windows client run through Wine
+/// Set `SC2_PROXY_WINE` to force it, otherwise a Windows install layout on Linux is detected
+pub fn uses_wine() -> bool {
+    if !cfg!(target_os = "linux") {
+        return false;
+    }
+    match var_os("SC2_PROXY_WINE") {
+        Some(wine) => !wine.is_empty() && wine != "0",
+        None => base_dir().join("Support64").is_dir(),
+    }
+}
 
+/// Wine binary, `WINE` or `wine` on the PATH
+pub fn wine_binary() -> PathBuf {
+    var_os("WINE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("wine"))
+}
+
+pub fn default_base() -> PathBuf {
     if let Some(base_dir) = var_os("SC2_PROXY_BASE") {
         Path::new(&base_dir).to_path_buf()
     } else if let Some(base_dir) = var_os("SC2PATH") {
@@ -19,7 +53,12 @@ pub fn default_base() -> PathBuf {
     } else if cfg!(target_os = "macos") {
         Path::new("/Applications/StarCraft II").to_path_buf()
     } else if cfg!(target_os = "linux") {
-        Path::new(&shellexpand::tilde("~/StarCraftII").into_owned()).to_path_buf()
+        let native = Path::new(&shellexpand::tilde("~/StarCraftII").into_owned()).to_path_buf();
+        if native.is_dir() {
+            native
+        } else {
+            wine_dir().unwrap_or(native)
+        }
     } else {
         panic!("Unknown system, use SC2_PROXY_BASE env var");
     }
@@ -29,11 +68,11 @@ pub fn default_base() -> PathBuf {
 pub fn bin_path() -> PathBuf {
     if let Some(base_dir) = var_os("SC2_PROXY_BIN") {
         Path::new(&base_dir).to_path_buf()
-    } else if cfg!(windows) {
+    } else if cfg!(windows) || uses_wine() {
         Path::new("SC2_x64.exe").to_path_buf()
     } else if cfg!(target_os = "macos") {
         Path::new("SC2.app/Contents/MacOS/SC2").to_path_buf()
-    } else if cfg!(linux) {
+    } else if cfg!(target_os = "linux") {
         Path::new(&shellexpand::tilde("SC2_x64").into_owned()).to_path_buf()
     } else {
         panic!("Unknown system, use SC2_PROXY_BIN env var");
@@ -44,33 +83,100 @@ pub fn bin_path() -> PathBuf {
 pub fn cwd() -> Option<PathBuf> {
     if let Some(base_dir) = var_os("SC2_PROXY_CWD") {
         Some(Path::new(&base_dir).to_path_buf())
-    } else if cfg!(windows) {
+    } else if cfg!(windows) || uses_wine() {
         Some(Path::new("Support64").to_path_buf())
     } else {
         None
     }
 }
 
+/// SC2 build installed in a `Versions/Base*` folder
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Sc2Version {
+    #[serde(rename = "BaseBuild")]
+    pub base_build: u64,
+    /// The `Base*` folder
+    #[serde(rename = "Path")]
+    pub path: PathBuf,
+    /// Full version such as `4.10.0.75689`, only known for the build `.build.info` describes
+    #[serde(rename = "GameVersion", skip_serializing_if = "Option::is_none")]
+    pub game_version: Option<String>,
+    /// Whether the folder contains the SC2 binary, partially installed builds do not
+    #[serde(rename = "Complete")]
+    pub complete: bool,
+}
+impl Sc2Version {
+    /// SC2 binary of this build
+    pub fn executable(&self) -> PathBuf {
+        self.path.join(bin_path())
+    }
+}
+
+/// `Base*` folders in the versions directory, oldest build first
+pub fn versions_in(versions_dir: &Path) -> Vec<Sc2Version> {
+    let mut versions: Vec<Sc2Version> = match fs::read_dir(versions_dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| -> Option<Sc2Version> {
+                let path = entry.ok()?.path();
+                let name = path.file_name()?.to_str()?;
+
+                if path.is_dir() && name.starts_with("Base") {
+                    let base_build = name.split_at(4).1.parse::<u64>().ok()?;
+                    let complete = path.join(bin_path()).is_file();
+                    Some(Sc2Version {
+                        base_build,
+                        path,
+                        game_version: None,
+                        complete,
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    versions.sort_by_key(|version| version.base_build);
+    versions
+}
+
+/// SC2 builds installed on this host, oldest first
+pub fn installed_versions() -> Vec<Sc2Version> {
+    let mut versions = versions_in(&versions_dir());
+    if let Some(info) = BuildInfo::from_file()
+        .ok()
+        .filter(|info| info.base_build > 0)
+    {
+        for version in versions
+            .iter_mut()
+            .filter(|version| version.base_build == u64::from(info.base_build))
+        {
+            version.game_version = Some(info.version.clone());
+        }
+    }
+    versions
+}
+
+/// Newest `Base*` folder in the versions directory, None if there is none
+pub fn latest_version(versions_dir: &Path) -> Option<(u64, PathBuf)> {
+    versions_in(versions_dir)
+        .pop()
+        .map(|version| (version.base_build, version.path))
+}
+
+/// SC2 binary of the `Base{build}` folder, None if that build is not installed
+pub fn build_executable_path(versions_dir: &Path, build: u64) -> Option<PathBuf> {
+    let path = versions_dir.join(format!("Base{}", build));
+    if path.is_dir() {
+        Some(path.join(bin_path()))
+    } else {
+        None
+    }
+}
+
 pub fn latest_executable_path(versions_dir: PathBuf) -> PathBuf {
-    let (max_version, path) = fs::read_dir(versions_dir)
-        .unwrap()
-        .filter_map(|entry| -> Option<(u64, PathBuf)> {
-            let path = entry.unwrap().path();
-            let name = path
-                .file_name()
-                .unwrap()
-                .to_str()
-                .expect("Invalid unicode in folder name");
-
-            if path.metadata().unwrap().is_dir() && name.starts_with("Base") {
-                let version: &str = name.split_at(4).1;
-                version.parse::<u64>().ok().map(|v| (v, path.to_path_buf()))
-            } else {
-                None
-            }
-        })
-        .max_by_key(|(v, _)| *v)
-        .expect("No downloaded SC2 binaries found");
+    let (max_version, path) =
+        latest_version(&versions_dir).expect("No downloaded SC2 binaries found");
 
     if max_version < 55958 {
         panic!("Your SC2 binary is too old. Upgrade to 3.16.1 or newer.");
@@ -134,7 +240,12 @@ pub fn base_dir() -> PathBuf {
 
 /// PathBuf to SC2 binary executable
 pub fn executable() -> PathBuf {
-    latest_executable_path(base_dir().join(Path::new("Versions")))
+    latest_executable_path(versions_dir())
+}
+
+/// Directory containing the Base* version folders
+pub fn versions_dir() -> PathBuf {
+    base_dir().join(Path::new("Versions"))
 }
 
 /// Directory to switch to before starting SC2
@@ -157,3 +268,31 @@ pub fn map_dir() -> PathBuf {
     // TODO: lowercase variant?
     base_dir().join(Path::new("Maps"))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_versions_in() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["Base81009", "Base75689", "Base", "Shaders"] {
+            fs::create_dir(dir.path().join(name)).unwrap();
+        }
+        let complete = dir.path().join("Base81009").join(bin_path());
+        fs::create_dir_all(complete.parent().unwrap()).unwrap();
+        fs::write(&complete, b"").unwrap();
+
+        let versions = versions_in(dir.path());
+        let builds: Vec<u64> = versions.iter().map(|v| v.base_build).collect();
+        assert_eq!(builds, vec![75689, 81009]);
+        assert!(!versions[0].complete);
+        assert!(versions[1].complete);
+        assert_eq!(versions[1].executable(), complete);
+        assert_eq!(
+            latest_version(dir.path()),
+            Some((81009, dir.path().join("Base81009")))
+        );
+        assert!(versions_in(&dir.path().join("Missing")).is_empty());
+    }
+}
@@ -0,0 +1,341 @@
+//! Bots waiting in the playlist, each served by its own task
+//!
+//! A task answers Ping, GameInfo and Quit requests and enforces the probe timeout by itself,
+//! so a stalled socket never delays the controller, other bots or the supervisor.
+//! The controller only hears about a bot again when it joins a game or is dropped.
+
+use crate::build_info::BuildInfo;
+use crate::channels::send_with_backpressure;
+use crate::clock::SharedClock;
+use crate::proxy::Client;
+use crate::result::BotCapabilities;
+use crossbeam::channel::Sender;
+use futures_util::SinkExt;
+use log::{debug, error, info, trace};
+use protobuf::Message;
+use sc2_proto::sc2api::{
+    Request, RequestJoinGame, Response, ResponseGameInfo, ResponsePing, ResponseQuit,
+};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message as TMessage;
+
+/// Identifier of a client in the playlist
+pub type ClientId = u64;
+
+/// Message from a playlist task to the controller
+pub enum PlaylistEvent {
+    /// The bot asked to join the game, its connection is handed back
+    Join {
+        client_id: ClientId,
+        client: Client,
+        req: RequestJoinGame,
+        capabilities: BotCapabilities,
+    },
+    /// The bot left or was kicked, its connection is closed
+    Dropped { client_id: ClientId },
+}
+
+/// Probe requirements of the match, copied into each task
+#[derive(Clone)]
+pub struct ProbeSettings {
+    /// Clients must send a Ping before joining a game
+    pub required: bool,
+    /// Clients must send a GameInfo request before joining a game, the capability exchange
+    pub require_game_info: bool,
+    /// Map of the match, answered to GameInfo requests
+    pub map_name: String,
+    /// Time a client has to complete the required probes after connecting
+    pub timeout: Duration,
+    /// Version and base build the match is pinned to, reported instead of the newest installed
+    pub pinned_build: Option<(String, u32)>,
+}
+
+enum PlaylistAction {
+    Respond(TMessage),
+    RespondQuit(TMessage),
+    JoinGame(RequestJoinGame),
+    Kick,
+}
+
+impl ProbeSettings {
+    /// Whether the client still has to complete a required probe
+    fn pending(&self, capabilities: &BotCapabilities) -> bool {
+        (self.required && !capabilities.probed)
+            || (self.require_game_info && !capabilities.capability_exchange)
+    }
+}
+
+impl PlaylistAction {
+    fn respond(r: Response) -> Self {
+        let m = TMessage::Binary(r.write_to_bytes().expect("Invalid protobuf message"));
+        PlaylistAction::Respond(m)
+    }
+    fn respond_quit(r: Response) -> Self {
+        let m = TMessage::Binary(r.write_to_bytes().expect("Invalid protobuf message"));
+        PlaylistAction::RespondQuit(m)
+    }
+}
+
+/// Process message from a client in the playlist
+fn process_client_message(
+    msg: TMessage,
+    probe: &ProbeSettings,
+    capabilities: &mut BotCapabilities,
+) -> PlaylistAction {
+    match msg {
+        TMessage::Binary(bytes) => {
+            let req = Request::parse_from_bytes(&bytes);
+            debug!("Incoming playlist request: {:?}", req);
+
+            match req {
+                Ok(ref m) if m.has_quit() => {
+                    info!("Client quit");
+                    let mut resp = Response::new();
+                    resp.set_quit(ResponseQuit::new());
+                    PlaylistAction::respond_quit(resp)
+                }
+                Ok(ref m) if m.has_ping() => {
+                    trace!("Ping => Pong");
+                    let mut resp = Response::new();
+                    let mut pong = ResponsePing::new();
+                    let b = match &probe.pinned_build {
+                        Some((version, build)) => BuildInfo {
+                            version: version.clone(),
+                            base_build: *build,
+                            data_build: *build,
+                        },
+                        None => BuildInfo::get_build_info_from_file(),
+                    };
+                    pong.set_game_version(b.version);
+                    pong.set_base_build(b.base_build);
+                    pong.set_data_build(b.data_build);
+                    pong.set_data_version("".to_string());
+                    resp.set_ping(pong);
+                    capabilities.probed = true;
+                    PlaylistAction::respond(resp)
+                }
+                Ok(ref m) if m.has_game_info() => {
+                    // No game exists yet, so only the map is known
+                    trace!("GameInfo capability exchange");
+                    let mut resp = Response::new();
+                    let mut game_info = ResponseGameInfo::new();
+                    game_info.set_map_name(probe.map_name.clone());
+                    resp.set_game_info(game_info);
+                    capabilities.capability_exchange = true;
+                    PlaylistAction::respond(resp)
+                }
+                Ok(ref m) if m.has_join_game() => {
+                    if probe.pending(capabilities) {
+                        error!("Client tried to join without completing the required probes");
+                        return PlaylistAction::Kick;
+                    }
+                    info!("Game join");
+                    PlaylistAction::JoinGame(m.join_game().clone())
+                }
+                Ok(other) => {
+                    error!("Unsupported message in playlist {:?}", other);
+                    PlaylistAction::Kick
+                }
+                Err(err) => {
+                    error!("Invalid message {:?}", err);
+                    PlaylistAction::Kick
+                }
+            }
+        }
+        other => {
+            error!("Unsupported message type {:?}", other);
+            PlaylistAction::Kick
+        }
+    }
+}
+
+/// Task serving one client of the playlist
+pub struct PlaylistTask {
+    stop: Option<oneshot::Sender<()>>,
+    handle: JoinHandle<()>,
+}
+impl PlaylistTask {
+    /// Close the connection of a client that is still in the playlist
+    pub async fn stop(mut self) {
+        if let Some(stop) = self.stop.take() {
+            // The task might have ended on its own already
+            stop.send(()).ok();
+        }
+        self.handle.await.ok();
+    }
+}
+
+/// Spawn the task serving `client` until it joins a game or is dropped
+pub fn spawn_client(
+    client_id: ClientId,
+    client: Client,
+    probe: ProbeSettings,
+    clock: SharedClock,
+    events: Sender<PlaylistEvent>,
+) -> PlaylistTask {
+    let (stop_tx, stop_rx) = oneshot::channel();
+    let connected_at = clock.now();
+    let handle = tokio::spawn(async move {
+        let event = serve_client(client_id, client, probe, clock, connected_at, stop_rx).await;
+        if let Some(event) = event {
+            send_with_backpressure(&events, event).await.ok();
+        }
+    });
+    PlaylistTask {
+        stop: Some(stop_tx),
+        handle,
+    }
+}
+
+/// Serve playlist requests, returning the event for the controller
+/// Returns None if the controller stopped the task
+async fn serve_client(
+    client_id: ClientId,
+    mut client: Client,
+    probe: ProbeSettings,
+    clock: SharedClock,
+    connected_at: Instant,
+    mut stop: oneshot::Receiver<()>,
+) -> Option<PlaylistEvent> {
+    let dropped = PlaylistEvent::Dropped { client_id };
+    let mut capabilities = BotCapabilities::default();
+    loop {
+        let deadline = if probe.pending(&capabilities) {
+            let elapsed = clock.now().saturating_duration_since(connected_at);
+            Some(probe.timeout.saturating_sub(elapsed))
+        } else {
+            None
+        };
+        let message = tokio::select! {
+            _ = &mut stop => None,
+            message = recv_until(&mut client, deadline) => Some(message),
+        };
+        let message = match message {
+            Some(Some(message)) => message,
+            None => {
+                client.shutdown().await.ok();
+                return None;
+            }
+            Some(None) => {
+                error!(
+                    "Client {:?} did not answer the probe in time",
+                    client.peer_addr()
+                );
+                client.shutdown().await.ok();
+                return Some(dropped);
+            }
+        };
+        match message {
+            Some(Ok(msg)) => match process_client_message(msg, &probe, &mut capabilities) {
+                PlaylistAction::Kick => {
+                    debug!("Kick client");
+                    client.shutdown().await.ok();
+                    return Some(dropped);
+                }
+                PlaylistAction::Respond(resp) => {
+                    debug!("Respond to {:?}", client.peer_addr());
+                    if let Err(e) = client.stream.send(resp).await {
+                        error!("Could not respond: {:?}", e);
+                        return Some(dropped);
+                    }
+                }
+                PlaylistAction::RespondQuit(resp) => {
+                    client.stream.send(resp).await.ok();
+                    debug!("RespondQuit");
+                    client.shutdown().await.ok();
+                    return Some(dropped);
+                }
+                PlaylistAction::JoinGame(req) => {
+                    debug!("JoinGame from {:?}", client.peer_addr());
+                    return Some(PlaylistEvent::Join {
+                        client_id,
+                        client,
+                        req,
+                        capabilities,
+                    });
+                }
+            },
+            None => {
+                info!("Client {:?} disconnected", client.peer_addr());
+                return Some(dropped);
+            }
+            Some(Err(err)) => {
+                error!("Invalid message {:?}", err);
+                return Some(dropped);
+            }
+        }
+    }
+}
+
+/// Receive the next message, None if `deadline` passed first
+async fn recv_until(
+    client: &mut Client,
+    deadline: Option<Duration>,
+) -> Option<Option<Result<TMessage, tokio_tungstenite::tungstenite::Error>>> {
+    match deadline {
+        Some(remaining) => tokio::time::timeout(remaining, client.recv_message())
+            .await
+            .ok(),
+        None => Some(client.recv_message().await),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sc2_proto::sc2api::{RequestGameInfo, RequestPing};
+
+    fn probe() -> ProbeSettings {
+        ProbeSettings {
+            required: true,
+            require_game_info: true,
+            map_name: "AutomatonLE".to_string(),
+            timeout: Duration::from_secs(30),
+            pinned_build: Some(("4.10.0.75689".to_string(), 75689)),
+        }
+    }
+
+    fn message(request: Request) -> TMessage {
+        TMessage::Binary(request.write_to_bytes().unwrap())
+    }
+
+    fn join() -> TMessage {
+        let mut request = Request::new();
+        request.set_join_game(RequestJoinGame::new());
+        message(request)
+    }
+
+    #[test]
+    fn test_capability_exchange() {
+        let probe = probe();
+        let mut capabilities = BotCapabilities::default();
+        let mut ping = Request::new();
+        ping.set_ping(RequestPing::new());
+        process_client_message(message(ping), &probe, &mut capabilities);
+        assert!(matches!(
+            process_client_message(join(), &probe, &mut capabilities),
+            PlaylistAction::Kick
+        ));
+
+        let mut capabilities = BotCapabilities {
+            probed: true,
+            ..Default::default()
+        };
+        let mut game_info = Request::new();
+        game_info.set_game_info(RequestGameInfo::new());
+        match process_client_message(message(game_info), &probe, &mut capabilities) {
+            PlaylistAction::Respond(TMessage::Binary(bytes)) => {
+                let response = Response::parse_from_bytes(&bytes).unwrap();
+                assert_eq!(response.game_info().map_name(), "AutomatonLE");
+            }
+            _ => panic!("Expected a GameInfo response"),
+        }
+        assert!(capabilities.capability_exchange);
+        assert!(matches!(
+            process_client_message(join(), &probe, &mut capabilities),
+            PlaylistAction::JoinGame(_)
+        ));
+    }
+}
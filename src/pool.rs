@@ -0,0 +1,266 @@
+//! Pool of pre-launched SC2 processes
+//!
+//! Launching SC2 takes several seconds, so orchestration can warm processes
+//! before a tournament block. Lobbies take a warm process when one is idle,
+//! and launch a new one otherwise. Processes are not reused after a game,
+//! instead the pool is refilled to its warmed size once a game ends.
+//! Processes are launched by `fill` without holding the pool lock, so lobbies can
+//! take the idle ones while the pool warms.
+
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::errors::ArenaError;
+use crate::sc2process::{LaunchOptions, Process};
+
+/// Pool shared between the controller and its lobbies
+pub type SharedPool = Arc<Mutex<ProcessPool>>;
+
+/// Largest `PoolWarm` target, unless the proxy is configured otherwise
+pub const DEFAULT_MAX_POOL_SIZE: usize = 8;
+
+/// Supervisor command managing the pool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolCommand {
+    Status,
+    /// Launch processes until this many are idle
+    Warm(usize),
+    /// Kill all idle processes
+    Drain,
+}
+
+impl PoolCommand {
+    /// Parse `PoolStatus`, `PoolWarm(n)` or `PoolDrain`
+    pub fn parse(command: &str) -> Option<Self> {
+        match command.trim() {
+            "PoolStatus" => Some(Self::Status),
+            "PoolDrain" => Some(Self::Drain),
+            other => other
+                .strip_prefix("PoolWarm(")?
+                .strip_suffix(')')?
+                .trim()
+                .parse()
+                .ok()
+                .map(Self::Warm),
+        }
+    }
+}
+
+/// Counters reported by `PoolStatus`
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStatus {
+    #[serde(rename = "Idle")]
+    pub idle: usize,
+    /// Processes launched by `PoolWarm`
+    #[serde(rename = "Warmed")]
+    pub warmed: u32,
+    /// Warm processes handed to games
+    #[serde(rename = "Used")]
+    pub used: u32,
+    /// Idle processes killed by `PoolDrain`
+    #[serde(rename = "Drained")]
+    pub drained: u32,
+    /// Idle processes the pool keeps after games, set by `PoolWarm`
+    #[serde(rename = "Target")]
+    pub target: usize,
+}
+
+#[derive(Serialize)]
+struct PoolMessage {
+    #[serde(rename = "Pool")]
+    pool: PoolStatus,
+}
+
+impl PoolStatus {
+    /// Message sent to the supervisor
+    pub fn to_message(self) -> String {
+        serde_json::to_string(&PoolMessage { pool: self }).expect("Could not serialize PoolStatus")
+    }
+}
+
+/// Idle SC2 processes
+pub struct ProcessPool {
+    idle: Vec<Process>,
+    target: usize,
+    /// Largest target `PoolWarm` can set
+    max_size: usize,
+    status: PoolStatus,
+}
+
+impl Default for ProcessPool {
+    fn default() -> Self {
+        Self {
+            idle: Vec::new(),
+            target: 0,
+            max_size: DEFAULT_MAX_POOL_SIZE,
+            status: PoolStatus::default(),
+        }
+    }
+}
+
+impl std::fmt::Debug for ProcessPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProcessPool")
+            .field("status", &self.status())
+            .finish()
+    }
+}
+
+impl ProcessPool {
+    pub fn shared() -> SharedPool {
+        Arc::new(Mutex::new(Self::default()))
+    }
+
+    pub fn status(&self) -> PoolStatus {
+        PoolStatus {
+            idle: self.idle.len(),
+            target: self.target,
+            ..self.status
+        }
+    }
+
+    /// Cap the target of `PoolWarm` at `max_size` processes
+    pub fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+        self.target = self.target.min(max_size);
+    }
+
+    /// Keep `count` processes idle, at most the maximum pool size, see `fill`
+    pub fn warm(&mut self, count: usize) {
+        if count > self.max_size {
+            warn!(
+                "PoolWarm({}) exceeds the maximum pool size, warming {}",
+                count, self.max_size
+            );
+        }
+        self.target = count.min(self.max_size);
+    }
+
+    /// Processes to launch until the target is idle
+    pub fn missing(&self) -> usize {
+        self.target.saturating_sub(self.idle.len())
+    }
+
+    /// Keep a process launched by `fill`, killing it if the pool was drained meanwhile
+    fn add(&mut self, mut process: Process) {
+        self.status.warmed += 1;
+        if self.idle.len() < self.target {
+            self.idle.push(process);
+        } else {
+            process.kill();
+        }
+    }
+
+    /// Kill all idle processes and stop refilling the pool
+    pub fn drain(&mut self) {
+        self.target = 0;
+        for mut process in self.idle.drain(..) {
+            process.kill();
+            self.status.drained += 1;
+        }
+        info!("Process pool drained");
+    }
+
+    /// A warm process, if one is idle
+    pub fn take(&mut self) -> Option<Process> {
+        let process = self.idle.pop()?;
+        self.status.used += 1;
+        Some(process)
+    }
+
+    /// Apply a supervisor command, returning the resulting status
+    /// The processes of `PoolWarm` are only launched by `fill`
+    pub fn apply(&mut self, command: PoolCommand) -> PoolStatus {
+        match command {
+            PoolCommand::Status => {}
+            PoolCommand::Warm(count) => self.warm(count),
+            PoolCommand::Drain => self.drain(),
+        }
+        self.status()
+    }
+}
+
+/// Launch processes until the target of `pool` is idle, replacing those used by games
+/// The lock is only held between launches, returns the status once the pool is warm
+pub fn fill(pool: &SharedPool) -> PoolStatus {
+    while pool.lock().unwrap().missing() > 0 {
+        match Process::new() {
+            Ok(process) => pool.lock().unwrap().add(process),
+            Err(e) => {
+                error!("Could not warm the process pool: {}", e);
+                break;
+            }
+        }
+    }
+    let status = pool.lock().unwrap().status();
+    info!("Process pool warmed to {} idle processes", status.idle);
+    status
+}
+
+/// Take a warm process from the pool, or launch a new one
+/// A match with its own `temp_root` or launch options always gets a new process,
+/// warm ones use the system temp dir and the default options
+pub fn take_or_launch(
+    pool: Option<&SharedPool>,
+    temp_root: Option<&Path>,
+    options: &LaunchOptions,
+) -> Result<Process, ArenaError> {
+    if temp_root.is_some() || !options.is_default() {
+        return Process::launch(temp_root, options);
+    }
+    match pool.and_then(|pool| pool.lock().unwrap().take()) {
+        Some(process) => Ok(process),
+        None => Process::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(PoolCommand::parse("PoolStatus"), Some(PoolCommand::Status));
+        assert_eq!(PoolCommand::parse("PoolDrain"), Some(PoolCommand::Drain));
+        assert_eq!(
+            PoolCommand::parse("PoolWarm(4)"),
+            Some(PoolCommand::Warm(4))
+        );
+        assert_eq!(
+            PoolCommand::parse("PoolWarm( 2 )"),
+            Some(PoolCommand::Warm(2))
+        );
+        assert_eq!(PoolCommand::parse("PoolWarm(x)"), None);
+        assert_eq!(PoolCommand::parse("PoolWarm"), None);
+        assert_eq!(PoolCommand::parse("Pool"), None);
+    }
+
+    #[test]
+    fn test_empty_pool() {
+        let shared = ProcessPool::shared();
+        assert_eq!(fill(&shared).warmed, 0);
+        let mut pool = ProcessPool::default();
+        assert!(pool.take().is_none());
+        assert_eq!(pool.apply(PoolCommand::Drain), PoolStatus::default());
+        assert_eq!(
+            PoolStatus::default().to_message(),
+            "{\"Pool\":{\"Idle\":0,\"Warmed\":0,\"Used\":0,\"Drained\":0,\"Target\":0}}"
+        );
+    }
+
+    #[test]
+    fn test_max_size() {
+        let mut pool = ProcessPool::default();
+        assert_eq!(
+            pool.apply(PoolCommand::Warm(100)).target,
+            DEFAULT_MAX_POOL_SIZE
+        );
+        pool.set_max_size(2);
+        assert_eq!(pool.missing(), 2);
+        assert_eq!(pool.apply(PoolCommand::Warm(1)).target, 1);
+        assert_eq!(pool.apply(PoolCommand::Drain).target, 0);
+        assert_eq!(pool.missing(), 0);
+    }
+}
@@ -1,7 +1,9 @@
 //! Full port configuration
 
-use portpicker::pick_unused_port;
 use protobuf::MessageField;
+use std::ops::RangeInclusive;
+
+use crate::ports::{self, PortReservation};
 
 use sc2_proto::sc2api::{PortSet, RequestJoinGame};
 
@@ -11,21 +13,39 @@ pub struct PortConfig {
     shared: u16,
     server_game: u16,
     server_base: u16,
-    client_game: u16,
-    client_base: u16,
+    /// Game and base port of every client but the host
+    clients: Vec<(u16, u16)>,
+    /// Keeps other matches from picking the ports until the game is over
+    reservation: PortReservation,
 }
 impl PortConfig {
-    /// Create a set of random ports
+    /// Reserve a set of ports for a game of two players
     pub fn new() -> Option<Self> {
+        Self::for_players(2, None)
+    }
+
+    /// Reserve a set of ports for a game of `players` players, from `range` if set
+    pub fn for_players(players: usize, range: Option<&RangeInclusive<u16>>) -> Option<Self> {
+        let clients = players.max(2) - 1;
+        let reservation = ports::reserve_in(3 + 2 * clients, range)?;
+        let picked = reservation.ports();
         Some(Self {
-            shared: pick_unused_port()?,
-            server_game: pick_unused_port()?,
-            server_base: pick_unused_port()?,
-            client_game: pick_unused_port()?,
-            client_base: pick_unused_port()?,
+            shared: picked[0],
+            server_game: picked[1],
+            server_base: picked[2],
+            clients: picked[3..]
+                .chunks(2)
+                .map(|pair| (pair[0], pair[1]))
+                .collect(),
+            reservation,
         })
     }
 
+    /// Reservation of the ports, to hold them for as long as the game runs
+    pub fn reservation(&self) -> PortReservation {
+        self.reservation.clone()
+    }
+
     /// Apply port config to a handler join request
     pub fn apply_proto(&self, req: &mut RequestJoinGame, singleplayer: bool) {
         req.set_shared_port(self.shared as i32);
@@ -36,10 +56,16 @@ impl PortConfig {
             server_ps.set_base_port(self.server_base as i32);
             req.server_ports = MessageField::from_option(Some(server_ps));
 
-            let mut client_ps = PortSet::new();
-            client_ps.set_game_port(self.client_game as i32);
-            client_ps.set_base_port(self.client_base as i32);
-            req.client_ports = vec![client_ps];
+            req.client_ports = self
+                .clients
+                .iter()
+                .map(|(game, base)| {
+                    let mut client_ps = PortSet::new();
+                    client_ps.set_game_port(*game as i32);
+                    client_ps.set_base_port(*base as i32);
+                    client_ps
+                })
+                .collect();
         }
     }
 }
@@ -55,5 +81,11 @@ mod tests {
         port_config.apply_proto(&mut request, false);
         assert!(request.server_ports.is_some());
         assert!(request.has_shared_port());
+        assert_eq!(request.client_ports.len(), 1);
+
+        let mut request = RequestJoinGame::new();
+        let port_config = PortConfig::for_players(4, None).expect("Could not create port config");
+        port_config.apply_proto(&mut request, false);
+        assert_eq!(request.client_ports.len(), 3);
     }
 }
@@ -0,0 +1,180 @@
+//! Ports handed to SC2, reserved until the process or game using them is gone
+//!
+//! A port that is free when picked can still be picked by a concurrent match before SC2 binds
+//! it. Every port the proxy gives to SC2 comes from one allocator, which does not hand out a
+//! port again while a `PortReservation` holds it, and skips ports other programs bound.
+
+use log::warn;
+use portpicker::pick_unused_port;
+use std::fmt;
+use std::net::{Ipv4Addr, TcpListener, UdpSocket};
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Random ports tried per port without a range before giving up
+const RANDOM_ATTEMPTS: usize = 32;
+
+static ALLOCATOR: Mutex<Allocator> = Mutex::new(Allocator::new());
+
+fn allocator() -> MutexGuard<'static, Allocator> {
+    ALLOCATOR.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Hand out ports from `range` only, instead of random unused ports
+pub fn set_range(range: RangeInclusive<u16>) {
+    let mut allocator = allocator();
+    allocator.range = Some(range);
+    allocator.next = 0;
+}
+
+/// Parse a range such as `20000-20999`
+pub fn parse_range(range: &str) -> Option<RangeInclusive<u16>> {
+    let (start, end) = range.split_once('-')?;
+    let (start, end) = (start.trim().parse().ok()?, end.trim().parse().ok()?);
+    if start == 0 || start > end {
+        return None;
+    }
+    Some(start..=end)
+}
+
+/// Reserve `count` free ports, None if there are not enough
+pub fn reserve(count: usize) -> Option<PortReservation> {
+    reserve_in(count, None)
+}
+
+/// Reserve `count` free ports from `range`, or from the range of the proxy if None
+pub fn reserve_in(count: usize, range: Option<&RangeInclusive<u16>>) -> Option<PortReservation> {
+    allocator()
+        .reserve(count, range)
+        .map(|ports| PortReservation(Arc::new(ports)))
+}
+
+/// Ports reserved from the allocator, released once the last clone is dropped
+#[derive(Clone)]
+pub struct PortReservation(Arc<Reserved>);
+impl PortReservation {
+    pub fn ports(&self) -> &[u16] {
+        &self.0 .0
+    }
+}
+impl fmt::Debug for PortReservation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PortReservation {:?}", self.ports())
+    }
+}
+
+struct Reserved(Vec<u16>);
+impl Drop for Reserved {
+    fn drop(&mut self) {
+        allocator().release(&self.0);
+    }
+}
+
+/// Whether nothing on the host listens on `port`
+fn is_free(port: u16) -> bool {
+    TcpListener::bind((Ipv4Addr::LOCALHOST, port)).is_ok()
+        && UdpSocket::bind((Ipv4Addr::LOCALHOST, port)).is_ok()
+}
+
+struct Allocator {
+    /// Ports to pick from, random unused ones if None
+    range: Option<RangeInclusive<u16>>,
+    /// Offset into `range` to try next, so released ports are not reused right away
+    next: u32,
+    /// Ports held by a reservation
+    reserved: Vec<u16>,
+}
+impl Allocator {
+    const fn new() -> Self {
+        Self {
+            range: None,
+            next: 0,
+            reserved: Vec::new(),
+        }
+    }
+
+    fn reserve(&mut self, count: usize, range: Option<&RangeInclusive<u16>>) -> Option<Reserved> {
+        let range = range.or(self.range.as_ref()).cloned();
+        let mut ports = Vec::with_capacity(count);
+        for _ in 0..count {
+            match self.pick(range.as_ref()) {
+                Some(port) => {
+                    self.reserved.push(port);
+                    ports.push(port);
+                }
+                None => {
+                    match &range {
+                        Some(range) => warn!(
+                            "No free ports left in {}-{} to reserve {} ports",
+                            range.start(),
+                            range.end(),
+                            count
+                        ),
+                        None => warn!("No free ports left to reserve {} ports", count),
+                    }
+                    self.release(&ports);
+                    return None;
+                }
+            }
+        }
+        Some(Reserved(ports))
+    }
+
+    fn pick(&mut self, range: Option<&RangeInclusive<u16>>) -> Option<u16> {
+        let range = match range {
+            Some(range) => range,
+            None => {
+                return (0..RANDOM_ATTEMPTS)
+                    .filter_map(|_| pick_unused_port())
+                    .find(|port| !self.reserved.contains(port))
+            }
+        };
+        let len = *range.end() as u32 - *range.start() as u32 + 1;
+        for _ in 0..len {
+            let port = (*range.start() as u32 + self.next % len) as u16;
+            self.next = (self.next + 1) % len;
+            if !self.reserved.contains(&port) && is_free(port) {
+                return Some(port);
+            }
+        }
+        None
+    }
+
+    fn release(&mut self, ports: &[u16]) {
+        self.reserved.retain(|port| !ports.contains(port));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("20000-20999"), Some(20000..=20999));
+        assert_eq!(parse_range("20999-20000"), None);
+        assert_eq!(parse_range("0-10"), None);
+        assert_eq!(parse_range("20000"), None);
+    }
+
+    #[test]
+    fn test_reserve() {
+        let mut allocator = Allocator::new();
+        allocator.range = Some(47300..=47303);
+        let first = allocator.reserve(3, None).expect("Could not reserve ports");
+        let mut ports = first.0.clone();
+        ports.sort_unstable();
+        ports.dedup();
+        assert_eq!(ports.len(), 3);
+        assert!(allocator.reserve(2, None).is_none());
+        assert_eq!(allocator.reserved, first.0);
+        allocator.release(&first.0);
+        std::mem::forget(first);
+        assert!(allocator.reserve(2, None).is_some());
+        let other = allocator
+            .reserve(1, Some(&(47310..=47310)))
+            .expect("Could not reserve a port of the match range");
+        assert_eq!(other.0, vec![47310]);
+        std::mem::forget(other);
+    }
+}
@@ -1,42 +1,222 @@
 //! Proxy WebSocket receiver
 
+use crate::auth::{
+    query_token, query_value, Auth, SharedAuth, BOT_TOKEN_HEADER, SUPERVISOR_SECRET_HEADER,
+};
+use crate::channels::send_with_backpressure;
+use crate::connections::{ConnectionOptions, Connections, Counted, SharedConnections};
 use crate::errors::proxy_error::ProxyError;
-use crate::server::ClientType;
 use crossbeam::channel::Sender;
 use futures_util::SinkExt;
 use futures_util::StreamExt;
-use log::{error, info};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::io;
 use std::net::SocketAddr;
-use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
-use tokio_tungstenite::tungstenite::handshake::server::{
-    Callback, ErrorResponse, Request, Response,
-};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
 use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
 use tokio_tungstenite::tungstenite::protocol::{CloseFrame, WebSocketConfig};
 use tokio_tungstenite::tungstenite::{Error, Message};
 use tokio_tungstenite::{accept_hdr_async_with_config, WebSocketStream};
 
-pub struct HeaderHandler {
-    is_supervisor: bool,
+/// Tolerance for unmasked client frames, which RFC 6455 forbids
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum FrameMasking {
+    /// Strict on non-loopback binds, lenient on loopback
+    Auto,
+    /// Reject unmasked client frames
+    Strict,
+    /// Accept unmasked frames from legacy clients
+    Lenient,
 }
 
-impl Callback for HeaderHandler {
-    fn on_request(
-        mut self,
-        request: &Request,
-        response: Response,
-    ) -> Result<Response, ErrorResponse> {
-        self.is_supervisor = request.headers().contains_key("supervisor");
-        Ok(response)
+impl FrameMasking {
+    pub fn parse(mode: &str) -> Option<Self> {
+        match &mode.to_lowercase()[..] {
+            "auto" => Some(Self::Auto),
+            "strict" => Some(Self::Strict),
+            "lenient" => Some(Self::Lenient),
+            _ => None,
+        }
+    }
+    /// Whether unmasked frames are accepted on a listener bound to `addr`
+    pub fn accept_unmasked_frames(self, addr: &SocketAddr) -> bool {
+        match self {
+            Self::Auto => addr.ip().is_loopback(),
+            Self::Strict => false,
+            Self::Lenient => true,
+        }
+    }
+}
+
+impl Default for FrameMasking {
+    fn default() -> Self {
+        FrameMasking::Auto
+    }
+}
+
+/// Websocket configuration shared by bot, supervisor and SC2 connections
+pub fn websocket_config(accept_unmasked_frames: bool) -> WebSocketConfig {
+    WebSocketConfig {
+        max_send_queue: None,
+        max_message_size: Some(128 << 20), // 128MiB
+        max_frame_size: Some(32 << 20),    // 32MiB
+        // Accepting client frames which are not masked is not in compliance
+        // with RFC 6455 but might be handy in some rare cases where it is
+        // necessary to integrate with existing/legacy clients which are
+        // sending unmasked frames
+        accept_unmasked_frames,
     }
 }
 
+/// Header of read-only supervisor connections, e.g. dashboards
+pub const OBSERVER_HEADER: &str = "supervisor-observer";
+
+/// Header with the token of the session a bot or observer joins, also accepted as `?match=`
+pub const SESSION_HEADER: &str = "match-token";
+
+/// Session a connection asks to join, None for the default session
+pub fn requested_session(request: &Request) -> Option<String> {
+    request
+        .headers()
+        .get(SESSION_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .or_else(|| query_value(request.uri().query(), "match"))
+        .filter(|session| !session.is_empty())
+        .map(String::from)
+}
+
+/// Checks the handshake headers of a new connection
+pub struct HeaderHandler<'a> {
+    auth: &'a Auth,
+    /// Whether supervisors may connect without TLS
+    plain_supervisor: bool,
+    client_type: ClientType,
+    /// Session the connection joins, None for the default session
+    session: Option<String>,
+    /// Why the connection was refused, if it was
+    rejection: Option<String>,
+}
+
+impl<'a> HeaderHandler<'a> {
+    pub fn new(auth: &'a Auth, plain_supervisor: bool) -> Self {
+        Self {
+            auth,
+            plain_supervisor,
+            client_type: ClientType::Bot,
+            session: None,
+            rejection: None,
+        }
+    }
+
+    fn reject(&mut self, status: StatusCode, reason: &str) -> ErrorResponse {
+        self.rejection = Some(reason.to_string());
+        let mut response = ErrorResponse::new(Some(reason.to_string()));
+        *response.status_mut() = status;
+        response
+    }
+
+    /// Classify the connection, refusing it if it may not connect
+    pub fn check(&mut self, request: &Request) -> Result<(), ErrorResponse> {
+        let headers = request.headers();
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+        let shutdown = headers.contains_key("shutdown");
+        if headers.contains_key(OBSERVER_HEADER) {
+            self.client_type = ClientType::Observer;
+        } else if headers.contains_key("supervisor") {
+            self.client_type = ClientType::Controller;
+        }
+        let is_bot = self.client_type == ClientType::Bot;
+        if !is_bot && !self.plain_supervisor {
+            return Err(self.reject(StatusCode::FORBIDDEN, "Supervisors must connect over TLS"));
+        }
+        if (!is_bot || shutdown) && !self.auth.check_supervisor(header(SUPERVISOR_SECRET_HEADER)) {
+            return Err(self.reject(StatusCode::UNAUTHORIZED, "Invalid supervisor secret"));
+        }
+        if shutdown {
+            return Err(ErrorResponse::new(Some("Shutdown Requested".to_string())));
+        }
+        self.session = requested_session(request);
+        if is_bot {
+            let token = header(BOT_TOKEN_HEADER).or_else(|| query_token(request.uri().query()));
+            if !self
+                .auth
+                .admit_bot(self.session.as_deref().unwrap_or_default(), token)
+            {
+                return Err(self.reject(StatusCode::UNAUTHORIZED, "Missing or unknown bot token"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Transport of a client connection, plain TCP or TLS
+pub trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+/// Websocket of a bot or supervisor
+pub type ClientStream = WebSocketStream<Box<dyn Connection>>;
+
+/// Keepalive Pings sent to a bot while the proxy waits for its messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Heartbeat {
+    /// Time between two Pings
+    pub interval: Duration,
+    /// Pings in a row the bot may leave unanswered before it is dropped
+    pub max_missed: u32,
+}
+
 pub struct Client {
-    pub(crate) stream: WebSocketStream<TcpStream>,
+    pub(crate) stream: ClientStream,
     addr: SocketAddr,
+    heartbeat: Option<Heartbeat>,
+    /// When the next Ping is due
+    next_ping: Option<Instant>,
+    /// Pings sent since the last message of the bot
+    missed: u32,
+    /// Session the client joins, None for the default session
+    session: Option<String>,
+}
+
+impl std::fmt::Debug for Client {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Client")
+            .field("addr", &self.addr)
+            .field("session", &self.session)
+            .finish()
+    }
 }
 
 impl Client {
+    pub(crate) fn new(stream: ClientStream, addr: SocketAddr) -> Self {
+        Self {
+            stream,
+            addr,
+            heartbeat: None,
+            next_ping: None,
+            missed: 0,
+            session: None,
+        }
+    }
+    /// Route the client to `session`
+    pub(crate) fn with_session(mut self, session: Option<String>) -> Self {
+        self.session = session;
+        self
+    }
+    pub fn session(&self) -> Option<&str> {
+        self.session.as_deref()
+    }
+    /// Ping the client while waiting for its messages, None to stop
+    pub fn set_heartbeat(&mut self, heartbeat: Option<Heartbeat>) {
+        self.heartbeat = heartbeat;
+        self.next_ping = heartbeat.map(|heartbeat| Instant::now() + heartbeat.interval);
+        self.missed = 0;
+    }
     pub async fn shutdown(&mut self) -> Result<(), Error> {
         self.stream
             .close(Some(CloseFrame {
@@ -48,52 +228,93 @@ impl Client {
     pub async fn send_message(&mut self, message: Message) -> Result<(), Error> {
         self.stream.send(message).await
     }
+    /// Receive the next message, answering for the heartbeat in between
+    /// Pongs are consumed, and a client that missed too many Pings fails with `TimedOut`
     pub async fn recv_message(&mut self) -> Option<Result<Message, Error>> {
-        self.stream.next().await
+        loop {
+            let (heartbeat, next_ping) = match (self.heartbeat, self.next_ping) {
+                (Some(heartbeat), Some(next_ping)) => (heartbeat, next_ping),
+                _ => return self.stream.next().await,
+            };
+            tokio::select! {
+                message = self.stream.next() => {
+                    self.missed = 0;
+                    match message {
+                        Some(Ok(Message::Pong(_))) => continue,
+                        message => return message,
+                    }
+                }
+                _ = tokio::time::sleep_until(next_ping) => {
+                    if self.missed >= heartbeat.max_missed {
+                        return Some(Err(Error::Io(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            format!("No answer to {} heartbeat pings", self.missed),
+                        ))));
+                    }
+                    self.missed += 1;
+                    self.next_ping = Some(next_ping + heartbeat.interval);
+                    if let Err(e) = self.stream.send(Message::Ping(Vec::new())).await {
+                        return Some(Err(e));
+                    }
+                }
+            }
+        }
     }
     pub fn peer_addr(&self) -> &SocketAddr {
         &self.addr
     }
 }
 
+/// Kind of client behind a new connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ClientType {
+    Bot,
+    #[serde(rename = "Supervisor")]
+    Controller,
+    /// Read-only supervisor, receives what the supervisor does but cannot control matches
+    Observer,
+}
+
 /// Accept a new connection
-async fn get_connection(server: &mut TcpListener) -> Result<(ClientType, Client), ProxyError> {
-    let mut is_supervisor = false;
+/// Supervisors are refused unless `plain_supervisor`, i.e. when they have to use TLS
+async fn get_connection(
+    server: &mut TcpListener,
+    accept_unmasked_frames: bool,
+    plain_supervisor: bool,
+    auth: &Auth,
+    connections: &Connections,
+) -> Result<(ClientType, Client), ProxyError> {
+    let mut handler = HeaderHandler::new(auth, plain_supervisor);
     let callback = |req: &Request, response: Response| {
-        if req.headers().contains_key("supervisor") {
-            is_supervisor = true;
-        }
-        if req.headers().contains_key("shutdown") {
-            return Err(ErrorResponse::new(Some("Shutdown Requested".to_string())));
-        }
+        handler.check(req)?;
         Ok(response)
     };
-    let config = Some(WebSocketConfig {
-        max_send_queue: None,
-        max_message_size: Some(128 << 20), // 128MiB
-        max_frame_size: Some(32 << 20),    // 32MiB
-        // This setting allows to accept client frames which are not masked
-        // This is not in compliance with RFC 6455 but might be handy in some
-        // rare cases where it is necessary to integrate with existing/legacy
-        // clients which are sending unmasked frames
-        accept_unmasked_frames: true,
-    });
+    let config = Some(websocket_config(accept_unmasked_frames));
     match server.accept().await {
         Ok((stream, peer)) => {
             // let peer = stream.peer_addr().expect("connected streams should have a peer address");
-            match accept_hdr_async_with_config(stream, callback, config).await {
-                Ok(ws_stream) => {
-                    let client = Client {
-                        stream: ws_stream,
-                        addr: peer,
+            let stream = Counted::new(Box::new(stream));
+            let counters = stream.counters();
+            let stream: Box<dyn Connection> = Box::new(stream);
+            let accepted = accept_hdr_async_with_config(stream, callback, config).await;
+            match (accepted, handler.rejection) {
+                (Ok(ws_stream), _) => {
+                    let options = ConnectionOptions {
+                        tls: false,
+                        unmasked_frames: accept_unmasked_frames,
+                        session: handler.session.clone(),
                     };
-                    if is_supervisor {
-                        Ok((ClientType::Controller, client))
-                    } else {
-                        Ok((ClientType::Bot, client))
-                    }
+                    connections.register(handler.client_type, peer, options, &counters);
+                    Ok((
+                        handler.client_type,
+                        Client::new(ws_stream, peer).with_session(handler.session),
+                    ))
+                }
+                (Err(e), Some(reason)) => {
+                    info!("{:?}", e);
+                    Err(ProxyError::Rejected(peer, reason))
                 }
-                Err(e) => {
+                (Err(e), None) => {
                     info!("1{:?}", e);
                     Err(ProxyError::ShutdownRequest)
                 }
@@ -106,15 +327,44 @@ async fn get_connection(server: &mut TcpListener) -> Result<(ClientType, Client)
     }
 }
 
-/// Run the proxy server
-pub async fn run<A: ToSocketAddrs>(addr: A, channel_out: Sender<(ClientType, Client)>) {
-    let mut server = TcpListener::bind(addr).await.expect("Unable to bind");
+/// Run the proxy server on the already bound `server`
+/// With `plain_supervisor` false, supervisors can only connect through `supervisor_tls::run`
+pub async fn run(
+    mut server: TcpListener,
+    channel_out: Sender<(ClientType, Client)>,
+    frame_masking: FrameMasking,
+    plain_supervisor: bool,
+    auth: SharedAuth,
+    connections: SharedConnections,
+) {
+    let accept_unmasked_frames = match server.local_addr() {
+        Ok(local_addr) => frame_masking.accept_unmasked_frames(&local_addr),
+        Err(_) => frame_masking == FrameMasking::Lenient,
+    };
+    info!(
+        "Accepting unmasked client frames: {:?}",
+        accept_unmasked_frames
+    );
 
     loop {
-        match get_connection(&mut server).await {
+        match get_connection(
+            &mut server,
+            accept_unmasked_frames,
+            plain_supervisor,
+            &auth,
+            &connections,
+        )
+        .await
+        {
             Ok((c_type, client)) => {
                 info!("Connection accepted: {:?}", client.addr);
-                channel_out.send((c_type, client)).expect("Send failed");
+                // Waits while the controller is behind, new connections queue up in the backlog
+                send_with_backpressure(&channel_out, (c_type, client))
+                    .await
+                    .expect("Send failed");
+            }
+            Err(ProxyError::Rejected(peer, reason)) => {
+                warn!("Refused {:?}: {}", peer, reason);
             }
             Err(ProxyError::AcceptError) => {
                 error!("Could not accept incoming request");
@@ -127,3 +377,47 @@ pub async fn run<A: ToSocketAddrs>(addr: A, channel_out: Sender<(ClientType, Cli
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_tungstenite::tungstenite::protocol::Role;
+
+    async fn pair() -> (Client, WebSocketStream<tokio::io::DuplexStream>) {
+        let (server, bot) = tokio::io::duplex(1024);
+        let server: Box<dyn Connection> = Box::new(server);
+        let server = WebSocketStream::from_raw_socket(server, Role::Server, None).await;
+        let bot = WebSocketStream::from_raw_socket(bot, Role::Client, None).await;
+        (Client::new(server, "127.0.0.1:0".parse().unwrap()), bot)
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat() {
+        let heartbeat = Heartbeat {
+            interval: Duration::from_millis(20),
+            max_missed: 2,
+        };
+
+        // A bot that never reads does not answer the Pings
+        let (mut client, _bot) = pair().await;
+        client.set_heartbeat(Some(heartbeat));
+        match client.recv_message().await {
+            Some(Err(Error::Io(e))) => assert_eq!(e.kind(), io::ErrorKind::TimedOut),
+            other => panic!("Expected a heartbeat timeout, got {:?}", other),
+        }
+
+        // Reading answers the Pings, the Pongs never reach the caller
+        let (mut client, mut bot) = pair().await;
+        client.set_heartbeat(Some(heartbeat));
+        tokio::spawn(async move {
+            let deadline = Instant::now() + Duration::from_millis(150);
+            while tokio::time::timeout_at(deadline, bot.next()).await.is_ok() {}
+            bot.send(Message::binary(vec![1, 2, 3])).await.unwrap();
+            while bot.next().await.is_some() {}
+        });
+        match client.recv_message().await {
+            Some(Ok(Message::Binary(data))) => assert_eq!(data, vec![1, 2, 3]),
+            other => panic!("Expected the message of the bot, got {:?}", other),
+        }
+    }
+}
@@ -1,10 +1,22 @@
+use crate::config::{Config, Violation};
+use crate::handler::{
+    ChatLine, Disconnect, LagReport, OutOfOrder, PlayerStats, Sc2Version, SizeWindow,
+    StateSnapshot, UnknownFieldCounts,
+};
+use crate::limits::{CpuTime, MemoryPeak};
+use crate::update::UpdateStatus;
+use sc2_proto::sc2api::RequestJoinGame;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
-#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Default, PartialEq)]
 pub(crate) struct JsonResult {
     #[serde(default, rename = "MatchID")]
     match_id: i64,
+    /// Identifier of the game in the proxy pool
+    #[serde(default, rename = "GameId")]
+    pub(crate) game_id: u64,
     #[serde(default, rename = "Result")]
     result: HashMap<String, String>,
     #[serde(default, rename = "GameTime")]
@@ -25,6 +37,242 @@ pub(crate) struct JsonResult {
     replay_path: String,
     #[serde(default, rename = "Tags")]
     tags: HashMap<String, Vec<String>>,
+    #[serde(
+        default,
+        rename = "Capabilities",
+        skip_serializing_if = "HashMap::is_empty"
+    )]
+    pub(crate) capabilities: HashMap<String, BotCapabilities>,
+    #[serde(default, rename = "WarmupDuration")]
+    pub(crate) warmup_duration: HashMap<String, f32>,
+    /// Wall-clock start of the game, RFC 3339
+    #[serde(default, rename = "StartedAt")]
+    pub(crate) started_at: String,
+    /// Wall-clock end of the game, RFC 3339
+    #[serde(default, rename = "FinishedAt")]
+    pub(crate) finished_at: String,
+    /// Monotonic duration of the game in seconds, unaffected by clock adjustments
+    #[serde(default, rename = "Duration")]
+    pub(crate) duration: f64,
+    #[serde(default, rename = "GameSpeed")]
+    pub(crate) game_speed: String,
+    #[serde(default, rename = "LoopsPerSecond")]
+    pub(crate) loops_per_second: f64,
+    #[serde(default, rename = "Update", skip_serializing_if = "Option::is_none")]
+    pub(crate) update: Option<UpdateStatus>,
+    /// Key the stored artifacts of the match were encrypted with
+    #[serde(
+        default,
+        rename = "ArtifactKeyId",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) artifact_key_id: Option<String>,
+    /// Artifact bundle of the match
+    #[serde(default, rename = "Bundle", skip_serializing_if = "Option::is_none")]
+    pub(crate) bundle: Option<String>,
+    /// Directories the match used, as set in the Config
+    #[serde(default, rename = "ReplayDir", skip_serializing_if = "Option::is_none")]
+    pub(crate) replay_dir: Option<String>,
+    #[serde(default, rename = "LogDir", skip_serializing_if = "Option::is_none")]
+    pub(crate) log_dir: Option<String>,
+    #[serde(default, rename = "TempDir", skip_serializing_if = "Option::is_none")]
+    pub(crate) temp_dir: Option<String>,
+    /// CSV file with the timing of every frame
+    #[serde(
+        default,
+        rename = "FrameTrace",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) frame_trace: Option<String>,
+    /// Frame budgets each bot exceeded, only bots with strikes are listed
+    #[serde(default, rename = "Strikes", skip_serializing_if = "HashMap::is_empty")]
+    pub(crate) strikes: HashMap<String, u32>,
+    /// Requests of each bot over `MaxActionsPerFrame`, only bots with strikes are listed
+    #[serde(
+        default,
+        rename = "ActionStrikes",
+        skip_serializing_if = "HashMap::is_empty"
+    )]
+    pub(crate) action_strikes: HashMap<String, u32>,
+    /// Rule violations of each bot the proxy caught, with every category listed
+    #[serde(
+        default,
+        rename = "Violations",
+        skip_serializing_if = "HashMap::is_empty"
+    )]
+    pub(crate) violations: HashMap<String, BTreeMap<Violation, u32>>,
+    /// How the connection of each bot broke, only bots that lost their connection are listed
+    #[serde(
+        default,
+        rename = "Disconnect",
+        skip_serializing_if = "HashMap::is_empty"
+    )]
+    pub(crate) disconnect: HashMap<String, Disconnect>,
+    /// Last observation sent to each bot, only present when the game ended abnormally
+    #[serde(
+        default,
+        rename = "LastObservation",
+        skip_serializing_if = "HashMap::is_empty"
+    )]
+    pub(crate) last_observation: HashMap<String, StateSnapshot>,
+    /// Score of each bot at its last observation, bots that got no observation are not listed
+    #[serde(default, rename = "Stats", skip_serializing_if = "HashMap::is_empty")]
+    pub(crate) stats: HashMap<String, PlayerStats>,
+    /// Chat messages of each bot, only bots that chatted are listed
+    #[serde(default, rename = "Chat", skip_serializing_if = "HashMap::is_empty")]
+    pub(crate) chat: HashMap<String, Vec<ChatLine>>,
+    /// Out-of-order requests of each bot with `RequestOrdering`, only bots that sent any are listed
+    #[serde(
+        default,
+        rename = "OutOfOrder",
+        skip_serializing_if = "HashMap::is_empty"
+    )]
+    pub(crate) out_of_order: HashMap<String, BTreeMap<OutOfOrder, u32>>,
+    /// Lags of each bot in real time with `RealtimeMaxLag`, only bots that lagged are listed
+    #[serde(
+        default,
+        rename = "RealtimeLag",
+        skip_serializing_if = "HashMap::is_empty"
+    )]
+    pub(crate) realtime_lag: HashMap<String, LagReport>,
+    /// Outcome of each team by team number, only for team games
+    #[serde(
+        default,
+        rename = "TeamResults",
+        skip_serializing_if = "HashMap::is_empty"
+    )]
+    pub(crate) team_results: HashMap<String, String>,
+    /// Observation sizes of each bot per window of game loops
+    #[serde(
+        default,
+        rename = "ObservationSizes",
+        skip_serializing_if = "HashMap::is_empty"
+    )]
+    pub(crate) observation_sizes: HashMap<String, Vec<SizeWindow>>,
+    /// Protocol fields unknown to the proxy per bot, only bots that met any are listed
+    #[serde(
+        default,
+        rename = "UnknownFields",
+        skip_serializing_if = "HashMap::is_empty"
+    )]
+    pub(crate) unknown_fields: HashMap<String, UnknownFieldCounts>,
+    /// Peak memory sampled for each bot, only bots that were sampled are listed
+    #[serde(
+        default,
+        rename = "PeakMemory",
+        skip_serializing_if = "HashMap::is_empty"
+    )]
+    pub(crate) peak_memory: HashMap<String, MemoryPeak>,
+    /// CPU time used by each bot and its SC2 during the game, only sampled processes are listed
+    #[serde(default, rename = "CpuTime", skip_serializing_if = "HashMap::is_empty")]
+    pub(crate) cpu_time: HashMap<String, CpuTime>,
+    /// Most game loops each bot was ahead of the other, not tracked in transparent mode
+    #[serde(
+        default,
+        rename = "LoopSkew",
+        skip_serializing_if = "HashMap::is_empty"
+    )]
+    pub(crate) loop_skew: HashMap<String, u32>,
+    #[serde(default, rename = "Rematch")]
+    pub(crate) rematch: bool,
+    /// Raw results reported by each player, present when their reports disagreed
+    #[serde(
+        default,
+        rename = "ResultDiscrepancy",
+        skip_serializing_if = "HashMap::is_empty"
+    )]
+    pub(crate) result_discrepancy: HashMap<String, Vec<String>>,
+    /// Version both SC2 processes ran
+    #[serde(
+        default,
+        rename = "Sc2Version",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) sc2_version: Option<Sc2Version>,
+    /// Environment variables the SC2 processes were launched with
+    #[serde(default, rename = "Sc2Env", skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) sc2_env: BTreeMap<String, String>,
+    /// Whether a well-formed replay was written to `ReplayPath`
+    #[serde(
+        default,
+        rename = "ReplaySaved",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) replay_saved: Option<bool>,
+    /// Seed the map was picked with, for a list or `random` Map
+    #[serde(default, rename = "MapSeed", skip_serializing_if = "Option::is_none")]
+    pub(crate) map_seed: Option<u64>,
+    /// Seed of the randomness of SC2 in the game, to play it again the same way
+    #[serde(
+        default,
+        rename = "RandomSeed",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) random_seed: Option<u32>,
+    /// The match was played against a mock SC2, its result is fake
+    #[serde(default, rename = "DryRun")]
+    pub(crate) dry_run: bool,
+    /// Problems after the game that did not change its result, e.g. `CleanupTimedOut`
+    #[serde(default, rename = "Warnings", skip_serializing_if = "Vec::is_empty")]
+    pub(crate) warnings: Vec<String>,
+}
+
+/// Capabilities a bot reported during the playlist phase
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Default, PartialEq, Eq)]
+pub(crate) struct BotCapabilities {
+    /// Bot answered the Ping probe before joining
+    #[serde(default, rename = "Probed")]
+    pub(crate) probed: bool,
+    /// Bot completed the `GameInfo` capability exchange before joining
+    #[serde(default, rename = "CapabilityExchange")]
+    pub(crate) capability_exchange: bool,
+    /// Framework reported through the `name|framework|version` player_name convention
+    #[serde(default, rename = "Framework")]
+    pub(crate) framework: Option<String>,
+    /// SC2 client version the bot was built against, reported like the framework
+    #[serde(
+        default,
+        rename = "ClientVersion",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub(crate) client_version: Option<String>,
+    /// Interfaces requested in the join request
+    #[serde(default, rename = "Interfaces")]
+    pub(crate) interfaces: Vec<String>,
+}
+impl BotCapabilities {
+    /// Record the framework, client version and interfaces advertised in a join request
+    pub(crate) fn record_join(&mut self, req: &RequestJoinGame) {
+        if req.has_player_name() {
+            let mut fields = req
+                .player_name()
+                .split('|')
+                .skip(1)
+                .map(|field| Some(field.trim().to_string()).filter(|field| !field.is_empty()));
+            self.framework = fields.next().flatten();
+            self.client_version = fields.next().flatten();
+        }
+        let options = &req.options;
+        let mut interfaces = Vec::new();
+        if options.raw() {
+            interfaces.push("raw".to_string());
+        }
+        if options.score() {
+            interfaces.push("score".to_string());
+        }
+        if options.feature_layer.is_some() {
+            interfaces.push("feature_layer".to_string());
+        }
+        if options.render.is_some() {
+            interfaces.push("render".to_string());
+        }
+        self.interfaces = interfaces;
+    }
+}
+/// Format game seconds as `MM:SS`, as shown by the SC2 client
+pub(crate) fn format_game_time(seconds: f64) -> String {
+    let seconds = seconds.max(0.0) as u64;
+    format!("{:02}:{:02}", seconds / 60, seconds % 60)
 }
 impl JsonResult {
     #[allow(clippy::too_many_arguments)]
@@ -53,9 +301,104 @@ impl JsonResult {
             replay_path: replay_path.unwrap_or_default(),
             match_id: match_id.unwrap_or_default(),
             tags: tags.unwrap_or_default(),
+            capabilities: HashMap::new(),
+            warmup_duration: HashMap::new(),
+            started_at: String::new(),
+            finished_at: String::new(),
+            duration: 0.0,
+            game_speed: String::new(),
+            loops_per_second: 0.0,
+            game_id: 0,
+            update: None,
+            artifact_key_id: None,
+            bundle: None,
+            replay_dir: None,
+            log_dir: None,
+            temp_dir: None,
+            frame_trace: None,
+            strikes: HashMap::new(),
+            action_strikes: HashMap::new(),
+            violations: HashMap::new(),
+            disconnect: HashMap::new(),
+            last_observation: HashMap::new(),
+            stats: HashMap::new(),
+            chat: HashMap::new(),
+            out_of_order: HashMap::new(),
+            realtime_lag: HashMap::new(),
+            team_results: HashMap::new(),
+            observation_sizes: HashMap::new(),
+            unknown_fields: HashMap::new(),
+            peak_memory: HashMap::new(),
+            cpu_time: HashMap::new(),
+            loop_skew: HashMap::new(),
+            rematch: false,
+            result_discrepancy: HashMap::new(),
+            sc2_version: None,
+            sc2_env: BTreeMap::new(),
+            replay_saved: None,
+            map_seed: None,
+            random_seed: None,
+            dry_run: false,
+            warnings: Vec::new(),
         }
     }
+    /// Echo where the match put its artifacts: its directories, absolute once the config
+    /// was accepted, and the frame trace
+    pub(crate) fn set_dirs(&mut self, config: &Config) {
+        self.replay_dir = config.replay_dir.clone();
+        self.log_dir = config.log_dir.clone();
+        self.temp_dir = config.temp_dir.clone();
+        self.frame_trace = config.frame_trace.clone();
+    }
     pub(crate) fn serialize(&self) -> String {
         serde_json::to_string(&self).expect("Could not serialize Result")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sc2_proto::sc2api::InterfaceOptions;
+
+    #[test]
+    fn test_record_join_framework() {
+        let mut req = RequestJoinGame::new();
+        req.set_player_name("Bot1|python-sc2 6.0".to_string());
+        let mut options = InterfaceOptions::new();
+        options.set_raw(true);
+        req.options = protobuf::MessageField::some(options);
+        let mut capabilities = BotCapabilities::default();
+        capabilities.record_join(&req);
+        assert_eq!(capabilities.framework, Some("python-sc2 6.0".to_string()));
+        assert_eq!(capabilities.interfaces, vec!["raw".to_string()]);
+        assert_eq!(capabilities.client_version, None);
+    }
+
+    #[test]
+    fn test_record_join_client_version() {
+        let mut req = RequestJoinGame::new();
+        req.set_player_name("Bot1|python-sc2 6.0| 4.10.0.75689".to_string());
+        let mut capabilities = BotCapabilities::default();
+        capabilities.record_join(&req);
+        assert_eq!(capabilities.framework, Some("python-sc2 6.0".to_string()));
+        assert_eq!(
+            capabilities.client_version,
+            Some("4.10.0.75689".to_string())
+        );
+
+        req.set_player_name("Bot1||4.10.0.75689".to_string());
+        capabilities.record_join(&req);
+        assert_eq!(capabilities.framework, None);
+        assert_eq!(
+            capabilities.client_version,
+            Some("4.10.0.75689".to_string())
+        );
+    }
+
+    #[test]
+    fn test_format_game_time() {
+        assert_eq!(format_game_time(0.0), "00:00");
+        assert_eq!(format_game_time(59.9), "00:59");
+        assert_eq!(format_game_time(22400.0 / 22.4), "16:40");
+    }
+}
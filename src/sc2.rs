@@ -13,14 +13,15 @@ pub enum Race {
     Random,
 }
 impl Race {
-    pub fn from_proto(race: sc2_proto::common::Race) -> Self {
+    /// None for `NoRace`, which a bot cannot play
+    pub fn from_proto(race: sc2_proto::common::Race) -> Option<Self> {
         use sc2_proto::common::Race;
         match race {
-            Race::Protoss => Self::Protoss,
-            Race::Terran => Self::Terran,
-            Race::Zerg => Self::Zerg,
-            Race::Random => Self::Random,
-            Race::NoRace => panic!("NoRace not allowed"),
+            Race::Protoss => Some(Self::Protoss),
+            Race::Terran => Some(Self::Terran),
+            Race::Zerg => Some(Self::Zerg),
+            Race::Random => Some(Self::Random),
+            Race::NoRace => None,
         }
     }
 
@@ -55,6 +56,22 @@ pub enum Difficulty {
     CheatInsane,
 }
 impl Difficulty {
+    pub fn parse(difficulty: &str) -> Option<Self> {
+        match &difficulty.to_lowercase()[..] {
+            "veryeasy" => Some(Self::VeryEasy),
+            "easy" => Some(Self::Easy),
+            "medium" => Some(Self::Medium),
+            "mediumhard" => Some(Self::MediumHard),
+            "hard" => Some(Self::Hard),
+            "harder" => Some(Self::Harder),
+            "veryhard" => Some(Self::VeryHard),
+            "cheatvision" => Some(Self::CheatVision),
+            "cheatmoney" => Some(Self::CheatMoney),
+            "cheatinsane" => Some(Self::CheatInsane),
+            _ => None,
+        }
+    }
+
     pub fn to_proto(self) -> sc2_proto::sc2api::Difficulty {
         use sc2_proto::sc2api::Difficulty;
         match self {
@@ -94,6 +111,12 @@ pub enum PlayerResult {
     #[allow(clippy::upper_case_acronyms)]
     SC2Crash,
     Timeout,
+    /// The bot could not join the game, e.g. because of a malformed join request
+    InitializationError,
+    /// SC2 was killed for exceeding the memory limit of the player
+    ResourceLimit,
+    /// The bot committed a violation listed in `ForfeitViolations`
+    Forfeit,
 }
 impl PlayerResult {
     pub fn from_proto(race: sc2_proto::sc2api::Result) -> Self {
@@ -106,6 +129,11 @@ impl PlayerResult {
         }
     }
 
+    /// The game did not end by SC2 deciding it, e.g. a crash or a timeout
+    pub fn is_abnormal(self) -> bool {
+        !matches!(self, Self::Victory | Self::Defeat | Self::Tie)
+    }
+
     pub fn to_proto(self) -> sc2_proto::sc2api::Result {
         use sc2_proto::sc2api::Result;
         match self {
@@ -114,6 +142,9 @@ impl PlayerResult {
             Self::Tie => Result::Tie,
             Self::Crash => Result::Defeat,
             Self::Timeout => Result::Defeat,
+            Self::ResourceLimit => Result::Defeat,
+            Self::Forfeit => Result::Defeat,
+            Self::InitializationError => Result::Defeat,
             Self::SC2Crash => Result::Undecided,
         }
     }
@@ -1,38 +1,169 @@
 //! SC2 process manager
 
+use std::collections::BTreeMap;
+use std::fmt;
 use std::io::ErrorKind::ConnectionRefused;
+use std::io::Read;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
 use std::process::{Child, Command, Stdio};
-use std::thread::sleep;
 use std::time::Duration;
 
-use log::{debug, info, warn};
+use log::{debug, error, info, warn};
 
-use portpicker::pick_unused_port;
 use tempfile::TempDir;
 use tokio::net::TcpStream;
-use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
 use tokio_tungstenite::WebSocketStream;
 
+use crate::errors::ArenaError;
+use crate::limits::{LimitGuard, ResourceLimits, Violation};
+use crate::mock_sc2::MockSc2;
 use crate::paths;
+use crate::ports::{self, PortReservation};
+use crate::proxy::websocket_config;
+
+/// Arguments the proxy passes to SC2 itself, user arguments must not repeat them
+pub const MANAGED_ARGS: [&str; 5] = ["-listen", "-port", "-dataDir", "-tempDir", "-displayMode"];
+
+/// Window SC2 renders the game in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisplayMode {
+    Windowed,
+    Fullscreen,
+}
+impl Default for DisplayMode {
+    fn default() -> Self {
+        DisplayMode::Windowed
+    }
+}
+impl DisplayMode {
+    pub fn parse(mode: &str) -> Option<Self> {
+        match &mode.to_lowercase()[..] {
+            "windowed" => Some(Self::Windowed),
+            "fullscreen" => Some(Self::Fullscreen),
+            _ => None,
+        }
+    }
+    /// Value of the `-displayMode` argument
+    fn arg(&self) -> &'static str {
+        match self {
+            Self::Windowed => "0",
+            Self::Fullscreen => "1",
+        }
+    }
+}
+
+/// Window of an SC2 process rendering the game for spectators, see `Visualize`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    pub width: u32,
+    pub height: u32,
+    /// Left edge on the screen, so the windows of several players are side by side
+    pub x: u32,
+}
+
+/// How SC2 is launched for a match
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LaunchOptions {
+    pub display_mode: DisplayMode,
+    /// Pass `-verbose`, so SC2 logs more to stderr
+    pub verbose: bool,
+    /// Data version hash passed as `-dataVersion`
+    pub data_version: Option<String>,
+    /// `Base*` folder to launch, None for the newest installed
+    pub base_build: Option<u64>,
+    /// Extra arguments appended as they are
+    pub args: Vec<String>,
+    /// Environment variables set for the process, on top of the proxy's own
+    pub env: BTreeMap<String, String>,
+    /// Serve the game from `MockSc2` instead of launching SC2
+    pub dry_run: bool,
+    /// Ports the websocket port is picked from, None for the range of the proxy
+    pub port_range: Option<RangeInclusive<u16>>,
+    /// Window the game is rendered in, None to let SC2 choose
+    pub window: Option<Window>,
+}
+impl LaunchOptions {
+    /// Whether processes launched with these options are interchangeable with warm ones
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn executable(&self) -> Result<PathBuf, ArenaError> {
+        match self.base_build {
+            Some(build) => paths::build_executable_path(&paths::versions_dir(), build)
+                .ok_or_else(|| ArenaError::Sc2Launch(format!("Base{} is not installed", build))),
+            None => Ok(paths::executable()),
+        }
+    }
+}
+
+/// What answers on the websocket port
+enum Instance {
+    Sc2(Child),
+    Mock(MockSc2),
+}
 
 /// SC2 process
 pub struct Process {
-    /// The actual SC2 process
-    process: Child,
+    /// The actual SC2 process, or its stand-in in a dry run
+    process: Instance,
     /// WebSocket port
     ws_port: u16,
+    /// Keeps other processes from picking the websocket port
+    _ws_reservation: PortReservation,
+    /// Game ports of the game the process plays, held until the process is gone
+    game_ports: Option<PortReservation>,
+    /// Temporary files of the process, removed once it is dropped
+    _tempdir: TempDir,
+    /// Memory and CPU caps of the player using the process
+    limits: Option<LimitGuard>,
 }
 
 impl Process {
-    /// Launch a new process
-    pub fn new() -> Self {
-        let ws_port = pick_unused_port().expect("Could not find a free port");
-        let tempdir = TempDir::new().expect("Could not create temp dir");
+    /// Launch a new process with the default options
+    pub fn new() -> Result<Self, ArenaError> {
+        Self::launch(None, &LaunchOptions::default())
+    }
+
+    /// Launch a new process keeping its temporary files below `temp_root`,
+    /// or the system temp dir if None
+    pub fn launch(temp_root: Option<&Path>, options: &LaunchOptions) -> Result<Self, ArenaError> {
+        let ws_reservation = ports::reserve_in(1, options.port_range.as_ref())
+            .ok_or_else(|| ArenaError::Sc2Launch("No free port".to_string()))?;
+        let ws_port = ws_reservation.ports()[0];
+        let tempdir = match temp_root {
+            Some(root) => TempDir::new_in(root),
+            None => TempDir::new(),
+        }
+        .map_err(|e| ArenaError::Sc2Launch(format!("Could not create temp dir: {}", e)))?;
 
-        debug!("Starting a new SC2 process");
+        if options.dry_run {
+            debug!("Starting a mock SC2 for a dry run");
+            let mock =
+                MockSc2::launch(ws_port).map_err(|e| ArenaError::Sc2Launch(e.to_string()))?;
+            return Ok(Self {
+                process: Instance::Mock(mock),
+                ws_port,
+                _ws_reservation: ws_reservation,
+                game_ports: None,
+                _tempdir: tempdir,
+                limits: None,
+            });
+        }
 
-        let process = (Command::new(paths::executable())
+        debug!("Starting a new SC2 process with {:?}", options);
+
+        let executable = options.executable()?;
+        let mut command = if paths::uses_wine() {
+            let mut command = Command::new(paths::wine_binary());
+            command.arg(executable);
+            command
+        } else {
+            Command::new(executable)
+        };
+        command
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .arg("-listen")
@@ -40,75 +171,181 @@ impl Process {
             .arg("-port")
             .arg(ws_port.to_string())
             .arg("-dataDir")
-            .arg(paths::base_dir().to_str().unwrap())
+            .arg(paths::base_dir())
             .arg("-displayMode")
-            .arg("0")
+            .arg(options.display_mode.arg())
             .arg("-tempDir")
-            .arg(tempdir.path().to_str().unwrap())
-            .current_dir(paths::cwd_dir()))
-        .spawn()
-        .expect("Could not launch SC2 process");
+            .arg(tempdir.path())
+            .current_dir(paths::cwd_dir());
+        if options.verbose {
+            command.arg("-verbose");
+        }
+        if let Some(window) = options.window {
+            command
+                .arg("-windowwidth")
+                .arg(window.width.to_string())
+                .arg("-windowheight")
+                .arg(window.height.to_string())
+                .arg("-windowx")
+                .arg(window.x.to_string())
+                .arg("-windowy")
+                .arg("0");
+        }
+        if let Some(data_version) = &options.data_version {
+            command.arg("-dataVersion").arg(data_version);
+        }
+        command.args(&options.args);
+        command.envs(&options.env);
+        let process = command
+            .spawn()
+            .map_err(|e| ArenaError::Sc2Launch(e.to_string()))?;
+
+        Ok(Self {
+            process: Instance::Sc2(process),
+            ws_port,
+            _ws_reservation: ws_reservation,
+            game_ports: None,
+            _tempdir: tempdir,
+            limits: None,
+        })
+    }
+
+    /// Hold the game ports of the game the process joins, until the process is dropped
+    pub fn hold_ports(&mut self, ports: PortReservation) {
+        self.game_ports = Some(ports);
+    }
+
+    /// Cap the memory and CPU of the process, once it is assigned to a player
+    pub fn apply_limits(&mut self, limits: &ResourceLimits) {
+        if limits.is_unlimited() {
+            return;
+        }
+        let child = match &self.process {
+            Instance::Sc2(child) => child,
+            Instance::Mock(_) => return,
+        };
+        match LimitGuard::apply(child, limits) {
+            Ok(guard) => {
+                info!("Limiting the sc2 process to {:?}", limits);
+                self.limits = Some(guard);
+            }
+            Err(e) => error!("Could not limit the sc2 process: {}", e),
+        }
+    }
+
+    /// PID of the SC2 process, None in a dry run
+    pub fn pid(&self) -> Option<u32> {
+        match &self.process {
+            Instance::Sc2(child) => Some(child.id()),
+            Instance::Mock(_) => None,
+        }
+    }
 
-        Self { process, ws_port }
+    /// The resource limit the process was stopped by, if any
+    pub fn limit_violation(&self) -> Option<Violation> {
+        self.limits.as_ref().and_then(LimitGuard::violation)
     }
 
     /// Connect the process websocket
-    pub async fn connect(&self) -> Option<WebSocketStream<TcpStream>> {
+    pub async fn connect(&self) -> Result<WebSocketStream<TcpStream>, ArenaError> {
         let url = format!("ws://127.0.0.1:{}/sc2api", self.ws_port);
         let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), self.ws_port);
 
         debug!("Connecting to the process");
 
         for _ in 0..60 {
-            sleep(Duration::new(1, 0));
+            // Yield to the runtime while SC2 boots, so processes connect in parallel
+            tokio::time::sleep(Duration::new(1, 0)).await;
             let socket =
                 match tokio::time::timeout(Duration::from_secs(120), TcpStream::connect(&addr))
                     .await
-                    .ok()?
+                    .map_err(|_| ArenaError::Sc2Launch("Timed out connecting".to_string()))?
                 {
                     Ok(e) => e,
                     Err(ref e) if e.kind() == ConnectionRefused => {
                         continue;
                     }
-                    Err(e) => panic!("E: {:?}", e),
+                    Err(e) => return Err(ArenaError::Sc2Launch(format!("Connect: {}", e))),
                 };
 
-            let config = Some(WebSocketConfig {
-                max_send_queue: None,
-                max_message_size: Some(128 << 20), // 128MiB
-                max_frame_size: Some(32 << 20),    // 32MiB
-                // This setting allows to accept client frames which are not masked
-                // This is not in compliance with RFC 6455 but might be handy in some
-                // rare cases where it is necessary to integrate with existing/legacy
-                // clients which are sending unmasked frames
-                accept_unmasked_frames: true,
-            });
+            // SC2 is a local trusted peer, stay lenient
+            let config = Some(websocket_config(true));
             let (ws_stream, _) = tokio_tungstenite::client_async_with_config(url, socket, config)
                 .await
-                .expect("Failed to connect");
+                .map_err(|e| ArenaError::Sc2Launch(format!("Websocket handshake: {}", e)))?;
 
-            return Some(ws_stream);
+            return Ok(ws_stream);
         }
 
         warn!("Websocket connection could not be formed");
-        None
+        Err(ArenaError::Sc2Launch(
+            "SC2 did not accept connections".to_string(),
+        ))
     }
 
     /// Wait for the process to exit
     pub fn wait(&mut self) {
         info!("Waiting for the sc2 process to exit");
-        self.process.kill().expect("SC2 process was not running");
+        match &mut self.process {
+            Instance::Sc2(child) => {
+                if let Err(e) = child.kill() {
+                    warn!("SC2 process was not running: {}", e);
+                }
+            }
+            Instance::Mock(mock) => mock.kill(),
+        }
+    }
+
+    /// Last `max_bytes` the process wrote to stderr, killing it first if it still runs
+    pub fn stderr_snippet(&mut self, max_bytes: usize) -> Option<String> {
+        let child = match &mut self.process {
+            Instance::Sc2(child) => child,
+            Instance::Mock(_) => return None,
+        };
+        // A killed process may not have exited yet, reading the pipe before would block
+        if !matches!(child.try_wait(), Ok(Some(_))) {
+            let _ = child.kill();
+            child.wait().ok()?;
+        }
+        let mut stderr = child.stderr.take()?;
+        let mut data = Vec::new();
+        stderr.read_to_end(&mut data).ok()?;
+        let start = data.len().saturating_sub(max_bytes);
+        Some(String::from_utf8_lossy(&data[start..]).into_owned())
     }
 
     /// Kill the process
     pub fn kill(&mut self) {
         info!("Killing the sc2 process");
-        self.process.kill().expect("Could not kill SC2 process");
+        match &mut self.process {
+            Instance::Sc2(child) => {
+                if let Err(e) = child.kill() {
+                    warn!("Could not kill the sc2 process: {}", e);
+                }
+            }
+            Instance::Mock(mock) => mock.kill(),
+        }
     }
 }
 
-impl Default for Process {
-    fn default() -> Self {
-        Self::new()
+impl fmt::Debug for Process {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Process {{ ws_port: {} }}", self.ws_port)
+    }
+}
+
+/// Never leave an SC2 process behind, e.g. when a game task is aborted
+impl Drop for Process {
+    fn drop(&mut self) {
+        match &mut self.process {
+            Instance::Sc2(child) => {
+                if let Ok(None) = child.try_wait() {
+                    debug!("Killing the sc2 process on drop");
+                    child.kill().ok();
+                    child.wait().ok();
+                }
+            }
+            Instance::Mock(mock) => mock.kill(),
+        }
     }
 }
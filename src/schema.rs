@@ -0,0 +1,209 @@
+//! Machine-readable description of the supervisor protocol
+
+use crate::config::Config;
+use crate::result::JsonResult;
+use schemars::schema_for;
+
+/// Version of the supervisor protocol, bumped on incompatible Config or Result changes
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// JSON schema of the Config the supervisor sends
+pub fn config_schema() -> String {
+    serde_json::to_string_pretty(&schema_for!(Config)).expect("Could not serialize schema")
+}
+
+/// JSON schema of the result the proxy sends after a game
+pub fn result_schema() -> String {
+    serde_json::to_string_pretty(&schema_for!(JsonResult)).expect("Could not serialize schema")
+}
+
+/// Status message sent to a supervisor when it connects,
+/// with the port the proxy listens on once it is bound,
+/// and the token bots send to join its session if it is not the default one
+pub fn connected_message(port: Option<u16>, session: Option<&str>) -> String {
+    let mut message = format!(
+        "{{\"Status\": \"Connected\", \"ProtocolVersion\": {}",
+        PROTOCOL_VERSION
+    );
+    if let Some(port) = port {
+        message.push_str(&format!(", \"Port\": {}", port));
+    }
+    if let Some(session) = session {
+        message.push_str(&format!(", \"Session\": {}", serde_json::json!(session)));
+    }
+    message.push('}');
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::collections::HashMap;
+    use std::process::Command;
+
+    /// Keys read by `Result.parse_result` in rust_ac/result.py
+    const PYTHON_RESULT_KEYS: [&str; 4] = [
+        "Result",
+        "GameTime",
+        "GameTimeFormatted",
+        "AverageFrameTime",
+    ];
+
+    /// Output of `GameConfig(<args>).to_json()` in rust_ac/game_config.py
+    fn python_config(args: &str) -> String {
+        let script = format!(
+            "import importlib.util, sys\n\
+             spec = importlib.util.spec_from_file_location('game_config', sys.argv[1])\n\
+             module = importlib.util.module_from_spec(spec)\n\
+             spec.loader.exec_module(module)\n\
+             print(module.GameConfig({}).to_json())",
+            args
+        );
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/rust_ac/game_config.py");
+        for python in ["python3", "python"] {
+            if let Ok(output) = Command::new(python)
+                .args(["-c", script.as_str(), path])
+                .output()
+            {
+                if output.status.success() {
+                    return String::from_utf8(output.stdout).unwrap();
+                }
+            }
+        }
+        panic!("Could not run rust_ac/game_config.py, Python is required for this test");
+    }
+
+    /// Whether `value` matches `schema`, checking types through references and alternatives
+    fn matches(schema: &Value, definitions: &Value, value: &Value) -> bool {
+        if let Some(reference) = schema["$ref"].as_str() {
+            let name = reference.trim_start_matches("#/definitions/");
+            return matches(&definitions[name], definitions, value);
+        }
+        for key in ["anyOf", "oneOf"] {
+            if let Some(alternatives) = schema[key].as_array() {
+                return alternatives.iter().any(|s| matches(s, definitions, value));
+            }
+        }
+        let type_matches = |name: &str| match name {
+            "null" => value.is_null(),
+            "boolean" => value.is_boolean(),
+            "integer" => value.is_i64() || value.is_u64(),
+            "number" => value.is_number(),
+            "string" => value.is_string(),
+            "array" => value.is_array(),
+            "object" => value.is_object(),
+            _ => false,
+        };
+        match &schema["type"] {
+            Value::String(name) => type_matches(name),
+            Value::Array(names) => names.iter().filter_map(Value::as_str).any(type_matches),
+            _ => true,
+        }
+    }
+
+    #[test]
+    fn test_python_config_keys_recognized() {
+        let config = Config::load_from_str(&python_config(
+            "'AutomatonLE', 'Bot1', 'Bot2', archon=True, real_time=True, light_mode=True, \
+             validate_race=True, player1_race='Terran', player2_race='Zerg', \
+             replay_name='replay.SC2Replay'",
+        ));
+        assert_eq!(config.map(), "AutomatonLE");
+        assert_eq!(config.max_game_time(), 60486);
+        assert_eq!(config.player1(), "Bot1");
+        assert_eq!(config.player2(), "Bot2");
+        assert_eq!(config.replay_path(), "replay.SC2Replay");
+        assert!(config.disable_debug());
+        assert!(config.realtime());
+        assert!(config.light_mode());
+        assert!(config.validate_race());
+        assert_eq!(config.player1_race(), &Some("Terran".to_string()));
+        assert_eq!(config.player2_race(), &Some("Zerg".to_string()));
+        assert!(config.archon());
+        assert_eq!(config.protocol_version, Some(PROTOCOL_VERSION));
+    }
+
+    #[test]
+    fn test_result_keys_for_python() {
+        let result = JsonResult::from(
+            Some(HashMap::new()),
+            Some(0),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        let value: serde_json::Value = serde_json::from_str(&result.serialize()).unwrap();
+        for key in PYTHON_RESULT_KEYS {
+            assert!(value.get(key).is_some(), "Result is missing {}", key);
+        }
+        let schema = result_schema();
+        for key in PYTHON_RESULT_KEYS {
+            assert!(schema.contains(key), "Result schema is missing {}", key);
+        }
+    }
+
+    #[test]
+    fn test_connected_message() {
+        let message: serde_json::Value =
+            serde_json::from_str(&connected_message(Some(40123), None)).unwrap();
+        assert_eq!(message["Status"], "Connected");
+        assert_eq!(message["Port"], 40123);
+        assert!(message.get("Session").is_none());
+        let message: serde_json::Value =
+            serde_json::from_str(&connected_message(None, Some("a1b2"))).unwrap();
+        assert!(message.get("Port").is_none());
+        assert_eq!(message["Session"], "a1b2");
+    }
+
+    #[test]
+    fn test_config_schema() {
+        let schema: Value = serde_json::from_str(&config_schema()).unwrap();
+        let properties = &schema["properties"];
+        for key in [
+            "Map",
+            "MaxGameTime",
+            "MatchID",
+            "ProtocolVersion",
+            "Player1Limits",
+        ] {
+            assert!(
+                properties.get(key).is_some(),
+                "Config schema is missing {}",
+                key
+            );
+        }
+        assert!(properties.get("map").is_none());
+        assert!(properties.get("pids").is_none());
+    }
+
+    #[test]
+    fn test_python_config_matches_schema() {
+        let schema: Value = serde_json::from_str(&config_schema()).unwrap();
+        let config: Value = serde_json::from_str(&python_config(
+            "'AutomatonLE', 'Bot1', 'Bot2', player3='Bot3', player4='Bot4', teams=[1, 2, 1, 2], \
+             strict_race=True, player1_race='Random', replay_dir='replays', log_dir='logs', \
+             temp_dir='tmp', progress_interval=10, live_tags=True",
+        ))
+        .unwrap();
+        for (key, value) in config.as_object().unwrap() {
+            let property = match schema["properties"].get(key) {
+                Some(property) => property,
+                None => panic!("Config schema is missing {}", key),
+            };
+            assert!(
+                matches(property, &schema["definitions"], value),
+                "{} = {} does not match {}",
+                key,
+                value,
+                property
+            );
+        }
+    }
+}
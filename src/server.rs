@@ -1,104 +1,589 @@
+use crate::auth::Auth;
+#[cfg(feature = "http-api")]
+use crate::channels::HTTP_CAPACITY;
+use crate::channels::{ChannelGauge, CONNECTION_CAPACITY, SUPERVISOR_CAPACITY};
+use crate::config::Config;
+use crate::connections::{ConnectionInfo, SharedConnections};
 use crate::controller::{create_supervisor_listener, Controller, SupervisorAction};
-use crate::proxy;
-#[cfg(not(feature = "no-pyo3"))]
+use crate::errors::ArenaError;
+use crate::handler::middleware::{MiddlewareFactory, Middlewares};
+#[cfg(feature = "http-api")]
+use crate::http_api::{self, HttpCommand};
+#[cfg(feature = "python")]
+use crate::maps;
+use crate::pool::DEFAULT_MAX_POOL_SIZE;
+use crate::ports;
+pub use crate::proxy::ClientType;
+use crate::proxy::{self, Client, FrameMasking};
+use crate::session::Sessions;
+use crate::shutdown::{panic_message, ShutdownReason, ShutdownReport};
+use crate::status::MatchStatus;
+#[cfg(feature = "supervisor-tls")]
+use crate::supervisor_tls::{self, SupervisorTls};
+use crate::update::UpdateChecker;
+#[cfg(feature = "python")]
 use bincode::{deserialize, serialize};
-use crossbeam::channel::{self, TryRecvError};
-use futures_util::StreamExt;
-use log::info;
-#[cfg(not(feature = "no-pyo3"))]
+use crossbeam::channel::{self, Receiver, Sender, TryRecvError};
+use futures_util::{FutureExt, StreamExt};
+use log::{error, info, warn};
+#[cfg(feature = "python")]
 use pyo3::prelude::*;
-#[cfg(not(feature = "no-pyo3"))]
+#[cfg(feature = "python")]
 use pyo3::types::{PyBytes, PyTuple};
-#[cfg(not(feature = "no-pyo3"))]
+#[cfg(feature = "python")]
 use pyo3::ToPyObject;
-#[cfg(not(feature = "no-pyo3"))]
+#[cfg(feature = "python")]
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::ops::RangeInclusive;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
 use tokio::runtime::Runtime;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
 
-pub enum ClientType {
-    Bot,
-    Controller,
-}
-
-#[cfg_attr(not(feature = "no-pyo3"), derive(Serialize, Deserialize, Clone))]
+#[cfg_attr(feature = "python", derive(Serialize, Deserialize, Clone))]
 pub struct RustServer {
     ip_addr: String,
+    frame_masking: FrameMasking,
+    check_updates: bool,
+    max_games: usize,
+    max_sessions: usize,
+    max_pool_size: usize,
+    http_addr: Option<String>,
+    quit_on_supervisor_close: bool,
+    match_config: Option<Config>,
+    dry_run: bool,
+    sc2_env: BTreeMap<String, String>,
+    supervisor_secret: Option<String>,
+    #[cfg(feature = "supervisor-tls")]
+    supervisor_tls: Option<SupervisorTls>,
+    /// Address the proxy listens on, once `run` bound it
+    #[cfg_attr(feature = "python", serde(skip))]
+    local_addr: Arc<Mutex<Option<SocketAddr>>>,
+    /// Open client connections, filled by the listeners
+    #[cfg_attr(feature = "python", serde(skip))]
+    connections: SharedConnections,
+    /// Middlewares registered with `add_middleware`
+    #[cfg_attr(feature = "python", serde(skip))]
+    middlewares: Middlewares,
 }
 
 impl RustServer {
     pub fn new(ip_addr: &str) -> Self {
         RustServer {
             ip_addr: String::from(ip_addr),
+            frame_masking: FrameMasking::default(),
+            check_updates: false,
+            max_games: 1,
+            max_sessions: 1,
+            max_pool_size: DEFAULT_MAX_POOL_SIZE,
+            http_addr: None,
+            quit_on_supervisor_close: false,
+            match_config: None,
+            dry_run: false,
+            sc2_env: BTreeMap::new(),
+            supervisor_secret: None,
+            #[cfg(feature = "supervisor-tls")]
+            supervisor_tls: None,
+            local_addr: Arc::default(),
+            connections: SharedConnections::default(),
+            middlewares: Middlewares::default(),
         }
     }
 
-    pub fn run(&self) -> tokio::task::JoinHandle<()> {
-        let (proxy_sender, proxy_receiver) = channel::unbounded();
-        let (sup_send, sup_recv) = channel::unbounded();
-        let addr = self.ip_addr.clone();
-        tokio::spawn(async move {
-            proxy::run(&addr, proxy_sender).await;
+    /// Set whether unmasked frames from clients are accepted
+    pub fn set_frame_masking(&mut self, frame_masking: FrameMasking) {
+        self.frame_masking = frame_masking;
+    }
+
+    /// Check for a newer release at startup
+    pub fn set_check_updates(&mut self, check_updates: bool) {
+        self.check_updates = check_updates;
+    }
+
+    /// Allow several games to run at the same time
+    pub fn set_max_games(&mut self, max_games: usize) {
+        self.max_games = max_games;
+    }
+
+    /// Cap the SC2 processes a supervisor can pre-launch with `PoolWarm`
+    pub fn set_max_pool_size(&mut self, max_pool_size: usize) {
+        self.max_pool_size = max_pool_size;
+    }
+
+    /// Hand out the ports of SC2 from `range` only, e.g. to match the ports a firewall opens
+    pub fn set_port_range(&mut self, range: RangeInclusive<u16>) {
+        ports::set_range(range);
+    }
+
+    /// Run a middleware from `factory` for every player of every game, see `ProxyMiddleware`
+    pub fn add_middleware(&mut self, factory: MiddlewareFactory) {
+        self.middlewares.add(factory);
+    }
+
+    /// Give up to `max_sessions` supervisors isolated sessions, instead of one supervisor
+    pub fn set_max_sessions(&mut self, max_sessions: usize) {
+        self.max_sessions = max_sessions;
+    }
+
+    /// Shut down when the supervisor closes its connection,
+    /// instead of waiting for it to connect again
+    pub fn set_quit_on_supervisor_close(&mut self, quit_on_supervisor_close: bool) {
+        self.quit_on_supervisor_close = quit_on_supervisor_close;
+    }
+
+    /// Play every match against a mock SC2, for machines without StarCraft II
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    /// Set environment variables for every SC2 process, e.g. driver tweaks of this host
+    pub fn set_sc2_env(&mut self, sc2_env: BTreeMap<String, String>) {
+        self.sc2_env = sc2_env;
+    }
+
+    /// Require `secret` from supervisors on the plain listener, and registered tokens from bots
+    pub fn set_supervisor_secret(&mut self, secret: String) {
+        self.supervisor_secret = Some(secret);
+    }
+
+    /// Run a single match with `config` without a supervisor, then shut down
+    pub fn set_match_config(&mut self, config: Config) {
+        self.match_config = Some(config);
+    }
+
+    /// Config of the single match, if one was set
+    pub fn match_config(&self) -> Option<&Config> {
+        self.match_config.as_ref()
+    }
+
+    /// Bind the proxy address
+    fn bind(&self) -> Result<std::net::TcpListener, ArenaError> {
+        std::net::TcpListener::bind(&self.ip_addr).map_err(|e| {
+            if e.kind() == std::io::ErrorKind::AddrInUse {
+                ArenaError::AddressInUse(self.ip_addr.clone())
+            } else {
+                ArenaError::Bind(self.ip_addr.clone(), e.to_string())
+            }
+        })
+    }
+
+    /// Address the proxy listens on, with the port the OS picked when binding port 0
+    /// None until `run` bound it
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        *self.local_addr.lock().unwrap()
+    }
+
+    /// Open connections of bots, supervisors and observers, oldest first
+    pub fn connections(&self) -> Vec<ConnectionInfo> {
+        self.connections.list()
+    }
+
+    /// Check that the proxy address can be bound, before starting the server
+    pub fn check_address(&self) -> Result<(), ArenaError> {
+        self.bind().map(|_| ())
+    }
+
+    /// Serve the HTTP control API on `http_addr`
+    #[cfg(feature = "http-api")]
+    pub fn set_http_addr(&mut self, http_addr: String) {
+        self.http_addr = Some(http_addr);
+    }
+
+    /// Accept supervisors only over mutual TLS, on their own address
+    #[cfg(feature = "supervisor-tls")]
+    pub fn set_supervisor_tls(&mut self, supervisor_tls: SupervisorTls) {
+        self.supervisor_tls = Some(supervisor_tls);
+    }
+
+    /// Whether supervisors may connect to the bot-facing listener
+    fn plain_supervisor(&self) -> bool {
+        #[cfg(feature = "supervisor-tls")]
+        if self.supervisor_tls.is_some() {
+            return false;
+        }
+        true
+    }
+
+    /// Start the server, failing if its address can not be bound
+    /// `ready` receives the address the proxy listens on, with the actual port when binding port 0.
+    /// The handle resolves to the outcome of the match set with `set_match_config`, if any.
+    /// A server that cannot start or panics writes a `FatalError` shutdown report
+    pub fn run(
+        &self,
+        ready: Option<oneshot::Sender<SocketAddr>>,
+    ) -> Result<JoinHandle<Option<MatchStatus>>, ArenaError> {
+        #[cfg(panic = "abort")]
+        install_fatal_panic_hook();
+        self.start(ready).map_err(|e| {
+            ShutdownReport::write_fatal(e.to_string());
+            e
+        })
+    }
+
+    fn start(
+        &self,
+        ready: Option<oneshot::Sender<SocketAddr>>,
+    ) -> Result<JoinHandle<Option<MatchStatus>>, ArenaError> {
+        let listener = self.bind()?;
+        let bind_error = |e: std::io::Error| ArenaError::Bind(self.ip_addr.clone(), e.to_string());
+        let local_addr = listener.local_addr().map_err(bind_error)?;
+        listener.set_nonblocking(true).map_err(bind_error)?;
+        let listener = TcpListener::from_std(listener).map_err(bind_error)?;
+        Ok(self.serve(listener, local_addr, ready))
+    }
+
+    /// Accept connections on `listener` until the server shuts down
+    fn serve(
+        &self,
+        listener: TcpListener,
+        local_addr: SocketAddr,
+        ready: Option<oneshot::Sender<SocketAddr>>,
+    ) -> JoinHandle<Option<MatchStatus>> {
+        info!("Listening on {}", local_addr);
+        *self.local_addr.lock().unwrap() = Some(local_addr);
+        if let Some(ready) = ready {
+            ready.send(local_addr).ok();
+        }
+        let (proxy_sender, proxy_receiver) = channel::bounded(CONNECTION_CAPACITY);
+        let (sup_send, sup_recv) = channel::bounded(SUPERVISOR_CAPACITY);
+        let frame_masking = self.frame_masking;
+        let quit_on_supervisor_close = self.quit_on_supervisor_close;
+        let plain_supervisor = self.plain_supervisor();
+        let auth = Arc::new(Auth::new(self.supervisor_secret.clone()));
+        let listener_auth = auth.clone();
+        #[cfg(feature = "http-api")]
+        let http_auth = auth.clone();
+        let listener_connections = self.connections.clone();
+        #[cfg(feature = "supervisor-tls")]
+        if let Some(tls) = self.supervisor_tls.clone() {
+            // Without valid certificates no supervisor can connect, the plain listener refuses them
+            match tls.acceptor() {
+                Ok(acceptor) => {
+                    let tls_sender = proxy_sender.clone();
+                    let tls_connections = self.connections.clone();
+                    tokio::spawn(async move {
+                        supervisor_tls::run(tls.addr, acceptor, tls_sender, tls_connections).await;
+                    });
+                }
+                Err(e) => error!("Could not set up the supervisor TLS listener: {}", e),
+            }
+        }
+        let proxy_task = tokio::spawn(async move {
+            proxy::run(
+                listener,
+                proxy_sender,
+                frame_masking,
+                plain_supervisor,
+                listener_auth,
+                listener_connections,
+            )
+            .await;
         });
-        let mut controller = Controller::new();
+        let new_controller = {
+            let max_games = self.max_games;
+            let max_pool_size = self.max_pool_size;
+            let dry_run = self.dry_run;
+            let sc2_env = self.sc2_env.clone();
+            let middlewares = self.middlewares.clone();
+            let connections = self.connections.clone();
+            move || {
+                let mut controller = Controller::new();
+                controller.set_max_games(max_games);
+                controller.set_max_pool_size(max_pool_size);
+                controller.set_dry_run(dry_run);
+                controller.set_sc2_env(sc2_env.clone());
+                controller.set_middlewares(middlewares.clone());
+                controller.set_auth(auth.clone());
+                controller.set_proxy_addr(local_addr);
+                controller.set_connections(connections.clone());
+                controller
+            }
+        };
+        let mut controller = new_controller();
+        let mut sessions = Sessions::new(self.max_sessions);
+        controller.add_channel_gauge(ChannelGauge::new("Connections", &proxy_receiver));
+        controller.add_channel_gauge(ChannelGauge::new("Supervisor", &sup_recv));
+        if self.check_updates {
+            controller.set_update_checker(UpdateChecker::spawn());
+        }
+        #[cfg(feature = "http-api")]
+        let (http_send, http_recv) = channel::bounded(HTTP_CAPACITY);
+        #[cfg(feature = "http-api")]
+        controller.add_channel_gauge(ChannelGauge::new("Http", &http_recv));
+        #[cfg(feature = "http-api")]
+        if let Some(http_addr) = self.http_addr.clone() {
+            controller.set_external_control(true);
+            tokio::spawn(async move {
+                http_api::run(http_addr, http_send, http_auth).await;
+            });
+        }
+        let match_config = self.match_config.clone();
+        let single_match = match_config.as_ref().map(|config| config.match_id);
+        if single_match.is_some() {
+            controller.set_external_control(true);
+        }
+        let (signal_send, signal_recv) = channel::bounded(1);
         tokio::spawn(async move {
-            loop {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                signal_send.send(()).ok();
+            }
+        });
+        let served = async move {
+            // `serve` is not async, so the single match is set up at the start of the server task
+            if let Some(config) = match_config {
+                if let Err(e) = controller.download_map(&config).await {
+                    error!("{}", e);
+                }
+                if let Err(errors) = controller.set_match_config(config) {
+                    for e in errors {
+                        error!("Match config rejected, {}", e);
+                    }
+                }
+            }
+            let mut outcome = None;
+            let reason = loop {
+                if signal_recv.try_recv().is_ok() {
+                    break ShutdownReason::Signal;
+                }
                 match proxy_receiver.try_recv() {
                     Ok((c_type, mut client)) => match c_type {
                         ClientType::Bot => {
-                            if !controller.has_supervisor() {
-                                info!("No supervisor - Client shutdown");
-                                client.shutdown().await.expect("Could not close connection");
-                            } else {
-                                controller.add_client(client);
-                                controller.send_message("{\"Bot\": \"Connected\"}").await
+                            match sessions.route(&mut controller, client.session()) {
+                                Some(target) if target.is_controlled() && target.has_config() => {
+                                    target.add_client(client);
+                                    target.send_message("{\"Bot\": \"Connected\"}").await
+                                }
+                                _ => {
+                                    info!("No supervisor or config - Client shutdown");
+                                    client.shutdown().await.expect("Could not close connection");
+                                }
                             }
                         }
                         ClientType::Controller => {
-                            let (ws_sender, ws_receiver) = client.stream.split();
-                            controller.add_supervisor(ws_sender, sup_recv.to_owned());
-                            create_supervisor_listener(ws_receiver, sup_send.to_owned());
-                            controller.send_message("{\"Status\": \"Connected\"}").await;
+                            if controller.has_supervisor() && sessions.has_capacity() {
+                                let (session_send, session_recv) =
+                                    channel::bounded(SUPERVISOR_CAPACITY);
+                                let session = sessions.open(new_controller());
+                                info!("Opened session {:?}", session.session());
+                                attach_supervisor(session, client, session_send, session_recv)
+                                    .await;
+                            } else {
+                                attach_supervisor(
+                                    &mut controller,
+                                    client,
+                                    sup_send.to_owned(),
+                                    sup_recv.to_owned(),
+                                )
+                                .await;
+                            }
+                        }
+                        ClientType::Observer => {
+                            match sessions.route(&mut controller, client.session()) {
+                                Some(target) => target.add_observer(client).await,
+                                None => {
+                                    info!("Unknown session - Observer shutdown");
+                                    client.shutdown().await.ok();
+                                }
+                            }
                         }
                     },
                     Err(TryRecvError::Empty) => {}
-                    Err(TryRecvError::Disconnected) => break,
+                    Err(TryRecvError::Disconnected) => break ShutdownReason::ProxyStopped,
                 }
                 if let Some(action) = controller.recv_msg() {
                     match action {
-                        SupervisorAction::Quit => {
-                            info!("Quit request received");
-                            controller.close().await;
-                            controller.send_message("Reset").await;
-                            controller.drop_supervisor().await;
-                        }
-                        SupervisorAction::Config(config) => {
-                            controller.set_config(config);
-                            controller.send_message("{\"Config\": \"Received\"}").await;
+                        SupervisorAction::ForceQuit => break ShutdownReason::ForceQuit,
+                        SupervisorAction::Closed => {
+                            if quit_on_supervisor_close {
+                                break ShutdownReason::SupervisorClosed;
+                            }
+                            controller.supervisor_closed().await;
                         }
-                        SupervisorAction::ForceQuit => break,
-                        SupervisorAction::Ping(payload) => {
-                            controller.send_pong(payload).await;
+                        action => handle_supervisor_action(&mut controller, action).await,
+                    }
+                }
+                for session in sessions.iter_mut() {
+                    if let Some(action) = session.recv_msg() {
+                        match action {
+                            // A session ends with its supervisor, it cannot shut down the proxy
+                            SupervisorAction::ForceQuit | SupervisorAction::Closed => {
+                                session.supervisor_closed().await;
+                            }
+                            action => handle_supervisor_action(session, action).await,
                         }
-                        _ => {}
                     }
+                    session.collect_stale_lobby().await;
+                    session.update_clients().await;
+                    session.update_games().await;
+                }
+                sessions.end_abandoned().await;
+
+                #[cfg(feature = "http-api")]
+                while let Ok(command) = http_recv.try_recv() {
+                    handle_http_command(&mut controller, command).await;
                 }
 
+                controller.collect_stale_lobby().await;
                 controller.update_clients().await;
                 controller.update_games().await;
+                if let Some(match_id) = single_match {
+                    match controller.match_status(match_id) {
+                        Some(MatchStatus::Pending) | Some(MatchStatus::Running { .. }) => {}
+                        status => {
+                            match &status {
+                                Some(MatchStatus::Finished { result }) => {
+                                    info!("Match result: {}", result)
+                                }
+                                _ => error!("Match {} did not finish", match_id),
+                            }
+                            outcome = status;
+                            break ShutdownReason::MatchFinished;
+                        }
+                    }
+                }
                 tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            };
+            // Stop accepting connections, then let running games finish
+            proxy_task.abort();
+            sessions.shutdown(reason.clone()).await;
+            controller.shutdown(reason).await;
+            outcome
+        };
+        tokio::spawn(async move {
+            match AssertUnwindSafe(served).catch_unwind().await {
+                Ok(outcome) => outcome,
+                Err(panic) => {
+                    ShutdownReport::write_fatal(panic_message(&*panic));
+                    None
+                }
             }
         })
     }
 }
 
-#[cfg(not(feature = "no-pyo3"))]
+/// Write the shutdown report of a panic, which aborts the process before it can be caught
+#[cfg(panic = "abort")]
+fn install_fatal_panic_hook() {
+    static INSTALL: std::sync::Once = std::sync::Once::new();
+    INSTALL.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            ShutdownReport::write_fatal(format!("Panic: {}", info));
+            previous(info);
+        }));
+    });
+}
+
+/// Attach a supervisor connection to `controller`, reading its messages into `sup_send`
+async fn attach_supervisor(
+    controller: &mut Controller,
+    client: Client,
+    sup_send: Sender<SupervisorAction>,
+    sup_recv: Receiver<SupervisorAction>,
+) {
+    let (ws_sender, ws_receiver) = client.stream.split();
+    let listener = create_supervisor_listener(ws_receiver, sup_send);
+    controller.add_supervisor(ws_sender, sup_recv, listener);
+    controller
+        .send_message(&controller.connected_message())
+        .await;
+    controller.send_unsent_results().await;
+}
+
+/// Apply a supervisor message that does not end its connection
+async fn handle_supervisor_action(controller: &mut Controller, action: SupervisorAction) {
+    match action {
+        SupervisorAction::Quit => {
+            info!("Quit request received");
+            controller.close().await;
+            controller.send_message("Reset").await;
+            controller.drop_supervisor().await;
+        }
+        SupervisorAction::Config(config) => {
+            controller.receive_config(config).await;
+        }
+        SupervisorAction::Ping(payload) => {
+            controller.send_pong(payload).await;
+        }
+        SupervisorAction::Status => {
+            controller.send_status().await;
+        }
+        SupervisorAction::Pool(command) => {
+            controller.manage_pool(command).await;
+        }
+        SupervisorAction::SmokeTest(map) => {
+            controller.run_smoke_test(map).await;
+        }
+        SupervisorAction::BotPid(player, pid) => {
+            controller.register_bot_pid(player, pid);
+        }
+        SupervisorAction::RegisterTokens(tokens) => {
+            controller.register_tokens(tokens);
+        }
+        SupervisorAction::Abort(match_id) => {
+            if !controller.abort_match(match_id).await {
+                warn!("Match {} to abort is unknown or already over", match_id);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Apply a request of the HTTP API to the controller
+#[cfg(feature = "http-api")]
+async fn handle_http_command(controller: &mut Controller, command: HttpCommand) {
+    match command {
+        HttpCommand::SubmitMatch(config, tokens, reply) => {
+            let result = match Config::try_load_from_str(&config) {
+                Err(e) => Err(e.to_string()),
+                Ok(_) if controller.has_config() => Err("A match is already pending".to_string()),
+                Ok(_) if !controller.has_capacity() => Err("Game pool full".to_string()),
+                Ok(parsed) => match controller.download_map(&parsed).await {
+                    Err(e) => Err(e.to_string()),
+                    Ok(()) => {
+                        let match_id = parsed.match_id;
+                        match controller.set_match_config(parsed) {
+                            Ok(()) => {
+                                if !tokens.is_empty() {
+                                    controller.register_tokens(tokens);
+                                }
+                                Ok(match_id)
+                            }
+                            Err(errors) => Err(errors
+                                .iter()
+                                .map(ToString::to_string)
+                                .collect::<Vec<_>>()
+                                .join("; ")),
+                        }
+                    }
+                },
+            };
+            reply.send(result).ok();
+        }
+        HttpCommand::Query(match_id, reply) => {
+            reply.send(controller.match_status(match_id)).ok();
+        }
+        HttpCommand::Abort(match_id, reply) => {
+            reply.send(controller.abort_match(match_id).await).ok();
+        }
+        HttpCommand::Connections(reply) => {
+            reply.send(controller.status().connections).ok();
+        }
+    }
+}
+
+#[cfg(feature = "python")]
 #[pyclass(module = "rust_ac")]
 #[pyo3(text_signature = "(ip_addr)")]
 pub(crate) struct PServer {
     server: Option<RustServer>,
 }
 
-#[cfg(not(feature = "no-pyo3"))]
+#[cfg(feature = "python")]
 #[pymethods]
 impl PServer {
     #[new]
@@ -118,20 +603,253 @@ impl PServer {
             _ => unreachable!(),
         }
     }
-    pub fn run(&self, py: Python) -> Result<(), PyErr> {
+    /// Run the server until it shuts down
+    /// `on_ready` is called with the address the proxy listens on, e.g. "127.0.0.1:8642"
+    /// Raises AddressInUse if the address is taken
+    /// With a match config, returns the result JSON or raises MatchAborted
+    #[args(on_ready = "None")]
+    pub fn run(&self, py: Python, on_ready: Option<PyObject>) -> PyResult<Option<String>> {
         match &self.server {
-            Some(server) => py.allow_threads(move || {
-                info!("Starting server on {:?}", server.ip_addr);
-                let rt = Runtime::new().unwrap();
-                rt.block_on(async move {
-                    match server.run().await {
-                        Ok(_) => Ok(()),
-                        Err(_) => Err(pyo3::exceptions::PyConnectionError::new_err(
-                            "Could not start server. Address in use {:?}",
-                        )),
+            Some(server) => {
+                let match_id = server.match_config().map(|config| config.match_id);
+                let outcome = py.allow_threads(move || {
+                    info!("Starting server on {:?}", server.ip_addr);
+                    let rt = Runtime::new().unwrap();
+                    rt.block_on(async move {
+                        let (ready_send, ready_recv) = oneshot::channel();
+                        let running = server.run(Some(ready_send))?;
+                        if let (Some(on_ready), Ok(addr)) = (on_ready, ready_recv.await) {
+                            if let Err(e) =
+                                Python::with_gil(|py| on_ready.call1(py, (addr.to_string(),)))
+                            {
+                                error!("on_ready callback failed: {}", e);
+                            }
+                        }
+                        Ok::<_, PyErr>(running.await)
+                    })
+                })?;
+                match (outcome, match_id) {
+                    (Err(e), _) => Err(pyo3::exceptions::PyRuntimeError::new_err(format!(
+                        "Server crashed: {}",
+                        e
+                    ))),
+                    (Ok(_), None) => Ok(None),
+                    (Ok(Some(MatchStatus::Finished { result })), Some(_)) => {
+                        Ok(Some(result.to_string()))
                     }
+                    (Ok(_), Some(match_id)) => Err(ArenaError::MatchAborted(match_id).into()),
+                }
+            }
+            None => Err(pyo3::exceptions::PyAssertionError::new_err(
+                "Server not set. Did you initialize the object?",
+            )),
+        }
+    }
+
+    /// Address the proxy listens on, None until it is running
+    pub fn local_addr(&self) -> Option<String> {
+        self.server
+            .as_ref()
+            .and_then(RustServer::local_addr)
+            .map(|addr| addr.to_string())
+    }
+
+    /// Open connections as dicts with role, peer, tls, unmasked_frames, session,
+    /// age_seconds, bytes_sent and bytes_received
+    pub fn connections(&self, py: Python) -> PyResult<Vec<PyObject>> {
+        match &self.server {
+            Some(server) => server
+                .connections()
+                .into_iter()
+                .map(|c| {
+                    let dict = pyo3::types::PyDict::new(py);
+                    let role = match c.role {
+                        ClientType::Bot => "Bot",
+                        ClientType::Controller => "Supervisor",
+                        ClientType::Observer => "Observer",
+                    };
+                    dict.set_item("role", role)?;
+                    dict.set_item("peer", c.peer.to_string())?;
+                    dict.set_item("tls", c.options.tls)?;
+                    dict.set_item("unmasked_frames", c.options.unmasked_frames)?;
+                    dict.set_item("session", c.options.session)?;
+                    dict.set_item("age_seconds", c.age_seconds)?;
+                    dict.set_item("bytes_sent", c.bytes_sent)?;
+                    dict.set_item("bytes_received", c.bytes_received)?;
+                    Ok(dict.to_object(py))
                 })
-            }),
+                .collect(),
+            None => Err(pyo3::exceptions::PyAssertionError::new_err(
+                "Server not set. Did you initialize the object?",
+            )),
+        }
+    }
+
+    /// Run a single match from a JSON config instead of waiting for a supervisor
+    /// Raises MapNotFound or InvalidConfig if the config is rejected
+    pub fn set_match_config(&mut self, config: &str) -> PyResult<()> {
+        match &mut self.server {
+            Some(server) => {
+                let mut parsed = Config::try_load_from_str(config)
+                    .map_err(|e| ArenaError::InvalidConfig(vec![e]))?;
+                parsed.dry_run |= server.dry_run;
+                let seed = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+                parsed
+                    .resolve_map(seed)
+                    .map_err(|e| ArenaError::InvalidConfig(vec![e]))?;
+                if let (Some(url), false) = (parsed.map_download_url(), parsed.dry_run) {
+                    maps::ensure_map(parsed.map(), url).map_err(|reason| {
+                        ArenaError::MapDownload(parsed.map().to_string(), reason)
+                    })?;
+                }
+                if let Err(errors) = parsed.validate() {
+                    return Err(ArenaError::from_config_errors(&parsed, errors).into());
+                }
+                server.set_match_config(parsed);
+                Ok(())
+            }
+            None => Err(pyo3::exceptions::PyAssertionError::new_err(
+                "Server not set. Did you initialize the object?",
+            )),
+        }
+    }
+
+    /// Set tolerance for unmasked client frames: "auto", "strict" or "lenient"
+    pub fn set_frame_masking(&mut self, mode: &str) -> PyResult<()> {
+        match (&mut self.server, FrameMasking::parse(mode)) {
+            (Some(server), Some(frame_masking)) => {
+                server.set_frame_masking(frame_masking);
+                Ok(())
+            }
+            (None, _) => Err(pyo3::exceptions::PyAssertionError::new_err(
+                "Server not set. Did you initialize the object?",
+            )),
+            (_, None) => Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "Unknown frame masking mode {:?}",
+                mode
+            ))),
+        }
+    }
+
+    /// Check for a newer release when the server starts
+    pub fn set_check_updates(&mut self, enabled: bool) -> PyResult<()> {
+        match &mut self.server {
+            Some(server) => {
+                server.set_check_updates(enabled);
+                Ok(())
+            }
+            None => Err(pyo3::exceptions::PyAssertionError::new_err(
+                "Server not set. Did you initialize the object?",
+            )),
+        }
+    }
+
+    /// Allow several games to run at the same time
+    pub fn set_max_games(&mut self, max_games: usize) -> PyResult<()> {
+        match &mut self.server {
+            Some(server) => {
+                server.set_max_games(max_games);
+                Ok(())
+            }
+            None => Err(pyo3::exceptions::PyAssertionError::new_err(
+                "Server not set. Did you initialize the object?",
+            )),
+        }
+    }
+
+    /// Cap the SC2 processes a supervisor can pre-launch with `PoolWarm`
+    pub fn set_max_pool_size(&mut self, max_pool_size: usize) -> PyResult<()> {
+        match &mut self.server {
+            Some(server) => {
+                server.set_max_pool_size(max_pool_size);
+                Ok(())
+            }
+            None => Err(pyo3::exceptions::PyAssertionError::new_err(
+                "Server not set. Did you initialize the object?",
+            )),
+        }
+    }
+
+    /// Hand out the ports of SC2 from `start` to `end` only
+    pub fn set_port_range(&mut self, start: u16, end: u16) -> PyResult<()> {
+        if start == 0 || start > end {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "Invalid port range {}-{}",
+                start, end
+            )));
+        }
+        match &mut self.server {
+            Some(server) => {
+                server.set_port_range(start..=end);
+                Ok(())
+            }
+            None => Err(pyo3::exceptions::PyAssertionError::new_err(
+                "Server not set. Did you initialize the object?",
+            )),
+        }
+    }
+
+    /// Give several supervisors isolated sessions
+    pub fn set_max_sessions(&mut self, max_sessions: usize) -> PyResult<()> {
+        match &mut self.server {
+            Some(server) => {
+                server.set_max_sessions(max_sessions);
+                Ok(())
+            }
+            None => Err(pyo3::exceptions::PyAssertionError::new_err(
+                "Server not set. Did you initialize the object?",
+            )),
+        }
+    }
+
+    /// Shut down when the supervisor closes its connection
+    pub fn set_quit_on_supervisor_close(&mut self, enabled: bool) -> PyResult<()> {
+        match &mut self.server {
+            Some(server) => {
+                server.set_quit_on_supervisor_close(enabled);
+                Ok(())
+            }
+            None => Err(pyo3::exceptions::PyAssertionError::new_err(
+                "Server not set. Did you initialize the object?",
+            )),
+        }
+    }
+
+    /// Play every match against a mock SC2 instead of launching SC2
+    pub fn set_dry_run(&mut self, enabled: bool) -> PyResult<()> {
+        match &mut self.server {
+            Some(server) => {
+                server.set_dry_run(enabled);
+                Ok(())
+            }
+            None => Err(pyo3::exceptions::PyAssertionError::new_err(
+                "Server not set. Did you initialize the object?",
+            )),
+        }
+    }
+
+    /// Set environment variables for every SC2 process
+    pub fn set_sc2_env(&mut self, env: BTreeMap<String, String>) -> PyResult<()> {
+        match &mut self.server {
+            Some(server) => {
+                server.set_sc2_env(env);
+                Ok(())
+            }
+            None => Err(pyo3::exceptions::PyAssertionError::new_err(
+                "Server not set. Did you initialize the object?",
+            )),
+        }
+    }
+
+    /// Require a shared secret from supervisors and registered tokens from bots
+    pub fn set_supervisor_secret(&mut self, secret: String) -> PyResult<()> {
+        match &mut self.server {
+            Some(server) => {
+                server.set_supervisor_secret(secret);
+                Ok(())
+            }
             None => Err(pyo3::exceptions::PyAssertionError::new_err(
                 "Server not set. Did you initialize the object?",
             )),
@@ -156,12 +874,12 @@ impl PServer {
 #[cfg(test)]
 mod tests {
     use super::*;
-    #[cfg(not(feature = "no-pyo3"))]
+    #[cfg(feature = "python")]
     use pyo3::py_run;
-    #[cfg(not(feature = "no-pyo3"))]
+    #[cfg(feature = "python")]
     use pyo3::types::PyDict;
 
-    #[cfg(not(feature = "no-pyo3"))]
+    #[cfg(feature = "python")]
     fn add_module(py: Python, module: &PyModule) -> PyResult<()> {
         py.import("sys")?
             .dict()
@@ -171,7 +889,7 @@ mod tests {
             .set_item(module.name()?, module)
     }
 
-    #[cfg(not(feature = "no-pyo3"))]
+    #[cfg(feature = "python")]
     #[test]
     fn test_pickle() {
         let gil = Python::acquire_gil();
@@ -0,0 +1,100 @@
+//! Isolated match sessions, for hosting the matches of several supervisors on one proxy
+//!
+//! The first supervisor drives the default session, which bots join without a token.
+//! With `max_sessions` above 1, every further supervisor gets a session of its own, with
+//! its own config, lobby, games and bot tokens, announced with `"Session": <token>` in its
+//! Connected message. Bots and observers join it by sending the token in the `match-token`
+//! header, or as `?match=` in the URL. A session ends when its supervisor leaves, aborting
+//! its matches.
+
+use crate::auth::random_token;
+use crate::controller::Controller;
+use crate::shutdown::ShutdownReason;
+
+/// Sessions besides the default one, each served by its own controller
+pub struct Sessions {
+    controllers: Vec<Controller>,
+    /// Sessions allowed at the same time, including the default one
+    max_sessions: usize,
+}
+impl Sessions {
+    pub fn new(max_sessions: usize) -> Self {
+        Self {
+            controllers: Vec::new(),
+            max_sessions: max_sessions.max(1),
+        }
+    }
+
+    /// Whether another supervisor can get a session of its own
+    pub fn has_capacity(&self) -> bool {
+        self.controllers.len() + 1 < self.max_sessions
+    }
+
+    /// Serve a new session with `controller`, under a fresh token
+    pub fn open(&mut self, mut controller: Controller) -> &mut Controller {
+        controller.set_session(random_token());
+        self.controllers.push(controller);
+        self.controllers.last_mut().unwrap()
+    }
+
+    /// Controller of the session a client asks to join, None if the session is unknown
+    pub fn route<'a>(
+        &'a mut self,
+        default: &'a mut Controller,
+        session: Option<&str>,
+    ) -> Option<&'a mut Controller> {
+        match session {
+            None => Some(default),
+            Some(token) => self
+                .controllers
+                .iter_mut()
+                .find(|controller| controller.session() == Some(token)),
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Controller> {
+        self.controllers.iter_mut()
+    }
+
+    /// End the sessions whose supervisor left
+    pub async fn end_abandoned(&mut self) {
+        for mut controller in std::mem::take(&mut self.controllers) {
+            if controller.has_supervisor() {
+                self.controllers.push(controller);
+            } else {
+                controller.end_session().await;
+            }
+        }
+    }
+
+    /// Shut down every session, letting running games finish
+    pub async fn shutdown(&mut self, reason: ShutdownReason) {
+        for controller in self.controllers.iter_mut() {
+            controller.shutdown(reason.clone()).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route() {
+        let mut default = Controller::new();
+        let mut sessions = Sessions::new(2);
+        assert!(sessions.has_capacity());
+        let token = sessions
+            .open(Controller::new())
+            .session()
+            .unwrap()
+            .to_string();
+        assert!(!sessions.has_capacity());
+        let routed = sessions.route(&mut default, None).unwrap();
+        assert_eq!(routed.session(), None);
+        let routed = sessions.route(&mut default, Some(&token)).unwrap();
+        assert_eq!(routed.session(), Some(token.as_str()));
+        assert!(sessions.route(&mut default, Some("unknown")).is_none());
+        assert!(!Sessions::new(0).has_capacity());
+    }
+}
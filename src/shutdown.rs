@@ -0,0 +1,122 @@
+//! Final report emitted when the server shuts down
+
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// Why the server shut down
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub enum ShutdownReason {
+    /// Supervisor sent `Quit`, or its connection failed
+    ForceQuit,
+    /// The supervisor closed its connection with `--quit-on-supervisor-close`
+    SupervisorClosed,
+    /// Process received Ctrl-C / SIGINT
+    Signal,
+    /// The proxy listener stopped, e.g. it could not bind
+    ProxyStopped,
+    /// The single match of a config file ended
+    MatchFinished,
+    /// The server could not start or its loop panicked
+    FatalError(String),
+}
+
+/// Summary of the work done by the server, sent to the supervisor and written to disk
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ShutdownReport {
+    #[serde(rename = "Reason")]
+    pub reason: ShutdownReason,
+    #[serde(rename = "MatchesCompleted")]
+    pub matches_completed: u32,
+    #[serde(rename = "MatchesAborted")]
+    pub matches_aborted: u32,
+    #[serde(rename = "ProcessesKilled")]
+    pub processes_killed: u32,
+    #[serde(rename = "UnsentResults")]
+    pub unsent_results: u32,
+    #[serde(rename = "Timestamp")]
+    pub timestamp: String,
+}
+
+#[derive(Serialize)]
+struct ShutdownMessage<'a> {
+    #[serde(rename = "Shutdown")]
+    shutdown: &'a ShutdownReport,
+}
+
+impl ShutdownReport {
+    /// Report of a server that stopped on `error`, without the counts of the controller it lost
+    pub fn fatal(error: String) -> Self {
+        Self {
+            reason: ShutdownReason::FatalError(error),
+            matches_completed: 0,
+            matches_aborted: 0,
+            processes_killed: 0,
+            unsent_results: 0,
+            timestamp: chrono::Local::now().to_rfc3339(),
+        }
+    }
+
+    /// Write the report of a server that stopped on `error` to disk, logging where it went
+    pub fn write_fatal(error: String) {
+        error!("Server stopped: {}", error);
+        match Self::fatal(error).write_to_disk() {
+            Ok(path) => info!("Shutdown report written to {:?}", path),
+            Err(e) => error!("Could not write shutdown report: {:?}", e),
+        }
+    }
+
+    /// Message sent to the supervisor
+    pub fn to_message(&self) -> String {
+        serde_json::to_string(&ShutdownMessage { shutdown: self })
+            .expect("Could not serialize ShutdownReport")
+    }
+
+    /// Write the report to `SC2_PROXY_REPORT_DIR`, or the temp dir if unset
+    pub fn write_to_disk(&self) -> io::Result<PathBuf> {
+        let dir = std::env::var_os("SC2_PROXY_REPORT_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir);
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join(format!(
+            "rust_ac_shutdown_{}.json",
+            chrono::Local::now().format("%Y%m%dT%H%M%S")
+        ));
+        let mut file = File::create(&path)?;
+        file.write_all(
+            serde_json::to_string_pretty(self)
+                .expect("Could not serialize ShutdownReport")
+                .as_bytes(),
+        )?;
+        Ok(path)
+    }
+}
+
+/// Message of a caught panic, if it was a string
+pub fn panic_message(panic: &(dyn Any + Send)) -> String {
+    match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+        (Some(message), _) => format!("Panic: {}", message),
+        (_, Some(message)) => format!("Panic: {}", message),
+        _ => "Panic without a message".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fatal_report() {
+        let report = ShutdownReport::fatal("Could not bind".to_string());
+        assert!(report
+            .to_message()
+            .starts_with("{\"Shutdown\":{\"Reason\":{\"FatalError\":\"Could not bind\"}"));
+        let panic = std::panic::catch_unwind(|| panic!("Lost game {}", 3)).unwrap_err();
+        assert_eq!(panic_message(&*panic), "Panic: Lost game 3");
+        let panic = std::panic::catch_unwind(|| panic!("Lost game")).unwrap_err();
+        assert_eq!(panic_message(&*panic), "Panic: Lost game");
+    }
+}
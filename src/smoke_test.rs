@@ -0,0 +1,235 @@
+//! Quick end-to-end check of a host, run with the supervisor command `SmokeTest`
+//!
+//! A scripted bot connects to the proxy like any other bot and plays the built-in AI
+//! on the smallest installed map until `SMOKE_TEST_GAME_LOOPS`. The supervisor gets a
+//! `{"SmokeTest": ...}` report instead of a match result.
+
+use crate::config::Config;
+use crate::sc2::PlayerResult;
+use futures_util::{SinkExt, StreamExt};
+use protobuf::Message;
+use sc2_proto::common::Race;
+use sc2_proto::sc2api::{Request, RequestObservation, Response, Status};
+use serde::Serialize;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message as TMessage;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+/// MatchID of smoke test matches, never used by a supervisor
+pub const SMOKE_TEST_MATCH_ID: i64 = -1;
+
+/// Name of the scripted bot
+pub const SMOKE_TEST_BOT: &str = "SmokeTestBot";
+
+/// Game loops of a smoke test, half a game minute
+pub const SMOKE_TEST_GAME_LOOPS: u32 = 672;
+
+/// Game loops the scripted bot steps between observations
+const STEP_LOOPS: u32 = 16;
+
+/// The scripted bot gives up after this long
+pub const SMOKE_TEST_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Map of a `SmokeTest` or `SmokeTest <Map>` command, None if `data` is another message
+pub fn parse_command(data: &str) -> Option<Option<String>> {
+    let rest = data.strip_prefix("SmokeTest")?;
+    if rest.is_empty() {
+        Some(None)
+    } else if rest.starts_with(' ') && !rest.trim().is_empty() {
+        Some(Some(rest.trim().to_string()))
+    } else {
+        None
+    }
+}
+
+/// Config of a smoke test on `map`, the scripted bot against the easiest built-in AI
+pub fn config(map: &str) -> Config {
+    Config {
+        map: map.into(),
+        max_game_time: SMOKE_TEST_GAME_LOOPS,
+        player1: SMOKE_TEST_BOT.to_string(),
+        player2: "Computer".to_string(),
+        player2_race: Some("Random".to_string()),
+        computer_difficulty: Some("VeryEasy".to_string()),
+        match_id: SMOKE_TEST_MATCH_ID,
+        disable_debug: true,
+        ..Default::default()
+    }
+}
+
+/// Outcome of a smoke test, sent to the supervisor as `{"SmokeTest": ...}`
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct SmokeTestReport {
+    #[serde(rename = "Passed")]
+    pub passed: bool,
+    /// Time from the command to the result
+    #[serde(rename = "Seconds")]
+    pub seconds: f64,
+    #[serde(rename = "Error", skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Result JSON of the smoke test match
+    #[serde(rename = "Result", skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+}
+impl SmokeTestReport {
+    /// Report for a smoke test that could not be started
+    pub fn failed(error: impl Into<String>, elapsed: Duration) -> Self {
+        Self {
+            passed: false,
+            seconds: elapsed.as_secs_f64(),
+            error: Some(error.into()),
+            result: None,
+        }
+    }
+
+    /// Report for the result JSON of the smoke test match
+    /// It passed if the game completed without a crash, a timeout or an initialization error
+    pub fn from_result(result_json: &str, elapsed: Duration) -> Self {
+        let result: serde_json::Value = serde_json::from_str(result_json).unwrap_or_default();
+        let complete = result["Status"] == "Complete";
+        let normal = result["Result"].as_object().map_or(false, |results| {
+            !results.is_empty()
+                && results.values().all(|r| {
+                    [
+                        PlayerResult::Victory,
+                        PlayerResult::Defeat,
+                        PlayerResult::Tie,
+                    ]
+                    .iter()
+                    .any(|normal| r.as_str() == Some(normal.to_string().as_str()))
+                })
+        });
+        let passed = complete && normal;
+        Self {
+            passed,
+            seconds: elapsed.as_secs_f64(),
+            error: if passed {
+                None
+            } else {
+                Some(format!("Match ended with status {}", result["Status"]))
+            },
+            result: Some(result),
+        }
+    }
+
+    pub fn to_message(&self) -> String {
+        serde_json::json!({ "SmokeTest": self }).to_string()
+    }
+}
+
+type BotStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn query(ws: &mut BotStream, request: &Request) -> Result<Response, String> {
+    let data = request.write_to_bytes().map_err(|e| e.to_string())?;
+    ws.send(TMessage::Binary(data))
+        .await
+        .map_err(|e| e.to_string())?;
+    loop {
+        match ws.next().await {
+            Some(Ok(TMessage::Binary(data))) => {
+                return Response::parse_from_bytes(&data).map_err(|e| e.to_string())
+            }
+            Some(Ok(TMessage::Close(_))) | None => return Err("Connection closed".to_string()),
+            Some(Ok(_)) => continue,
+            Some(Err(e)) => return Err(e.to_string()),
+        }
+    }
+}
+
+/// Play the scripted bot against the proxy at `addr`, returning the last game loop seen
+/// The bot joins, then observes and steps until the game ends or the proxy closes the connection
+/// `token` is needed when the proxy checks bot tokens, `session` joins a session other than the default one
+pub async fn run_bot(
+    addr: &str,
+    token: Option<String>,
+    session: Option<String>,
+) -> Result<u32, String> {
+    // A proxy listening on all interfaces is reached through localhost
+    let addr = addr.replace("0.0.0.0", "127.0.0.1");
+    let query: Vec<String> = token
+        .map(|token| format!("token={}", token))
+        .into_iter()
+        .chain(session.map(|session| format!("match={}", session)))
+        .collect();
+    let url = if query.is_empty() {
+        format!("ws://{}/sc2api", addr)
+    } else {
+        format!("ws://{}/sc2api?{}", addr, query.join("&"))
+    };
+    let (mut ws, _) = tokio_tungstenite::connect_async(url)
+        .await
+        .map_err(|e| format!("Could not connect to the proxy: {}", e))?;
+
+    let mut join = Request::new();
+    join.mut_join_game().set_race(Race::Random);
+    join.mut_join_game()
+        .options
+        .mut_or_insert_default()
+        .set_raw(true);
+    let response = query(&mut ws, &join).await?;
+    if !response.error.is_empty() {
+        return Err(response.error.join("; "));
+    }
+
+    let mut game_loop = 0;
+    let mut observation = Request::new();
+    observation.set_observation(RequestObservation::new());
+    let mut step = Request::new();
+    step.mut_step().set_count(STEP_LOOPS);
+    loop {
+        // The proxy closes the connection once it saw the end of the game
+        let response = match query(&mut ws, &observation).await {
+            Ok(response) => response,
+            Err(_) => break,
+        };
+        game_loop = response.observation().observation.game_loop();
+        if !response.observation().player_result.is_empty() || response.status() == Status::ended {
+            break;
+        }
+        if query(&mut ws, &step).await.is_err() {
+            break;
+        }
+    }
+    ws.close(None).await.ok();
+    Ok(game_loop)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command("SmokeTest"), Some(None));
+        assert_eq!(
+            parse_command("SmokeTest Flat32"),
+            Some(Some("Flat32".to_string()))
+        );
+        assert_eq!(parse_command("SmokeTests"), None);
+        assert_eq!(parse_command("SmokeTest "), None);
+        assert_eq!(parse_command("Status"), None);
+    }
+
+    #[test]
+    fn test_report() {
+        let elapsed = Duration::from_secs(30);
+        let result = "{\"Status\": \"Complete\", \"Result\": {\"SmokeTestBot\": \"Tie\", \"Computer\": \"Tie\"}}";
+        let report = SmokeTestReport::from_result(result, elapsed);
+        assert!(report.passed);
+        assert_eq!(report.seconds, 30.0);
+        assert!(report
+            .to_message()
+            .starts_with("{\"SmokeTest\":{\"Passed\":true"));
+
+        let crashed = result.replace("\"Computer\": \"Tie\"", "\"Computer\": \"SC2Crash\"");
+        assert!(!SmokeTestReport::from_result(&crashed, elapsed).passed);
+        let aborted = "{\"Status\": \"Error\", \"Result\": {}}";
+        let report = SmokeTestReport::from_result(aborted, elapsed);
+        assert!(!report.passed);
+        assert_eq!(
+            report.error.as_deref(),
+            Some("Match ended with status \"Error\"")
+        );
+    }
+}
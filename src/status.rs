@@ -0,0 +1,115 @@
+//! Answer to the supervisor `Status` query
+
+use crate::channels::ChannelDepth;
+use crate::connections::ConnectionInfo;
+use crate::paths::Sc2Version;
+use crate::update::UpdateStatus;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Snapshot of the proxy state
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ProxyStatus {
+    #[serde(rename = "Version")]
+    pub version: String,
+    #[serde(rename = "ProtocolVersion")]
+    pub protocol_version: u32,
+    /// Open connections of bots, supervisors and observers
+    #[serde(rename = "Connections")]
+    pub connections: Vec<ConnectionInfo>,
+    #[serde(rename = "GameRunning")]
+    pub game_running: bool,
+    #[serde(rename = "RunningGames")]
+    pub running_games: usize,
+    #[serde(rename = "MatchesCompleted")]
+    pub matches_completed: u32,
+    /// CPU time the bots and SC2 used in the completed matches, in seconds
+    #[serde(rename = "CpuSeconds")]
+    pub cpu_seconds: f64,
+    /// Fill level of the internal channels, to spot an overloaded proxy
+    #[serde(rename = "Channels")]
+    pub channels: HashMap<String, ChannelDepth>,
+    /// SC2 builds a match can pin with `Sc2BaseBuild` or `Sc2Version`
+    #[serde(rename = "InstalledVersions")]
+    pub installed_versions: Vec<Sc2Version>,
+    /// Present once the opt-in update check finished
+    #[serde(rename = "Update", skip_serializing_if = "Option::is_none")]
+    pub update: Option<UpdateStatus>,
+}
+
+#[derive(Serialize)]
+struct StatusMessage<'a> {
+    #[serde(rename = "ProxyStatus")]
+    status: &'a ProxyStatus,
+}
+
+impl ProxyStatus {
+    /// Message sent to the supervisor
+    pub fn to_message(&self) -> String {
+        serde_json::to_string(&StatusMessage { status: self })
+            .expect("Could not serialize ProxyStatus")
+    }
+}
+
+/// Exit code of a standalone match, from the result of `player`:
+/// 0 victory, 1 defeat, 2 tie, 3 crash or timeout, 4 the match did not finish
+pub fn match_exit_code(status: Option<&MatchStatus>, player: &str) -> i32 {
+    let player_result = match status {
+        Some(MatchStatus::Finished { result }) => result["Result"][player].as_str(),
+        _ => None,
+    };
+    match player_result {
+        Some("Victory") => 0,
+        Some("Defeat") => 1,
+        Some("Tie") => 2,
+        Some(_) => 3,
+        None => 4,
+    }
+}
+
+/// Match of an `Abort <MatchID>` command, None if `data` is another message
+pub fn parse_abort(data: &str) -> Option<i64> {
+    data.strip_prefix("Abort ")?.trim().parse().ok()
+}
+
+/// State of a match known to the controller
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "Status")]
+pub enum MatchStatus {
+    /// Config received, waiting for the bots
+    Pending,
+    Running {
+        #[serde(rename = "GameId")]
+        game_id: u64,
+    },
+    Finished {
+        #[serde(rename = "Result")]
+        result: serde_json::Value,
+    },
+    Aborted,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_exit_code() {
+        let finished = MatchStatus::Finished {
+            result: serde_json::json!({"Result": {"Bot1": "Defeat", "Bot2": "Victory"}}),
+        };
+        assert_eq!(match_exit_code(Some(&finished), "Bot1"), 1);
+        assert_eq!(match_exit_code(Some(&finished), "Bot2"), 0);
+        assert_eq!(match_exit_code(Some(&finished), "Bot3"), 4);
+        assert_eq!(match_exit_code(Some(&MatchStatus::Aborted), "Bot1"), 4);
+        assert_eq!(match_exit_code(None, "Bot1"), 4);
+    }
+
+    #[test]
+    fn test_parse_abort() {
+        assert_eq!(parse_abort("Abort 42"), Some(42));
+        assert_eq!(parse_abort("Abort x"), None);
+        assert_eq!(parse_abort("Abort"), None);
+        assert_eq!(parse_abort("Status"), None);
+    }
+}
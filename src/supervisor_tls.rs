@@ -0,0 +1,186 @@
+//! Mutual TLS listener for supervisors on another host
+//!
+//! Supervisors connect to a separate address and must present a client
+//! certificate signed by the configured CA before their websocket is accepted.
+//! While this listener is enabled, the bot-facing listener refuses supervisors.
+
+use crate::channels::send_with_backpressure;
+use crate::connections::{ConnectionOptions, Counted, SharedConnections};
+use crate::proxy::{
+    requested_session, websocket_config, Client, ClientType, Connection, OBSERVER_HEADER,
+};
+use crossbeam::channel::Sender;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::server::AllowAnyAuthenticatedClient;
+use tokio_rustls::rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+
+/// Address and PEM files of the supervisor listener
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct SupervisorTls {
+    pub addr: String,
+    /// Certificate chain of the proxy
+    pub cert: PathBuf,
+    /// Private key of the proxy, PKCS#8, RSA or SEC1
+    pub key: PathBuf,
+    /// CA the certificates of supervisors must be signed by
+    pub client_ca: PathBuf,
+}
+
+fn invalid(path: &Path, message: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {}", path.display(), message),
+    )
+}
+
+fn load_certs(path: &Path) -> io::Result<Vec<Certificate>> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(path)?))?;
+    if certs.is_empty() {
+        return Err(invalid(path, "No certificate found"));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn load_key(path: &Path) -> io::Result<PrivateKey> {
+    use rustls_pemfile::Item;
+    for item in rustls_pemfile::read_all(&mut BufReader::new(File::open(path)?))? {
+        match item {
+            Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) => {
+                return Ok(PrivateKey(key))
+            }
+            _ => {}
+        }
+    }
+    Err(invalid(path, "No private key found"))
+}
+
+impl SupervisorTls {
+    /// Load the certificates, failing if any file is missing or invalid
+    pub fn acceptor(&self) -> io::Result<TlsAcceptor> {
+        let mut roots = RootCertStore::empty();
+        for cert in load_certs(&self.client_ca)? {
+            roots
+                .add(&cert)
+                .map_err(|e| invalid(&self.client_ca, format!("{:?}", e)))?;
+        }
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots))
+            .with_single_cert(load_certs(&self.cert)?, load_key(&self.key)?)
+            .map_err(|e| invalid(&self.key, e))?;
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+/// Accept supervisors on `addr` until the listener fails
+/// Every connection that completes the handshakes is a supervisor, no header is needed,
+/// or an observer with the `supervisor-observer` header
+pub async fn run(
+    addr: String,
+    acceptor: TlsAcceptor,
+    channel_out: Sender<(ClientType, Client)>,
+    connections: SharedConnections,
+) {
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Could not bind the supervisor TLS listener: {:?}", e);
+            return;
+        }
+    };
+    info!("Supervisor TLS listening on {:?}", listener.local_addr());
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                error!("Supervisor TLS accept failed: {:?}", e);
+                return;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let channel_out = channel_out.clone();
+        let connections = connections.clone();
+        // A slow handshake must not hold up the next supervisor
+        tokio::spawn(async move {
+            let stream = Counted::new(Box::new(stream));
+            let counters = stream.counters();
+            let stream: Box<dyn Connection> = match acceptor.accept(stream).await {
+                Ok(stream) => Box::new(stream),
+                Err(e) => {
+                    warn!("Refused supervisor {:?}: {}", peer, e);
+                    return;
+                }
+            };
+            let config = Some(websocket_config(false));
+            let mut client_type = ClientType::Controller;
+            let mut session = None;
+            let callback = |request: &Request, response: Response| {
+                if request.headers().contains_key(OBSERVER_HEADER) {
+                    client_type = ClientType::Observer;
+                }
+                session = requested_session(request);
+                Ok::<_, ErrorResponse>(response)
+            };
+            match tokio_tungstenite::accept_hdr_async_with_config(stream, callback, config).await {
+                Ok(ws_stream) => {
+                    info!("{:?} connected over TLS: {:?}", client_type, peer);
+                    let options = ConnectionOptions {
+                        tls: true,
+                        unmasked_frames: false,
+                        session: session.clone(),
+                    };
+                    connections.register(client_type, peer, options, &counters);
+                    send_with_backpressure(
+                        &channel_out,
+                        (
+                            client_type,
+                            Client::new(ws_stream, peer).with_session(session),
+                        ),
+                    )
+                    .await
+                    .ok();
+                }
+                Err(e) => warn!("Supervisor websocket handshake failed: {:?}", e),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let empty = dir.path().join("empty.pem");
+        std::fs::write(&empty, b"").unwrap();
+        let tls = SupervisorTls {
+            addr: "127.0.0.1:0".to_string(),
+            cert: empty.clone(),
+            key: empty.clone(),
+            client_ca: dir.path().join("missing.pem"),
+        };
+        let error = tls.acceptor().err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+
+        let tls = SupervisorTls {
+            client_ca: empty.clone(),
+            ..tls
+        };
+        let error = tls.acceptor().err().unwrap();
+        assert!(error.to_string().contains("No certificate found"));
+        assert_eq!(
+            load_key(&empty).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+}
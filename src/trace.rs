@@ -0,0 +1,473 @@
+//! Protocol traces of matches, to reproduce bugs of SC2 or the proxy offline
+//!
+//! With `ProtocolTrace` set, every message between the proxy and the SC2 process of a player
+//! is appended to its own `.pbtrace` file, from the version check on. Compact traces keep
+//! only the time, direction, message type and size of each message; full traces keep the
+//! message itself and can be replayed against a live SC2 with `replay`.
+//!
+//! A trace starts with `MAGIC` and a mode byte, 0 for full and 1 for compact, followed by the
+//! messages: direction byte, microseconds since the trace started as u64, size as u32, then
+//! the message in full traces or its type, prefixed by its length as u8, in compact ones.
+//! Numbers are little-endian.
+
+use futures_util::{SinkExt, StreamExt};
+use log::{error, warn};
+use protobuf::Message;
+use sc2_proto::sc2api::{Request, Response};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::tungstenite::Message as TMessage;
+use tokio_tungstenite::WebSocketStream;
+
+use crate::errors::ArenaError;
+use crate::sc2process::{LaunchOptions, Process};
+
+/// First bytes of every protocol trace
+pub const MAGIC: &[u8; 8] = b"PBTRACE1";
+
+/// Extension of protocol trace files
+pub const EXTENSION: &str = "pbtrace";
+
+/// File of the protocol trace of player `index` of match `match_id` in `dir`
+pub fn trace_path(dir: &Path, match_id: i64, index: usize) -> PathBuf {
+    dir.join(format!("{}_player{}.{}", match_id, index + 1, EXTENSION))
+}
+
+/// Which way a message went
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// From the proxy to SC2
+    Request,
+    /// From SC2 to the proxy
+    Response,
+}
+impl Direction {
+    fn to_byte(self) -> u8 {
+        match self {
+            Self::Request => 0,
+            Self::Response => 1,
+        }
+    }
+    fn from_byte(byte: u8) -> io::Result<Self> {
+        match byte {
+            0 => Ok(Self::Request),
+            1 => Ok(Self::Response),
+            _ => Err(invalid(format!("Unknown direction {}", byte))),
+        }
+    }
+}
+
+/// Protocol trace of one player, written as the messages pass
+pub struct ProtocolTrace {
+    file: BufWriter<File>,
+    started: Instant,
+    compact: bool,
+    /// Set after a write failed, nothing more is written then
+    failed: bool,
+}
+impl ProtocolTrace {
+    /// Create the trace file at `path`, writing the header
+    pub fn create(path: &Path, compact: bool) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(MAGIC)?;
+        file.write_all(&[compact as u8])?;
+        Ok(Self {
+            file,
+            started: Instant::now(),
+            compact,
+            failed: false,
+        })
+    }
+
+    /// Append a message
+    pub fn record(&mut self, direction: Direction, message: &[u8]) {
+        if self.failed {
+            return;
+        }
+        if let Err(e) = self.write(direction, message) {
+            error!("Could not write the protocol trace: {:?}", e);
+            self.failed = true;
+        }
+    }
+
+    fn write(&mut self, direction: Direction, message: &[u8]) -> io::Result<()> {
+        let micros = self.started.elapsed().as_micros() as u64;
+        self.file.write_all(&[direction.to_byte()])?;
+        self.file.write_all(&micros.to_le_bytes())?;
+        self.file.write_all(&(message.len() as u32).to_le_bytes())?;
+        if self.compact {
+            let message_type = message_type(direction, message);
+            self.file.write_all(&[message_type.len() as u8])?;
+            self.file.write_all(message_type.as_bytes())
+        } else {
+            self.file.write_all(message)
+        }
+    }
+
+    /// Write buffered messages to the file
+    pub fn flush(&mut self) {
+        if let Err(e) = self.file.flush() {
+            warn!("Could not flush the protocol trace: {:?}", e);
+        }
+    }
+}
+impl Drop for ProtocolTrace {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/// A message of a trace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub direction: Direction,
+    /// Microseconds since the trace started
+    pub micros: u64,
+    /// Size of the message in bytes
+    pub size: u32,
+    /// The message, None in compact traces
+    pub message: Option<Vec<u8>>,
+    /// Type of the message, only kept in compact traces, see `TraceEntry::message_type`
+    compact_type: Option<String>,
+}
+impl TraceEntry {
+    /// Type of the message, e.g. `Observation`
+    pub fn message_type(&self) -> String {
+        match (&self.compact_type, &self.message) {
+            (Some(message_type), _) => message_type.clone(),
+            (None, Some(message)) => message_type(self.direction, message).to_string(),
+            (None, None) => "Other".to_string(),
+        }
+    }
+}
+
+/// Read a whole trace
+pub fn read(path: &Path) -> io::Result<Vec<TraceEntry>> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut header = [0_u8; 9];
+    file.read_exact(&mut header)
+        .map_err(|_| invalid("Missing header".to_string()))?;
+    if &header[..8] != MAGIC {
+        return Err(invalid("Not a protocol trace".to_string()));
+    }
+    let compact = header[8] == 1;
+    let mut entries = Vec::new();
+    loop {
+        let mut direction = [0_u8; 1];
+        match file.read_exact(&mut direction) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(entries),
+            Err(e) => return Err(e),
+        }
+        let mut micros = [0_u8; 8];
+        file.read_exact(&mut micros)?;
+        let mut size = [0_u8; 4];
+        file.read_exact(&mut size)?;
+        let size = u32::from_le_bytes(size);
+        let (message, compact_type) = if compact {
+            let mut length = [0_u8; 1];
+            file.read_exact(&mut length)?;
+            let mut message_type = vec![0_u8; length[0] as usize];
+            file.read_exact(&mut message_type)?;
+            let message_type = String::from_utf8(message_type)
+                .map_err(|_| invalid("Invalid message type".to_string()))?;
+            (None, Some(message_type))
+        } else {
+            let mut message = vec![0_u8; size as usize];
+            file.read_exact(&mut message)?;
+            (Some(message), None)
+        };
+        entries.push(TraceEntry {
+            direction: Direction::from_byte(direction[0])?,
+            micros: u64::from_le_bytes(micros),
+            size,
+            message,
+            compact_type,
+        });
+    }
+}
+
+fn invalid(reason: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, reason)
+}
+
+/// Type of a serialized request or response, `Invalid` if it does not parse
+fn message_type(direction: Direction, message: &[u8]) -> &'static str {
+    match direction {
+        Direction::Request => Request::parse_from_bytes(message)
+            .map(|request| request_type(&request))
+            .unwrap_or("Invalid"),
+        Direction::Response => Response::parse_from_bytes(message)
+            .map(|response| response_type(&response))
+            .unwrap_or("Invalid"),
+    }
+}
+
+/// Name of the request in traces
+pub fn request_type(request: &Request) -> &'static str {
+    if request.has_create_game() {
+        "CreateGame"
+    } else if request.has_join_game() {
+        "JoinGame"
+    } else if request.has_restart_game() {
+        "RestartGame"
+    } else if request.has_start_replay() {
+        "StartReplay"
+    } else if request.has_leave_game() {
+        "LeaveGame"
+    } else if request.has_quick_save() {
+        "QuickSave"
+    } else if request.has_quick_load() {
+        "QuickLoad"
+    } else if request.has_quit() {
+        "Quit"
+    } else if request.has_game_info() {
+        "GameInfo"
+    } else if request.has_observation() {
+        "Observation"
+    } else if request.has_action() {
+        "Action"
+    } else if request.has_obs_action() {
+        "ObsAction"
+    } else if request.has_step() {
+        "Step"
+    } else if request.has_data() {
+        "Data"
+    } else if request.has_query() {
+        "Query"
+    } else if request.has_save_replay() {
+        "SaveReplay"
+    } else if request.has_map_command() {
+        "MapCommand"
+    } else if request.has_replay_info() {
+        "ReplayInfo"
+    } else if request.has_available_maps() {
+        "AvailableMaps"
+    } else if request.has_save_map() {
+        "SaveMap"
+    } else if request.has_ping() {
+        "Ping"
+    } else if request.has_debug() {
+        "Debug"
+    } else {
+        "Other"
+    }
+}
+
+/// Name of the response in traces
+pub fn response_type(response: &Response) -> &'static str {
+    if response.has_create_game() {
+        "CreateGame"
+    } else if response.has_join_game() {
+        "JoinGame"
+    } else if response.has_restart_game() {
+        "RestartGame"
+    } else if response.has_start_replay() {
+        "StartReplay"
+    } else if response.has_leave_game() {
+        "LeaveGame"
+    } else if response.has_quick_save() {
+        "QuickSave"
+    } else if response.has_quick_load() {
+        "QuickLoad"
+    } else if response.has_quit() {
+        "Quit"
+    } else if response.has_game_info() {
+        "GameInfo"
+    } else if response.has_observation() {
+        "Observation"
+    } else if response.has_action() {
+        "Action"
+    } else if response.has_obs_action() {
+        "ObsAction"
+    } else if response.has_step() {
+        "Step"
+    } else if response.has_data() {
+        "Data"
+    } else if response.has_query() {
+        "Query"
+    } else if response.has_save_replay() {
+        "SaveReplay"
+    } else if response.has_map_command() {
+        "MapCommand"
+    } else if response.has_replay_info() {
+        "ReplayInfo"
+    } else if response.has_available_maps() {
+        "AvailableMaps"
+    } else if response.has_save_map() {
+        "SaveMap"
+    } else if response.has_ping() {
+        "Ping"
+    } else if response.has_debug() {
+        "Debug"
+    } else {
+        "Other"
+    }
+}
+
+/// A response of the replay that differs from the trace
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Mismatch {
+    /// Position of the request in the replayed requests, from 0
+    #[serde(rename = "Request")]
+    pub request: usize,
+    #[serde(rename = "Type")]
+    pub request_type: String,
+    /// Type, status and errors of the traced response
+    #[serde(rename = "Expected")]
+    pub expected: String,
+    /// Type, status and errors of the response during the replay
+    #[serde(rename = "Actual")]
+    pub actual: String,
+}
+
+/// Outcome of a replay
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ReplayReport {
+    /// Requests sent to SC2
+    #[serde(rename = "Requests")]
+    pub requests: usize,
+    #[serde(rename = "Mismatches")]
+    pub mismatches: Vec<Mismatch>,
+}
+
+/// What replays compare of a response: its type, status and errors
+fn summary(message: &[u8]) -> String {
+    match Response::parse_from_bytes(message) {
+        Ok(response) => format!(
+            "{} {:?} {:?}",
+            response_type(&response),
+            response.status(),
+            response.error
+        ),
+        Err(_) => "Invalid".to_string(),
+    }
+}
+
+/// Send the requests of a full trace to SC2 in order, comparing the responses with the traced ones
+/// Requests are sent as soon as SC2 answered the previous one, the traced timing is not kept.
+/// SC2 must be in the state it was in when the trace started, i.e. freshly launched.
+pub async fn replay<S>(
+    entries: &[TraceEntry],
+    ws: &mut WebSocketStream<S>,
+) -> Result<ReplayReport, ArenaError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut report = ReplayReport::default();
+    let mut entries = entries.iter().peekable();
+    while let Some(entry) = entries.next() {
+        if entry.direction != Direction::Request {
+            continue;
+        }
+        let request = entry
+            .message
+            .as_ref()
+            .ok_or_else(|| ArenaError::Protocol("Compact traces cannot be replayed".to_string()))?;
+        ws.send(TMessage::binary(request.clone()))
+            .await
+            .map_err(|e| ArenaError::Protocol(format!("Could not send to SC2: {}", e)))?;
+        report.requests += 1;
+        let expected = match entries.peek() {
+            Some(next) if next.direction == Direction::Response => entries.next(),
+            // The proxy never read the response, e.g. it was killed
+            _ => continue,
+        };
+        let actual = loop {
+            match ws.next().await {
+                Some(Ok(TMessage::Binary(bytes))) => break bytes,
+                Some(Ok(TMessage::Close(_))) | None => {
+                    return Err(ArenaError::Protocol(format!(
+                        "SC2 closed the connection at request {}",
+                        report.requests - 1
+                    )))
+                }
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => {
+                    return Err(ArenaError::Protocol(format!(
+                        "Could not receive from SC2: {}",
+                        e
+                    )))
+                }
+            }
+        };
+        let expected = expected
+            .and_then(|entry| entry.message.as_deref())
+            .map(summary)
+            .unwrap_or_default();
+        let actual = summary(&actual);
+        if expected != actual {
+            report.mismatches.push(Mismatch {
+                request: report.requests - 1,
+                request_type: message_type(Direction::Request, request).to_string(),
+                expected,
+                actual,
+            });
+        }
+    }
+    Ok(report)
+}
+
+/// Replay the trace at `path` against a new SC2 process, see `replay`
+pub async fn replay_file(path: &Path, options: &LaunchOptions) -> Result<ReplayReport, ArenaError> {
+    let entries = read(path)
+        .map_err(|e| ArenaError::Protocol(format!("Could not read the trace {:?}: {}", path, e)))?;
+    let process = Process::launch(None, options)?;
+    let mut ws = process.connect().await?;
+    replay(&entries, &mut ws).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sc2_proto::sc2api::{RequestPing, ResponsePing};
+
+    fn ping() -> (Vec<u8>, Vec<u8>) {
+        let mut request = Request::new();
+        request.set_ping(RequestPing::new());
+        let mut response = Response::new();
+        response.set_ping(ResponsePing::new());
+        (
+            request.write_to_bytes().unwrap(),
+            response.write_to_bytes().unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_trace_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let (request, response) = ping();
+        for compact in [false, true] {
+            let path = trace_path(dir.path(), 7, compact as usize);
+            let mut trace = ProtocolTrace::create(&path, compact).unwrap();
+            trace.record(Direction::Request, &request);
+            trace.record(Direction::Response, &response);
+            drop(trace);
+
+            let entries = read(&path).unwrap();
+            assert_eq!(entries.len(), 2);
+            assert_eq!(entries[0].direction, Direction::Request);
+            assert_eq!(entries[1].direction, Direction::Response);
+            assert_eq!(entries[1].size as usize, response.len());
+            assert!(entries[0].micros <= entries[1].micros);
+            for entry in &entries {
+                assert_eq!(entry.message_type(), "Ping");
+            }
+            let message = entries[0].message.clone();
+            assert_eq!(message, if compact { None } else { Some(request.clone()) });
+        }
+        assert!(dir.path().join("7_player2.pbtrace").exists());
+    }
+
+    #[test]
+    fn test_read_rejects_other_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trace.csv");
+        std::fs::write(&path, "game_loop,player\n").unwrap();
+        assert_eq!(read(&path).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+}
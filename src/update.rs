@@ -0,0 +1,138 @@
+//! Opt-in check for newer rust-arenaclient releases
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+/// Version of the running proxy
+pub const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// GitHub API endpoint of the latest release
+#[cfg(feature = "update-check")]
+const LATEST_RELEASE_URL: &str =
+    "https://api.github.com/repos/aiarena/rust-arenaclient/releases/latest";
+
+/// Outcome of comparing the running version with the latest release
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "supervisor", derive(schemars::JsonSchema))]
+pub struct UpdateStatus {
+    #[serde(rename = "CurrentVersion")]
+    pub current: String,
+    #[serde(rename = "LatestVersion")]
+    pub latest: String,
+    #[serde(rename = "UpdateAvailable")]
+    pub update_available: bool,
+}
+
+impl UpdateStatus {
+    /// Compare the running version with the tag of the latest release
+    pub fn from_latest(latest: &str) -> Self {
+        Self {
+            current: CURRENT_VERSION.to_string(),
+            latest: latest.trim_start_matches('v').to_string(),
+            update_available: is_newer(latest, CURRENT_VERSION),
+        }
+    }
+}
+
+/// Parse `v1.2.3` or `1.2.3`, ignoring pre-release and build suffixes
+fn parse_version(version: &str) -> Option<Vec<u64>> {
+    version
+        .trim()
+        .trim_start_matches('v')
+        .split(|c| c == '-' || c == '+')
+        .next()?
+        .split('.')
+        .map(|part| part.parse().ok())
+        .collect()
+}
+
+/// Whether `latest` is a higher version than `current`
+pub fn is_newer(latest: &str, current: &str) -> bool {
+    match (parse_version(latest), parse_version(current)) {
+        (Some(latest), Some(current)) => latest > current,
+        _ => false,
+    }
+}
+
+/// Tag name of the latest GitHub release
+#[cfg(feature = "update-check")]
+fn fetch_latest_release() -> Result<String, String> {
+    #[derive(Deserialize)]
+    struct Release {
+        tag_name: String,
+    }
+    let release: Release = ureq::get(LATEST_RELEASE_URL)
+        .set(
+            "User-Agent",
+            concat!("rust-arenaclient/", env!("CARGO_PKG_VERSION")),
+        )
+        .timeout(std::time::Duration::from_secs(10))
+        .call()
+        .map_err(|e| e.to_string())?
+        .into_json()
+        .map_err(|e| e.to_string())?;
+    Ok(release.tag_name)
+}
+
+#[cfg(not(feature = "update-check"))]
+fn fetch_latest_release() -> Result<String, String> {
+    Err("built without the update-check feature".to_string())
+}
+
+/// Background update check, shared between the server and the controller
+#[derive(Debug, Clone, Default)]
+pub struct UpdateChecker {
+    status: Arc<Mutex<Option<UpdateStatus>>>,
+}
+
+impl UpdateChecker {
+    /// Start checking in a background thread, so startup is not delayed by the network
+    pub fn spawn() -> Self {
+        let checker = Self::default();
+        let status = checker.status.clone();
+        std::thread::spawn(move || match fetch_latest_release() {
+            Ok(tag) => {
+                let update = UpdateStatus::from_latest(&tag);
+                if update.update_available {
+                    warn!(
+                        "Update available: rust-arenaclient {} (running {})",
+                        update.latest, update.current
+                    );
+                } else {
+                    info!("rust-arenaclient {} is up to date", update.current);
+                }
+                *status.lock().unwrap() = Some(update);
+            }
+            Err(e) => warn!("Could not check for updates: {}", e),
+        });
+        checker
+    }
+
+    /// Result of the check, None while it is running or if it failed
+    pub fn status(&self) -> Option<UpdateStatus> {
+        self.status.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_newer() {
+        assert!(is_newer("v0.2.5", "0.2.4"));
+        assert!(is_newer("1.0.0", "0.9.12"));
+        assert!(!is_newer("v0.2.4", "0.2.4"));
+        assert!(!is_newer("0.2.3", "0.2.4"));
+        assert!(is_newer("0.2.5-rc1", "0.2.4"));
+        assert!(!is_newer("nightly", "0.2.4"));
+    }
+
+    #[test]
+    fn test_from_latest() {
+        let status = UpdateStatus::from_latest(&format!("v{}", CURRENT_VERSION));
+        assert_eq!(status.latest, CURRENT_VERSION);
+        assert!(!status.update_available);
+    }
+}
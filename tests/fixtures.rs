@@ -0,0 +1,39 @@
+#![cfg(feature = "fixtures")]
+
+use rust_ac::config::Config;
+use rust_ac::fixtures::{self, SupervisorMessage};
+use rust_ac::sc2::PlayerResult;
+
+#[test]
+fn test_config_json_loads() {
+    let config = Config::load_from_str(&fixtures::config_json("AutomatonLE", "Bot1", "Bot2"));
+    assert_eq!(config.map(), "AutomatonLE");
+    assert_eq!(config.player1(), "Bot1");
+    assert_eq!(config.player2(), "Bot2");
+    assert!(config.disable_debug());
+}
+
+#[test]
+fn test_result_json_fields() {
+    let config = fixtures::config("AutomatonLE", "Bot1", "Bot2");
+    let result = fixtures::result_json(&config, [PlayerResult::Victory, PlayerResult::Defeat], 224);
+    let value: serde_json::Value = serde_json::from_str(&result).unwrap();
+    assert_eq!(value["Result"]["Bot1"], "Victory");
+    assert_eq!(value["Result"]["Bot2"], "Defeat");
+    assert_eq!(value["GameTime"], 224);
+    assert_eq!(value["Status"], "Complete");
+}
+
+#[test]
+fn test_match_session_config_roundtrip() {
+    let config = fixtures::config("AutomatonLE", "Bot1", "Bot2");
+    let session = fixtures::match_session(&config, [PlayerResult::Tie, PlayerResult::Tie]);
+    let sent_config = session
+        .iter()
+        .find_map(|m| match m {
+            SupervisorMessage::ToProxy(data) if data.contains("map") => Some(data),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(Config::load_from_str(sent_config), config);
+}
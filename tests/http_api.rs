@@ -0,0 +1,83 @@
+//! Matches submitted over the HTTP API to a proxy checking bot tokens, no StarCraft II needed
+#![cfg(feature = "http-api")]
+
+use rust_ac::server::RustServer;
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::oneshot;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+const SECRET: &str = "s3cret";
+
+/// Free local address for the HTTP API
+fn free_addr() -> SocketAddr {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+/// Send `body` to `POST /match`, returning the response
+async fn submit(http_addr: SocketAddr, body: &str) -> String {
+    // The API starts listening shortly after the proxy
+    let mut stream = loop {
+        match TcpStream::connect(http_addr).await {
+            Ok(stream) => break stream,
+            Err(_) => tokio::time::sleep(std::time::Duration::from_millis(50)).await,
+        }
+    };
+    let request = format!(
+        "POST /match HTTP/1.1\r\nSupervisor-Secret: {}\r\nContent-Length: {}\r\n\r\n{}",
+        SECRET,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+/// Whether a bot presenting `token` completes the handshake
+async fn bot_connects(addr: SocketAddr, token: &str) -> bool {
+    let mut request = format!("ws://{}/sc2api", addr)
+        .into_client_request()
+        .unwrap();
+    request
+        .headers_mut()
+        .insert("bot-token", token.parse().unwrap());
+    tokio_tungstenite::connect_async(request).await.is_ok()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_submitted_match_admits_bots() {
+    let http_addr = free_addr();
+    let mut server = RustServer::new("127.0.0.1:0");
+    server.set_supervisor_secret(SECRET.to_string());
+    server.set_http_addr(http_addr.to_string());
+    server.set_dry_run(true);
+    let (ready_send, ready_recv) = oneshot::channel();
+    let _running = server
+        .run(Some(ready_send))
+        .expect("Could not start the proxy");
+    let addr = ready_recv.await.unwrap();
+
+    let config = serde_json::json!({
+        "Map": "AutomatonLE",
+        "Player1": "Bot1",
+        "Player2": "Bot2",
+        "MatchID": 7,
+        "ReplayPath": std::env::temp_dir().join("http_api_7.SC2Replay"),
+        "Tokens": ["token1", "token2"],
+    });
+    let response = submit(http_addr, &config.to_string()).await;
+    assert!(response.starts_with("HTTP/1.1 202"), "{}", response);
+    assert!(response.ends_with("{\"MatchID\": 7}"), "{}", response);
+
+    assert!(!bot_connects(addr, "unknown").await);
+    assert!(bot_connects(addr, "token1").await);
+    // Each token admits a single connection
+    assert!(!bot_connects(addr, "token1").await);
+    assert!(bot_connects(addr, "token2").await);
+}
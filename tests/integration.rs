@@ -0,0 +1,121 @@
+//! Full match between two scripted bots on real SC2 instances
+//!
+//! Needs StarCraft II, so it is ignored by default. Point `SC2_PROXY_BASE` at the
+//! installation and run it with
+//! `cargo test --no-default-features --features supervisor --test integration -- --ignored`.
+//! The match is played on `SC2_TEST_MAP`, or the smallest installed map.
+#![cfg(feature = "supervisor")]
+
+use futures_util::{SinkExt, StreamExt};
+use rust_ac::maps::smallest_map;
+use rust_ac::server::RustServer;
+use rust_ac::smoke_test::run_bot;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Game loops the match is capped at, ending it in a tie
+const MAX_GAME_TIME: u32 = 1344;
+
+/// Longest the whole match may take, including the launch of both SC2 instances
+const MATCH_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Next JSON message from the proxy, skipping the ones that are not JSON
+async fn next_json<S>(supervisor: &mut S) -> serde_json::Value
+where
+    S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+{
+    loop {
+        match supervisor.next().await {
+            Some(Ok(Message::Text(data))) => match serde_json::from_str(&data) {
+                Ok(value) => return value,
+                Err(_) => continue,
+            },
+            Some(Ok(_)) => continue,
+            other => panic!("Supervisor connection ended: {:?}", other),
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore]
+async fn test_tie() {
+    if std::env::var_os("SC2_PROXY_BASE").is_none() {
+        eprintln!("SC2_PROXY_BASE is not set, skipping the integration test");
+        return;
+    }
+    let map = std::env::var("SC2_TEST_MAP")
+        .ok()
+        .or_else(smallest_map)
+        .expect("No map installed");
+
+    let server = RustServer::new("127.0.0.1:0");
+    let (ready_send, ready_recv) = oneshot::channel();
+    let _running = server
+        .run(Some(ready_send))
+        .expect("Could not start the proxy");
+    let addr = ready_recv.await.unwrap().to_string();
+
+    let mut request = format!("ws://{}/sc2api", addr)
+        .into_client_request()
+        .unwrap();
+    request
+        .headers_mut()
+        .insert("supervisor", "true".parse().unwrap());
+    let (mut supervisor, _) = tokio_tungstenite::connect_async(request)
+        .await
+        .expect("Could not connect the supervisor");
+    assert_eq!(next_json(&mut supervisor).await["Status"], "Connected");
+
+    let config = serde_json::json!({
+        "Map": map,
+        "MaxGameTime": MAX_GAME_TIME,
+        "Player1": "Bot1",
+        "Player2": "Bot2",
+        "MatchID": 1,
+        "DisableDebug": true,
+    });
+    supervisor
+        .send(Message::Text(config.to_string()))
+        .await
+        .unwrap();
+    let response = next_json(&mut supervisor).await;
+    assert_eq!(response["Config"], "Accepted", "{}", response);
+
+    let bots: Vec<_> = (0..2)
+        .map(|_| {
+            let addr = addr.clone();
+            tokio::spawn(async move { run_bot(&addr, None, None).await })
+        })
+        .collect();
+
+    let result = timeout(MATCH_TIMEOUT, async {
+        loop {
+            let message = next_json(&mut supervisor).await;
+            if message.get("Result").is_some() {
+                supervisor
+                    .send(Message::Text("Received".to_string()))
+                    .await
+                    .ok();
+                return message;
+            }
+            assert!(message.get("Error").is_none(), "{}", message);
+        }
+    })
+    .await
+    .expect("The match did not finish in time");
+
+    for bot in bots {
+        let game_loop = bot.await.unwrap().expect("Scripted bot failed");
+        assert!(game_loop > 0);
+    }
+    assert_eq!(result["Status"], "Complete", "{}", result);
+    assert_eq!(result["Result"]["Bot1"], "Tie", "{}", result);
+    assert_eq!(result["Result"]["Bot2"], "Tie", "{}", result);
+    assert!(result["GameTime"].as_u64().unwrap() >= MAX_GAME_TIME as u64);
+    for bot in ["Bot1", "Bot2"] {
+        assert!(result["AverageFrameTime"][bot].is_number(), "{}", result);
+    }
+}
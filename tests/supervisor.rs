@@ -0,0 +1,80 @@
+//! Supervisor connections to a proxy, no StarCraft II needed
+#![cfg(feature = "supervisor")]
+
+use futures_util::{SinkExt, StreamExt};
+use rust_ac::server::RustServer;
+use std::net::SocketAddr;
+use tokio::sync::oneshot;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Supervisors connecting one after the other
+const CYCLES: usize = 20;
+
+/// Threads of the test process, None where they cannot be counted
+fn thread_count() -> Option<usize> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("Threads:"))
+        .and_then(|count| count.trim().parse().ok())
+}
+
+async fn next_json<S>(supervisor: &mut S) -> serde_json::Value
+where
+    S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+{
+    loop {
+        match supervisor.next().await {
+            Some(Ok(Message::Text(data))) => match serde_json::from_str(&data) {
+                Ok(value) => return value,
+                Err(_) => continue,
+            },
+            Some(Ok(_)) => continue,
+            other => panic!("Supervisor connection ended: {:?}", other),
+        }
+    }
+}
+
+/// Connect a supervisor, check the proxy answers it, and disconnect it again
+async fn connect_cycle(addr: SocketAddr) {
+    let mut request = format!("ws://{}/sc2api", addr)
+        .into_client_request()
+        .unwrap();
+    request
+        .headers_mut()
+        .insert("supervisor", "true".parse().unwrap());
+    let (mut supervisor, _) = tokio_tungstenite::connect_async(request)
+        .await
+        .expect("Could not connect the supervisor");
+    assert_eq!(next_json(&mut supervisor).await["Status"], "Connected");
+    supervisor
+        .send(Message::Text("Status".to_string()))
+        .await
+        .unwrap();
+    let status = next_json(&mut supervisor).await;
+    assert!(status.get("ProxyStatus").is_some(), "{}", status);
+    supervisor.close(None).await.unwrap();
+    // The proxy answers the close once it forgot the supervisor
+    while supervisor.next().await.is_some() {}
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_supervisor_reconnects() {
+    let server = RustServer::new("127.0.0.1:0");
+    let (ready_send, ready_recv) = oneshot::channel();
+    let _running = server
+        .run(Some(ready_send))
+        .expect("Could not start the proxy");
+    let addr = ready_recv.await.unwrap();
+
+    connect_cycle(addr).await;
+    let threads = thread_count();
+    for _ in 1..CYCLES {
+        connect_cycle(addr).await;
+    }
+    // Listening to a supervisor takes no thread of its own
+    if let (Some(before), Some(after)) = (threads, thread_count()) {
+        assert!(after <= before + 2, "{} threads, {} before", after, before);
+    }
+}